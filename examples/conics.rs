@@ -23,26 +23,15 @@ pub fn objects_scene() -> Scene {
 
     // NOTE: The cylinder normals face outward by default, but we want the lighting to be correct
     // from both sides, at least on the cylinder where we can see quite a lot on both sides.
-    // Therefore, I'm creating two copies of the same cylinder, scaled slightly differently, with
-    // the normals flipped on the inner one.
     scene.add_object(
         RenderObject::new(Cylinder::partial(1.5, 3., 300., uv_image_mat))
-            .position(3.0, 1.5, 1.)
-            .rotate(Rotor3::from_euler_angles(
-                90f32.to_radians(),
-                30f32.to_radians(),
-                -35f32.to_radians(),
-            )),
-    );
-    scene.add_object(
-        RenderObject::new(Cylinder::partial(1.49, 3., 300., uv_image_mat))
             .position(3.0, 1.5, 1.)
             .rotate(Rotor3::from_euler_angles(
                 90f32.to_radians(),
                 30f32.to_radians(),
                 -35f32.to_radians(),
             ))
-            .flip_normals(),
+            .two_sided(),
     );
 
     scene.add_object(
@@ -20,7 +20,7 @@ pub fn cornell_box() -> Scene {
 
     let light = world.add_material(EmissiveMat::with_color(Vec3::new(15., 15., 15.)));
 
-    world.add_object(RenderObject::new(XZRect::new(
+    world.add_light(RenderObject::new(XZRect::new(
         213., 343., 227., 332., 554., light,
     )));
     world
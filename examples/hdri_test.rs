@@ -1,87 +1,13 @@
 use firework::camera::CameraSettings;
-use firework::environment::Environment;
+use firework::environment::HdrEnvironment;
 use firework::material::{DielectricMat, LambertianMat, MetalMat};
 use firework::objects::{Sphere, XZRect};
 use firework::render::Renderer;
 use firework::scene::{RenderObject, Scene};
 use firework::window::RenderWindow;
-use std::convert::TryFrom;
-use std::f32::consts::PI;
-use std::path::{Path, PathBuf};
 use std::time;
-use ultraviolet::{Vec2, Vec3};
+use ultraviolet::Vec3;
 
-pub fn sphere_uv(point: &Vec3) -> Vec2 {
-    let phi = point.z.atan2(point.x);
-    let theta = point.y.asin();
-    let u = 1. - (phi + PI) / (2. * PI);
-    let v = (theta + PI / 2.) / PI;
-    Vec2::new(u, v)
-}
-
-#[derive(serde::Deserialize, serde::Serialize, Clone)]
-#[serde(try_from = "PathBuf")]
-#[serde(into = "PathBuf")]
-struct HdrEnvironment {
-    pixels: Vec<image::Rgb<f32>>,
-    path: PathBuf,
-    width: f32,
-    height: f32,
-}
-
-impl HdrEnvironment {
-    pub fn from_path(path: impl AsRef<Path>) -> Result<HdrEnvironment, Box<dyn std::error::Error>> {
-        let path_buf = path.as_ref().to_owned();
-        HdrEnvironment::try_from(path_buf)
-    }
-}
-
-impl Into<PathBuf> for HdrEnvironment {
-    fn into(self) -> PathBuf {
-        self.path
-    }
-}
-
-impl TryFrom<PathBuf> for HdrEnvironment {
-    type Error = Box<dyn std::error::Error>;
-    fn try_from(path: PathBuf) -> Result<HdrEnvironment, Self::Error> {
-        use image::hdr::HdrDecoder;
-        use std::fs::File;
-        use std::io::BufReader;
-
-        let hdri = File::open("urban_street_04_4k.hdr")?;
-        let hdri = BufReader::new(hdri);
-        let hdri = HdrDecoder::new(hdri)?;
-
-        let hdri_width = hdri.metadata().width as f32;
-        let hdri_height = hdri.metadata().height as f32;
-
-        let pixels = hdri.read_image_hdr()?;
-
-        Ok(HdrEnvironment {
-            pixels,
-            path,
-            width: hdri_width,
-            height: hdri_height,
-        })
-    }
-}
-
-#[typetag::serde]
-impl Environment for HdrEnvironment {
-    fn sample(&self, dir: Vec3) -> Vec3 {
-        let uv = sphere_uv(&dir);
-
-        let x = (uv.x * self.width) as usize;
-        let y = ((1. - uv.y) * self.height) as usize;
-
-        let idx = (y as f32 * self.width) as usize + x;
-
-        self.pixels[idx].0.into()
-    }
-}
-
-// NOTE: Currently, there isn't importance sampling, so even with insane numbers of samples, it's impossible to get accurate HDR lighting that isn't noisy.
 pub fn hdri_test() -> Scene {
     let mut scene = Scene::new();
 
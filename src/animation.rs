@@ -0,0 +1,227 @@
+use crate::camera::CameraSettings;
+use crate::scene::RenderObjectIdx;
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+use ultraviolet::{Rotor3, Vec3};
+
+/// Describes how a scene changes over time, by keyframes of the transforms of its objects and of
+/// the camera, which are interpolated linearly between them (see `Scene::set_time`). Keyframes
+/// must be in order of time. Before the first keyframe and after the last one, their values are
+/// held.
+/// ```
+/// use firework::animation::{Animation, CameraKey, TransformKey};
+/// use firework::camera::CameraSettings;
+/// use firework::objects::Sphere;
+/// use firework::{RenderObject, Scene};
+/// use ultraviolet::Vec3;
+///
+/// let mut scene = Scene::new();
+/// let ball = scene.add_object(RenderObject::new(Sphere::new(1., 0)));
+///
+/// let camera = CameraSettings::default().look_at(Vec3::zero());
+/// let animation = Animation::new(24., 0, 47)
+///     .object(ball, vec![
+///         TransformKey::new(0.).position(Vec3::new(0., 5., 0.)),
+///         TransformKey::new(1.).position(Vec3::new(0., 1., 0.)),
+///     ])
+///     .camera(vec![
+///         CameraKey::new(0., camera.cam_pos(Vec3::new(0., 2., 10.))),
+///         CameraKey::new(2., camera.cam_pos(Vec3::new(10., 2., 0.))),
+///     ]);
+/// scene.set_animation(animation);
+///
+/// scene.set_time(0.5);
+/// assert_eq!(scene.stats().bounds.unwrap().1.y, 4.);
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Animation {
+    /// The number of frames per second
+    #[serde(default = "default_frame_rate")]
+    pub frame_rate: f32,
+    /// The first frame of the shot. Frame 0 is at time 0.
+    #[serde(default)]
+    pub start_frame: u32,
+    /// The last frame of the shot
+    #[serde(default)]
+    pub end_frame: u32,
+    /// The keyframes of each animated object
+    #[serde(default)]
+    pub objects: Vec<ObjectTrack>,
+    /// The keyframes of the camera, if it moves
+    #[serde(default)]
+    pub camera: Vec<CameraKey>,
+}
+
+fn default_frame_rate() -> f32 {
+    24.
+}
+
+/// The keyframes of the transform of one object
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectTrack {
+    pub object: RenderObjectIdx,
+    pub keys: Vec<TransformKey>,
+}
+
+/// The position, rotation and scale of an object at a point in time. These replace the ones the
+/// object was created with (relative to its `parent`, if it has one).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TransformKey {
+    /// The time of the keyframe, in seconds
+    pub time: f32,
+    #[serde(default = "Vec3::zero")]
+    pub position: Vec3,
+    #[serde(default = "Rotor3::identity", with = "crate::serde_compat::Rotor3Def")]
+    pub rotation: Rotor3,
+    #[serde(default = "Vec3::one")]
+    pub scale: Vec3,
+}
+
+/// The camera at a point in time
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CameraKey {
+    /// The time of the keyframe, in seconds
+    pub time: f32,
+    pub camera: CameraSettings,
+}
+
+impl Animation {
+    /// Creates an animation of the frames from `start_frame` to `end_frame` (inclusive), which
+    /// doesn't move anything yet
+    pub fn new(frame_rate: f32, start_frame: u32, end_frame: u32) -> Self {
+        Animation {
+            frame_rate,
+            start_frame,
+            end_frame,
+            objects: Vec::new(),
+            camera: Vec::new(),
+        }
+    }
+
+    /// Adds the keyframes of an object
+    pub fn object(mut self, object: RenderObjectIdx, keys: Vec<TransformKey>) -> Self {
+        self.objects.push(ObjectTrack { object, keys });
+        self
+    }
+
+    /// Sets the keyframes of the camera
+    pub fn camera(mut self, keys: Vec<CameraKey>) -> Self {
+        self.camera = keys;
+        self
+    }
+
+    /// The frames of the shot
+    pub fn frames(&self) -> RangeInclusive<u32> {
+        self.start_frame..=self.end_frame
+    }
+
+    /// The time of a frame, in seconds
+    pub fn frame_time(&self, frame: u32) -> f32 {
+        frame as f32 / self.frame_rate
+    }
+
+    /// The transform of an object at the given time, or `None` if it has no keyframes
+    pub(crate) fn transform_at(keys: &[TransformKey], time: f32) -> Option<TransformKey> {
+        let (a, b, t) = surrounding_keys(keys, time, |key| key.time)?;
+        Some(TransformKey {
+            time,
+            position: a.position + t * (b.position - a.position),
+            rotation: nlerp(a.rotation, b.rotation, t),
+            scale: a.scale + t * (b.scale - a.scale),
+        })
+    }
+
+    /// The camera at the given time, or `None` if it has no keyframes
+    pub(crate) fn camera_at(&self, time: f32) -> Option<CameraSettings> {
+        let (a, b, t) = surrounding_keys(&self.camera, time, |key| key.time)?;
+        Some(a.camera.lerp(&b.camera, t))
+    }
+
+    /// Combines the animations of two scenes being merged. The frames and the camera of `first`
+    /// are used if there is one, and the object indices in `second` are offset by
+    /// `object_offset`.
+    pub(crate) fn combine(
+        first: Option<Animation>,
+        second: Option<Animation>,
+        object_offset: usize,
+    ) -> Option<Animation> {
+        let mut second = match second {
+            Some(second) => second,
+            None => return first,
+        };
+        for track in &mut second.objects {
+            track.object = track.object.offset(object_offset);
+        }
+        Some(match first {
+            Some(mut first) => {
+                first.objects.extend(second.objects);
+                if first.camera.is_empty() {
+                    first.camera = second.camera;
+                }
+                first
+            }
+            None => second,
+        })
+    }
+}
+
+/// Finds the keyframes on either side of `time`, and how far between them it is
+fn surrounding_keys<K>(
+    keys: &[K],
+    time: f32,
+    key_time: impl Fn(&K) -> f32,
+) -> Option<(&K, &K, f32)> {
+    let first = keys.first()?;
+    let next = match keys.iter().position(|key| key_time(key) > time) {
+        Some(0) => return Some((first, first, 0.)),
+        Some(next) => next,
+        None => {
+            let last = keys.last()?;
+            return Some((last, last, 0.));
+        }
+    };
+    let (a, b) = (&keys[next - 1], &keys[next]);
+    Some((a, b, (time - key_time(a)) / (key_time(b) - key_time(a))))
+}
+
+/// Interpolates between two rotations, the shorter way around. This isn't quite a constant
+/// angular speed, but is close for keyframes which aren't too far apart.
+fn nlerp(a: Rotor3, b: Rotor3, t: f32) -> Rotor3 {
+    let dot = a.s * b.s + a.bv.xy * b.bv.xy + a.bv.xz * b.bv.xz + a.bv.yz * b.bv.yz;
+    let b = if dot < 0. { b * -1. } else { b };
+    (a * (1. - t) + b * t).normalized()
+}
+
+impl TransformKey {
+    /// Creates a keyframe at `time` (in seconds), with no translation, rotation or scale
+    pub fn new(time: f32) -> Self {
+        TransformKey {
+            time,
+            position: Vec3::zero(),
+            rotation: Rotor3::identity(),
+            scale: Vec3::one(),
+        }
+    }
+
+    pub fn position(mut self, position: Vec3) -> Self {
+        self.position = position;
+        self
+    }
+
+    pub fn rotate(mut self, rotation: Rotor3) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn scale(mut self, scale: Vec3) -> Self {
+        self.scale = scale;
+        self
+    }
+}
+
+impl CameraKey {
+    /// Creates a keyframe of the camera at `time` (in seconds)
+    pub fn new(time: f32, camera: CameraSettings) -> Self {
+        CameraKey { time, camera }
+    }
+}
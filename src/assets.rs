@@ -0,0 +1,94 @@
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+
+/// Where the files a scene file refers to (image textures, environment maps, volumes and IES
+/// profiles) are looked for while it's read. A relative path is tried relative to the directory of
+/// the scene file it's in first, then relative to each of the search paths in order, and if none
+/// of those exist, it's used as it is (relative to the current directory).
+/// ```no_run
+/// use firework::assets::AssetPaths;
+/// use firework::Scene;
+///
+/// let assets = AssetPaths::new()
+///     .search_path("assets/textures")
+///     .search_path("/usr/share/hdris");
+/// let scene = Scene::from_path_with_assets("scenes/room.yml", &assets).unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AssetPaths {
+    /// The directory of the scene file being read, if it's read from a file
+    base: Option<PathBuf>,
+    search_paths: Vec<PathBuf>,
+}
+
+thread_local! {
+    /// The paths used by the scene being read on this thread, if any
+    static CURRENT: RefCell<Option<AssetPaths>> = const { RefCell::new(None) };
+}
+
+impl AssetPaths {
+    /// Creates an `AssetPaths` with no search paths
+    pub fn new() -> Self {
+        AssetPaths::default()
+    }
+
+    /// Adds a directory to look for assets in, after the ones already added
+    pub fn search_path(mut self, dir: impl AsRef<Path>) -> Self {
+        self.search_paths.push(dir.as_ref().to_owned());
+        self
+    }
+
+    /// The directories assets are looked for in, after the directory of the scene file
+    pub fn search_paths(&self) -> &[PathBuf] {
+        &self.search_paths
+    }
+
+    /// Finds the file a path in a scene file refers to
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        if path.is_absolute() {
+            return path.to_owned();
+        }
+        self.base
+            .iter()
+            .chain(&self.search_paths)
+            .map(|dir| dir.join(path))
+            .find(|candidate| candidate.exists())
+            .unwrap_or_else(|| path.to_owned())
+    }
+
+    /// Runs `f` (which reads a scene) with these paths, and assets relative to `base`, if given.
+    /// The paths in use before are restored afterwards, so included files can have their own
+    /// base directory.
+    pub(crate) fn scope<T>(&self, base: Option<&Path>, f: impl FnOnce() -> T) -> T {
+        /// Restores the previous paths, even if `f` panics
+        struct Restore(Option<AssetPaths>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                let previous = self.0.take();
+                CURRENT.with(|current| *current.borrow_mut() = previous);
+            }
+        }
+
+        let paths = AssetPaths {
+            base: base.map(Path::to_owned),
+            search_paths: self.search_paths.clone(),
+        };
+        let _restore = Restore(CURRENT.with(|current| current.replace(Some(paths))));
+        f()
+    }
+
+    /// The paths used by the scene being read, or none if no scene is being read
+    pub(crate) fn current() -> AssetPaths {
+        CURRENT.with(|current| current.borrow().clone().unwrap_or_default())
+    }
+}
+
+/// Resolves a path while deserializing, using the paths of the scene being read (see
+/// `AssetPaths`). Outside of reading a scene, the path is returned as it is.
+pub(crate) fn resolve_asset(path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+    CURRENT.with(|current| match &*current.borrow() {
+        Some(paths) => paths.resolve(path),
+        None => path.to_owned(),
+    })
+}
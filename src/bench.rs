@@ -0,0 +1,148 @@
+use crate::bvh::Aggregate;
+use crate::render::Renderer;
+use crate::scene::Scene;
+use serde::Serialize;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// The number of bounces rays are counted separately for. Rays after more bounces are counted
+/// with the last.
+const BOUNCES: usize = 11;
+
+/// Whether rays are being counted, which is only done while benchmarking
+static COUNTING: AtomicBool = AtomicBool::new(false);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+/// The rays counted by all the threads: the rays at each bounce, then the shadow rays
+static TOTALS: [AtomicUsize; BOUNCES + 1] = [ZERO; BOUNCES + 1];
+
+thread_local! {
+    /// The rays counted on this thread since they were last added to `TOTALS`
+    static COUNTS: Cell<[usize; BOUNCES + 1]> = const { Cell::new([0; BOUNCES + 1]) };
+}
+
+/// Counts a ray traced after `depth` bounces (0 for rays from the camera), while benchmarking
+pub(crate) fn count_ray(depth: usize) {
+    count(depth.min(BOUNCES - 1));
+}
+
+/// Counts a shadow ray traced towards a light or the environment, while benchmarking
+pub(crate) fn count_shadow_ray() {
+    count(BOUNCES);
+}
+
+fn count(slot: usize) {
+    if COUNTING.load(Ordering::Relaxed) {
+        COUNTS.with(|counts| {
+            let mut new = counts.get();
+            new[slot] += 1;
+            counts.set(new);
+        });
+    }
+}
+
+/// Adds the rays counted on this thread to the totals. This is done after each pixel, rather
+/// than for each ray, so the threads don't wait on each other.
+pub(crate) fn flush_rays() {
+    if COUNTING.load(Ordering::Relaxed) {
+        for (total, count) in TOTALS.iter().zip(&COUNTS.with(|counts| counts.take())) {
+            total.fetch_add(*count, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The timings of a render by `bench`, which serialize to JSON to keep track of them over time
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchResult {
+    /// The name given to `bench`, e.g. the preset rendered
+    pub name: String,
+    pub width: usize,
+    pub height: usize,
+    pub samples: usize,
+    /// The time taken to build the BVH over the scene, in seconds (0 if it doesn't use one)
+    pub bvh_build_time: f64,
+    /// The time taken to render the scene, not counting building the BVH, in seconds
+    pub render_time: f64,
+    /// The number of rays traced, including shadow rays
+    pub rays: usize,
+    /// The millions of rays traced each second
+    pub mrays_per_second: f64,
+    /// The millions of rays traced after each number of bounces, starting with the rays from the
+    /// camera. Rays after 10 or more bounces are counted together.
+    pub mrays_by_bounce: Vec<f64>,
+    /// The millions of shadow rays traced towards lights and the environment
+    pub shadow_mrays: f64,
+    /// A rough estimate of the memory taken up by the scene, in bytes (see `SceneStats`)
+    pub scene_memory: usize,
+    /// The most memory the process has used so far, in bytes, where that can be found (on Linux)
+    pub peak_memory: Option<usize>,
+}
+
+/// Renders a scene, and measures how long building the BVH and tracing the rays take, and how
+/// many rays are traced. Renders are deterministic, so the same scene and settings always trace
+/// the same rays, and the results can be compared between versions of firework.
+///
+/// Rays are counted over the whole process, so only one benchmark should run at a time.
+/// ```
+/// use firework::bench::bench;
+/// use firework::procgen::Preset;
+///
+/// let mut scene = Preset::CornellBox.build();
+/// let renderer = scene.render_settings.take().unwrap().width(16).height(16).samples(2);
+/// let result = bench("cornell", scene, &renderer);
+/// // at least one ray from the camera for each sample
+/// assert!(result.mrays_by_bounce[0] >= 16. * 16. * 2. / 1e6);
+/// println!("{}", serde_json::to_string(&result).unwrap());
+/// ```
+pub fn bench(name: &str, scene: Scene, renderer: &Renderer) -> BenchResult {
+    let scene_memory = scene.stats().memory;
+    let scene = renderer.prepare(scene);
+
+    // the BVH is built again by the render, so it's timed by itself first
+    let start = Instant::now();
+    if renderer.use_bvh {
+        drop(scene.build_bvh());
+    }
+    let bvh_build_time = start.elapsed().as_secs_f64();
+
+    for total in &TOTALS {
+        total.store(0, Ordering::Relaxed);
+    }
+    COUNTING.store(true, Ordering::Relaxed);
+    let start = Instant::now();
+    renderer.render_prepared(&scene, None, None);
+    let render_time = (start.elapsed().as_secs_f64() - bvh_build_time).max(0.);
+    COUNTING.store(false, Ordering::Relaxed);
+
+    let totals: Vec<usize> = TOTALS
+        .iter()
+        .map(|total| total.load(Ordering::Relaxed))
+        .collect();
+    let rays = totals.iter().sum();
+    let mrays = |rays: usize| rays as f64 / 1e6;
+    BenchResult {
+        name: name.to_owned(),
+        width: renderer.width,
+        height: renderer.height,
+        samples: renderer.samples,
+        bvh_build_time,
+        render_time,
+        rays,
+        mrays_per_second: mrays(rays) / render_time.max(1e-9),
+        mrays_by_bounce: totals[..BOUNCES].iter().map(|&rays| mrays(rays)).collect(),
+        shadow_mrays: mrays(totals[BOUNCES]),
+        scene_memory,
+        peak_memory: peak_memory(),
+    }
+}
+
+/// The most memory the process has used, from `/proc`
+fn peak_memory() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    // e.g. "VmHWM:     12345 kB"
+    let kilobytes: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
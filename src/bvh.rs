@@ -70,6 +70,52 @@ where
     }
 }
 
+/// Like `new_helper`, but for a BVH which owns its leaves, so it doesn't need an `Aggregate` to
+/// index into
+fn owned_helper<T: Hitable>(mut items: Vec<T>, depth: usize) -> BVHNode<T> {
+    items.sort_by(|a, b| {
+        a.bounding_box().center()[depth % 3]
+            .partial_cmp(&b.bounding_box().center()[depth % 3])
+            .expect("Float comparison failed in BVH constructor")
+    });
+
+    match items.len() {
+        1 => {
+            let a = items.pop().unwrap();
+            BVHNode {
+                aabb: a.bounding_box(),
+                next: BVHNodeVariant::Leaf(a),
+            }
+        }
+        2 => {
+            let b = items.pop().unwrap();
+            let a = items.pop().unwrap();
+            BVHNode {
+                aabb: a.bounding_box().expand(&b.bounding_box()),
+                next: BVHNodeVariant::DoubleLeaf(a, b),
+            }
+        }
+        len => {
+            let back_half = items.split_off(len / 2);
+            let left = owned_helper(items, depth + 1);
+            let right = owned_helper(back_half, depth + 1);
+            BVHNode {
+                aabb: left.bounding_box().expand(&right.bounding_box()),
+                next: BVHNodeVariant::Branch(Box::new(left), Box::new(right)),
+            }
+        }
+    }
+}
+
+impl<T: Hitable> BVHNode<T> {
+    /// Builds a BVH which owns the hitables in it, e.g. the objects of a `SubScene`. There must
+    /// be at least one.
+    pub(crate) fn from_owned(items: Vec<T>) -> BVHNode<T> {
+        assert!(!items.is_empty(), "Can't build a BVH with nothing in it");
+        owned_helper(items, 0)
+    }
+}
+
 pub trait Aggregate<'a> {
     type BVHType;
 
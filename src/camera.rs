@@ -1,5 +1,6 @@
 use crate::ray::Ray;
 use crate::util::random_in_unit_disk;
+use serde::{Deserialize, Serialize};
 use std::f32::consts::PI;
 use tiny_rng::Rand;
 use ultraviolet::Vec3;
@@ -13,8 +14,11 @@ pub struct Camera {
     v: Vec3,
     _w: Vec3,
     lens_radius: f32,
+    pixel_spread: f32,
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
 pub struct CameraSettings {
     cam_pos: Vec3,
     look_at: Vec3,
@@ -68,6 +72,61 @@ impl CameraSettings {
         self.focus_dist = focus_dist;
         self
     }
+
+    /// Moves the camera around the point it looks at, keeping its distance: by `yaw` (in radians)
+    /// around the vertical axis, and by `pitch` up or down, stopping short of looking straight
+    /// down or up
+    pub fn orbit(mut self, yaw: f32, pitch: f32) -> CameraSettings {
+        let offset = self.cam_pos - self.look_at;
+        let distance = offset.mag();
+        if distance == 0. {
+            return self;
+        }
+        let yaw = offset.x.atan2(offset.z) + yaw;
+        let pitch = ((offset.y / distance).asin() + pitch).clamp(-1.5, 1.5);
+        self.cam_pos = self.look_at
+            + distance
+                * Vec3::new(
+                    pitch.cos() * yaw.sin(),
+                    pitch.sin(),
+                    pitch.cos() * yaw.cos(),
+                );
+        self
+    }
+
+    /// Moves the camera and the point it looks at to the right by `x` and up by `y`, in units of
+    /// the height of the view at the point it looks at
+    pub fn pan(mut self, x: f32, y: f32) -> CameraSettings {
+        let offset = self.cam_pos - self.look_at;
+        let w = offset.normalized();
+        let u = Vec3::unit_y().cross(w).normalized();
+        let v = w.cross(u);
+        let height = 2. * (self.vfov.to_radians() / 2.).tan() * offset.mag();
+        let shift = height * (x * u + y * v);
+        self.cam_pos += shift;
+        self.look_at += shift;
+        self
+    }
+
+    /// Moves the camera towards the point it looks at, to `factor` times its distance. The focus
+    /// distance is scaled too, so the same point stays in focus.
+    pub fn zoom(mut self, factor: f32) -> CameraSettings {
+        self.cam_pos = self.look_at + factor * (self.cam_pos - self.look_at);
+        self.focus_dist *= factor;
+        self
+    }
+
+    /// Interpolates linearly between two cameras, `t` of the way from this one to `other`
+    pub fn lerp(&self, other: &CameraSettings, t: f32) -> CameraSettings {
+        let mix = |a: f32, b: f32| a + t * (b - a);
+        CameraSettings {
+            cam_pos: self.cam_pos + t * (other.cam_pos - self.cam_pos),
+            look_at: self.look_at + t * (other.look_at - self.look_at),
+            vfov: mix(self.vfov, other.vfov),
+            aperture: mix(self.aperture, other.aperture),
+            focus_dist: mix(self.focus_dist, other.focus_dist),
+        }
+    }
 }
 
 impl Camera {
@@ -103,9 +162,15 @@ impl Camera {
             v,
             _w: w,
             lens_radius: aperture / 2.,
+            pixel_spread: 2.0 * half_height / height as f32,
         }
     }
 
+    /// The (approximate) angle covered by a single pixel, in radians
+    pub fn pixel_spread(&self) -> f32 {
+        self.pixel_spread
+    }
+
     pub fn ray(&self, s: f32, t: f32, rand: &mut impl Rand) -> Ray {
         let rd = self.lens_radius * random_in_unit_disk(rand);
         let offset = self.u * rd.x + self.v * rd.y;
@@ -0,0 +1,241 @@
+use crate::render::{Renderer, TileState, TILE_SIZE};
+use crate::scene::SceneInternal;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tiny_rng::LcRng;
+use ultraviolet::Vec3;
+
+/// The start of a checkpoint file, with the version of its format
+const MAGIC: &[u8; 8] = b"FWCKPT1\n";
+
+/// The samples taken of the pixels of a tile so far, summed
+#[derive(Clone)]
+struct TileProgress {
+    samples: usize,
+    totals: Vec<(Vec3, f32, Vec<Vec3>)>,
+}
+
+/// Saves the progress of a render to a file every so often, so it can be resumed from there if
+/// it's stopped (see `Renderer::render_checkpointed`)
+pub(crate) struct Checkpoint {
+    path: PathBuf,
+    interval: Duration,
+    /// The size of the image, the crop window, the size of the tiles and the number of light
+    /// groups, which must be the same for a render to be resumed
+    layout: [usize; 8],
+    /// The number of pixels in each tile
+    tile_pixels: Vec<usize>,
+    /// The number of light groups
+    groups: usize,
+    /// The width of the image, to find the pixels of the tiles
+    width: usize,
+    /// The samples taken of each tile, as of the last pass over it that was finished
+    tiles: Mutex<Vec<TileProgress>>,
+    last_save: Mutex<Instant>,
+    /// Held while the file is written, so saves from different threads don't overlap
+    writing: Mutex<()>,
+}
+
+impl Checkpoint {
+    /// Reads the progress of a render from the file at `path`, if it exists, to save the
+    /// progress of the render back to every `interval`
+    pub(crate) fn open(
+        path: &Path,
+        interval: Duration,
+        renderer: &Renderer,
+        scene: &SceneInternal,
+    ) -> io::Result<Checkpoint> {
+        let rects = renderer.tile_rects();
+        let groups = scene.light_groups.len();
+        let (x0, y0) = rects.first().map_or((0, 0), |&[x, y, _, _]| (x, y));
+        let (x1, y1) = rects
+            .last()
+            .map_or((0, 0), |&[x, y, width, height]| (x + width, y + height));
+        let layout = [
+            renderer.width,
+            renderer.height,
+            x0,
+            y0,
+            x1,
+            y1,
+            TILE_SIZE,
+            groups,
+        ];
+        let tile_pixels: Vec<usize> = rects.iter().map(|[_, _, w, h]| w * h).collect();
+
+        let empty = |pixels: usize| TileProgress {
+            samples: 0,
+            totals: vec![(Vec3::zero(), 0., vec![Vec3::zero(); groups]); pixels],
+        };
+        let mut checkpoint = Checkpoint {
+            path: path.to_owned(),
+            interval,
+            layout,
+            tiles: Mutex::new(tile_pixels.iter().map(|&pixels| empty(pixels)).collect()),
+            tile_pixels,
+            groups,
+            width: renderer.width,
+            last_save: Mutex::new(Instant::now()),
+            writing: Mutex::new(()),
+        };
+        match std::fs::File::open(path) {
+            Ok(file) => {
+                let tiles = checkpoint.read(&mut io::BufReader::new(file))?;
+                checkpoint.tiles = Mutex::new(tiles);
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(checkpoint)
+    }
+
+    /// Reads the tiles saved to a checkpoint file
+    fn read(&self, reader: &mut impl Read) -> io::Result<Vec<TileProgress>> {
+        let invalid = |message: &str| {
+            let message = format!("{:?} {}", self.path, message);
+            io::Error::new(io::ErrorKind::InvalidData, message)
+        };
+
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("isn't a checkpoint of a render"));
+        }
+        let mut layout = [0; 8];
+        for value in &mut layout {
+            *value = read_u64(reader)? as usize;
+        }
+        if layout != self.layout {
+            return Err(invalid(
+                "is a checkpoint of a render with a different size, crop window or light groups",
+            ));
+        }
+
+        let mut tiles = Vec::with_capacity(self.tile_pixels.len());
+        for &pixels in &self.tile_pixels {
+            let samples = read_u64(reader)? as usize;
+            let mut totals = Vec::with_capacity(pixels);
+            for _ in 0..pixels {
+                let color = read_vec3(reader)?;
+                let alpha = read_f32(reader)?;
+                let groups = (0..self.groups)
+                    .map(|_| read_vec3(reader))
+                    .collect::<io::Result<_>>()?;
+                totals.push((color, alpha, groups));
+            }
+            tiles.push(TileProgress { samples, totals });
+        }
+        Ok(tiles)
+    }
+
+    /// Carries on the tiles of a render from where they were saved
+    pub(crate) fn resume(&self, states: &mut [TileState]) {
+        let tiles = self.tiles.lock().unwrap();
+        for (state, tile) in states.iter_mut().zip(tiles.iter()) {
+            if tile.samples == 0 {
+                continue;
+            }
+            state.samples = tile.samples;
+            state.totals = tile.totals.clone();
+            // the random numbers the tile was using weren't saved, so new ones are started,
+            // which are different for each time the render is resumed
+            for (i, rng) in state.rngs.iter_mut().enumerate() {
+                let idx = (state.y + i / state.width) * self.width + state.x + i % state.width;
+                *rng = LcRng::new(idx as u64 ^ ((tile.samples as u64) << 32));
+            }
+        }
+    }
+
+    /// Keeps the samples of a tile which has finished a pass, and saves the render if it's been
+    /// `interval` since it was last saved. Failing to save doesn't stop the render, which might
+    /// still finish.
+    pub(crate) fn tile_done(&self, tile_idx: usize, state: &TileState, samples: usize) {
+        let progress = TileProgress {
+            samples: state.samples,
+            totals: state.totals.clone(),
+        };
+        self.tiles.lock().unwrap()[tile_idx] = progress;
+
+        {
+            let mut last_save = self.last_save.lock().unwrap();
+            if last_save.elapsed() < self.interval {
+                return;
+            }
+            *last_save = Instant::now();
+        }
+        // the other threads carry on rendering (and finishing tiles) while this one saves
+        let _writing = self.writing.lock().unwrap();
+        let tiles = self.tiles.lock().unwrap().clone();
+        match self.write(&tiles) {
+            Ok(()) => {
+                let done: usize = tiles.iter().map(|tile| tile.samples.min(samples)).sum();
+                let percent = 100. * done as f32 / (samples * tiles.len()).max(1) as f32;
+                eprintln!("Saved checkpoint to {:?} ({:.1}% done)", self.path, percent);
+            }
+            Err(e) => eprintln!("Couldn't save checkpoint to {:?} -- {}", self.path, e),
+        }
+    }
+
+    /// Saves the tiles as they are now
+    pub(crate) fn save(&self) -> io::Result<()> {
+        let _writing = self.writing.lock().unwrap();
+        let tiles = self.tiles.lock().unwrap().clone();
+        self.write(&tiles)
+    }
+
+    /// Writes the tiles to the file. They're written to another file first, which replaces it
+    /// once it's complete, so a crash while saving doesn't lose the checkpoint before. Only
+    /// called with `writing` held, as the other file is the same every time.
+    fn write(&self, tiles: &[TileProgress]) -> io::Result<()> {
+        let mut partial = self.path.clone().into_os_string();
+        partial.push(".partial");
+        let partial = PathBuf::from(partial);
+
+        let mut file = io::BufWriter::new(std::fs::File::create(&partial)?);
+        file.write_all(MAGIC)?;
+        for &value in &self.layout {
+            file.write_all(&(value as u64).to_le_bytes())?;
+        }
+        for tile in tiles {
+            file.write_all(&(tile.samples as u64).to_le_bytes())?;
+            for (color, alpha, groups) in &tile.totals {
+                write_vec3(&mut file, *color)?;
+                file.write_all(&alpha.to_le_bytes())?;
+                for &light in groups {
+                    write_vec3(&mut file, light)?;
+                }
+            }
+        }
+        file.into_inner()?.sync_all()?;
+        std::fs::rename(&partial, &self.path)
+    }
+}
+
+fn write_vec3(writer: &mut impl Write, v: Vec3) -> io::Result<()> {
+    for x in &[v.x, v.y, v.z] {
+        writer.write_all(&x.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f32(reader: &mut impl Read) -> io::Result<f32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+fn read_vec3(reader: &mut impl Read) -> io::Result<Vec3> {
+    Ok(Vec3::new(
+        read_f32(reader)?,
+        read_f32(reader)?,
+        read_f32(reader)?,
+    ))
+}
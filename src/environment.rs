@@ -1,10 +1,46 @@
-use serde::{Deserialize, Serialize};
-use ultraviolet::Vec3;
+use crate::assets::resolve_asset;
+use crate::objects::sphere_uv;
+use crate::texture::{
+    blackbody, eval_stops, sorted_stops, xyz_to_linear_srgb, ColorSpace, ColorStop,
+    RampInterpolation,
+};
+use crate::util::{cone_solid_angle, random_unit_vector, sample_cone, Distribution2D};
+use serde::{Deserialize, Serialize, Serializer};
+use std::convert::TryFrom;
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use tiny_rng::{LcRng, Rand};
+use ultraviolet::{Mat3, Rotor3, Vec2, Vec3};
 
 /// A trait for the world environment
 #[typetag::serde(tag = "environment")]
 pub trait Environment: Sync {
     fn sample(&self, dir: Vec3) -> Vec3;
+
+    /// Called once before rendering starts, so environments can defer expensive work (like
+    /// decoding images) until they are actually needed.
+    fn prepare(&mut self) {}
+
+    /// Picks a direction towards the environment, ideally in proportion to the light arriving
+    /// from it, and returns it along with its probability density (with respect to solid angle).
+    /// Returns `None` if the environment can't be sampled, in which case it's only found by rays
+    /// that escape the scene.
+    fn sample_direction(&self, _rand: &mut LcRng) -> Option<(Vec3, f32)> {
+        None
+    }
+
+    /// Whether `sample_direction` picks directions at all (once the environment is prepared).
+    /// Environments which implement it should return true.
+    fn can_sample(&self) -> bool {
+        false
+    }
+
+    /// The probability density that `sample_direction` picks `dir`
+    fn pdf(&self, _dir: Vec3) -> f32 {
+        0.
+    }
 }
 
 #[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
@@ -65,3 +101,1231 @@ impl Environment for SkyEnv {
         (1. - t) * self.horizon_color + t * self.zenith_color
     }
 }
+
+/// A sky made of any number of color stops over elevation, for stylized skies like sunset bands
+/// or night gradients. Each stop's `position` is an elevation in degrees, from -90 (straight
+/// down) to 90 (straight up). Directions outside the first and last stop take the color of the
+/// nearest stop.
+///
+/// If a `ground_color` is set, everything below the horizon is that color instead, like a flat
+/// ground plane stretching to infinity.
+/// ```
+/// use firework::environment::{Environment, GradientEnv};
+/// use firework::texture::{ColorStop, RampInterpolation};
+/// use ultraviolet::Vec3;
+///
+/// let sunset = GradientEnv::new(vec![
+///     ColorStop { position: 0., color: Vec3::new(1.0, 0.45, 0.15) },
+///     ColorStop { position: 8., color: Vec3::new(0.9, 0.35, 0.4) },
+///     ColorStop { position: 30., color: Vec3::new(0.25, 0.2, 0.5) },
+///     ColorStop { position: 90., color: Vec3::new(0.05, 0.05, 0.2) },
+/// ])
+/// .interpolation(RampInterpolation::Smooth)
+/// .ground_color(Vec3::new(0.05, 0.04, 0.03));
+///
+/// // the stops in a scene file are sorted too
+/// let night: GradientEnv = serde_yaml::from_str("stops: [
+///     {position: 90, color: {x: 0, y: 0, z: 0.1}},
+///     {position: 0, color: {x: 0.2, y: 0.2, z: 0.3}},
+/// ]").unwrap();
+/// assert_eq!(night.sample(Vec3::unit_y()), Vec3::new(0., 0., 0.1));
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "GradientEnvDef")]
+pub struct GradientEnv {
+    /// Sorted by elevation
+    stops: Vec<ColorStop>,
+    #[serde(default = "default_interpolation")]
+    interpolation: RampInterpolation,
+    #[serde(default)]
+    ground_color: Option<Vec3>,
+}
+
+fn default_interpolation() -> RampInterpolation {
+    RampInterpolation::Linear
+}
+
+#[derive(Deserialize)]
+struct GradientEnvDef {
+    stops: Vec<ColorStop>,
+    #[serde(default = "default_interpolation")]
+    interpolation: RampInterpolation,
+    #[serde(default)]
+    ground_color: Option<Vec3>,
+}
+
+impl TryFrom<GradientEnvDef> for GradientEnv {
+    type Error = String;
+    fn try_from(def: GradientEnvDef) -> Result<GradientEnv, String> {
+        Ok(GradientEnv {
+            stops: sorted_stops(def.stops)?,
+            interpolation: def.interpolation,
+            ground_color: def.ground_color,
+        })
+    }
+}
+
+impl GradientEnv {
+    /// Creates a linearly interpolated gradient. Panics if `stops` is empty, or a position is NaN.
+    pub fn new(stops: Vec<ColorStop>) -> Self {
+        let stops = sorted_stops(stops).unwrap_or_else(|e| panic!("GradientEnv::new() -- {}", e));
+        GradientEnv {
+            stops,
+            interpolation: RampInterpolation::Linear,
+            ground_color: None,
+        }
+    }
+
+    pub fn interpolation(mut self, interpolation: RampInterpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
+    /// Replaces the gradient below the horizon with a solid color
+    pub fn ground_color(mut self, color: Vec3) -> Self {
+        self.ground_color = Some(color);
+        self
+    }
+}
+
+#[typetag::serde]
+impl Environment for GradientEnv {
+    fn sample(&self, dir: Vec3) -> Vec3 {
+        let dir = dir.normalized();
+        match self.ground_color {
+            Some(ground) if dir.y < 0. => ground,
+            _ => {
+                let elevation = dir.y.clamp(-1., 1.).asin().to_degrees();
+                eval_stops(&self.stops, self.interpolation, elevation)
+            }
+        }
+    }
+}
+
+/// A physically-based clear sky lit by the sun, using the analytic model from Preetham, Shirley
+/// and Smits, "A Practical Analytic Model for Daylight". The sun is included as a bright disk, and
+/// can be importance sampled.
+///
+/// Radiance is scaled so that 1 corresponds to 10,000 cd/m², which puts a clear sky around 1 and
+/// the sun around 10⁵. The model is only meant for the sun above the horizon.
+/// ```
+/// use firework::environment::SunSkyEnv;
+/// use firework::scene::Scene;
+///
+/// let mut scene = Scene::new();
+/// // a hazy late afternoon, with the sun in the west
+/// scene.set_environment(SunSkyEnv::new(15., 270.).turbidity(6.));
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SunSkyEnv {
+    /// The angle of the sun above the horizon, in degrees
+    elevation: f32,
+    /// The direction of the sun around the y axis, in degrees, from +z towards +x
+    azimuth: f32,
+    /// The haziness of the atmosphere, from 2 (very clear) to 10 (hazy)
+    #[serde(default = "SunSkyEnv::default_turbidity")]
+    turbidity: f32,
+    /// The angular diameter of the sun, in degrees
+    #[serde(default = "SunSkyEnv::default_sun_size")]
+    sun_size: f32,
+    /// The albedo of the ground below the horizon, which is lit by the sun and the sky
+    #[serde(default = "SunSkyEnv::default_ground_color")]
+    ground_color: Vec3,
+}
+
+impl SunSkyEnv {
+    pub fn new(elevation: f32, azimuth: f32) -> Self {
+        SunSkyEnv {
+            elevation,
+            azimuth,
+            turbidity: Self::default_turbidity(),
+            sun_size: Self::default_sun_size(),
+            ground_color: Self::default_ground_color(),
+        }
+    }
+
+    /// Sets the turbidity, clamped to the range 1.7..10 that the model was fit to
+    pub fn turbidity(mut self, turbidity: f32) -> Self {
+        self.turbidity = turbidity.clamp(1.7, 10.);
+        self
+    }
+
+    /// Sets the angular diameter of the sun (in degrees), which controls how soft its shadows are
+    pub fn sun_size(mut self, sun_size: f32) -> Self {
+        self.sun_size = sun_size;
+        self
+    }
+
+    pub fn ground_color(mut self, ground_color: Vec3) -> Self {
+        self.ground_color = ground_color;
+        self
+    }
+
+    fn default_turbidity() -> f32 {
+        3.
+    }
+
+    fn default_sun_size() -> f32 {
+        0.53
+    }
+
+    fn default_ground_color() -> Vec3 {
+        Vec3::broadcast(0.3)
+    }
+
+    /// The (normalized) direction towards the sun
+    pub fn sun_direction(&self) -> Vec3 {
+        let elevation = self.elevation.to_radians();
+        let azimuth = self.azimuth.to_radians();
+        Vec3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            elevation.cos() * azimuth.cos(),
+        )
+    }
+
+    fn sun_radius(&self) -> f32 {
+        (0.5 * self.sun_size).to_radians()
+    }
+
+    /// The radiance of the sky (without the sun) in the direction `dir`, above the horizon
+    fn sky(&self, dir: Vec3) -> Vec3 {
+        let t = self.turbidity;
+        // the zenith angle of the sun, kept above the horizon
+        let theta_s = (PI / 2. - self.elevation.to_radians()).clamp(0., PI / 2. - 0.01);
+        let cos_theta = dir.y.max(0.001);
+        let gamma = dir.dot(self.sun_direction()).clamp(-1., 1.).acos();
+
+        // the Perez sky distribution, relative to the zenith
+        let perez = |[a, b, c, d, e]: [f32; 5]| {
+            let f = |cos_theta: f32, gamma: f32| {
+                (1. + a * (b / cos_theta).exp())
+                    * (1. + c * (d * gamma).exp() + e * gamma.cos() * gamma.cos())
+            };
+            f(cos_theta, gamma) / f(1., theta_s)
+        };
+        let lum = perez([
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ]);
+        let x = perez([
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ]);
+        let y = perez([
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ]);
+
+        // the luminance (in kcd/m²) and chromaticity at the zenith
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_s);
+        let zenith_lum = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cubic =
+            |c: [f32; 4]| c[0] * theta_s.powi(3) + c[1] * theta_s.powi(2) + c[2] * theta_s + c[3];
+        let zenith_x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        // scale from kcd/m² to units of 10,000 cd/m²
+        let lum = (0.1 * zenith_lum * lum).max(0.);
+        let (x, y) = (zenith_x * x, zenith_y * y);
+        xyz_to_linear_srgb(Vec3::new(x / y * lum, lum, (1. - x - y) / y * lum))
+    }
+
+    /// The radiance of the sun disk, after passing through the atmosphere
+    fn sun(&self) -> Vec3 {
+        if self.elevation <= 0. {
+            return Vec3::zero();
+        }
+        // the relative optical mass of the atmosphere along the path to the sun
+        let theta_s = 90. - self.elevation;
+        let mass = 1. / (theta_s.to_radians().cos() + 0.15 * (93.885 - theta_s).powf(-1.253));
+
+        // Rayleigh and aerosol extinction, at wavelengths (in micrometers) for red, green and blue
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let transmittance = Vec3::new(0.68, 0.55, 0.44).map(|lambda| {
+            let rayleigh = 0.008735 * lambda.powf(-4.08);
+            let aerosol = beta * lambda.powf(-1.3);
+            (-(rayleigh + aerosol) * mass).exp()
+        });
+
+        // the sun is roughly 2*10^9 cd/m² outside the atmosphere
+        2e5 * blackbody(5778.) * transmittance
+    }
+
+    /// The probability of sampling the sun rather than the whole sky
+    fn sun_probability(&self) -> f32 {
+        if self.elevation > 0. && self.sun_size > 0. {
+            0.5
+        } else {
+            0.
+        }
+    }
+}
+
+#[typetag::serde]
+impl Environment for SunSkyEnv {
+    fn sample(&self, dir: Vec3) -> Vec3 {
+        let dir = dir.normalized();
+        if dir.y < 0. {
+            // the ground is diffuse, so it reflects the irradiance from the sun and the sky
+            // (approximating the sky as uniformly as bright as its zenith) divided by pi
+            let solid_angle = cone_solid_angle(self.sun_radius());
+            let sun = self.sun() * solid_angle * self.sun_direction().y.max(0.) / PI;
+            return self.ground_color * (self.sky(Vec3::unit_y()) + sun);
+        }
+
+        let sky = self.sky(dir);
+        if dir.dot(self.sun_direction()) >= self.sun_radius().cos() {
+            sky + self.sun()
+        } else {
+            sky
+        }
+    }
+
+    fn sample_direction(&self, rand: &mut LcRng) -> Option<(Vec3, f32)> {
+        let dir = if rand.rand_f32() < self.sun_probability() {
+            sample_cone(self.sun_direction(), self.sun_radius(), rand)
+        } else {
+            random_unit_vector(rand)
+        };
+        Some((dir, self.pdf(dir)))
+    }
+
+    fn can_sample(&self) -> bool {
+        true
+    }
+
+    fn pdf(&self, dir: Vec3) -> f32 {
+        let dir = dir.normalized();
+        let p_sun = self.sun_probability();
+        let sun = if dir.dot(self.sun_direction()) >= self.sun_radius().cos() {
+            p_sun / cone_solid_angle(self.sun_radius())
+        } else {
+            0.
+        };
+        sun + (1. - p_sun) / (4. * PI)
+    }
+}
+
+/// Adds a sun (or any other distant light, like the moon) to another environment. The sun is a
+/// small disk of constant radiance, and since it's sampled directly by the renderer, it casts
+/// sharp but soft-edged shadows, which get softer as the disk gets larger.
+/// ```
+/// use firework::environment::{SkyEnv, SunEnv};
+/// use firework::scene::Scene;
+/// use ultraviolet::Vec3;
+///
+/// let mut scene = Scene::new();
+/// let sun = SunEnv::new(
+///     SkyEnv::default(),
+///     Vec3::new(1., 2., -1.),
+///     Vec3::new(1., 0.9, 0.8) * 20000.,
+/// )
+/// .angular_radius(1.);
+/// scene.set_environment(sun);
+/// ```
+#[derive(Serialize, Deserialize)]
+pub struct SunEnv {
+    /// The environment behind the sun. This can't be called `environment`, since that's the
+    /// key for the type of environment in scene files.
+    base: Box<dyn Environment>,
+    /// The (normalized) direction towards the sun
+    direction: Vec3,
+    /// The radiance of the sun disk. The irradiance on a surface facing the sun is this times
+    /// the solid angle of the disk (about 6.8e-5 sr for the real sun).
+    radiance: Vec3,
+    /// The angle between the center and the edge of the disk, in degrees
+    #[serde(default = "SunEnv::default_angular_radius")]
+    angular_radius: f32,
+}
+
+impl SunEnv {
+    /// The probability of sampling the sun rather than the environment behind it
+    const SUN_PROBABILITY: f32 = 0.5;
+
+    pub fn new(base: impl Environment + 'static, direction: Vec3, radiance: Vec3) -> Self {
+        SunEnv {
+            base: Box::new(base),
+            direction: direction.normalized(),
+            radiance,
+            angular_radius: Self::default_angular_radius(),
+        }
+    }
+
+    /// Sets the angular radius of the sun (in degrees), which controls how soft its shadows are.
+    /// The real sun is about 0.27 degrees.
+    pub fn angular_radius(mut self, angular_radius: f32) -> Self {
+        self.angular_radius = angular_radius.max(0.01);
+        self
+    }
+
+    fn default_angular_radius() -> f32 {
+        0.27
+    }
+}
+
+#[typetag::serde]
+impl Environment for SunEnv {
+    fn sample(&self, dir: Vec3) -> Vec3 {
+        let background = self.base.sample(dir);
+        if dir.normalized().dot(self.direction) >= self.angular_radius.to_radians().cos() {
+            background + self.radiance
+        } else {
+            background
+        }
+    }
+
+    fn prepare(&mut self) {
+        self.base.prepare();
+    }
+
+    fn sample_direction(&self, rand: &mut LcRng) -> Option<(Vec3, f32)> {
+        let dir = if rand.rand_f32() < Self::SUN_PROBABILITY {
+            sample_cone(self.direction, self.angular_radius.to_radians(), rand)
+        } else {
+            // if the environment behind the sun can't be sampled, this sample is wasted
+            self.base.sample_direction(rand)?.0
+        };
+        Some((dir, self.pdf(dir)))
+    }
+
+    fn can_sample(&self) -> bool {
+        // the sun can always be sampled, even if the environment behind it can't
+        true
+    }
+
+    fn pdf(&self, dir: Vec3) -> f32 {
+        let radius = self.angular_radius.to_radians();
+        let sun = if dir.normalized().dot(self.direction) >= radius.cos() {
+            1. / cone_solid_angle(radius)
+        } else {
+            0.
+        };
+        Self::SUN_PROBABILITY * sun + (1. - Self::SUN_PROBABILITY) * self.base.pdf(dir)
+    }
+}
+
+/// Adjusts the brightness and color of another environment, e.g. to balance an HDRI against the
+/// emissive objects in a scene without editing the image. The radiance is multiplied by
+/// `intensity * 2^exposure * tint`.
+/// ```no_run
+/// use firework::environment::{HdrEnvironment, ScaledEnv};
+/// use ultraviolet::Vec3;
+///
+/// // one stop darker, and slightly cooler
+/// let env = ScaledEnv::new(HdrEnvironment::from_path("urban_street_04_4k.hdr").unwrap())
+///     .exposure(-1.)
+///     .tint(Vec3::new(0.9, 0.95, 1.));
+/// ```
+#[derive(Serialize, Deserialize)]
+pub struct ScaledEnv {
+    base: Box<dyn Environment>,
+    #[serde(default = "ScaledEnv::default_intensity")]
+    intensity: f32,
+    /// An additional scale, in stops (powers of 2)
+    #[serde(default)]
+    exposure: f32,
+    #[serde(default = "Vec3::one")]
+    tint: Vec3,
+}
+
+impl ScaledEnv {
+    pub fn new(base: impl Environment + 'static) -> Self {
+        ScaledEnv {
+            base: Box::new(base),
+            intensity: Self::default_intensity(),
+            exposure: 0.,
+            tint: Vec3::one(),
+        }
+    }
+
+    pub fn intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Sets the exposure in stops, so each +1 doubles the brightness
+    pub fn exposure(mut self, exposure: f32) -> Self {
+        self.exposure = exposure;
+        self
+    }
+
+    pub fn tint(mut self, tint: Vec3) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    fn default_intensity() -> f32 {
+        1.
+    }
+
+    fn scale(&self) -> Vec3 {
+        self.intensity * 2f32.powf(self.exposure) * self.tint
+    }
+}
+
+#[typetag::serde]
+impl Environment for ScaledEnv {
+    fn sample(&self, dir: Vec3) -> Vec3 {
+        self.scale() * self.base.sample(dir)
+    }
+
+    fn prepare(&mut self) {
+        self.base.prepare();
+    }
+
+    fn sample_direction(&self, rand: &mut LcRng) -> Option<(Vec3, f32)> {
+        self.base.sample_direction(rand)
+    }
+
+    fn can_sample(&self) -> bool {
+        self.base.can_sample()
+    }
+
+    fn pdf(&self, dir: Vec3) -> f32 {
+        self.base.pdf(dir)
+    }
+}
+
+/// An environment backed by an equirectangular (latitude-longitude) HDR image, as used by most
+/// HDRI libraries.
+///
+/// Only the header is read by [`HdrEnvironment::from_path`], so building a scene is cheap. The
+/// pixels are decoded when rendering starts, or when [`HdrEnvironment::load`] is called (which
+/// reports images that can't be decoded, rather than rendering a black environment). When
+/// reading a scene file, they're decoded straight away, so a broken image is an error there.
+///
+/// ```no_run
+/// use firework::environment::HdrEnvironment;
+/// use firework::scene::Scene;
+///
+/// let mut scene = Scene::new();
+/// scene.set_environment(HdrEnvironment::from_path("urban_street_04_4k.hdr").unwrap());
+/// ```
+///
+/// The image can be spun around (e.g. to move the sun) with `rotate`:
+/// ```no_run
+/// # use firework::environment::HdrEnvironment;
+/// use ultraviolet::Rotor3;
+///
+/// let env = HdrEnvironment::from_path("urban_street_04_4k.hdr")
+///     .unwrap()
+///     .rotate(Rotor3::from_rotation_xz(1.2));
+/// ```
+#[derive(Deserialize)]
+#[serde(try_from = "HdrEnvironmentDef")]
+pub struct HdrEnvironment {
+    path: PathBuf,
+    width: usize,
+    height: usize,
+    pixels: Option<Vec<Vec3>>,
+    /// Distribution over the pixels, weighted by luminance and the solid angle they cover
+    distribution: Option<Distribution2D>,
+    rotation: Rotor3,
+    rotation_mat: Mat3,
+    inv_rotation_mat: Mat3,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HdrEnvironmentDef {
+    path: PathBuf,
+    #[serde(default = "Rotor3::identity", with = "crate::serde_compat::Rotor3Def")]
+    rotation: Rotor3,
+}
+
+impl TryFrom<HdrEnvironmentDef> for HdrEnvironment {
+    type Error = image::ImageError;
+    fn try_from(def: HdrEnvironmentDef) -> Result<HdrEnvironment, Self::Error> {
+        let mut env = HdrEnvironment::from_path(resolve_asset(&def.path))?.rotate(def.rotation);
+        env.load()?;
+        // the pixels are loaded, so the path is only used to write the scene back, the same way
+        // it was written
+        env.path = def.path;
+        Ok(env)
+    }
+}
+
+impl Serialize for HdrEnvironment {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        HdrEnvironmentDef {
+            path: self.path.clone(),
+            rotation: self.rotation,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl HdrEnvironment {
+    /// Creates an `HdrEnvironment` from a Radiance `.hdr` file. This only reads the header, the
+    /// pixels are loaded lazily.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<HdrEnvironment, image::ImageError> {
+        let path = path.as_ref().to_owned();
+        let decoder = Self::decoder(&path)?;
+        let metadata = decoder.metadata();
+
+        Ok(HdrEnvironment {
+            path,
+            width: metadata.width as usize,
+            height: metadata.height as usize,
+            pixels: None,
+            distribution: None,
+            rotation: Rotor3::identity(),
+            rotation_mat: Mat3::identity(),
+            inv_rotation_mat: Mat3::identity(),
+        })
+    }
+
+    /// Sets the orientation of the environment. Without a rotation, the center of the image is
+    /// in the +x direction, and the top is +y.
+    pub fn rotate(mut self, rotor: Rotor3) -> Self {
+        self.rotation = rotor;
+        self.rotation_mat = rotor.into_matrix();
+        self.inv_rotation_mat = rotor.reversed().into_matrix();
+        self
+    }
+
+    fn decoder(
+        path: &Path,
+    ) -> Result<image::codecs::hdr::HdrDecoder<BufReader<File>>, image::ImageError> {
+        let file = BufReader::new(File::open(path)?);
+        image::codecs::hdr::HdrDecoder::new(file)
+    }
+
+    /// Decodes the pixels now, rather than when rendering starts. Does nothing if they have
+    /// already been loaded.
+    pub fn load(&mut self) -> Result<(), image::ImageError> {
+        if self.pixels.is_none() {
+            let pixels = Self::decoder(&self.path)?.read_image_hdr()?;
+            let pixels: Vec<Vec3> = pixels.into_iter().map(|p| p.0.into()).collect();
+            self.distribution = Some(self.build_distribution(&pixels));
+            self.pixels = Some(pixels);
+        }
+        Ok(())
+    }
+
+    /// Builds the distribution used for importance sampling. Each pixel's weight is the highest
+    /// luminance of it and its neighbours, since bilinear filtering blends them together, scaled
+    /// by the solid angle of its row (which shrinks towards the poles).
+    fn build_distribution(&self, pixels: &[Vec3]) -> Distribution2D {
+        let (w, h) = (self.width, self.height);
+        let luminance: Vec<f32> = pixels
+            .iter()
+            .map(|c| c.dot(Vec3::new(0.2126, 0.7152, 0.0722)).max(0.))
+            .collect();
+
+        let horizontal: Vec<f32> = (0..w * h)
+            .map(|i| {
+                let (x, row) = (i % w, i - i % w);
+                let left = luminance[row + (x + w - 1) % w];
+                let right = luminance[row + (x + 1) % w];
+                luminance[i].max(left).max(right)
+            })
+            .collect();
+
+        let func: Vec<f32> = (0..w * h)
+            .map(|i| {
+                let (x, y) = (i % w, i / w);
+                let up = horizontal[y.saturating_sub(1) * w + x];
+                let down = horizontal[(y + 1).min(h - 1) * w + x];
+                let sin_theta = (PI * (y as f32 + 0.5) / h as f32).sin();
+                horizontal[i].max(up).max(down) * sin_theta
+            })
+            .collect();
+
+        Distribution2D::new(&func, w, h)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn pixel(pixels: &[Vec3], width: usize, x: usize, y: usize) -> Vec3 {
+        pixels[y * width + x]
+    }
+}
+
+#[typetag::serde]
+impl Environment for HdrEnvironment {
+    fn sample(&self, dir: Vec3) -> Vec3 {
+        let pixels = match &self.pixels {
+            Some(pixels) => pixels,
+            None => return Vec3::zero(),
+        };
+
+        let dir = (self.inv_rotation_mat * dir).normalized();
+        let dir = Vec3::new(dir.x, dir.y.clamp(-1., 1.), dir.z);
+        let uv = sphere_uv(&dir);
+
+        // Bilinear interpolation between pixel centers, wrapping around horizontally and clamping
+        // at the poles
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = ((1. - uv.y) * self.height as f32 - 0.5).clamp(0., (self.height - 1) as f32);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let x0 = (x0 as isize).rem_euclid(self.width as isize) as usize;
+        let x1 = (x0 + 1) % self.width;
+        let y0 = y0 as usize;
+        let y1 = (y0 + 1).min(self.height - 1);
+
+        let w = self.width;
+        let top = (1. - fx) * Self::pixel(pixels, w, x0, y0) + fx * Self::pixel(pixels, w, x1, y0);
+        let bottom =
+            (1. - fx) * Self::pixel(pixels, w, x0, y1) + fx * Self::pixel(pixels, w, x1, y1);
+        (1. - fy) * top + fy * bottom
+    }
+
+    fn sample_direction(&self, rand: &mut LcRng) -> Option<(Vec3, f32)> {
+        let distribution = self.distribution.as_ref()?;
+        let (p, pdf) = distribution.sample(Vec2::new(rand.rand_f32(), rand.rand_f32()));
+
+        // p.x runs along the image (the same as u), and p.y down it
+        let phi = (1. - p.x) * 2. * PI - PI;
+        let theta = PI / 2. - p.y * PI;
+        let cos_theta = theta.cos();
+        if pdf == 0. || cos_theta <= 0. {
+            return None;
+        }
+
+        let dir = Vec3::new(cos_theta * phi.cos(), theta.sin(), cos_theta * phi.sin());
+        Some((self.rotation_mat * dir, pdf / (2. * PI * PI * cos_theta)))
+    }
+
+    fn pdf(&self, dir: Vec3) -> f32 {
+        let distribution = match &self.distribution {
+            Some(distribution) => distribution,
+            None => return 0.,
+        };
+
+        let dir = (self.inv_rotation_mat * dir).normalized();
+        let cos_theta = (1. - dir.y * dir.y).max(0.).sqrt();
+        if cos_theta == 0. {
+            return 0.;
+        }
+        let uv = sphere_uv(&Vec3::new(dir.x, dir.y.clamp(-1., 1.), dir.z));
+        distribution.pdf(Vec2::new(uv.x, 1. - uv.y)) / (2. * PI * PI * cos_theta)
+    }
+
+    fn can_sample(&self) -> bool {
+        self.distribution.is_some()
+    }
+
+    fn prepare(&mut self) {
+        if let Err(e) = self.load() {
+            eprintln!("Failed to load environment {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Where the faces of a [`CubemapEnv`] are loaded from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CubemapSource {
+    /// Six square images, in the order +x, -x, +y, -y, +z, -z
+    Faces([PathBuf; 6]),
+    /// A single image with the faces laid out in a horizontal (4x3) or vertical (3x4) cross
+    Cross(PathBuf),
+}
+
+/// An environment backed by six square images on the faces of a cube, as distributed by many
+/// game engine skybox packs. The faces follow the usual OpenGL convention: looking down -z (the
+/// default camera direction), +y is up and +x is to the right.
+///
+/// Radiance `.hdr` faces are used as is, anything else is assumed to be sRGB-encoded. Like
+/// [`HdrEnvironment`], only the image sizes are read when the environment is created, the
+/// pixels are decoded when rendering starts or when [`CubemapEnv::load`] is called, except when
+/// reading a scene file, where a broken image is an error.
+///
+/// ```no_run
+/// use firework::environment::CubemapEnv;
+/// use firework::scene::Scene;
+///
+/// let mut scene = Scene::new();
+/// scene.set_environment(
+///     CubemapEnv::from_faces([
+///         "skybox/right.png",
+///         "skybox/left.png",
+///         "skybox/top.png",
+///         "skybox/bottom.png",
+///         "skybox/front.png",
+///         "skybox/back.png",
+///     ])
+///     .unwrap(),
+/// );
+/// ```
+///
+/// A single image with the faces arranged in a cross works too:
+/// ```text
+///       +y                 +y
+///   -x  +z  +x  -z     -x  +z  +x
+///       -y                 -y
+///                          -z (upside down)
+/// ```
+/// ```no_run
+/// # use firework::environment::CubemapEnv;
+/// let env = CubemapEnv::from_cross("skybox_cross.hdr").unwrap();
+/// ```
+#[derive(Deserialize)]
+#[serde(try_from = "CubemapEnvDef")]
+pub struct CubemapEnv {
+    source: CubemapSource,
+    /// The width and height of each face, in pixels
+    size: usize,
+    /// The pixels of each face, one after the other in the order +x, -x, +y, -y, +z, -z
+    pixels: Option<Vec<Vec3>>,
+    /// Distribution over the pixels, treating the faces as stacked on top of each other, weighted
+    /// by luminance and the solid angle they cover
+    distribution: Option<Distribution2D>,
+    rotation: Rotor3,
+    rotation_mat: Mat3,
+    inv_rotation_mat: Mat3,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CubemapEnvDef {
+    source: CubemapSource,
+    #[serde(default = "Rotor3::identity", with = "crate::serde_compat::Rotor3Def")]
+    rotation: Rotor3,
+}
+
+impl TryFrom<CubemapEnvDef> for CubemapEnv {
+    type Error = image::ImageError;
+    fn try_from(def: CubemapEnvDef) -> Result<CubemapEnv, Self::Error> {
+        let source = match &def.source {
+            CubemapSource::Faces(paths) => CubemapSource::Faces(paths.clone().map(resolve_asset)),
+            CubemapSource::Cross(path) => CubemapSource::Cross(resolve_asset(path)),
+        };
+        let mut env = CubemapEnv::new(source)?.rotate(def.rotation);
+        env.load()?;
+        // the pixels are loaded, so the paths are only used to write the scene back, the same way
+        // they were written
+        env.source = def.source;
+        Ok(env)
+    }
+}
+
+impl Serialize for CubemapEnv {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        CubemapEnvDef {
+            source: self.source.clone(),
+            rotation: self.rotation,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl CubemapEnv {
+    /// Creates a `CubemapEnv` from six square images of the same size, in the order +x, -x, +y,
+    /// -y, +z, -z
+    pub fn from_faces<P: AsRef<Path>>(faces: [P; 6]) -> Result<CubemapEnv, image::ImageError> {
+        let [px, nx, py, ny, pz, nz] = faces;
+        let to_owned = |p: P| p.as_ref().to_owned();
+        Self::new(CubemapSource::Faces([
+            to_owned(px),
+            to_owned(nx),
+            to_owned(py),
+            to_owned(ny),
+            to_owned(pz),
+            to_owned(nz),
+        ]))
+    }
+
+    /// Creates a `CubemapEnv` from a single image with the faces laid out in a horizontal (4x3)
+    /// or vertical (3x4) cross
+    pub fn from_cross(path: impl AsRef<Path>) -> Result<CubemapEnv, image::ImageError> {
+        Self::new(CubemapSource::Cross(path.as_ref().to_owned()))
+    }
+
+    fn new(source: CubemapSource) -> Result<CubemapEnv, image::ImageError> {
+        let size = match &source {
+            CubemapSource::Faces(paths) => {
+                let (width, height) = image::image_dimensions(&paths[0])?;
+                for path in &paths[1..] {
+                    if image::image_dimensions(path)? != (width, height) {
+                        return Err(dimension_mismatch());
+                    }
+                }
+                if width != height {
+                    return Err(dimension_mismatch());
+                }
+                width
+            }
+            CubemapSource::Cross(path) => Self::cross_layout(image::image_dimensions(path)?)?.0,
+        };
+
+        Ok(CubemapEnv {
+            source,
+            size: size as usize,
+            pixels: None,
+            distribution: None,
+            rotation: Rotor3::identity(),
+            rotation_mat: Mat3::identity(),
+            inv_rotation_mat: Mat3::identity(),
+        })
+    }
+
+    /// Returns the face size for an image with the given dimensions, and whether the cross is
+    /// vertical
+    fn cross_layout((width, height): (u32, u32)) -> Result<(u32, bool), image::ImageError> {
+        if width % 4 == 0 && width / 4 * 3 == height {
+            Ok((width / 4, false))
+        } else if width % 3 == 0 && width / 3 * 4 == height {
+            Ok((width / 3, true))
+        } else {
+            Err(dimension_mismatch())
+        }
+    }
+
+    /// Sets the orientation of the environment
+    pub fn rotate(mut self, rotor: Rotor3) -> Self {
+        self.rotation = rotor;
+        self.rotation_mat = rotor.into_matrix();
+        self.inv_rotation_mat = rotor.reversed().into_matrix();
+        self
+    }
+
+    /// Decodes the pixels now, rather than when rendering starts. Does nothing if they have
+    /// already been loaded.
+    pub fn load(&mut self) -> Result<(), image::ImageError> {
+        if self.pixels.is_some() {
+            return Ok(());
+        }
+
+        let n = self.size;
+        let pixels = match &self.source {
+            CubemapSource::Faces(paths) => {
+                let mut pixels = Vec::with_capacity(6 * n * n);
+                for path in paths {
+                    let (width, height, face) = load_linear_image(path)?;
+                    if (width, height) != (n, n) {
+                        return Err(dimension_mismatch());
+                    }
+                    pixels.extend(face);
+                }
+                pixels
+            }
+            CubemapSource::Cross(path) => {
+                let (width, height, image) = load_linear_image(path)?;
+                let (size, vertical) = Self::cross_layout((width as u32, height as u32))?;
+                if size as usize != n {
+                    return Err(dimension_mismatch());
+                }
+
+                // The column and row of each face in the cross
+                let mut cells = [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (3, 1)];
+                if vertical {
+                    cells[5] = (1, 3);
+                }
+
+                let mut pixels = Vec::with_capacity(6 * n * n);
+                for (face, &(col, row)) in cells.iter().enumerate() {
+                    for y in 0..n {
+                        for x in 0..n {
+                            // The -z face of a vertical cross is upside down
+                            let (x, y) = if vertical && face == 5 {
+                                (n - 1 - x, n - 1 - y)
+                            } else {
+                                (x, y)
+                            };
+                            pixels.push(image[(row * n + y) * width + col * n + x]);
+                        }
+                    }
+                }
+                pixels
+            }
+        };
+
+        self.distribution = Some(self.build_distribution(&pixels));
+        self.pixels = Some(pixels);
+        Ok(())
+    }
+
+    /// Builds the distribution used for importance sampling. Like `HdrEnvironment`, each pixel's
+    /// weight is the highest luminance of it and its neighbours (within the face), scaled by the
+    /// solid angle it covers, which shrinks towards the corners of the face.
+    fn build_distribution(&self, pixels: &[Vec3]) -> Distribution2D {
+        let n = self.size;
+        let luminance: Vec<f32> = pixels
+            .iter()
+            .map(|c| c.dot(Vec3::new(0.2126, 0.7152, 0.0722)).max(0.))
+            .collect();
+
+        let func: Vec<f32> = (0..6 * n * n)
+            .map(|i| {
+                let face = i - i % (n * n);
+                let (x, y) = (i % n, i % (n * n) / n);
+                let mut max = 0f32;
+                for ny in y.saturating_sub(1)..=(y + 1).min(n - 1) {
+                    for nx in x.saturating_sub(1)..=(x + 1).min(n - 1) {
+                        max = max.max(luminance[face + ny * n + nx]);
+                    }
+                }
+
+                let a = 2. * (x as f32 + 0.5) / n as f32 - 1.;
+                let b = 2. * (y as f32 + 0.5) / n as f32 - 1.;
+                max / (1. + a * a + b * b).powf(1.5)
+            })
+            .collect();
+
+        Distribution2D::new(&func, n, 6 * n)
+    }
+
+    /// The width and height of each face, in pixels
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Finds the face `dir` points at, and the position on that face (from 0 to 1, starting at the
+    /// top left)
+    fn face_coords(dir: Vec3) -> (usize, f32, f32) {
+        let (ax, ay, az) = (dir.x.abs(), dir.y.abs(), dir.z.abs());
+        let (face, sc, tc, ma) = if ax >= ay && ax >= az {
+            if dir.x > 0. {
+                (0, -dir.z, -dir.y, ax)
+            } else {
+                (1, dir.z, -dir.y, ax)
+            }
+        } else if ay >= az {
+            if dir.y > 0. {
+                (2, dir.x, dir.z, ay)
+            } else {
+                (3, dir.x, -dir.z, ay)
+            }
+        } else if dir.z > 0. {
+            (4, dir.x, -dir.y, az)
+        } else {
+            (5, -dir.x, -dir.y, az)
+        };
+        (face, 0.5 * (sc / ma + 1.), 0.5 * (tc / ma + 1.))
+    }
+
+    /// The inverse of `face_coords`. The returned direction isn't normalized.
+    fn face_direction(face: usize, s: f32, t: f32) -> Vec3 {
+        let (a, b) = (2. * s - 1., 2. * t - 1.);
+        match face {
+            0 => Vec3::new(1., -b, -a),
+            1 => Vec3::new(-1., -b, a),
+            2 => Vec3::new(a, 1., b),
+            3 => Vec3::new(a, -1., -b),
+            4 => Vec3::new(a, -b, 1.),
+            _ => Vec3::new(-a, -b, -1.),
+        }
+    }
+
+    /// Converts the solid angle density of a direction through (s, t) on a face into a density
+    /// over the stacked faces used by the distribution
+    fn area_to_solid_angle(s: f32, t: f32) -> f32 {
+        let (a, b) = (2. * s - 1., 2. * t - 1.);
+        (1. + a * a + b * b).powf(1.5) / 24.
+    }
+}
+
+#[typetag::serde]
+impl Environment for CubemapEnv {
+    fn sample(&self, dir: Vec3) -> Vec3 {
+        let pixels = match &self.pixels {
+            Some(pixels) => pixels,
+            None => return Vec3::zero(),
+        };
+
+        let (face, s, t) = Self::face_coords(self.inv_rotation_mat * dir);
+
+        // Bilinear interpolation between pixel centers, clamping at the edges of the face
+        let n = self.size;
+        let max = (n - 1) as f32;
+        let x = (s * n as f32 - 0.5).clamp(0., max);
+        let y = (t * n as f32 - 0.5).clamp(0., max);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let (x1, y1) = ((x0 + 1).min(n - 1), (y0 + 1).min(n - 1));
+
+        let base = face * n * n;
+        let pixel = |x: usize, y: usize| pixels[base + y * n + x];
+        let top = (1. - fx) * pixel(x0, y0) + fx * pixel(x1, y0);
+        let bottom = (1. - fx) * pixel(x0, y1) + fx * pixel(x1, y1);
+        (1. - fy) * top + fy * bottom
+    }
+
+    fn sample_direction(&self, rand: &mut LcRng) -> Option<(Vec3, f32)> {
+        let distribution = self.distribution.as_ref()?;
+        let (p, pdf) = distribution.sample(Vec2::new(rand.rand_f32(), rand.rand_f32()));
+        if pdf == 0. {
+            return None;
+        }
+
+        let face = ((p.y * 6.) as usize).min(5);
+        let (s, t) = (p.x, p.y * 6. - face as f32);
+        let dir = Self::face_direction(face, s, t).normalized();
+        Some((
+            self.rotation_mat * dir,
+            pdf * Self::area_to_solid_angle(s, t),
+        ))
+    }
+
+    fn pdf(&self, dir: Vec3) -> f32 {
+        let distribution = match &self.distribution {
+            Some(distribution) => distribution,
+            None => return 0.,
+        };
+
+        let (face, s, t) = Self::face_coords(self.inv_rotation_mat * dir);
+        let p = Vec2::new(s, (face as f32 + t) / 6.);
+        distribution.pdf(p) * Self::area_to_solid_angle(s, t)
+    }
+
+    fn can_sample(&self) -> bool {
+        self.distribution.is_some()
+    }
+
+    fn prepare(&mut self) {
+        if let Err(e) = self.load() {
+            eprintln!("Failed to load cubemap environment: {}", e);
+        }
+    }
+}
+
+/// Loads an image as linear colors, returning its width, height and pixels. Radiance `.hdr` files
+/// are already linear, anything else is decoded from sRGB.
+fn load_linear_image(path: &Path) -> Result<(usize, usize, Vec<Vec3>), image::ImageError> {
+    let is_hdr = match path.extension() {
+        Some(ext) => ext.eq_ignore_ascii_case("hdr"),
+        None => false,
+    };
+
+    if is_hdr {
+        let file = BufReader::new(File::open(path)?);
+        let decoder = image::codecs::hdr::HdrDecoder::new(file)?;
+        let metadata = decoder.metadata();
+        let pixels = decoder.read_image_hdr()?;
+        Ok((
+            metadata.width as usize,
+            metadata.height as usize,
+            pixels.into_iter().map(|p| p.0.into()).collect(),
+        ))
+    } else {
+        let image = image::open(path)?.into_rgb8();
+        let pixels = image
+            .pixels()
+            .map(|p| {
+                let [r, g, b] = p.0.map(|c| ColorSpace::Srgb.to_linear(c as f32 / 255.));
+                Vec3::new(r, g, b)
+            })
+            .collect();
+        Ok((image.width() as usize, image.height() as usize, pixels))
+    }
+}
+
+fn dimension_mismatch() -> image::ImageError {
+    image::ImageError::Parameter(image::error::ParameterError::from_kind(
+        image::error::ParameterErrorKind::DimensionMismatch,
+    ))
+}
+
+/// A window (or any other opening) that the environment lights an interior through. When a scene
+/// has portals, directions towards the environment are chosen through them rather than over the
+/// whole sky, so rooms lit from outside aren't noisy from the many directions blocked by walls.
+/// Light arriving from anywhere else is still found, just less efficiently.
+///
+/// A portal is the parallelogram spanned by `edge1` and `edge2` from `corner`. It isn't part of
+/// the geometry, so it should cover the opening without being hit by anything.
+/// ```
+/// use firework::environment::{Portal, SkyEnv};
+/// use firework::Scene;
+/// use ultraviolet::Vec3;
+///
+/// let mut scene = Scene::new();
+/// scene.set_environment(SkyEnv::default());
+/// // a 2x1.5 window in the wall at z = -5
+/// scene.add_portal(Portal::new(
+///     Vec3::new(-1., 1., -5.),
+///     Vec3::new(2., 0., 0.),
+///     Vec3::new(0., 1.5, 0.),
+/// ));
+/// ```
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Portal {
+    pub corner: Vec3,
+    pub edge1: Vec3,
+    pub edge2: Vec3,
+}
+
+impl Portal {
+    pub fn new(corner: Vec3, edge1: Vec3, edge2: Vec3) -> Self {
+        Portal {
+            corner,
+            edge1,
+            edge2,
+        }
+    }
+
+    /// Chooses a direction from `origin` through a uniformly chosen point on the portal, and
+    /// returns it with its probability density (with respect to solid angle)
+    pub(crate) fn sample_direction(&self, origin: Vec3, rand: &mut LcRng) -> Option<(Vec3, f32)> {
+        let point = self.corner + rand.rand_f32() * self.edge1 + rand.rand_f32() * self.edge2;
+        let to_portal = point - origin;
+        let distance_sq = to_portal.mag_sq();
+        if distance_sq == 0. {
+            return None;
+        }
+        let dir = to_portal / distance_sq.sqrt();
+        let pdf = self.pdf_at(dir, distance_sq);
+        if pdf > 0. {
+            Some((dir, pdf))
+        } else {
+            None
+        }
+    }
+
+    /// The probability density that `sample_direction` chooses `dir` (which must be normalized)
+    /// from `origin`, or 0 if it doesn't pass through the portal
+    pub(crate) fn pdf(&self, origin: Vec3, dir: Vec3) -> f32 {
+        let normal = self.edge1.cross(self.edge2);
+        let denom = dir.dot(normal);
+        if denom == 0. {
+            return 0.;
+        }
+        let t = (self.corner - origin).dot(normal) / denom;
+        if t <= 0. {
+            return 0.;
+        }
+
+        // the coordinates of the crossing point along each edge
+        let offset = origin + t * dir - self.corner;
+        let area_sq = normal.mag_sq();
+        let u = offset.cross(self.edge2).dot(normal) / area_sq;
+        let v = self.edge1.cross(offset).dot(normal) / area_sq;
+        if !(0. ..=1.).contains(&u) || !(0. ..=1.).contains(&v) {
+            return 0.;
+        }
+        self.pdf_at(dir, t * t)
+    }
+
+    /// Converts the uniform density over the portal's area into a density over solid angle, for
+    /// a direction which crosses it at the given squared distance
+    fn pdf_at(&self, dir: Vec3, distance_sq: f32) -> f32 {
+        let normal = self.edge1.cross(self.edge2);
+        let area = normal.mag();
+        let cos = dir.dot(normal).abs() / area;
+        if cos == 0. || area == 0. {
+            0.
+        } else {
+            distance_sq / (cos * area)
+        }
+    }
+}
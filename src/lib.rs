@@ -5,19 +5,31 @@
 
 mod aabb;
 mod bvh;
+mod checkpoint;
+mod light_bvh;
+mod macros;
 mod ray;
 mod serde_compat;
 mod util;
 
+pub mod animation;
+pub mod assets;
+pub mod bench;
 pub mod camera;
 pub mod environment;
+pub mod light;
 pub mod material;
 pub mod objects;
+pub mod output;
+pub mod procgen;
 pub mod render;
 pub mod scene;
+pub mod shader;
 pub mod texture;
+#[cfg(feature = "window")]
 pub mod window;
 
 pub use crate::render::Renderer;
 pub use crate::scene::{RenderObject, Scene};
+#[cfg(feature = "window")]
 pub use crate::window::RenderWindow;
@@ -0,0 +1,543 @@
+use crate::assets::resolve_asset;
+use crate::util::{cone_solid_angle, sample_cone, CoordinateSystem};
+use serde::{Deserialize, Serialize, Serializer};
+use std::convert::TryFrom;
+use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
+use tiny_rng::LcRng;
+use ultraviolet::Vec3;
+
+/// A light which isn't part of the scene's geometry, like a point light. These can't be hit by
+/// rays (so they're never seen directly), and only light surfaces through the direct lighting
+/// in the renderer. Lights made of emissive geometry are added with `Scene::add_light` instead.
+#[typetag::serde(tag = "light")]
+pub trait Light: Sync {
+    /// Chooses a direction from `point` towards the light, and returns the light arriving along
+    /// it. Returns `None` if the light doesn't reach `point` at all.
+    fn sample(&self, point: Vec3, rand: &mut LcRng) -> Option<LightSample>;
+
+    /// Bounds on where the light is and which way it shines, which direct lighting uses to
+    /// choose the lights that matter most at each point. Lights without bounds (like a
+    /// `DirectionalLight`) are chosen with a fixed probability everywhere instead.
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
+
+/// Where a `Light` is and which way it shines, conservatively, following Conty Estevez and
+/// Kulla, "Importance Sampling of Many Lights with Adaptive Tree Splitting"
+#[derive(Debug, Clone, Copy)]
+pub struct LightBounds {
+    /// The corner of a box containing the whole light with the smallest coordinates
+    pub min: Vec3,
+    /// The corner of the box with the largest coordinates
+    pub max: Vec3,
+    /// The total power the light emits, as luminance. Only how it compares between lights matters.
+    pub power: f32,
+    /// The direction the light faces
+    pub axis: Vec3,
+    /// The cosine of the angle around `axis` that every part of the light faces within (-1 when
+    /// it faces every direction)
+    pub cos_theta_o: f32,
+    /// The cosine of the angle past those directions that light is still emitted in (0 for a
+    /// surface emitting over its whole hemisphere)
+    pub cos_theta_e: f32,
+    /// Whether the light also emits from its back
+    pub two_sided: bool,
+}
+
+/// The luminance of a linear RGB color, as `LightBounds::power` is measured in
+pub(crate) fn luminance(color: Vec3) -> f32 {
+    color.dot(Vec3::new(0.2126, 0.7152, 0.0722)).max(0.)
+}
+
+/// A direction towards a `Light`, chosen by `Light::sample`
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample {
+    /// The (normalized) direction from the point being lit towards the light
+    pub wi: Vec3,
+    /// The distance to the light along `wi`, which shadow rays are traced up to
+    pub distance: f32,
+    /// The radiance arriving along `wi`
+    pub radiance: Vec3,
+    /// The probability density of choosing `wi`, with respect to solid angle. Lights which are
+    /// a single point (and so have no density) use 1, and include the falloff with distance in
+    /// `radiance` instead.
+    pub pdf: f32,
+}
+
+/// A light which shines equally in all directions from a point. The light reaching a surface
+/// facing it is `intensity / distance²`.
+///
+/// Giving it a radius turns it into a sphere with the same total power, which casts soft
+/// shadows, without the noise of finding a small emissive sphere by chance.
+/// ```
+/// use firework::light::PointLight;
+/// use firework::Scene;
+/// use ultraviolet::Vec3;
+///
+/// let mut scene = Scene::new();
+/// let bulb = PointLight::new(Vec3::new(0., 5., 0.), Vec3::broadcast(50.)).radius(0.2);
+/// scene.add_analytic_light(bulb);
+/// ```
+///
+/// An `IesProfile` gives the light the angular distribution of a real luminaire. The profile
+/// points straight down (-y), with a horizontal angle of 0 along +z, and `intensity` becomes the
+/// intensity in its brightest direction.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PointLight {
+    position: Vec3,
+    intensity: Vec3,
+    #[serde(default)]
+    radius: f32,
+    #[serde(default)]
+    profile: Option<IesProfile>,
+}
+
+impl PointLight {
+    pub fn new(position: Vec3, intensity: Vec3) -> Self {
+        PointLight {
+            position,
+            intensity,
+            radius: 0.,
+            profile: None,
+        }
+    }
+
+    pub fn radius(mut self, radius: f32) -> Self {
+        self.radius = radius.max(0.);
+        self
+    }
+
+    /// Shapes the light with a photometric profile (see `IesProfile`)
+    pub fn profile(mut self, profile: IesProfile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// The intensity of the light shone in the direction `dir` (which must be normalized)
+    fn intensity(&self, dir: Vec3) -> Vec3 {
+        match &self.profile {
+            Some(profile) => {
+                let frame = CoordinateSystem::from_one_vec(&-Vec3::unit_y());
+                profile.eval(&frame, dir) * self.intensity
+            }
+            None => self.intensity,
+        }
+    }
+}
+
+#[typetag::serde]
+impl Light for PointLight {
+    fn sample(&self, point: Vec3, rand: &mut LcRng) -> Option<LightSample> {
+        let to_light = self.position - point;
+        let distance_sq = to_light.mag_sq();
+        if distance_sq == 0. {
+            return None;
+        }
+        let distance = distance_sq.sqrt();
+        let axis = to_light / distance;
+
+        // points inside the sphere are lit as if it were a point
+        if self.radius <= 0. || distance <= self.radius {
+            return Some(LightSample {
+                wi: axis,
+                distance,
+                radiance: self.intensity(-axis) / distance_sq,
+                pdf: 1.,
+            });
+        }
+
+        // choose a direction in the cone the sphere covers, and stop the shadow ray at its surface
+        let half_angle = (self.radius / distance).asin();
+        let wi = sample_cone(axis, half_angle, rand);
+        let b = wi.dot(to_light);
+        let disc = (b * b - distance_sq + self.radius * self.radius).max(0.);
+        Some(LightSample {
+            wi,
+            distance: b - disc.sqrt(),
+            // spreading the intensity over the sphere's silhouette
+            radiance: self.intensity(-axis) / (PI * self.radius * self.radius),
+            pdf: 1. / cone_solid_angle(half_angle),
+        })
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let radius = Vec3::broadcast(self.radius);
+        Some(LightBounds {
+            min: self.position - radius,
+            max: self.position + radius,
+            power: 4. * PI * luminance(self.intensity),
+            axis: Vec3::unit_y(),
+            cos_theta_o: -1.,
+            cos_theta_e: 0.,
+            two_sided: false,
+        })
+    }
+}
+
+/// A point light which only shines in a cone, like a stage light, flashlight or headlight. The
+/// light is at full `intensity` within `inner_angle` of `direction`, and fades out smoothly
+/// towards `outer_angle` (both half-angles, in degrees). Like `PointLight`, it can be shaped
+/// with an `IesProfile`, which points along `direction`.
+/// ```
+/// use firework::light::SpotLight;
+/// use firework::Scene;
+/// use ultraviolet::Vec3;
+///
+/// let mut scene = Scene::new();
+/// let spot = SpotLight::new(Vec3::new(0., 5., 0.), -Vec3::unit_y(), Vec3::broadcast(100.))
+///     .angles(15., 25.);
+/// scene.add_analytic_light(spot);
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpotLight {
+    position: Vec3,
+    direction: Vec3,
+    intensity: Vec3,
+    #[serde(default = "default_inner_angle")]
+    inner_angle: f32,
+    #[serde(default = "default_outer_angle")]
+    outer_angle: f32,
+    #[serde(default)]
+    profile: Option<IesProfile>,
+}
+
+fn default_inner_angle() -> f32 {
+    20.
+}
+
+fn default_outer_angle() -> f32 {
+    30.
+}
+
+impl SpotLight {
+    pub fn new(position: Vec3, direction: Vec3, intensity: Vec3) -> Self {
+        SpotLight {
+            position,
+            direction: direction.normalized(),
+            intensity,
+            inner_angle: default_inner_angle(),
+            outer_angle: default_outer_angle(),
+            profile: None,
+        }
+    }
+
+    /// Shapes the light with a photometric profile (see `IesProfile`)
+    pub fn profile(mut self, profile: IesProfile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Sets the half-angles (in degrees) of the fully lit cone, and of the edge of the light
+    pub fn angles(mut self, inner_angle: f32, outer_angle: f32) -> Self {
+        self.outer_angle = outer_angle.clamp(0., 180.);
+        self.inner_angle = inner_angle.clamp(0., self.outer_angle);
+        self
+    }
+
+    /// The fraction of the intensity shone in the direction `dir` (which must be normalized)
+    fn falloff(&self, dir: Vec3) -> f32 {
+        let cos_theta = dir.dot(self.direction.normalized());
+        let cos_inner = self.inner_angle.to_radians().cos();
+        let cos_outer = self.outer_angle.to_radians().cos();
+        let cone = if cos_theta >= cos_inner {
+            1.
+        } else if cos_theta <= cos_outer {
+            0.
+        } else {
+            let t = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+            t * t * (3. - 2. * t)
+        };
+        match &self.profile {
+            Some(profile) if cone > 0. => {
+                let frame = CoordinateSystem::from_one_vec(&self.direction.normalized());
+                cone * profile.eval(&frame, dir)
+            }
+            _ => cone,
+        }
+    }
+}
+
+#[typetag::serde]
+impl Light for SpotLight {
+    fn sample(&self, point: Vec3, _rand: &mut LcRng) -> Option<LightSample> {
+        let to_light = self.position - point;
+        let distance_sq = to_light.mag_sq();
+        if distance_sq == 0. {
+            return None;
+        }
+        let distance = distance_sq.sqrt();
+        let wi = to_light / distance;
+        let falloff = self.falloff(-wi);
+        if falloff == 0. {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            distance,
+            radiance: falloff * self.intensity / distance_sq,
+            pdf: 1.,
+        })
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let (inner, outer) = (self.inner_angle.to_radians(), self.outer_angle.to_radians());
+        // the power of the fully lit cone, and roughly half that of the falloff around it
+        let solid_angle = 2. * PI * ((1. - inner.cos()) + 0.5 * (inner.cos() - outer.cos()));
+        Some(LightBounds {
+            min: self.position,
+            max: self.position,
+            power: solid_angle * luminance(self.intensity),
+            axis: self.direction.normalized(),
+            cos_theta_o: inner.cos(),
+            cos_theta_e: (outer - inner).cos(),
+            two_sided: false,
+        })
+    }
+}
+
+/// A light infinitely far away, shining in `direction` from everywhere, like the sun. The light
+/// reaching a surface facing it is `irradiance`, however far away it is.
+///
+/// Giving it an angular size (the angle it covers in the sky, in degrees) softens its shadows.
+/// Unlike `SunEnv`, it's independent of the environment, so it can be combined with any other
+/// lights, e.g. as the key light of a studio rig.
+/// ```
+/// use firework::light::DirectionalLight;
+/// use firework::Scene;
+/// use ultraviolet::Vec3;
+///
+/// let mut scene = Scene::new();
+/// let key = DirectionalLight::new(Vec3::new(-1., -2., -1.), Vec3::broadcast(3.)).angular_size(2.);
+/// scene.add_analytic_light(key);
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectionalLight {
+    direction: Vec3,
+    irradiance: Vec3,
+    #[serde(default)]
+    angular_size: f32,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Vec3) -> Self {
+        DirectionalLight {
+            direction: direction.normalized(),
+            irradiance,
+            angular_size: 0.,
+        }
+    }
+
+    pub fn angular_size(mut self, angular_size: f32) -> Self {
+        self.angular_size = angular_size.clamp(0., 180.);
+        self
+    }
+}
+
+#[typetag::serde]
+impl Light for DirectionalLight {
+    fn sample(&self, _point: Vec3, rand: &mut LcRng) -> Option<LightSample> {
+        let axis = -self.direction.normalized();
+        if self.angular_size <= 0. {
+            return Some(LightSample {
+                wi: axis,
+                distance: f32::INFINITY,
+                radiance: self.irradiance,
+                pdf: 1.,
+            });
+        }
+
+        // a disk of constant radiance, whose irradiance (the integral of radiance * cos over the
+        // cone) on a surface facing it is π sin²(half_angle) times the radiance
+        let half_angle = 0.5 * self.angular_size.to_radians();
+        let sin_half = half_angle.sin();
+        Some(LightSample {
+            wi: sample_cone(axis, half_angle, rand),
+            distance: f32::INFINITY,
+            radiance: self.irradiance / (PI * sin_half * sin_half),
+            pdf: 1. / cone_solid_angle(half_angle),
+        })
+    }
+}
+
+/// The angular distribution of light from a real luminaire, read from an IES (LM-63) photometric
+/// file, as published by most lighting manufacturers. Profiles are normalized so their brightest
+/// direction is 1, and scale the intensity of the light they're attached to.
+///
+/// Angles follow the IES convention: in the profile's frame, a vertical angle of 0 points along
+/// the light (down, for a `PointLight`), and 180 points away from it.
+/// ```no_run
+/// use firework::light::{IesProfile, SpotLight};
+/// use ultraviolet::Vec3;
+///
+/// let profile = IesProfile::from_path("downlight.ies").unwrap();
+/// let light = SpotLight::new(Vec3::new(0., 3., 0.), -Vec3::unit_y(), Vec3::broadcast(20.))
+///     .angles(80., 90.)
+///     .profile(profile);
+/// ```
+/// Like `ImageTexture`, a profile is serialized as the path it was loaded from.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "PathBuf")]
+pub struct IesProfile {
+    path: PathBuf,
+    /// In degrees, increasing
+    vertical_angles: Vec<f32>,
+    /// In degrees, increasing
+    horizontal_angles: Vec<f32>,
+    /// The relative intensity at each pair of angles, one horizontal angle at a time
+    values: Vec<f32>,
+}
+
+impl TryFrom<PathBuf> for IesProfile {
+    type Error = std::io::Error;
+    fn try_from(path: PathBuf) -> Result<IesProfile, Self::Error> {
+        let mut profile = IesProfile::from_path(resolve_asset(&path))?;
+        // keep the path as it was written, so the scene is written back the same way
+        profile.path = path;
+        Ok(profile)
+    }
+}
+
+impl Serialize for IesProfile {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.path.serialize(serializer)
+    }
+}
+
+impl IesProfile {
+    /// Reads a profile from an IES file. Only type C photometry (used by nearly all
+    /// architectural luminaires) is supported.
+    pub fn from_path(path: impl AsRef<Path>) -> std::io::Result<IesProfile> {
+        let text = std::fs::read_to_string(path.as_ref())?;
+
+        // the keywords in the header end at the TILT line, and everything after it is numbers
+        let tilt = text
+            .find("TILT=")
+            .ok_or_else(|| invalid_data("IES file has no TILT line"))?;
+        let rest = &text[tilt..];
+        let (tilt_line, data) = rest.split_at(rest.find('\n').unwrap_or(rest.len()));
+        let mut numbers = data
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                s.parse::<f32>()
+                    .map_err(|_| invalid_data(format!("invalid number {} in IES file", s)))
+            });
+        let mut next = || {
+            numbers
+                .next()
+                .unwrap_or_else(|| Err(invalid_data("IES file is truncated")))
+        };
+
+        // lamp tilt data is included for some luminaires, but doesn't affect the profile
+        if tilt_line.trim() == "TILT=INCLUDE" {
+            let _geometry = next()?;
+            let pairs = next()? as usize;
+            for _ in 0..2 * pairs {
+                next()?;
+            }
+        }
+
+        let _lamps = next()?;
+        let _lumens = next()?;
+        let _multiplier = next()?;
+        let vertical = next()? as usize;
+        let horizontal = next()? as usize;
+        let photometric_type = next()?;
+        // units, width, length, height, ballast factor, file generation type and input watts
+        for _ in 0..7 {
+            next()?;
+        }
+        if photometric_type != 1. {
+            return Err(invalid_data("only type C IES files are supported"));
+        }
+        if vertical == 0 || horizontal == 0 {
+            return Err(invalid_data("IES file has no angles"));
+        }
+
+        let mut read = |n: usize| {
+            (0..n)
+                .map(|_| next())
+                .collect::<std::io::Result<Vec<f32>>>()
+        };
+        let vertical_angles = read(vertical)?;
+        let horizontal_angles = read(horizontal)?;
+        let mut values = read(vertical * horizontal)?;
+
+        let max = values.iter().cloned().fold(0., f32::max);
+        if max > 0. {
+            values.iter_mut().for_each(|v| *v /= max);
+        }
+
+        Ok(IesProfile {
+            path: path.as_ref().to_owned(),
+            vertical_angles,
+            horizontal_angles,
+            values,
+        })
+    }
+
+    /// The relative intensity in the direction `dir` (which must be normalized), where `frame.v1`
+    /// is the direction of the profile's vertical angle of 0
+    fn eval(&self, frame: &CoordinateSystem, dir: Vec3) -> f32 {
+        let vertical = dir.dot(frame.v1).clamp(-1., 1.).acos().to_degrees();
+        let phi = dir.dot(frame.v3).atan2(dir.dot(frame.v2)).to_degrees();
+        let phi = if phi < 0. { phi + 360. } else { phi };
+
+        // profiles only store the angles they need for their symmetry
+        let last = self.horizontal_angles[self.horizontal_angles.len() - 1];
+        let horizontal = if last <= 0. {
+            0.
+        } else if last <= 90. {
+            let phi = phi % 180.;
+            if phi > 90. {
+                180. - phi
+            } else {
+                phi
+            }
+        } else if last <= 180. && phi > 180. {
+            360. - phi
+        } else {
+            phi
+        };
+
+        let (v0, v1, fv) = match Self::lookup(&self.vertical_angles, vertical) {
+            Some(lookup) => lookup,
+            None => return 0.,
+        };
+        let (h0, h1, fh) = Self::lookup(&self.horizontal_angles, horizontal).unwrap_or((0, 0, 0.));
+        let n = self.vertical_angles.len();
+        let value = |h: usize, v: usize| self.values[h * n + v];
+        let a = (1. - fv) * value(h0, v0) + fv * value(h0, v1);
+        let b = (1. - fv) * value(h1, v0) + fv * value(h1, v1);
+        (1. - fh) * a + fh * b
+    }
+
+    /// Finds the pair of angles `angle` is between, and how far it is from the first. Returns
+    /// `None` if it's outside of the angles. A single angle means the intensity doesn't change,
+    /// so it covers every angle.
+    fn lookup(angles: &[f32], angle: f32) -> Option<(usize, usize, f32)> {
+        if angles.len() == 1 {
+            return Some((0, 0, 0.));
+        }
+        let (first, last) = (angles[0], angles[angles.len() - 1]);
+        if angle < first || angle > last {
+            return None;
+        }
+        let i = angles
+            .partition_point(|a| *a <= angle)
+            .clamp(1, angles.len() - 1);
+        let (a0, a1) = (angles[i - 1], angles[i]);
+        let t = if a1 > a0 {
+            (angle - a0) / (a1 - a0)
+        } else {
+            0.
+        };
+        Some((i - 1, i, t))
+    }
+}
+
+fn invalid_data(msg: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.into())
+}
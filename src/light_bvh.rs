@@ -0,0 +1,361 @@
+use crate::light::{luminance, LightBounds};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::render::Hitable;
+use crate::scene::{MaterialIdx, RenderObjectInternal};
+use crate::util;
+use std::f32::consts::PI;
+use tiny_rng::{LcRng, Rand};
+use ultraviolet::Vec3;
+
+/// A hierarchy over the lights in a scene, which chooses a light for each point in proportion to
+/// how much it might light it, from the bounds on each group of lights. Scenes with many lights
+/// (like a street full of lamps) are then mostly lit by the lights nearby, rather than ones far
+/// away that barely matter. See Conty Estevez and Kulla, "Importance Sampling of Many Lights with
+/// Adaptive Tree Splitting".
+pub(crate) struct LightBVH {
+    root: Option<LightBVHNode>,
+    /// The lights without bounds, which are chosen uniformly with a fixed probability
+    unbounded: Vec<usize>,
+    /// The path from the root to each light's leaf, as a bit for each level (set for the second
+    /// child), or `None` if it isn't in the hierarchy
+    trails: Vec<Option<u64>>,
+}
+
+struct LightBVHNode {
+    bounds: LightBounds,
+    next: LightBVHVariant,
+}
+
+enum LightBVHVariant {
+    Leaf(usize),
+    Branch(Box<LightBVHNode>, Box<LightBVHNode>),
+}
+
+fn new_helper(
+    lights: &mut [(usize, LightBounds)],
+    trail: u64,
+    depth: u32,
+    trails: &mut [Option<u64>],
+) -> LightBVHNode {
+    if let [(idx, bounds)] = *lights {
+        trails[idx] = Some(trail);
+        return LightBVHNode {
+            bounds,
+            next: LightBVHVariant::Leaf(idx),
+        };
+    }
+
+    // split at the median along the axis the lights are spread furthest over
+    let center = |bounds: &LightBounds| 0.5 * (bounds.min + bounds.max);
+    let (min, max) = lights.iter().fold(
+        (Vec3::broadcast(f32::MAX), Vec3::broadcast(f32::MIN)),
+        |(min, max), (_, bounds)| {
+            let center = center(bounds);
+            (min.min_by_component(center), max.max_by_component(center))
+        },
+    );
+    let axis = util::max_component_idx(max - min);
+    lights.sort_by(|a, b| {
+        center(&a.1)[axis]
+            .partial_cmp(&center(&b.1)[axis])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    let (front_half, back_half) = lights.split_at_mut(lights.len() / 2);
+    let left = new_helper(front_half, trail, depth + 1, trails);
+    let right = new_helper(back_half, trail | 1 << depth, depth + 1, trails);
+    LightBVHNode {
+        bounds: union(&left.bounds, &right.bounds),
+        next: LightBVHVariant::Branch(Box::new(left), Box::new(right)),
+    }
+}
+
+impl LightBVH {
+    /// Builds the hierarchy over lights with the given bounds (`None` for unbounded lights).
+    /// Lights which don't emit anything are never chosen.
+    pub fn new(bounds: Vec<Option<LightBounds>>) -> LightBVH {
+        let mut trails = vec![None; bounds.len()];
+        let mut unbounded = Vec::new();
+        let mut bounded = Vec::new();
+        for (idx, bounds) in bounds.into_iter().enumerate() {
+            match bounds {
+                Some(bounds) if bounds.power > 0. => bounded.push((idx, bounds)),
+                Some(_) => {}
+                None => unbounded.push(idx),
+            }
+        }
+        let root = if bounded.is_empty() {
+            None
+        } else {
+            Some(new_helper(&mut bounded, 0, 0, &mut trails))
+        };
+        LightBVH {
+            root,
+            unbounded,
+            trails,
+        }
+    }
+
+    /// The probability of choosing one of the unbounded lights, rather than the hierarchy, which
+    /// counts as much as a single unbounded light
+    fn unbounded_probability(&self) -> f32 {
+        let count = self.unbounded.len() as f32;
+        if self.root.is_some() {
+            count / (count + 1.)
+        } else {
+            1.
+        }
+    }
+
+    /// Chooses a light to light `point` with, and returns its index along with the probability
+    /// of choosing it. Returns `None` if no light can reach `point`.
+    pub fn choose(&self, point: Vec3, rand: &mut LcRng) -> Option<(usize, f32)> {
+        let unbounded_probability = self.unbounded_probability();
+        if !self.unbounded.is_empty() && rand.rand_f32() < unbounded_probability {
+            let count = self.unbounded.len();
+            let idx = ((rand.rand_f32() * count as f32) as usize).min(count - 1);
+            return Some((self.unbounded[idx], unbounded_probability / count as f32));
+        }
+
+        let mut node = self.root.as_ref()?;
+        let mut probability = 1. - unbounded_probability;
+        loop {
+            match &node.next {
+                LightBVHVariant::Leaf(idx) => {
+                    return if importance(&node.bounds, point) > 0. {
+                        Some((*idx, probability))
+                    } else {
+                        None
+                    };
+                }
+                LightBVHVariant::Branch(a, b) => {
+                    let (a_importance, b_importance) =
+                        (importance(&a.bounds, point), importance(&b.bounds, point));
+                    let total = a_importance + b_importance;
+                    if total <= 0. {
+                        return None;
+                    }
+                    let a_probability = a_importance / total;
+                    if rand.rand_f32() < a_probability {
+                        node = a;
+                        probability *= a_probability;
+                    } else {
+                        node = b;
+                        probability *= 1. - a_probability;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The probability that `choose` chooses the light `idx` for `point`
+    pub fn probability(&self, idx: usize, point: Vec3) -> f32 {
+        let unbounded_probability = self.unbounded_probability();
+        if self.unbounded.contains(&idx) {
+            return unbounded_probability / self.unbounded.len() as f32;
+        }
+        let (mut node, mut trail) = match (&self.root, self.trails.get(idx)) {
+            (Some(root), Some(Some(trail))) => (root, *trail),
+            _ => return 0.,
+        };
+
+        let mut probability = 1. - unbounded_probability;
+        while let LightBVHVariant::Branch(a, b) = &node.next {
+            let (a_importance, b_importance) =
+                (importance(&a.bounds, point), importance(&b.bounds, point));
+            let total = a_importance + b_importance;
+            if total <= 0. {
+                return 0.;
+            }
+            if trail & 1 == 0 {
+                node = a;
+                probability *= a_importance / total;
+            } else {
+                node = b;
+                probability *= b_importance / total;
+            }
+            trail >>= 1;
+        }
+        if importance(&node.bounds, point) > 0. {
+            probability
+        } else {
+            0.
+        }
+    }
+}
+
+/// cos(max(0, a - b)), from the sines and cosines of `a` and `b`
+fn cos_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+    if cos_a > cos_b {
+        1.
+    } else {
+        cos_a * cos_b + sin_a * sin_b
+    }
+}
+
+/// sin(max(0, a - b)), from the sines and cosines of `a` and `b`
+fn sin_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+    if cos_a > cos_b {
+        0.
+    } else {
+        sin_a * cos_b - cos_a * sin_b
+    }
+}
+
+fn sin_from_cos(cos: f32) -> f32 {
+    (1. - cos * cos).max(0.).sqrt()
+}
+
+/// An upper bound on how much light from within `bounds` reaches `point`, up to a constant
+/// factor, using the smallest angle between the directions it emits in and the direction to
+/// `point`
+fn importance(bounds: &LightBounds, point: Vec3) -> f32 {
+    let center = 0.5 * (bounds.min + bounds.max);
+    let radius = 0.5 * (bounds.max - bounds.min).mag();
+    let to_point = point - center;
+    let distance_sq = to_point.mag_sq();
+    // don't let lights close to `point` dominate completely, since it could be anywhere within
+    // the bounds
+    let clamped_distance_sq = distance_sq.max(radius).max(1e-8);
+
+    let mut cos_w = if distance_sq > 0. {
+        bounds.axis.dot(to_point) / distance_sq.sqrt()
+    } else {
+        1.
+    };
+    if bounds.two_sided {
+        cos_w = cos_w.abs();
+    }
+    let sin_w = sin_from_cos(cos_w);
+
+    // the angle the bounds cover as seen from `point`
+    let inside = (0..3).all(|i| point[i] >= bounds.min[i] && point[i] <= bounds.max[i]);
+    let (sin_b, cos_b) = if inside || distance_sq <= radius * radius {
+        (0., -1.)
+    } else {
+        let sin_b_sq = radius * radius / distance_sq;
+        (sin_b_sq.sqrt(), (1. - sin_b_sq).sqrt())
+    };
+
+    let sin_o = sin_from_cos(bounds.cos_theta_o);
+    let cos_x = cos_sub_clamped(sin_w, cos_w, sin_o, bounds.cos_theta_o);
+    let sin_x = sin_sub_clamped(sin_w, cos_w, sin_o, bounds.cos_theta_o);
+    let cos_theta = cos_sub_clamped(sin_x, cos_x, sin_b, cos_b);
+    if cos_theta <= bounds.cos_theta_e {
+        return 0.;
+    }
+    (bounds.power * cos_theta / clamped_distance_sq).max(0.)
+}
+
+/// The smallest cone (as an axis and the cosine of its half-angle) containing two others
+fn union_cones(a: (Vec3, f32), b: (Vec3, f32)) -> (Vec3, f32) {
+    let (theta_a, theta_b) = (a.1.clamp(-1., 1.).acos(), b.1.clamp(-1., 1.).acos());
+    let theta_d = a.0.dot(b.0).clamp(-1., 1.).acos();
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return a;
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return b;
+    }
+
+    let theta_o = 0.5 * (theta_a + theta_d + theta_b);
+    let rotation_axis = a.0.cross(b.0);
+    if theta_o >= PI || rotation_axis.mag_sq() < 1e-12 {
+        return (a.0, -1.);
+    }
+    // rotate the axis of `a` towards `b`, so the new cone just reaches the far side of `a`
+    let theta_r = theta_o - theta_a;
+    let axis = a.0 * theta_r.cos() + rotation_axis.normalized().cross(a.0) * theta_r.sin();
+    (axis.normalized(), theta_o.cos())
+}
+
+fn union(a: &LightBounds, b: &LightBounds) -> LightBounds {
+    let (axis, cos_theta_o) = union_cones((a.axis, a.cos_theta_o), (b.axis, b.cos_theta_o));
+    LightBounds {
+        min: a.min.min_by_component(b.min),
+        max: a.max.max_by_component(b.max),
+        power: a.power + b.power,
+        axis,
+        cos_theta_o,
+        cos_theta_e: a.cos_theta_e.min(b.cos_theta_e),
+        two_sided: a.two_sided || b.two_sided,
+    }
+}
+
+/// Finds the bounds of an object added with `Scene::add_light`, which uses the materials `used`,
+/// from the bounds on the light they emit (see `Material::emission_bounds`). Returns `None` if
+/// any of those aren't known, so that the light is chosen as if it were unbounded.
+pub(crate) fn area_light_bounds(
+    obj: &RenderObjectInternal,
+    used: &[MaterialIdx],
+    materials: &[Box<dyn Material + 'static>],
+) -> Option<LightBounds> {
+    let mut max = Vec3::zero();
+    let mut one_sided = true;
+    for &idx in used {
+        let emission = materials.get(idx)?.emission_bounds()?;
+        max = max.max_by_component(emission.max);
+        one_sided &= emission.one_sided;
+    }
+
+    let aabb = obj.bounding_box();
+    let center = aabb.center();
+    let offset = 1e-4 * (aabb.max - aabb.min).mag().max(1.);
+    let mut rand = LcRng::new(0);
+
+    // whether the surface at `point` faces `dir`, as seen by a ray coming from that side
+    let faces = |point: Vec3, dir: Vec3, rand: &mut LcRng| {
+        let ray = Ray::new(point + offset * dir, -dir);
+        obj.hit(&ray, 0., 2. * offset, rand)
+            .is_some_and(|hit| hit.normal.dot(dir) > 0.)
+    };
+
+    // the area only decides how often the light is chosen compared to others, so an estimate is
+    // enough, unlike the directions it emits in
+    let (mut count, mut area) = (0, 0.);
+    let mut first: Option<(Vec3, Vec3)> = None;
+    let mut planar = true;
+    for _ in 0..64 {
+        let sample = match obj.sample_point(center, &mut rand) {
+            Some(sample) if sample.pdf > 0. => sample,
+            _ => continue,
+        };
+        let n = sample.normal.normalized();
+        match first {
+            Some((_, normal)) if normal.dot(n) < 0.9999 => planar = false,
+            Some(_) => {}
+            None => first = Some((sample.point, n)),
+        }
+        count += 1;
+        area += 1. / sample.pdf;
+    }
+
+    let (point, normal) = first?;
+    let (axis, cos_theta_o, two_sided) = if !planar {
+        (normal, -1., false)
+    } else if !one_sided {
+        (normal, 1., true)
+    } else {
+        // a plane faces the same way everywhere, so one point shows which side emits
+        match (
+            faces(point, normal, &mut rand),
+            faces(point, -normal, &mut rand),
+        ) {
+            (true, false) => (normal, 1., false),
+            (false, true) => (-normal, 1., false),
+            _ => (normal, 1., true),
+        }
+    };
+    let sides = if one_sided && !two_sided { 1. } else { 2. };
+    Some(LightBounds {
+        min: aabb.min,
+        max: aabb.max,
+        // a diffuse emitter's power is π times its area and radiance
+        power: PI * (area / count as f32) * sides * luminance(max),
+        axis,
+        cos_theta_o,
+        cos_theta_e: 0.,
+        two_sided,
+    })
+}
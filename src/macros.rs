@@ -0,0 +1,101 @@
+/// Builds a `Scene` from a list of statements, each ending in a semicolon, which expand to the
+/// `Scene` methods of the same name:
+///
+/// - `material name = material;` adds a material (see `Scene::add_material_named`). `name` can
+///   then be used as its `MaterialIdx` in the statements after it.
+/// - `object shape, method(args), ...;` adds a `RenderObject` of the shape, with the given
+///   `RenderObject` methods applied in order (e.g. `position(0., 1., 0.)` or `two_sided()`).
+///   `object name = shape, ...;` names it, and binds `name` to its `RenderObjectIdx`.
+/// - `light shape, method(args), ...;` (or `light name = shape, ...;`) adds an object which is
+///   sampled as a light (see `Scene::add_light`).
+/// - `group name = group;` adds a `Group`, and binds `name` to its `GroupIdx`.
+/// - `environment env;`, `background env;`, `atmosphere atmosphere;` and
+///   `render_settings renderer;` set those parts of the scene.
+///
+/// Anything else can be done to the returned `Scene` afterwards.
+/// ```
+/// use firework::environment::ColorEnv;
+/// use firework::material::{EmissiveMat, LambertianMat};
+/// use firework::objects::{Sphere, XZRect};
+/// use firework::scene;
+/// use ultraviolet::Vec3;
+///
+/// let scene = scene! {
+///     material red = LambertianMat::with_color(Vec3::new(0.8, 0.1, 0.1));
+///     material lamp = EmissiveMat::with_color(4. * Vec3::one());
+///
+///     environment ColorEnv::new(Vec3::new(0.1, 0.1, 0.2));
+///     object ball = Sphere::new(1., red), position(0., 1., 0.);
+///     object XZRect::new(-5., 5., -5., 5., 0., red), two_sided();
+///     light XZRect::new(-1., 1., -1., 1., 4., lamp), flip_normals();
+/// };
+/// assert!(scene.object_by_name("ball").is_some());
+/// assert_eq!(scene.material_by_name("lamp"), Some(1));
+/// assert_eq!(scene.lights.len(), 1);
+/// ```
+#[macro_export]
+macro_rules! scene {
+    ($($statements:tt)*) => {{
+        let mut scene = $crate::Scene::new();
+        $crate::__scene_statements!(scene; $($statements)*);
+        scene
+    }};
+}
+
+/// Adds the statements of `scene!` to the scene one at a time
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __scene_statements {
+    ($scene:ident;) => {};
+    ($scene:ident; material $name:ident = $material:expr; $($rest:tt)*) => {
+        #[allow(unused_variables)]
+        let $name = $scene.add_material_named(stringify!($name), $material);
+        $crate::__scene_statements!($scene; $($rest)*);
+    };
+    ($scene:ident; object $name:ident = $shape:expr
+        $(, $method:ident($($arg:expr),* $(,)?))*; $($rest:tt)*) => {
+        #[allow(unused_variables)]
+        let $name = $scene.add_object_named(
+            stringify!($name),
+            $crate::RenderObject::new($shape)$(.$method($($arg),*))*,
+        );
+        $crate::__scene_statements!($scene; $($rest)*);
+    };
+    ($scene:ident; object $shape:expr $(, $method:ident($($arg:expr),* $(,)?))*; $($rest:tt)*) => {
+        $scene.add_object($crate::RenderObject::new($shape)$(.$method($($arg),*))*);
+        $crate::__scene_statements!($scene; $($rest)*);
+    };
+    ($scene:ident; light $name:ident = $shape:expr
+        $(, $method:ident($($arg:expr),* $(,)?))*; $($rest:tt)*) => {
+        #[allow(unused_variables)]
+        let $name = $scene.add_light(
+            $crate::RenderObject::new($shape)$(.$method($($arg),*))*.name(stringify!($name)),
+        );
+        $crate::__scene_statements!($scene; $($rest)*);
+    };
+    ($scene:ident; light $shape:expr $(, $method:ident($($arg:expr),* $(,)?))*; $($rest:tt)*) => {
+        $scene.add_light($crate::RenderObject::new($shape)$(.$method($($arg),*))*);
+        $crate::__scene_statements!($scene; $($rest)*);
+    };
+    ($scene:ident; group $name:ident = $group:expr; $($rest:tt)*) => {
+        #[allow(unused_variables)]
+        let $name = $scene.add_group($group);
+        $crate::__scene_statements!($scene; $($rest)*);
+    };
+    ($scene:ident; environment $environment:expr; $($rest:tt)*) => {
+        $scene.set_environment($environment);
+        $crate::__scene_statements!($scene; $($rest)*);
+    };
+    ($scene:ident; background $background:expr; $($rest:tt)*) => {
+        $scene.set_background($background);
+        $crate::__scene_statements!($scene; $($rest)*);
+    };
+    ($scene:ident; atmosphere $atmosphere:expr; $($rest:tt)*) => {
+        $scene.set_atmosphere($atmosphere);
+        $crate::__scene_statements!($scene; $($rest)*);
+    };
+    ($scene:ident; render_settings $renderer:expr; $($rest:tt)*) => {
+        $scene.set_render_settings($renderer);
+        $crate::__scene_statements!($scene; $($rest)*);
+    };
+}
@@ -1,48 +1,500 @@
-use firework::{camera::CameraSettings, window::save_image, RenderWindow, Renderer, Scene};
-use std::path::PathBuf;
+use firework::animation::Animation;
+use firework::assets::AssetPaths;
+use firework::bench::bench;
+use firework::output::{
+    save_render_with_metadata, terminal_preview, OutputFormat, RenderMetadata, TerminalStyle,
+};
+use firework::procgen::Preset;
+use firework::render::RenderBuffer;
+use firework::{Renderer, Scene};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use structopt::StructOpt;
 use ultraviolet::Vec3;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "firework")]
 struct Opt {
+    #[structopt(long, required_unless_one = &["preset", "bench"])]
+    scene_file: Option<PathBuf>,
+
+    /// Renders one of the demo scenes instead of a scene file: cornell, random-spheres, conics or
+    /// volume
+    #[structopt(long, conflicts_with = "scene-file")]
+    preset: Option<Preset>,
+
+    /// Renders the scene file or preset (or every preset, if neither is given) with a fixed
+    /// number of samples, and prints the time taken to build the BVH and render, the rays traced
+    /// at each bounce and the memory used, as JSON, to keep track of the renderer's performance
     #[structopt(long)]
-    scene_file: PathBuf,
+    bench: bool,
 
     #[structopt(short, long)]
     name: Option<String>,
 
+    /// A directory to look for textures and other assets in, if they aren't next to the scene
+    /// file. Can be given more than once.
+    #[structopt(long)]
+    asset_path: Vec<PathBuf>,
+
+    /// Overrides the number of samples in the scene's render settings
     #[structopt(short, long)]
-    samples: usize,
+    samples: Option<usize>,
+
+    /// The number of threads to render with, e.g. to leave some cores free on a shared machine.
+    /// Every core is used by default.
+    #[structopt(long, conflicts_with = "single-threaded")]
+    threads: Option<usize>,
+
+    /// Renders on a single thread
+    #[structopt(long)]
+    single_threaded: bool,
+
+    /// Overrides the width of the render, in pixels. If only one of --width and --height is
+    /// given, the other is scaled to keep the shape of the image.
+    #[structopt(long)]
+    width: Option<usize>,
+
+    /// Overrides the height of the render, in pixels
+    #[structopt(long)]
+    height: Option<usize>,
+
+    /// Overrides the position of the camera, as `x,y,z` (e.g. `--cam-pos=-5,2,10`)
+    #[structopt(long, parse(try_from_str = parse_vec3))]
+    cam_pos: Option<Vec3>,
+
+    /// Overrides the point the camera looks at, as `x,y,z`
+    #[structopt(long, parse(try_from_str = parse_vec3))]
+    look_at: Option<Vec3>,
 
+    /// Overrides the vertical field of view of the camera, in degrees
+    #[structopt(long)]
+    fov: Option<f32>,
+
+    /// Overrides the aperture of the camera, for depth of field
+    #[structopt(long)]
+    aperture: Option<f32>,
+
+    /// Overrides the distance from the camera which is in focus
+    #[structopt(long)]
+    focus_dist: Option<f32>,
+
+    /// Where to save the render. An `.exr` file keeps the full range of the colors, and the light
+    /// groups, and a `.pfm` file keeps the full range of the colors. For animated scenes, every
+    /// frame is rendered, and saved with its number after the file name.
     #[structopt(short, long)]
     output: Option<PathBuf>,
+
+    /// The frames of the animation to render, e.g. `1..240` (including both ends) or a single
+    /// frame. Rendering more than one frame needs --output, and the scene doesn't need to be
+    /// animated, e.g. to benchmark the same frame again.
+    #[structopt(long, parse(try_from_str = parse_frames))]
+    frames: Option<(u32, u32)>,
+
+    /// Overrides the frame rate of the animation, in frames per second
+    #[structopt(long)]
+    fps: Option<f32>,
+
+    /// Encodes the frames of an animation as a video (e.g. `shot.mp4`) once they're rendered,
+    /// by running ffmpeg, which must be installed
+    #[structopt(long, requires = "output")]
+    video: Option<PathBuf>,
+
+    /// Saves the progress of the render this often, e.g. `30s`, `5m` or `2h`, so a long render
+    /// can be resumed if it's stopped. The checkpoint is saved next to the output, as
+    /// `<output>.ckpt`, unless --resume is given, and is deleted once the render is saved.
+    #[structopt(long, requires = "output", parse(try_from_str = parse_duration))]
+    checkpoint_every: Option<Duration>,
+
+    /// Resumes the render from a checkpoint file, and checkpoints it to the same file (every 10
+    /// minutes, unless --checkpoint-every is given). If the file doesn't exist yet, the render
+    /// starts from the beginning. Each frame of an animation has its own checkpoint, with the
+    /// number of the frame after the file name.
+    #[structopt(long, requires = "output")]
+    resume: Option<PathBuf>,
+
+    /// Saves PNG and TIFF images with 16 bits for each color, rather than 8
+    #[structopt(long)]
+    sixteen_bit: bool,
+
+    /// Never opens a window, e.g. on a server without a display. The render must then be saved
+    /// with --output, or printed with --preview.
+    #[structopt(long)]
+    no_window: bool,
+
+    /// Prints a preview of the render in the terminal once it's done, in color if the terminal
+    /// supports it, e.g. to see renders over SSH. Without --output, the render is only previewed,
+    /// rather than shown in a window.
+    #[structopt(long)]
+    preview: bool,
+
+    /// The width of the preview printed by --preview, in characters
+    #[structopt(long, default_value = "80")]
+    preview_width: usize,
+
+    /// Shows the render this many times as large in the window, e.g. 2 for a small test render
+    #[structopt(long, default_value = "1")]
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    window_scale: f32,
+
+    /// Smooths the render when it's scaled up in the window, rather than showing blocky pixels
+    #[structopt(long)]
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    smooth_scaling: bool,
 }
 
-fn main() -> serde_yaml::Result<()> {
-    let opt = Opt::from_args();
+/// Parses a vector given on the command line as `x,y,z`
+fn parse_vec3(s: &str) -> Result<Vec3, String> {
+    let values = s
+        .split(',')
+        .map(|x| x.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    match values[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(format!("expected a vector as x,y,z, not {:?}", s)),
+    }
+}
+
+/// Parses a range of frames given on the command line as `start..end` (including `end`) or as a
+/// single frame
+fn parse_frames(s: &str) -> Result<(u32, u32), String> {
+    let parse = |frame: &str| frame.trim().parse::<u32>().map_err(|e| e.to_string());
+    let (start, end) = match s.split_once("..") {
+        Some((start, end)) => (parse(start)?, parse(end.trim_start_matches('='))?),
+        None => (parse(s)?, parse(s)?),
+    };
+    if end < start {
+        return Err(format!("the frames {:?} end before they start", s));
+    }
+    Ok((start, end))
+}
+
+/// Parses a length of time given on the command line, e.g. `90s`, `5m` or `1.5h`, in seconds if
+/// no unit is given
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, unit) = match s.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let number = number.trim().parse::<f64>().map_err(|e| e.to_string())?;
+    let seconds = match unit {
+        "s" => 1.,
+        "m" => 60.,
+        "h" => 3600.,
+        _ => return Err(format!("unknown unit {:?}, expected s, m or h", unit)),
+    };
+    if !number.is_finite() || number < 0. {
+        return Err(format!("{:?} isn't a length of time", s));
+    }
+    Ok(Duration::from_secs_f64(number * seconds))
+}
+
+/// The renderer for a scene, from its render settings if it has them, with the settings given on
+/// the command line
+fn scene_renderer(scene: &mut Scene, opt: &Opt) -> Renderer {
+    let renderer = scene
+        .render_settings
+        .take()
+        .unwrap_or_else(Renderer::scene_default);
+    let mut camera = renderer.camera_settings();
+    if let Some(cam_pos) = opt.cam_pos {
+        camera = camera.cam_pos(cam_pos);
+    }
+    if let Some(look_at) = opt.look_at {
+        camera = camera.look_at(look_at);
+    }
+    if let Some(fov) = opt.fov {
+        camera = camera.field_of_view(fov);
+    }
+    if let Some(aperture) = opt.aperture {
+        camera = camera.aperture(aperture);
+    }
+    if let Some(focus_dist) = opt.focus_dist {
+        camera = camera.focus_dist(focus_dist);
+    }
+    let renderer = renderer.camera(camera);
+
+    let renderer = match opt.samples {
+        Some(samples) => renderer.samples(samples),
+        None => renderer,
+    };
+    let renderer = match opt.threads {
+        Some(threads) => renderer.multithreaded(true).threads(threads),
+        None if opt.single_threaded => renderer.multithreaded(false),
+        None => renderer,
+    };
+
+    let scaled = |size: usize, to: usize, from: usize| {
+        ((size * to) as f32 / from as f32).round().max(1.) as usize
+    };
+    let (width, height) = match (opt.width, opt.height) {
+        (Some(width), Some(height)) => (width, height),
+        (Some(width), None) => (width, scaled(renderer.height, width, renderer.width)),
+        (None, Some(height)) => (scaled(renderer.width, height, renderer.height), height),
+        (None, None) => return renderer,
+    };
+    renderer.width(width).height(height)
+}
 
-    let file = std::fs::File::open(opt.scene_file).unwrap();
-    let scene: Scene = serde_yaml::from_reader(file)?;
+/// The path a frame of an animation is saved to, e.g. `shot_0012.png` for frame 12 of `shot.png`
+fn frame_path(output: &Path, frame: u32) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    output.with_file_name(match output.extension() {
+        Some(extension) => format!("{}_{:04}.{}", stem, frame, extension.to_string_lossy()),
+        None => format!("{}_{:04}", stem, frame),
+    })
+}
 
-    let camera = CameraSettings::default()
-        .cam_pos(Vec3::new(0., 30., 50.))
-        .look_at(Vec3::new(0., 0., 0.))
-        .field_of_view(40.);
+/// The pattern of the paths of the frames of an animation saved to `output`, as expected by
+/// ffmpeg, e.g. `shot_%04d.png` for `shot.png` (see `frame_path`)
+fn frame_pattern(output: &Path) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    output.with_file_name(match output.extension() {
+        Some(extension) => format!("{}_%04d.{}", stem, extension.to_string_lossy()),
+        None => format!("{}_%04d", stem),
+    })
+}
 
-    let renderer = Renderer::default()
-        .width(960)
-        .height(540)
-        .samples(opt.samples)
-        .use_bvh(true)
-        .camera(camera);
+/// The animation of the scene, with the frames and frame rate given on the command line, or
+/// `None` if the scene isn't animated and no frames were given
+fn scene_animation(scene: &Scene, opt: &Opt) -> Option<Animation> {
+    if scene.animation.is_none() && opt.frames.is_none() {
+        return None;
+    }
+    let mut animation = scene
+        .animation
+        .clone()
+        .unwrap_or_else(|| Animation::new(24., 0, 0));
+    if let Some((start, end)) = opt.frames {
+        animation.start_frame = start;
+        animation.end_frame = end;
+    }
+    if let Some(fps) = opt.fps {
+        animation.frame_rate = fps;
+    }
+    Some(animation)
+}
+
+/// Encodes the frames of an animation saved to `output` as a video, with ffmpeg
+fn encode_video(
+    output: &Path,
+    animation: &Animation,
+    video: &Path,
+) -> Result<(), firework::scene::SceneFileError> {
+    let status = std::process::Command::new("ffmpeg")
+        .arg("-y")
+        .args(["-framerate", &animation.frame_rate.to_string()])
+        .args(["-start_number", &animation.start_frame.to_string()])
+        .arg("-i")
+        .arg(frame_pattern(output))
+        // the pixel format most players can play
+        .args(["-pix_fmt", "yuv420p"])
+        .arg(video)
+        .status()
+        .map_err(|e| format!("couldn't run ffmpeg, is it installed? -- {}", e))?;
+    if !status.success() {
+        return Err(format!("ffmpeg failed ({})", status).into());
+    }
+    Ok(())
+}
+
+/// Renders the scene and saves it, in the format given by the extension of `path` (see
+/// `OutputFormat::from_path`), with metadata about the render. Images are saved with 8 bits for
+/// each color unless `--sixteen-bit` was given.
+fn render_to(
+    renderer: &Renderer,
+    scene: Scene,
+    path: &Path,
+    checkpoint: Option<&Path>,
+    opt: &Opt,
+) -> Result<(), firework::scene::SceneFileError> {
+    let format = match OutputFormat::from_path(path) {
+        OutputFormat::Image if opt.sixteen_bit => OutputFormat::Image16,
+        format => format,
+    };
 
     let start = std::time::Instant::now();
+    let buffer = match checkpoint {
+        Some(checkpoint) => {
+            let interval = opt.checkpoint_every.unwrap_or(DEFAULT_CHECKPOINT_INTERVAL);
+            renderer.render_checkpointed(scene, checkpoint, interval)?
+        }
+        None => renderer.render_buffer(scene),
+    };
+    let mut metadata = RenderMetadata::new(renderer).render_time(start.elapsed());
+    if let Some(seed) = opt.preset.and_then(Preset::seed) {
+        metadata = metadata.seed(seed);
+    }
+    if let Some(scene_file) = &opt.scene_file {
+        metadata = metadata.scene_source(&std::fs::read(scene_file)?);
+    }
+    save_render_with_metadata(&buffer, path, renderer.gamma, format, &metadata)?;
+    if let Some(checkpoint) = checkpoint {
+        // the render is saved, so it won't need to be resumed
+        std::fs::remove_file(checkpoint)?;
+    }
+    if opt.preview {
+        print_preview(&buffer, renderer.gamma, opt);
+    }
+    Ok(())
+}
+
+/// How often renders are checkpointed with --resume, if --checkpoint-every isn't given
+const DEFAULT_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// The checkpoint file for a render saved to `output`, if it's checkpointed
+fn checkpoint_path(opt: &Opt, output: &Path) -> Option<PathBuf> {
+    match (&opt.resume, opt.checkpoint_every) {
+        (Some(resume), _) => Some(resume.clone()),
+        (None, Some(_)) => {
+            let mut path = output.as_os_str().to_owned();
+            path.push(".ckpt");
+            Some(path.into())
+        }
+        (None, None) => None,
+    }
+}
+
+/// Reads the scene file, or builds the preset
+fn load_scene(opt: &Opt, assets: &AssetPaths) -> Result<Scene, firework::scene::SceneFileError> {
+    match (opt.preset, &opt.scene_file) {
+        (Some(preset), _) => Ok(preset.build()),
+        (None, Some(scene_file)) => Scene::from_path_with_assets(scene_file, assets),
+        (None, None) => Err("either --scene-file or --preset must be given".into()),
+    }
+}
+
+/// The number of samples rendered by `--bench`, unless `--samples` is given
+const BENCH_SAMPLES: usize = 16;
 
-    let render = renderer.render(scene);
+/// Renders the scenes for `--bench`, and prints how long they took as JSON
+fn run_bench(opt: &Opt, assets: &AssetPaths) -> Result<(), firework::scene::SceneFileError> {
+    // `None` for the scene file
+    let presets = match (&opt.scene_file, opt.preset) {
+        (Some(_), _) => vec![None],
+        (None, Some(preset)) => vec![Some(preset)],
+        (None, None) => Preset::ALL.iter().copied().map(Some).collect(),
+    };
 
-    let end = std::time::Instant::now();
-    println!("Finished Rendering in {} s", (end - start).as_secs());
+    let mut results = Vec::new();
+    for preset in presets {
+        let (name, mut scene) = match (preset, &opt.scene_file) {
+            (Some(preset), _) => (preset.name().to_owned(), preset.build()),
+            (None, scene_file) => {
+                let name = scene_file.as_deref().unwrap_or_else(|| Path::new(""));
+                (name.display().to_string(), load_scene(opt, assets)?)
+            }
+        };
+        let mut renderer = scene_renderer(&mut scene, opt);
+        if opt.samples.is_none() {
+            renderer = renderer.samples(BENCH_SAMPLES);
+        }
+        eprintln!("Benchmarking {}", name);
+        results.push(bench(&name, scene, &renderer));
+    }
+    println!("{}", serde_json::to_string_pretty(&results)?);
+    Ok(())
+}
+
+/// Prints a preview of a render in the terminal, for `--preview`
+fn print_preview(buffer: &RenderBuffer, gamma: f32, opt: &Opt) {
+    let style = TerminalStyle::from_env();
+    print!(
+        "{}",
+        terminal_preview(buffer, opt.preview_width, gamma, style)
+    );
+}
+
+/// Shows the render as it forms, and lets the camera be moved around
+#[cfg(feature = "window")]
+fn explore(
+    name: &str,
+    renderer: &Renderer,
+    scene: Scene,
+    opt: &Opt,
+) -> Result<(), firework::scene::SceneFileError> {
+    use firework::window::{RenderWindow, ScaleFilter};
+
+    let mut window = RenderWindow::new(name, Default::default(), renderer.width, renderer.height);
+    window.set_scale(opt.window_scale);
+    if opt.smooth_scaling {
+        window.set_filter(ScaleFilter::Linear);
+    }
+    let camera = window.explore(renderer, scene);
+    println!("Camera:\n{}", serde_yaml::to_string(&camera)?);
+    Ok(())
+}
+
+#[cfg(not(feature = "window"))]
+fn explore(
+    _: &str,
+    _: &Renderer,
+    _: Scene,
+    _: &Opt,
+) -> Result<(), firework::scene::SceneFileError> {
+    Err(
+        "firework was built without the `window` feature, so the render must be saved with \
+         --output"
+            .into(),
+    )
+}
+
+fn main() -> Result<(), firework::scene::SceneFileError> {
+    let opt = Opt::from_args();
+    if opt.no_window && opt.output.is_none() && !opt.preview {
+        return Err(
+            "with --no-window, the render must be saved with --output, or printed with \
+             --preview"
+                .into(),
+        );
+    }
+
+    let assets = opt
+        .asset_path
+        .iter()
+        .fold(AssetPaths::new(), |assets, dir| assets.search_path(dir));
+    if opt.bench {
+        return run_bench(&opt, &assets);
+    }
+    let mut scene = load_scene(&opt, &assets)?;
+
+    let animation = scene_animation(&scene, &opt);
+    let frames = animation
+        .as_ref()
+        .map_or(0, |animation| animation.frames().count());
+    if let (Some(animation), Some(output)) = (&animation, &opt.output) {
+        let start = std::time::Instant::now();
+        for frame in animation.frames() {
+            // rendering consumes the scene, so it's read again for every frame
+            let mut scene = load_scene(&opt, &assets)?;
+            scene.set_animation(animation.clone());
+            scene.set_frame(frame);
+            let renderer = scene_renderer(&mut scene, &opt);
+
+            let path = frame_path(output, frame);
+            let checkpoint = checkpoint_path(&opt, output).map(|file| frame_path(&file, frame));
+            render_to(&renderer, scene, &path, checkpoint.as_deref(), &opt)?;
+            println!("Saved frame {} to {:?}", frame, path);
+        }
+        let end = std::time::Instant::now();
+        println!("Finished Rendering in {} s", (end - start).as_secs());
+        if let Some(video) = &opt.video {
+            encode_video(output, animation, video)?;
+            println!("Saved video to {:?}", video);
+        }
+        return Ok(());
+    }
+    if frames > 1 {
+        return Err("rendering more than one frame needs --output".into());
+    }
+
+    if let Some(animation) = animation {
+        let frame = animation.start_frame;
+        scene.set_animation(animation);
+        scene.set_frame(frame);
+    }
+    let renderer = scene_renderer(&mut scene, &opt);
 
     let name: &str = opt
         .name
@@ -50,12 +502,20 @@ fn main() -> serde_yaml::Result<()> {
         .map(|x| x.as_str())
         .unwrap_or("Firework Render");
 
-    if let Some(output) = opt.output {
-        println!("Saving image to {:?}", output);
-        save_image(&render, output, renderer.width, renderer.height)
+    if let Some(output) = &opt.output {
+        let start = std::time::Instant::now();
+
+        let checkpoint = checkpoint_path(&opt, output);
+        render_to(&renderer, scene, output, checkpoint.as_deref(), &opt)?;
+
+        let end = std::time::Instant::now();
+        println!("Finished Rendering in {} s", (end - start).as_secs());
+        println!("Saved image to {:?}", output);
+    } else if opt.preview {
+        let buffer = renderer.render_buffer(scene);
+        print_preview(&buffer, renderer.gamma, &opt);
     } else {
-        let window = RenderWindow::new(name, Default::default(), renderer.width, renderer.height);
-        window.display(&render);
+        explore(name, &renderer, scene, &opt)?;
     }
 
     Ok(())
@@ -1,10 +1,15 @@
 use crate::ray::Ray;
 use crate::render::RaycastHit;
-use crate::texture::{ConstantTexture, Texture};
-use crate::util::{random_in_unit_sphere, reflect, refract, schlick};
-use serde::{Deserialize, Serialize};
+use crate::shader::{ShadeContext, ShaderGraph};
+use crate::texture::{lattice_hash, ConstantTexture, Texture};
+use crate::util::{
+    random_in_unit_sphere, random_unit_vector, reflect, refract, schlick, CoordinateSystem,
+};
+use serde::de::{MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::f32::consts::PI;
 use tiny_rng::{LcRng, Rand};
-use ultraviolet::{Vec2, Vec3};
+use ultraviolet::{Rotor3, Vec2, Vec3};
 
 #[typetag::serde(tag = "material")]
 pub trait Material: Sync {
@@ -13,11 +18,89 @@ pub trait Material: Sync {
     fn emit(&self, _uv: Vec2, _point: &Vec3) -> Vec3 {
         Vec3::zero()
     }
+
+    /// The light emitted back along `r_in` from where it hit the surface, which is what the
+    /// renderer uses. Materials whose emission depends on the direction it's seen from, or on more
+    /// of the hit than its uv and point, should override this. Defaults to `emit`.
+    fn emit_directional(&self, _r_in: &Ray, hit: &RaycastHit) -> Vec3 {
+        self.emit(hit.uv, &hit.point)
+    }
+
+    /// A tangent space normal map, applied to the `RaycastHit` before `scatter` is called.
+    fn normal_map(&self) -> Option<&dyn Texture> {
+        None
+    }
+
+    /// The index of refraction of materials that light travels through, like glass or water.
+    /// The renderer uses this to track which objects a path is inside of, and fills in
+    /// `RaycastHit::outside_ior` with the index of refraction on the other side of the surface.
+    fn ior(&self) -> Option<f32> {
+        None
+    }
+
+    /// Evaluates the BSDF for light arriving from `wi` and leaving towards `wo`, multiplied by the
+    /// cosine of the angle between `wi` and the normal. Both directions are normalized and point
+    /// away from the surface.
+    /// The default returns zero, which is correct for perfectly specular materials, since they can
+    /// only be sampled through `scatter`.
+    fn eval(&self, _wo: Vec3, _wi: Vec3, _hit: &RaycastHit) -> Vec3 {
+        Vec3::zero()
+    }
+
+    /// Returns the probability density (with respect to solid angle) that `scatter` produces the
+    /// direction `wi` for light leaving towards `wo`.
+    /// A pdf of zero tells the integrator that the material can't be explicitly sampled (e.g. for
+    /// next event estimation or multiple importance sampling).
+    fn pdf(&self, _wo: Vec3, _wi: Vec3, _hit: &RaycastHit) -> f32 {
+        0.
+    }
+
+    /// Describes anything wrong with the material which would break rendering
+    fn problems(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Bounds on the light the material emits, which decide how often the lights using it are
+    /// chosen for direct lighting. Lights with a material that returns `None` are chosen as if
+    /// they could be anywhere, so materials which emit light should return bounds if they can.
+    fn emission_bounds(&self) -> Option<EmissionBounds> {
+        None
+    }
+}
+
+/// Bounds on the light emitted by a material, see `Material::emission_bounds`
+#[derive(Debug, Clone, Copy)]
+pub struct EmissionBounds {
+    /// At least the radiance emitted in each channel, at any point and in any direction
+    pub max: Vec3,
+    /// Whether light is only emitted from the front of surfaces (the side the normal points to)
+    pub one_sided: bool,
+}
+
+/// Perturbs the normal of `hit` using a tangent space normal map, where the red, green, and blue
+/// channels of the texture are mapped from 0..1 to -1..1 along the tangent, bitangent and normal.
+/// If the shape does not provide a tangent, an arbitrary one perpendicular to the normal is used.
+pub(crate) fn apply_normal_map(hit: &mut RaycastHit, normal_map: &dyn Texture) {
+    let n = hit.normal.normalized();
+    let tangent = hit
+        .tangent
+        .map(|t| t - n * n.dot(t))
+        .filter(|t| t.mag_sq() > 1e-8)
+        .map(|t| t.normalized())
+        .unwrap_or_else(|| CoordinateSystem::from_one_vec(&n).v2);
+    let bitangent = n.cross(tangent);
+
+    let c = 2. * normal_map.sample_hit(hit) - Vec3::one();
+    hit.normal = (c.x * tangent + c.y * bitangent + c.z * n).normalized();
 }
 
 pub struct ScatterResult {
     pub attenuation: Vec3,
     pub scattered: Ray,
+    /// Whether `scattered` was chosen from a specular part of the material (like a mirror, or the
+    /// coating of `PlasticMat`), which `eval` and `pdf` don't include. The renderer uses this to
+    /// combine the scattered ray with light sampling.
+    pub specular: bool,
 }
 
 /// Represents a diffuse (Lambertian) material.
@@ -26,6 +109,8 @@ pub struct ScatterResult {
 #[derive(Serialize, Deserialize)]
 pub struct LambertianMat {
     albedo: Box<dyn Texture + Sync>,
+    #[serde(default)]
+    normal_map: Option<Box<dyn Texture + Sync>>,
 }
 
 impl LambertianMat {
@@ -40,6 +125,7 @@ impl LambertianMat {
     pub fn new<T: Texture + Sync + 'static>(albedo: T) -> LambertianMat {
         LambertianMat {
             albedo: Box::new(albedo),
+            normal_map: None,
         }
     }
 
@@ -56,64 +142,238 @@ impl LambertianMat {
     pub fn with_color(albedo: Vec3) -> LambertianMat {
         LambertianMat {
             albedo: Box::new(ConstantTexture::new(albedo)),
+            normal_map: None,
         }
     }
+
+    /// Adds a tangent space normal map to the material
+    /// ```
+    /// use firework::material::LambertianMat;
+    /// use firework::texture::ConstantTexture;
+    /// use ultraviolet::Vec3;
+    ///
+    /// let flat = LambertianMat::with_color(Vec3::new(1., 0., 0.))
+    ///     .with_normal_map(ConstantTexture::new(Vec3::new(0.5, 0.5, 1.)));
+    /// ```
+    pub fn with_normal_map<T: Texture + Sync + 'static>(mut self, normal_map: T) -> LambertianMat {
+        self.normal_map = Some(Box::new(normal_map));
+        self
+    }
+}
+
+/// The density of the directions diffuse surfaces scatter towards, by offsetting the normal with a
+/// random point in the unit sphere. The point is within `2 cos(theta)` of the hit point along a
+/// direction at an angle `theta` to the normal, so the density is `2 cos^3(theta) / pi`, rather
+/// than the `cos(theta) / pi` of an ideal Lambertian surface. Since the attenuation of those
+/// surfaces is just their albedo, `eval` is the albedo times this density.
+fn diffuse_pdf(wi: Vec3, normal: Vec3) -> f32 {
+    let cosine = wi.dot(normal).max(0.);
+    2. * cosine * cosine * cosine / PI
 }
 
 #[typetag::serde]
 impl Material for LambertianMat {
     fn scatter(&self, _r_in: &Ray, hit: &RaycastHit, rand: &mut LcRng) -> Option<ScatterResult> {
-        let target = hit.point + hit.normal + random_in_unit_sphere(rand);
+        let target = hit.point + hit.normal.normalized() + random_in_unit_sphere(rand);
         let scattered = Ray::new(hit.point, target - hit.point);
         // TODO: Use proper UV Mapping
-        let attenuation = self.albedo.sample(hit.uv, &hit.point);
+        let attenuation = self.albedo.sample_hit(hit);
         Some(ScatterResult {
             scattered,
             attenuation,
+            specular: false,
         })
     }
+
+    fn eval(&self, _wo: Vec3, wi: Vec3, hit: &RaycastHit) -> Vec3 {
+        self.albedo.sample_hit(hit) * diffuse_pdf(wi, hit.normal.normalized())
+    }
+
+    fn pdf(&self, _wo: Vec3, wi: Vec3, hit: &RaycastHit) -> f32 {
+        diffuse_pdf(wi, hit.normal.normalized())
+    }
+
+    fn normal_map(&self) -> Option<&dyn Texture> {
+        self.normal_map.as_ref().map(|t| t.as_ref() as &dyn Texture)
+    }
 }
 
+/// A reflective metal. Both the `roughness` (how blurry the reflections are) and the `metalness`
+/// (the probability that light is reflected rather than diffusely scattered) are textures, so they
+/// can be driven by maps to create scratches, fingerprints, etc. Either can also be given as a
+/// number in a scene file, as the roughness was before it could be a texture.
+/// ```
+/// use firework::material::Material;
+///
+/// let metal = "
+/// material: MetalMat
+/// albedo: {x: 0.8, y: 0.8, z: 0.8}
+/// roughness: 0.2
+/// ";
+/// let metal: Box<dyn Material> = serde_yaml::from_str(metal).unwrap();
+/// ```
 #[derive(Serialize, Deserialize)]
 pub struct MetalMat {
     albedo: Vec3,
-    roughness: f32,
+    #[serde(deserialize_with = "texture_or_constant")]
+    roughness: Box<dyn Texture + Sync>,
+    #[serde(
+        default = "default_metalness",
+        deserialize_with = "texture_or_constant"
+    )]
+    metalness: Box<dyn Texture + Sync>,
+    #[serde(default)]
+    normal_map: Option<Box<dyn Texture + Sync>>,
+}
+
+fn default_metalness() -> Box<dyn Texture + Sync> {
+    Box::new(ConstantTexture::new(Vec3::one()))
+}
+
+/// Reads a texture, or a number for a constant texture, as the roughness of a `MetalMat` was
+/// before it could be a texture, so older scene files still load
+fn texture_or_constant<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Box<dyn Texture + Sync>, D::Error> {
+    struct TextureVisitor;
+
+    impl<'de> Visitor<'de> for TextureVisitor {
+        type Value = Box<dyn Texture + Sync>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a texture or a number")
+        }
+
+        fn visit_f64<E: serde::de::Error>(self, value: f64) -> Result<Self::Value, E> {
+            Ok(Box::new(ConstantTexture::new(Vec3::broadcast(
+                value as f32,
+            ))))
+        }
+
+        fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<Self::Value, E> {
+            self.visit_f64(value as f64)
+        }
+
+        fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<Self::Value, E> {
+            self.visit_f64(value as f64)
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+            Deserialize::deserialize(serde::de::value::MapAccessDeserializer::new(map))
+        }
+    }
+
+    deserializer.deserialize_any(TextureVisitor)
 }
 
 impl MetalMat {
+    /// Creates a new fully metallic material with a constant roughness.
     pub fn new(albedo: Vec3, roughness: f32) -> MetalMat {
-        MetalMat { albedo, roughness }
+        MetalMat {
+            albedo,
+            roughness: Box::new(ConstantTexture::new(Vec3::broadcast(roughness))),
+            metalness: default_metalness(),
+            normal_map: None,
+        }
+    }
+
+    /// Replaces the roughness with a texture. Only the first (red) channel is used.
+    /// ```
+    /// use firework::material::MetalMat;
+    /// use firework::texture::TurbulenceTexture;
+    /// use ultraviolet::Vec3;
+    ///
+    /// let scratched = MetalMat::new(Vec3::new(0.8, 0.8, 0.8), 0.0)
+    ///     .roughness_map(TurbulenceTexture::new(4, 20.));
+    /// ```
+    pub fn roughness_map<T: Texture + Sync + 'static>(mut self, roughness: T) -> MetalMat {
+        self.roughness = Box::new(roughness);
+        self
+    }
+
+    /// Replaces the metalness with a texture. Only the first (red) channel is used. Where the
+    /// metalness is less than 1, light is sometimes scattered diffusely using the albedo instead.
+    pub fn metalness_map<T: Texture + Sync + 'static>(mut self, metalness: T) -> MetalMat {
+        self.metalness = Box::new(metalness);
+        self
+    }
+
+    /// Adds a tangent space normal map to the material
+    pub fn with_normal_map<T: Texture + Sync + 'static>(mut self, normal_map: T) -> MetalMat {
+        self.normal_map = Some(Box::new(normal_map));
+        self
     }
 }
 
 #[typetag::serde]
 impl Material for MetalMat {
     fn scatter(&self, r_in: &Ray, hit: &RaycastHit, rand: &mut LcRng) -> Option<ScatterResult> {
+        let attenuation = self.albedo;
+
+        let metalness = self.metalness.sample_hit(hit).x;
+        if rand.rand_f32() > metalness {
+            let target = hit.point + hit.normal + random_in_unit_sphere(rand);
+            return Some(ScatterResult {
+                scattered: Ray::new(hit.point, target - hit.point),
+                attenuation,
+                specular: false,
+            });
+        }
+
+        let roughness = self.roughness.sample_hit(hit).x;
         let reflected = reflect(r_in.direction(), &hit.normal);
         let scattered = Ray::new(
             hit.point,
-            reflected + self.roughness * random_in_unit_sphere(rand),
+            reflected + roughness * random_in_unit_sphere(rand),
         );
-        let attenuation = self.albedo;
         if scattered.direction().dot(hit.normal) > 0. {
             Some(ScatterResult {
                 scattered,
                 attenuation,
+                specular: true,
             })
         } else {
             None
         }
     }
+
+    fn normal_map(&self) -> Option<&dyn Texture> {
+        self.normal_map.as_ref().map(|t| t.as_ref() as &dyn Texture)
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct DielectricMat {
     ref_idx: f32,
+    /// The absorption coefficient for each color channel, per unit of distance travelled inside
+    /// the material. Zero means perfectly clear.
+    #[serde(default)]
+    absorption: Vec3,
 }
 
 impl DielectricMat {
     pub fn new(ref_idx: f32) -> DielectricMat {
-        DielectricMat { ref_idx }
+        DielectricMat {
+            ref_idx,
+            absorption: Vec3::zero(),
+        }
+    }
+
+    /// Creates a tinted dielectric (colored glass, murky water, etc.).
+    /// Light travelling through the material is attenuated according to the Beer-Lambert law, so
+    /// thick sections look darker than thin ones. `color` is the tint of the light that is
+    /// absorbed least, and `density` controls how quickly the absorption happens.
+    /// ```
+    /// use firework::material::DielectricMat;
+    /// use ultraviolet::Vec3;
+    ///
+    /// let green_glass = DielectricMat::with_absorption(1.5, Vec3::new(0.2, 0.9, 0.3), 0.5);
+    /// ```
+    pub fn with_absorption(ref_idx: f32, color: Vec3, density: f32) -> DielectricMat {
+        DielectricMat {
+            ref_idx,
+            absorption: density * (Vec3::one() - color.map(|c| c.clamp(0., 1.))),
+        }
     }
 }
 
@@ -121,51 +381,112 @@ impl DielectricMat {
 impl Material for DielectricMat {
     fn scatter(&self, r_in: &Ray, hit: &RaycastHit, rand: &mut LcRng) -> Option<ScatterResult> {
         let reflected = reflect(r_in.direction(), &hit.normal);
-        let (outward_normal, ni_over_nt, cosine) = if r_in.direction().dot(hit.normal) > 0. {
+        let exiting = r_in.direction().dot(hit.normal) > 0.;
+        // If the ray is leaving the material, it has travelled `hit.t` along `r_in` through the
+        // interior, so apply the Beer-Lambert absorption for that distance.
+        let attenuation = if exiting {
+            let distance = hit.t * r_in.direction().mag();
+            (-self.absorption * distance).map(f32::exp)
+        } else {
+            Vec3::one()
+        };
+        // the ratio of the refractive indices of this material and whatever it's nested in
+        let ratio = self.ref_idx / hit.outside_ior;
+        let (outward_normal, ni_over_nt, cosine) = if exiting {
             (
                 -hit.normal,
-                self.ref_idx,
-                self.ref_idx * r_in.direction().dot(hit.normal) / r_in.direction().mag(),
+                ratio,
+                ratio * r_in.direction().dot(hit.normal) / r_in.direction().mag(),
             )
         } else {
             (
                 hit.normal,
-                1.0 / self.ref_idx,
+                1.0 / ratio,
                 -r_in.direction().dot(hit.normal) / r_in.direction().mag(),
             )
         };
 
         if let Some(refracted) = refract(r_in.direction(), &outward_normal, ni_over_nt) {
-            if rand.rand_f32() > schlick(cosine, self.ref_idx) {
+            if rand.rand_f32() > schlick(cosine, ratio) {
                 return Some(ScatterResult {
                     scattered: Ray::new(hit.point, refracted),
-                    attenuation: Vec3::one(),
+                    attenuation,
+                    specular: true,
                 });
             }
         }
         Some(ScatterResult {
             scattered: Ray::new(hit.point, reflected),
-            attenuation: Vec3::one(),
+            attenuation,
+            specular: true,
         })
     }
+
+    fn ior(&self) -> Option<f32> {
+        Some(self.ref_idx)
+    }
 }
 
+/// A material that emits light. The emitted color is given by `albedo` scaled by `intensity`.
+/// Optionally, the material can emit only from the front face (the side the normal points to),
+/// and the emission can fall off away from the normal, like a spot light.
 #[derive(Serialize, Deserialize)]
 pub struct EmissiveMat {
     albedo: Box<dyn Texture + Sync>,
+    #[serde(default = "default_intensity")]
+    intensity: f32,
+    #[serde(default)]
+    one_sided: bool,
+    /// The exponent applied to the cosine between the normal and the outgoing direction. Zero
+    /// means the emission is uniform in all directions.
+    #[serde(default)]
+    falloff: f32,
+}
+
+fn default_intensity() -> f32 {
+    1.
 }
 
 impl EmissiveMat {
     pub fn new<T: Texture + Sync + 'static>(albedo: T) -> EmissiveMat {
         EmissiveMat {
             albedo: Box::new(albedo),
+            intensity: default_intensity(),
+            one_sided: false,
+            falloff: 0.,
         }
     }
 
     pub fn with_color(albedo: Vec3) -> EmissiveMat {
-        EmissiveMat {
-            albedo: Box::new(ConstantTexture::new(albedo)),
-        }
+        EmissiveMat::new(ConstantTexture::new(albedo))
+    }
+
+    /// Sets the multiplier applied to the emitted color
+    /// ```
+    /// use firework::material::EmissiveMat;
+    /// use ultraviolet::Vec3;
+    ///
+    /// let panel = EmissiveMat::with_color(Vec3::new(1., 0.9, 0.8))
+    ///     .intensity(15.)
+    ///     .one_sided()
+    ///     .falloff(2.);
+    /// ```
+    pub fn intensity(mut self, intensity: f32) -> EmissiveMat {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Only emit light from the front face, i.e. the side the normal points to
+    pub fn one_sided(mut self) -> EmissiveMat {
+        self.one_sided = true;
+        self
+    }
+
+    /// Sets the exponent of the angular falloff. The emission is scaled by `cos(theta)^falloff`,
+    /// where theta is the angle between the normal and the outgoing direction.
+    pub fn falloff(mut self, falloff: f32) -> EmissiveMat {
+        self.falloff = falloff;
+        self
     }
 }
 
@@ -175,8 +496,30 @@ impl Material for EmissiveMat {
         None
     }
 
+    /// The emission without the falloff, as the direction it's seen from isn't known
     fn emit(&self, uv: Vec2, point: &Vec3) -> Vec3 {
-        self.albedo.sample(uv, point)
+        self.intensity * self.albedo.sample(uv, point)
+    }
+
+    fn emit_directional(&self, r_in: &Ray, hit: &RaycastHit) -> Vec3 {
+        let cosine = -r_in.direction().normalized().dot(hit.normal.normalized());
+        if self.one_sided && cosine <= 0. {
+            return Vec3::zero();
+        }
+        let falloff = if self.falloff > 0. {
+            cosine.abs().powf(self.falloff)
+        } else {
+            1.
+        };
+        self.intensity * falloff * self.albedo.sample_hit(hit)
+    }
+
+    fn emission_bounds(&self) -> Option<EmissionBounds> {
+        // the falloff only ever makes the emission dimmer
+        Some(EmissionBounds {
+            max: self.intensity * self.albedo.max()?,
+            one_sided: self.one_sided,
+        })
     }
 }
 
@@ -197,8 +540,868 @@ impl IsotropicMat {
 impl Material for IsotropicMat {
     fn scatter(&self, _r_in: &Ray, hit: &RaycastHit, rand: &mut LcRng) -> Option<ScatterResult> {
         Some(ScatterResult {
-            attenuation: self.texture.sample(hit.uv, &hit.point),
+            attenuation: self.texture.sample_hit(hit),
+            scattered: Ray::new(hit.point, random_in_unit_sphere(rand)),
+            specular: false,
+        })
+    }
+
+    fn eval(&self, _wo: Vec3, _wi: Vec3, hit: &RaycastHit) -> Vec3 {
+        self.texture.sample_hit(hit) / (4. * PI)
+    }
+
+    fn pdf(&self, _wo: Vec3, _wi: Vec3, _hit: &RaycastHit) -> f32 {
+        1. / (4. * PI)
+    }
+}
+
+/// A Henyey-Greenstein phase function, for participating media which scatter light unevenly.
+/// `g` (between -1 and 1) is the average cosine of the scattering angle: 0 scatters uniformly
+/// (like `IsotropicMat`), positive values scatter light forwards (clouds, fog halos around
+/// lights, god rays), and negative values scatter it back towards where it came from.
+/// ```
+/// use firework::material::HenyeyGreensteinMat;
+/// use firework::objects::{ConstantMedium, Sphere};
+/// use firework::texture::ConstantTexture;
+/// use firework::{RenderObject, Scene};
+///
+/// let mut scene = Scene::new();
+/// let fog = scene.add_material(HenyeyGreensteinMat::new(
+///     ConstantTexture::from_rgb(0.9, 0.9, 0.9),
+///     0.7,
+/// ));
+/// scene.add_object(RenderObject::new(ConstantMedium::new(Sphere::new(5., fog), 0.1, fog)));
+/// ```
+#[derive(Serialize, Deserialize)]
+pub struct HenyeyGreensteinMat {
+    albedo: Box<dyn Texture + Sync>,
+    g: f32,
+}
+
+impl HenyeyGreensteinMat {
+    pub fn new<T: Texture + 'static>(albedo: T, g: f32) -> Self {
+        HenyeyGreensteinMat {
+            albedo: Box::new(albedo),
+            // the phase function is singular at |g| = 1
+            g: g.clamp(-0.99, 0.99),
+        }
+    }
+
+    /// Evaluates the phase function, where `cos_theta` is the cosine of the angle between the
+    /// direction the light was travelling in, and the direction it's scattered in
+    fn phase(&self, cos_theta: f32) -> f32 {
+        let g = self.g;
+        let denom = 1. + g * g - 2. * g * cos_theta;
+        (1. - g * g) / (4. * PI * denom * denom.sqrt())
+    }
+}
+
+/// Samples a direction from the Henyey-Greenstein phase function, for light travelling in the
+/// (normalized) direction `forward`
+pub(crate) fn sample_henyey_greenstein(g: f32, forward: Vec3, rand: &mut LcRng) -> Vec3 {
+    let xi = rand.rand_f32();
+    // invert the CDF of the phase function to find the scattering angle
+    let cos_theta = if g.abs() < 1e-3 {
+        1. - 2. * xi
+    } else {
+        let s = (1. - g * g) / (1. - g + 2. * g * xi);
+        (1. + g * g - s * s) / (2. * g)
+    };
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * PI * rand.rand_f32();
+
+    let frame = CoordinateSystem::from_one_vec(&forward);
+    sin_theta * phi.cos() * frame.v2 + sin_theta * phi.sin() * frame.v3 + cos_theta * forward
+}
+
+#[typetag::serde]
+impl Material for HenyeyGreensteinMat {
+    fn scatter(&self, r_in: &Ray, hit: &RaycastHit, rand: &mut LcRng) -> Option<ScatterResult> {
+        let direction = sample_henyey_greenstein(self.g, r_in.direction().normalized(), rand);
+        Some(ScatterResult {
+            attenuation: self.albedo.sample_hit(hit),
+            scattered: Ray::new(hit.point, direction),
+            specular: false,
+        })
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, hit: &RaycastHit) -> Vec3 {
+        self.albedo.sample_hit(hit) * self.phase((-wo).dot(wi))
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, _hit: &RaycastHit) -> f32 {
+        self.phase((-wo).dot(wi))
+    }
+}
+
+/// A material for glowing participating media, like fire, plasma or nebulae. At each collision in
+/// the medium, light is either scattered (uniformly, with probability `albedo`) or absorbed, and
+/// absorbing media emit light: `emission * intensity` for a medium that absorbs everything. The
+/// emission is a texture sampled at the collision point, so it can follow a density or
+/// temperature field, e.g. a `VolumeTexture`.
+/// ```
+/// use firework::material::EmissiveMediumMat;
+/// use firework::texture::ConstantTexture;
+///
+/// let fire = EmissiveMediumMat::new(
+///     ConstantTexture::from_rgb(0., 0., 0.),
+///     ConstantTexture::from_rgb(1., 0.45, 0.1),
+/// )
+/// .intensity(4.);
+/// ```
+#[derive(Serialize, Deserialize)]
+pub struct EmissiveMediumMat {
+    albedo: Box<dyn Texture + Sync>,
+    emission: Box<dyn Texture + Sync>,
+    #[serde(default = "default_intensity")]
+    intensity: f32,
+}
+
+impl EmissiveMediumMat {
+    pub fn new<A: Texture + 'static, E: Texture + 'static>(albedo: A, emission: E) -> Self {
+        EmissiveMediumMat {
+            albedo: Box::new(albedo),
+            emission: Box::new(emission),
+            intensity: 1.,
+        }
+    }
+
+    /// Sets the multiplier applied to the emission texture
+    pub fn intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+}
+
+#[typetag::serde]
+impl Material for EmissiveMediumMat {
+    fn scatter(&self, _r_in: &Ray, hit: &RaycastHit, rand: &mut LcRng) -> Option<ScatterResult> {
+        let albedo = self.albedo.sample_hit(hit);
+        if albedo == Vec3::zero() {
+            return None;
+        }
+        Some(ScatterResult {
+            attenuation: albedo,
             scattered: Ray::new(hit.point, random_in_unit_sphere(rand)),
+            specular: false,
+        })
+    }
+
+    fn emit(&self, uv: Vec2, point: &Vec3) -> Vec3 {
+        let albedo = self.albedo.sample(uv, point).map(|x| x.clamp(0., 1.));
+        let absorbed = Vec3::one() - albedo;
+        absorbed * self.emission.sample(uv, point) * self.intensity
+    }
+
+    fn eval(&self, _wo: Vec3, _wi: Vec3, hit: &RaycastHit) -> Vec3 {
+        self.albedo.sample_hit(hit) / (4. * PI)
+    }
+
+    fn pdf(&self, _wo: Vec3, _wi: Vec3, _hit: &RaycastHit) -> f32 {
+        1. / (4. * PI)
+    }
+}
+
+/// Combines two materials, choosing between them at each hit based on `factor`. Where the factor
+/// is 0, only `a` is used, and where it is 1, only `b` is used. The factor is a texture, so it
+/// can be used for things like rust patches on metal, or puddles on pavement.
+#[derive(Serialize, Deserialize)]
+pub struct MixMat {
+    a: Box<dyn Material>,
+    b: Box<dyn Material>,
+    factor: Box<dyn Texture + Sync>,
+}
+
+impl MixMat {
+    /// Creates a new `MixMat` with a constant factor
+    /// ```
+    /// use firework::material::{LambertianMat, MetalMat, MixMat};
+    /// use ultraviolet::Vec3;
+    ///
+    /// let dull_metal = MixMat::new(
+    ///     MetalMat::new(Vec3::new(0.8, 0.8, 0.8), 0.1),
+    ///     LambertianMat::with_color(Vec3::new(0.5, 0.2, 0.1)),
+    ///     0.3,
+    /// );
+    /// ```
+    pub fn new<A, B>(a: A, b: B, factor: f32) -> MixMat
+    where
+        A: Material + 'static,
+        B: Material + 'static,
+    {
+        MixMat::with_texture(a, b, ConstantTexture::new(Vec3::broadcast(factor)))
+    }
+
+    /// Creates a new `MixMat` where the factor is given by a texture. Only the first (red)
+    /// channel is used.
+    pub fn with_texture<A, B, T>(a: A, b: B, factor: T) -> MixMat
+    where
+        A: Material + 'static,
+        B: Material + 'static,
+        T: Texture + Sync + 'static,
+    {
+        MixMat {
+            a: Box::new(a),
+            b: Box::new(b),
+            factor: Box::new(factor),
+        }
+    }
+
+    fn choose(&self, hit: &RaycastHit, rand: &mut LcRng) -> &dyn Material {
+        if rand.rand_f32() < self.factor.sample_hit(hit).x {
+            self.b.as_ref()
+        } else {
+            self.a.as_ref()
+        }
+    }
+}
+
+#[typetag::serde]
+impl Material for MixMat {
+    fn scatter(&self, r_in: &Ray, hit: &RaycastHit, rand: &mut LcRng) -> Option<ScatterResult> {
+        let material = self.choose(hit, rand);
+        if let Some(normal_map) = material.normal_map() {
+            let mut hit = hit.clone();
+            apply_normal_map(&mut hit, normal_map);
+            material.scatter(r_in, &hit, rand)
+        } else {
+            material.scatter(r_in, hit, rand)
+        }
+    }
+
+    fn emit(&self, uv: Vec2, point: &Vec3) -> Vec3 {
+        let factor = self.factor.sample(uv, point).x;
+        (1. - factor) * self.a.emit(uv, point) + factor * self.b.emit(uv, point)
+    }
+
+    fn emit_directional(&self, r_in: &Ray, hit: &RaycastHit) -> Vec3 {
+        let factor = self.factor.sample_hit(hit).x;
+        (1. - factor) * self.a.emit_directional(r_in, hit)
+            + factor * self.b.emit_directional(r_in, hit)
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, hit: &RaycastHit) -> Vec3 {
+        let factor = self.factor.sample_hit(hit).x;
+        (1. - factor) * self.a.eval(wo, wi, hit) + factor * self.b.eval(wo, wi, hit)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, hit: &RaycastHit) -> f32 {
+        let factor = self.factor.sample_hit(hit).x;
+        (1. - factor) * self.a.pdf(wo, wi, hit) + factor * self.b.pdf(wo, wi, hit)
+    }
+
+    fn emission_bounds(&self) -> Option<EmissionBounds> {
+        let (a, b) = (self.a.emission_bounds()?, self.b.emission_bounds()?);
+        Some(EmissionBounds {
+            max: a.max.max_by_component(b.max),
+            one_sided: a.one_sided && b.one_sided,
+        })
+    }
+}
+
+/// A diffuse substrate with a glossy dielectric coating, like most plastics and painted surfaces.
+/// Light is specularly reflected by the coating with a probability given by the Fresnel
+/// reflectance (Schlick's approximation), and otherwise scattered diffusely using the albedo.
+#[derive(Serialize, Deserialize)]
+pub struct PlasticMat {
+    albedo: Box<dyn Texture + Sync>,
+    ref_idx: f32,
+    roughness: f32,
+}
+
+impl PlasticMat {
+    /// Creates a new plastic material with a given albedo texture, index of refraction for the
+    /// coating, and roughness for the specular reflection.
+    pub fn new<T: Texture + Sync + 'static>(albedo: T, ref_idx: f32, roughness: f32) -> PlasticMat {
+        PlasticMat {
+            albedo: Box::new(albedo),
+            ref_idx,
+            roughness,
+        }
+    }
+
+    /// Creates a new plastic material with a given albedo color. The coating has an index of
+    /// refraction of 1.5, and is perfectly smooth.
+    /// ```
+    /// use firework::material::PlasticMat;
+    /// use ultraviolet::Vec3;
+    ///
+    /// let red_plastic = PlasticMat::with_color(Vec3::new(0.8, 0.1, 0.1));
+    /// ```
+    pub fn with_color(albedo: Vec3) -> PlasticMat {
+        PlasticMat::new(ConstantTexture::new(albedo), 1.5, 0.)
+    }
+}
+
+#[typetag::serde]
+impl Material for PlasticMat {
+    fn scatter(&self, r_in: &Ray, hit: &RaycastHit, rand: &mut LcRng) -> Option<ScatterResult> {
+        let cosine = (-r_in.direction().dot(hit.normal) / r_in.direction().mag()).max(0.);
+        if rand.rand_f32() < schlick(cosine, self.ref_idx) {
+            let reflected = reflect(r_in.direction(), &hit.normal);
+            let scattered = Ray::new(
+                hit.point,
+                reflected + self.roughness * random_in_unit_sphere(rand),
+            );
+            if scattered.direction().dot(hit.normal) > 0. {
+                return Some(ScatterResult {
+                    scattered,
+                    attenuation: Vec3::one(),
+                    specular: true,
+                });
+            }
+        }
+
+        let target = hit.point + hit.normal.normalized() + random_in_unit_sphere(rand);
+        Some(ScatterResult {
+            scattered: Ray::new(hit.point, target - hit.point),
+            attenuation: self.albedo.sample_hit(hit),
+            specular: false,
+        })
+    }
+
+    // Only the diffuse substrate can be explicitly sampled, the coating is treated as specular.
+    fn eval(&self, wo: Vec3, wi: Vec3, hit: &RaycastHit) -> Vec3 {
+        let n = hit.normal.normalized();
+        let specular = schlick(wo.dot(n).max(0.), self.ref_idx);
+        (1. - specular) * self.albedo.sample_hit(hit) * diffuse_pdf(wi, n)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, hit: &RaycastHit) -> f32 {
+        let n = hit.normal.normalized();
+        let specular = schlick(wo.dot(n).max(0.), self.ref_idx);
+        (1. - specular) * diffuse_pdf(wi, n)
+    }
+}
+
+/// A layered car paint material: a diffuse base coat sprinkled with small metallic flakes, under a
+/// glossy clear coat.
+/// The flakes are generated procedurally by dividing space into cells of size `flake_size`, and
+/// placing a randomly oriented flake in a fraction `flake_density` of those cells.
+#[derive(Serialize, Deserialize)]
+pub struct CarPaintMat {
+    base_color: Box<dyn Texture + Sync>,
+    flake_color: Vec3,
+    flake_density: f32,
+    flake_size: f32,
+    /// How far the flake normals are tilted away from the surface normal
+    flake_roughness: f32,
+    clearcoat_ior: f32,
+}
+
+impl CarPaintMat {
+    /// Creates a new car paint with the given base color and flake color. The flakes cover 30%
+    /// of the surface, are 0.01 units across, and the clear coat has an index of refraction of 1.5
+    /// ```
+    /// use firework::material::CarPaintMat;
+    /// use ultraviolet::Vec3;
+    ///
+    /// let paint = CarPaintMat::new(Vec3::new(0.5, 0.02, 0.02), Vec3::new(0.9, 0.6, 0.5))
+    ///     .flakes(0.5, 0.005)
+    ///     .flake_roughness(0.4);
+    /// ```
+    pub fn new(base_color: Vec3, flake_color: Vec3) -> CarPaintMat {
+        CarPaintMat::with_texture(ConstantTexture::new(base_color), flake_color)
+    }
+
+    /// Creates a new car paint where the base color is given by a texture
+    pub fn with_texture<T: Texture + Sync + 'static>(base_color: T, flake_color: Vec3) -> Self {
+        CarPaintMat {
+            base_color: Box::new(base_color),
+            flake_color,
+            flake_density: 0.3,
+            flake_size: 0.01,
+            flake_roughness: 0.3,
+            clearcoat_ior: 1.5,
+        }
+    }
+
+    /// Sets the fraction of the surface covered by flakes (from 0 to 1), and the size of each
+    /// flake
+    pub fn flakes(mut self, density: f32, size: f32) -> CarPaintMat {
+        self.flake_density = density;
+        self.flake_size = size;
+        self
+    }
+
+    /// Sets how much the flakes are tilted relative to the surface
+    pub fn flake_roughness(mut self, roughness: f32) -> CarPaintMat {
+        self.flake_roughness = roughness;
+        self
+    }
+
+    /// Sets the index of refraction of the clear coat
+    pub fn clearcoat_ior(mut self, ior: f32) -> CarPaintMat {
+        self.clearcoat_ior = ior;
+        self
+    }
+
+    /// Returns the normal of the flake at the given point, if there is one
+    fn flake_normal(&self, point: Vec3, normal: Vec3) -> Option<Vec3> {
+        let cell = point / self.flake_size;
+        let (x, y, z) = (
+            cell.x.floor() as i32,
+            cell.y.floor() as i32,
+            cell.z.floor() as i32,
+        );
+        if lattice_hash(x, y, z) as f32 / 256. >= self.flake_density {
+            return None;
+        }
+        let tilt = Vec3::new(
+            lattice_hash(x + 17, y, z) as f32 / 255. - 0.5,
+            lattice_hash(x, y + 17, z) as f32 / 255. - 0.5,
+            lattice_hash(x, y, z + 17) as f32 / 255. - 0.5,
+        );
+        Some((normal + 2. * self.flake_roughness * tilt).normalized())
+    }
+}
+
+#[typetag::serde]
+impl Material for CarPaintMat {
+    fn scatter(&self, r_in: &Ray, hit: &RaycastHit, rand: &mut LcRng) -> Option<ScatterResult> {
+        let normal = hit.normal.normalized();
+
+        // Clear coat
+        let cosine = (-r_in.direction().dot(normal) / r_in.direction().mag()).max(0.);
+        if rand.rand_f32() < schlick(cosine, self.clearcoat_ior) {
+            return Some(ScatterResult {
+                scattered: Ray::new(hit.point, reflect(r_in.direction(), &normal)),
+                attenuation: Vec3::one(),
+                specular: true,
+            });
+        }
+
+        // Metallic flakes
+        if let Some(flake_normal) = self.flake_normal(hit.point, normal) {
+            let reflected = reflect(r_in.direction(), &flake_normal);
+            if reflected.dot(normal) > 0. {
+                return Some(ScatterResult {
+                    scattered: Ray::new(hit.point, reflected),
+                    attenuation: self.flake_color,
+                    specular: true,
+                });
+            }
+        }
+
+        // Base coat
+        let target = hit.point + normal + random_unit_vector(rand);
+        Some(ScatterResult {
+            scattered: Ray::new(hit.point, target - hit.point),
+            attenuation: self.base_color.sample_hit(hit),
+            specular: false,
+        })
+    }
+}
+
+/// A fabric material, like velvet or carpet. Fibers sticking out of the surface scatter extra
+/// light back at grazing angles, so the silhouette of the object appears brighter (the "sheen").
+/// This is modeled as a diffuse surface whose color blends from the albedo towards the sheen color
+/// by `(1 - cos(theta))^exponent`, where theta is the angle between the view direction and the
+/// normal, so it reflects no more light than the brighter of the two.
+#[derive(Serialize, Deserialize)]
+pub struct SheenMat {
+    albedo: Box<dyn Texture + Sync>,
+    sheen: Vec3,
+    exponent: f32,
+}
+
+impl SheenMat {
+    /// Creates a new sheen material with a diffuse albedo texture, sheen color and sheen exponent.
+    /// Higher exponents concentrate the sheen closer to the silhouette.
+    pub fn new<T: Texture + Sync + 'static>(albedo: T, sheen: Vec3, exponent: f32) -> SheenMat {
+        SheenMat {
+            albedo: Box::new(albedo),
+            sheen,
+            exponent,
+        }
+    }
+
+    /// Creates a new velvet-like material with the given color, where the sheen is a lighter
+    /// version of the same color.
+    /// ```
+    /// use firework::material::SheenMat;
+    /// use ultraviolet::Vec3;
+    ///
+    /// let red_velvet = SheenMat::with_color(Vec3::new(0.5, 0.02, 0.05));
+    /// ```
+    pub fn with_color(albedo: Vec3) -> SheenMat {
+        let sheen = 0.5 * (albedo + Vec3::one());
+        SheenMat::new(ConstantTexture::new(albedo), sheen, 5.)
+    }
+
+    fn attenuation(&self, wo: Vec3, hit: &RaycastHit) -> Vec3 {
+        let cosine = wo.dot(hit.normal.normalized()).abs().min(1.);
+        let sheen = (1. - cosine).powf(self.exponent);
+        self.albedo.sample_hit(hit) * (1. - sheen) + self.sheen * sheen
+    }
+}
+
+#[typetag::serde]
+impl Material for SheenMat {
+    fn scatter(&self, r_in: &Ray, hit: &RaycastHit, rand: &mut LcRng) -> Option<ScatterResult> {
+        let target = hit.point + hit.normal.normalized() + random_unit_vector(rand);
+        Some(ScatterResult {
+            scattered: Ray::new(hit.point, target - hit.point),
+            attenuation: self.attenuation(-r_in.direction().normalized(), hit),
+            specular: false,
+        })
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, hit: &RaycastHit) -> Vec3 {
+        self.attenuation(wo, hit) * wi.dot(hit.normal.normalized()).max(0.) / PI
+    }
+
+    fn pdf(&self, _wo: Vec3, wi: Vec3, hit: &RaycastHit) -> f32 {
+        wi.dot(hit.normal.normalized()).max(0.) / PI
+    }
+}
+
+/// A stylized, non-photorealistic material. The diffuse response to a fixed key light is quantized
+/// into a number of flat bands, blending from `shadow_color` to `color`. The material does not
+/// scatter light, so it is unaffected by the rest of the scene's lighting.
+/// Combine this with `Renderer::outline` for a cel-shaded look.
+#[derive(Serialize, Deserialize)]
+pub struct ToonMat {
+    color: Box<dyn Texture + Sync>,
+    shadow_color: Vec3,
+    bands: usize,
+    light_dir: Vec3,
+}
+
+impl ToonMat {
+    /// Creates a new toon material with the given color and number of bands. By default, the key
+    /// light comes from above and slightly in front, and the shadows are black.
+    /// ```
+    /// use firework::material::ToonMat;
+    /// use ultraviolet::Vec3;
+    ///
+    /// let toon = ToonMat::new(Vec3::new(0.9, 0.4, 0.1), 3)
+    ///     .shadow_color(Vec3::new(0.2, 0.05, 0.1))
+    ///     .light_dir(Vec3::new(1., 1., -1.));
+    /// ```
+    pub fn new(color: Vec3, bands: usize) -> ToonMat {
+        ToonMat::with_texture(ConstantTexture::new(color), bands)
+    }
+
+    /// Creates a new toon material where the lit color is given by a texture
+    pub fn with_texture<T: Texture + Sync + 'static>(color: T, bands: usize) -> ToonMat {
+        ToonMat {
+            color: Box::new(color),
+            shadow_color: Vec3::zero(),
+            bands: bands.max(1),
+            light_dir: Vec3::new(0.3, 1., -0.5).normalized(),
+        }
+    }
+
+    /// Sets the color of the darkest band
+    pub fn shadow_color(mut self, shadow_color: Vec3) -> ToonMat {
+        self.shadow_color = shadow_color;
+        self
+    }
+
+    /// Sets the direction towards the key light
+    pub fn light_dir(mut self, light_dir: Vec3) -> ToonMat {
+        self.light_dir = light_dir.normalized();
+        self
+    }
+}
+
+#[typetag::serde]
+impl Material for ToonMat {
+    fn scatter(&self, _r_in: &Ray, _hit: &RaycastHit, _rand: &mut LcRng) -> Option<ScatterResult> {
+        None
+    }
+
+    fn emit_directional(&self, _r_in: &Ray, hit: &RaycastHit) -> Vec3 {
+        let intensity = hit.normal.normalized().dot(self.light_dir).max(0.);
+        let band = ((intensity * self.bands as f32).ceil() / self.bands as f32).min(1.);
+        let color = self.color.sample_hit(hit);
+        (1. - band) * self.shadow_color + band * color
+    }
+}
+
+/// The classic Blinn-Phong material, a diffuse lobe plus a specular lobe around the half vector
+/// with a given exponent. This is mostly useful for matching older assets, like the `Kd`, `Ks`
+/// and `Ns` parameters in MTL files.
+#[derive(Serialize, Deserialize)]
+pub struct BlinnPhongMat {
+    diffuse: Box<dyn Texture + Sync>,
+    specular: Vec3,
+    exponent: f32,
+}
+
+impl BlinnPhongMat {
+    /// Creates a new Blinn-Phong material with a diffuse texture, specular color, and specular
+    /// exponent.
+    pub fn new<T: Texture + Sync + 'static>(diffuse: T, specular: Vec3, exponent: f32) -> Self {
+        BlinnPhongMat {
+            diffuse: Box::new(diffuse),
+            specular,
+            exponent,
+        }
+    }
+
+    /// Creates a new Blinn-Phong material with a diffuse color, specular color, and specular
+    /// exponent.
+    /// ```
+    /// use firework::material::BlinnPhongMat;
+    /// use ultraviolet::Vec3;
+    ///
+    /// let shiny = BlinnPhongMat::with_color(Vec3::new(0.6, 0.1, 0.1), Vec3::broadcast(0.3), 64.);
+    /// ```
+    pub fn with_color(diffuse: Vec3, specular: Vec3, exponent: f32) -> BlinnPhongMat {
+        BlinnPhongMat::new(ConstantTexture::new(diffuse), specular, exponent)
+    }
+
+    /// Creates a new Blinn-Phong material from the `Kd`, `Ks`, and `Ns` parameters of a material
+    /// loaded from an MTL file.
+    pub fn from_mtl(mtl: &tobj::Material) -> BlinnPhongMat {
+        BlinnPhongMat::with_color(mtl.diffuse.into(), mtl.specular.into(), mtl.shininess)
+    }
+
+    /// The probability of sampling the specular lobe rather than the diffuse one
+    fn specular_weight(&self, hit: &RaycastHit) -> f32 {
+        let luminance = |c: Vec3| c.dot(Vec3::new(0.2126, 0.7152, 0.0722));
+        let diffuse = luminance(self.diffuse.sample_hit(hit));
+        let specular = luminance(self.specular);
+        if diffuse + specular > 0. {
+            specular / (diffuse + specular)
+        } else {
+            0.
+        }
+    }
+}
+
+#[typetag::serde]
+impl Material for BlinnPhongMat {
+    fn scatter(&self, r_in: &Ray, hit: &RaycastHit, rand: &mut LcRng) -> Option<ScatterResult> {
+        let n = hit.normal.normalized();
+        let wo = -r_in.direction().normalized();
+
+        let scattered = if rand.rand_f32() < self.specular_weight(hit) {
+            // Sample a half vector around the normal, proportional to cos^exponent
+            let cos_theta = rand.rand_f32().powf(1. / (self.exponent + 1.));
+            let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+            let phi = 2. * PI * rand.rand_f32();
+            let frame = CoordinateSystem::from_one_vec(&n);
+            let h =
+                sin_theta * phi.cos() * frame.v2 + sin_theta * phi.sin() * frame.v3 + cos_theta * n;
+            reflect(&-wo, &h)
+        } else {
+            n + random_unit_vector(rand)
+        };
+
+        let wi = scattered.normalized();
+        let pdf = self.pdf(wo, wi, hit);
+        if wi.dot(n) <= 0. || pdf <= 0. {
+            return None;
+        }
+        Some(ScatterResult {
+            scattered: Ray::new(hit.point, scattered),
+            attenuation: self.eval(wo, wi, hit) / pdf,
+            specular: false,
+        })
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, hit: &RaycastHit) -> Vec3 {
+        let n = hit.normal.normalized();
+        let cos_i = wi.dot(n).max(0.);
+        let h = (wo + wi).normalized();
+        let normalization = (self.exponent + 2.) / (2. * PI);
+        let specular = self.specular * normalization * h.dot(n).max(0.).powf(self.exponent);
+        (self.diffuse.sample_hit(hit) / PI + specular) * cos_i
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, hit: &RaycastHit) -> f32 {
+        let n = hit.normal.normalized();
+        let h = (wo + wi).normalized();
+        let cos_h = h.dot(n).max(0.);
+        let specular_pdf = (self.exponent + 1.) / (2. * PI) * cos_h.powf(self.exponent)
+            / (4. * wo.dot(h).abs().max(1e-4));
+        let diffuse_pdf = wi.dot(n).max(0.) / PI;
+        let weight = self.specular_weight(hit);
+        weight * specular_pdf + (1. - weight) * diffuse_pdf
+    }
+}
+
+/// A material whose parameters are computed by `ShaderGraph`s at each hit, so complex looks can
+/// be authored in scene files.
+/// Light is specularly reflected with a probability given by `specular` (blurred by `roughness`),
+/// and otherwise scattered diffusely using `albedo`. `emission` is added on top.
+#[derive(Serialize, Deserialize)]
+pub struct GraphMat {
+    albedo: ShaderGraph,
+    #[serde(default)]
+    specular: Option<ShaderGraph>,
+    #[serde(default)]
+    roughness: Option<ShaderGraph>,
+    #[serde(default)]
+    emission: Option<ShaderGraph>,
+}
+
+impl GraphMat {
+    /// Creates a new, purely diffuse, `GraphMat` with the given albedo graph
+    pub fn new(albedo: ShaderGraph) -> GraphMat {
+        GraphMat {
+            albedo,
+            specular: None,
+            roughness: None,
+            emission: None,
+        }
+    }
+
+    /// Sets the graph for the probability of specular reflection, and the graph for the roughness
+    /// of those reflections
+    pub fn specular(mut self, specular: ShaderGraph, roughness: ShaderGraph) -> GraphMat {
+        self.specular = Some(specular);
+        self.roughness = Some(roughness);
+        self
+    }
+
+    /// Sets the graph for the emitted light
+    pub fn emission(mut self, emission: ShaderGraph) -> GraphMat {
+        self.emission = Some(emission);
+        self
+    }
+
+    fn context(r_in: &Ray, hit: &RaycastHit) -> ShadeContext {
+        ShadeContext {
+            uv: hit.uv,
+            point: hit.point,
+            normal: hit.normal.normalized(),
+            view: -r_in.direction().normalized(),
+        }
+    }
+}
+
+#[typetag::serde]
+impl Material for GraphMat {
+    fn scatter(&self, r_in: &Ray, hit: &RaycastHit, rand: &mut LcRng) -> Option<ScatterResult> {
+        let ctx = GraphMat::context(r_in, hit);
+
+        if let Some(specular) = &self.specular {
+            if rand.rand_f32() < specular.eval(&ctx).x {
+                let roughness = self.roughness.as_ref().map_or(0., |r| r.eval(&ctx).x);
+                let reflected = reflect(r_in.direction(), &ctx.normal);
+                let scattered = Ray::new(
+                    hit.point,
+                    reflected + roughness * random_in_unit_sphere(rand),
+                );
+                return if scattered.direction().dot(ctx.normal) > 0. {
+                    Some(ScatterResult {
+                        scattered,
+                        attenuation: Vec3::one(),
+                        specular: true,
+                    })
+                } else {
+                    None
+                };
+            }
+        }
+
+        let target = hit.point + ctx.normal + random_unit_vector(rand);
+        Some(ScatterResult {
+            scattered: Ray::new(hit.point, target - hit.point),
+            attenuation: self.albedo.eval(&ctx),
+            specular: false,
+        })
+    }
+
+    fn emit_directional(&self, r_in: &Ray, hit: &RaycastHit) -> Vec3 {
+        match &self.emission {
+            Some(emission) => emission.eval(&GraphMat::context(r_in, hit)),
+            None => Vec3::zero(),
+        }
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let graphs = [
+            ("albedo", Some(&self.albedo)),
+            ("specular", self.specular.as_ref()),
+            ("roughness", self.roughness.as_ref()),
+            ("emission", self.emission.as_ref()),
+        ];
+        graphs
+            .iter()
+            .filter_map(|(name, graph)| graph.map(|graph| (name, graph)))
+            .flat_map(|(name, graph)| {
+                graph
+                    .problems()
+                    .into_iter()
+                    .map(move |problem| format!("{}: {}", name, problem))
+            })
+            .collect()
+    }
+}
+
+/// A portal, which teleports rays that hit it. Rays continue from the point given by applying the
+/// relative transform (rotation, then translation) to the hit point, with their direction rotated
+/// by the same rotation.
+/// Put this material on one surface, and make sure the transform maps it onto a paired surface
+/// somewhere else in the scene (which can use another `PortalMat` with the inverse transform, so
+/// the portal works in both directions).
+#[derive(Serialize, Deserialize)]
+pub struct PortalMat {
+    translation: Vec3,
+    #[serde(with = "crate::serde_compat::Rotor3Def")]
+    rotation: Rotor3,
+    /// Multiplied with the light passing through the portal
+    tint: Vec3,
+}
+
+impl PortalMat {
+    /// Creates a new portal with the given relative transform
+    pub fn new(translation: Vec3, rotation: Rotor3) -> PortalMat {
+        PortalMat {
+            translation,
+            rotation,
+            tint: Vec3::one(),
+        }
+    }
+
+    /// Creates a portal from a surface with the given position and rotation to another surface.
+    /// These should be the same as the position and rotation of the two `RenderObject`s.
+    /// ```
+    /// use firework::material::PortalMat;
+    /// use ultraviolet::{Rotor3, Vec3};
+    ///
+    /// let portal = PortalMat::between(
+    ///     Vec3::new(0., 0., 5.),
+    ///     Rotor3::identity(),
+    ///     Vec3::new(10., 0., 0.),
+    ///     Rotor3::from_rotation_xz(90f32.to_radians()),
+    /// );
+    /// ```
+    pub fn between(
+        from_position: Vec3,
+        from_rotation: Rotor3,
+        to_position: Vec3,
+        to_rotation: Rotor3,
+    ) -> PortalMat {
+        let rotation = to_rotation * from_rotation.reversed();
+        let mut rotated_from = from_position;
+        rotation.rotate_vec(&mut rotated_from);
+        PortalMat::new(to_position - rotated_from, rotation)
+    }
+
+    /// Sets the color multiplied with the light passing through the portal
+    pub fn tint(mut self, tint: Vec3) -> PortalMat {
+        self.tint = tint;
+        self
+    }
+}
+
+#[typetag::serde]
+impl Material for PortalMat {
+    fn scatter(&self, r_in: &Ray, hit: &RaycastHit, _rand: &mut LcRng) -> Option<ScatterResult> {
+        let mut origin = hit.point;
+        let mut direction = *r_in.direction();
+        self.rotation.rotate_vec(&mut origin);
+        self.rotation.rotate_vec(&mut direction);
+        Some(ScatterResult {
+            scattered: Ray::new(origin + self.translation, direction),
+            attenuation: self.tint,
+            specular: true,
         })
     }
 }
@@ -23,6 +23,16 @@ impl Cone {
     }
 }
 
+impl crate::serde_compat::MapMaterials for Cone {
+    fn map_materials(&mut self, f: &mut dyn FnMut(MaterialIdx) -> MaterialIdx) {
+        self.material = f(self.material);
+    }
+
+    fn materials(&self) -> Vec<MaterialIdx> {
+        vec![self.material]
+    }
+}
+
 impl Hitable for Cone {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32, _rand: &mut LcRng) -> Option<RaycastHit> {
         let o = *r.origin();
@@ -75,6 +85,13 @@ impl Hitable for Cone {
                     normal: dpdv.cross(dpdu).normalized(),
                     material: self.material,
                     uv: Vec2::new(u, v),
+                    tangent: Some(2. * std::f32::consts::PI * dpdu),
+                    footprint: 0.,
+                    time: 0.,
+                    priority: 0,
+                    outside_ior: 1.,
+                    light: None,
+                    light_group: None,
                 });
             };
 
@@ -37,6 +37,16 @@ impl Cylinder {
     }
 }
 
+impl crate::serde_compat::MapMaterials for Cylinder {
+    fn map_materials(&mut self, f: &mut dyn FnMut(MaterialIdx) -> MaterialIdx) {
+        self.material = f(self.material);
+    }
+
+    fn materials(&self) -> Vec<MaterialIdx> {
+        vec![self.material]
+    }
+}
+
 impl Hitable for Cylinder {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32, _rand: &mut LcRng) -> Option<RaycastHit> {
         let o = r.origin();
@@ -74,6 +84,13 @@ impl Hitable for Cylinder {
                             normal: Vec3::new(point.x / self.radius, 0., point.z / self.radius),
                             material: self.material,
                             uv: Vec2::new(u, v),
+                            tangent: Some(self.max_phi * Vec3::new(-point.z, 0., point.x)),
+                            footprint: 0.,
+                            time: 0.,
+                            priority: 0,
+                            outside_ior: 1.,
+                            light: None,
+                            light_group: None,
                         })
                     } else {
                         None
@@ -1,8 +1,8 @@
 use crate::aabb::AABB;
 use crate::ray::Ray;
-use crate::render::{Hitable, RaycastHit};
+use crate::render::{Hitable, RaycastHit, SurfaceSample};
 use crate::scene::MaterialIdx;
-use tiny_rng::LcRng;
+use tiny_rng::{LcRng, Rand};
 use ultraviolet::{Vec2, Vec3};
 
 /// Creates a disk facing upwards with a given radius.
@@ -36,6 +36,16 @@ impl Disk {
     }
 }
 
+impl crate::serde_compat::MapMaterials for Disk {
+    fn map_materials(&mut self, f: &mut dyn FnMut(MaterialIdx) -> MaterialIdx) {
+        self.material = f(self.material);
+    }
+
+    fn materials(&self) -> Vec<MaterialIdx> {
+        vec![self.material]
+    }
+}
+
 impl Hitable for Disk {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32, _rand: &mut LcRng) -> Option<RaycastHit> {
         // Ignore rays parallel to disk, to avoid divide by zero errors
@@ -79,6 +89,13 @@ impl Hitable for Disk {
             normal: Vec3::unit_y(),
             material: self.material,
             uv: Vec2::new(u, v),
+            tangent: Some(self.phi_max * Vec3::new(-point.z, 0., point.x)),
+            footprint: 0.,
+            time: 0.,
+            priority: 0,
+            outside_ior: 1.,
+            light: None,
+            light_group: None,
         })
     }
 
@@ -88,4 +105,27 @@ impl Hitable for Disk {
             Vec3::new(-self.radius, 0.001, self.radius),
         )
     }
+
+    fn sample_point(&self, origin: Vec3, rand: &mut LcRng) -> Option<SurfaceSample> {
+        // uniform in area, so the radius is distributed like the square root
+        let (r0, r1) = (
+            self.inner_radius * self.inner_radius,
+            self.radius * self.radius,
+        );
+        let r = (r0 + rand.rand_f32() * (r1 - r0)).sqrt();
+        let phi = rand.rand_f32() * self.phi_max;
+        let point = Vec3::new(r * phi.cos(), 0., r * phi.sin());
+        Some(SurfaceSample {
+            point,
+            normal: Vec3::unit_y(),
+            pdf: self.pdf(origin, point),
+        })
+    }
+
+    fn pdf(&self, _origin: Vec3, _point: Vec3) -> f32 {
+        let area = 0.5
+            * self.phi_max
+            * (self.radius * self.radius - self.inner_radius * self.inner_radius);
+        1. / area
+    }
 }
@@ -1,10 +1,11 @@
 use crate::aabb::AABB;
+use crate::bvh::BVHNode;
 use crate::ray::Ray;
-use crate::render::{Hitable, RaycastHit};
+use crate::render::{Hitable, RaycastHit, SurfaceSample};
 use crate::scene::MaterialIdx;
-use crate::util;
+use crate::util::{self, Distribution1D};
 use std::sync::Arc;
-use tiny_rng::LcRng;
+use tiny_rng::{LcRng, Rand};
 use ultraviolet::{Vec2, Vec3};
 
 type TriangleIdx = usize;
@@ -25,7 +26,132 @@ impl crate::serde_compat::AsHitable for TriangleMesh {
     {
         use crate::bvh::Aggregate;
         let arc: Arc<TriangleMesh> = self.into();
-        Box::new(arc.build_bvh())
+        let areas = (0..arc.num_tris())
+            .map(|idx| {
+                let [p0, p1, p2] = arc.get_triangle_verts(idx);
+                0.5 * (p1 - p0).cross(p2 - p0).mag()
+            })
+            .collect();
+        Box::new(MeshAggregate {
+            bvh: arc.build_bvh(),
+            areas: Distribution1D::new(areas),
+            mesh: arc,
+        })
+    }
+}
+
+/// A `TriangleMesh` with a BVH over its triangles, and a distribution over their areas so that
+/// it can be sampled as a light, e.g. for neon signs or softboxes (see `Scene::add_light`)
+struct MeshAggregate {
+    bvh: BVHNode<Triangle>,
+    mesh: Arc<TriangleMesh>,
+    areas: Distribution1D,
+}
+
+impl Hitable for MeshAggregate {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rand: &mut LcRng) -> Option<RaycastHit> {
+        self.bvh.hit(r, t_min, t_max, rand)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.bvh.bounding_box()
+    }
+
+    /// Chooses a triangle in proportion to its area, and then a point uniformly within it
+    fn sample_point(&self, origin: Vec3, rand: &mut LcRng) -> Option<SurfaceSample> {
+        if self.areas.integral() <= 0. {
+            return None;
+        }
+        let (_, _, idx) = self.areas.sample(rand.rand_f32());
+        let [p0, p1, p2] = self.mesh.get_triangle_verts(idx);
+        let su = rand.rand_f32().sqrt();
+        let (b0, b1) = (1. - su, rand.rand_f32() * su);
+        let point = b0 * p0 + b1 * p1 + (1. - b0 - b1) * p2;
+        Some(SurfaceSample {
+            point,
+            // the same side as the geometric normal of a hit
+            normal: (p0 - p2).cross(p1 - p2).normalized(),
+            pdf: self.pdf(origin, point),
+        })
+    }
+
+    fn pdf(&self, _origin: Vec3, _point: Vec3) -> f32 {
+        1. / (self.areas.integral() * self.areas.len() as f32)
+    }
+}
+
+impl crate::serde_compat::MapMaterials for TriangleMesh {
+    fn map_materials(&mut self, f: &mut dyn FnMut(MaterialIdx) -> MaterialIdx) {
+        self.material = f(self.material);
+    }
+
+    fn materials(&self) -> Vec<MaterialIdx> {
+        vec![self.material]
+    }
+}
+
+impl crate::serde_compat::InspectShape for TriangleMesh {
+    fn local_bounds(&self) -> AABB {
+        let mut verts = self.verts.iter();
+        match verts.next() {
+            Some(&first) => verts.fold(AABB::from_two_points(first, first), |aabb, &vert| {
+                aabb.expand_to_point(vert)
+            }),
+            None => AABB::new(Vec3::zero(), Vec3::zero()),
+        }
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let num_verts = self.num_verts();
+        if !self.indicies.len().is_multiple_of(3) {
+            problems.push(format!(
+                "the mesh has {} indices, which isn't a multiple of 3",
+                self.indicies.len()
+            ));
+        }
+        if let Some(&idx) = self.indicies.iter().find(|&&idx| idx >= num_verts) {
+            problems.push(format!(
+                "the mesh refers to vertex {}, but only has {} vertices",
+                idx, num_verts
+            ));
+        }
+        if let Some(normals) = &self.normals {
+            if normals.len() != num_verts {
+                problems.push(format!(
+                    "the mesh has {} normals for {} vertices",
+                    normals.len(),
+                    num_verts
+                ));
+            }
+        }
+        if let Some(uvs) = &self.uvs {
+            if uvs.len() != num_verts {
+                problems.push(format!(
+                    "the mesh has {} uvs for {} vertices",
+                    uvs.len(),
+                    num_verts
+                ));
+            }
+        }
+        problems
+    }
+
+    fn triangle_count(&self) -> usize {
+        self.num_tris()
+    }
+
+    fn memory_size(&self) -> usize {
+        use std::mem::size_of;
+        let normals = self.normals.as_ref().map_or(0, Vec::len);
+        let uvs = self.uvs.as_ref().map_or(0, Vec::len);
+        // the BVH has about one leaf and one inner node for every triangle
+        let bvh = self.num_tris() * (size_of::<Triangle>() + 2 * size_of::<BVHNode<Triangle>>());
+        (self.verts.len() + normals) * size_of::<Vec3>()
+            + uvs * size_of::<Vec2>()
+            + self.indicies.len() * size_of::<usize>()
+            + self.num_tris() * size_of::<f32>()
+            + bvh
     }
 }
 
@@ -209,12 +335,31 @@ impl Hitable for Triangle {
             (p0 - p2).cross(p1 - p2)
         };
 
+        // Solve for dp/du using the uv coordinates of the verticies. See The PBR Book Section 3.6.2
+        let tangent = {
+            let duv02 = uvs[0] - uvs[2];
+            let duv12 = uvs[1] - uvs[2];
+            let det = duv02.x * duv12.y - duv02.y * duv12.x;
+            if det.abs() < 1e-8 {
+                None
+            } else {
+                Some((duv12.y * (p0 - p2) - duv02.y * (p1 - p2)) / det)
+            }
+        };
+
         Some(RaycastHit {
             t,
             point,
             normal,
             material: self.mesh.material,
             uv,
+            tangent,
+            footprint: 0.,
+            time: 0.,
+            priority: 0,
+            outside_ior: 1.,
+            light: None,
+            light_group: None,
         })
     }
 
@@ -5,6 +5,7 @@ mod mesh;
 mod rect;
 mod rect3d;
 mod sphere;
+mod subscene;
 mod volume;
 
 pub use cone::Cone;
@@ -13,8 +14,10 @@ pub use disk::Disk;
 pub use mesh::{Triangle, TriangleMesh};
 pub use rect::{XYRect, XZRect, YZRect};
 pub use rect3d::Rect3d;
+pub(crate) use sphere::sphere_uv;
 pub use sphere::Sphere;
-pub use volume::ConstantMedium;
+pub use subscene::SubScene;
+pub use volume::{ConstantMedium, HeterogeneousMedium};
 
 pub(crate) fn solve_quadratic(a: f32, b: f32, c: f32) -> [Option<f32>; 2] {
     let disc = b * b - 4. * a * c;
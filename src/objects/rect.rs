@@ -1,10 +1,10 @@
 use crate::aabb::AABB;
 use crate::ray::Ray;
-use crate::render::{Hitable, RaycastHit};
+use crate::render::{Hitable, RaycastHit, SurfaceSample};
 use crate::scene::MaterialIdx;
-use crate::util::Axis;
-use tiny_rng::LcRng;
-use ultraviolet::Vec2;
+use crate::util::{Axis, SphericalRect};
+use tiny_rng::{LcRng, Rand};
+use ultraviolet::{Vec2, Vec3};
 
 pub type XYRect = AARect<{ Axis::X }, { Axis::Y }>;
 pub type YZRect = AARect<{ Axis::Y }, { Axis::Z }>;
@@ -42,6 +42,37 @@ impl<const A1: Axis, const A2: Axis> AARect<{ A1 }, { A2 }> {
         self.flip_normal = true;
         self
     }
+
+    /// The directions the rectangle covers as seen from `origin`, if they're worth sampling
+    /// instead of its area (and precise enough to)
+    fn spherical_rect(&self, origin: Vec3) -> Option<SphericalRect> {
+        let mut corner = [0f32; 3];
+        corner[A1 as usize] = self.min.x;
+        corner[A2 as usize] = self.min.y;
+        corner[Axis::other(A1, A2) as usize] = self.k;
+        let size = self.max - self.min;
+        let spherical = SphericalRect::new(
+            origin,
+            corner.into(),
+            size.x * A1.unit_vec(),
+            size.y * A2.unit_vec(),
+        );
+        if spherical.solid_angle > 1e-3 {
+            Some(spherical)
+        } else {
+            None
+        }
+    }
+}
+
+impl<const A1: Axis, const A2: Axis> crate::serde_compat::MapMaterials for AARect<{ A1 }, { A2 }> {
+    fn map_materials(&mut self, f: &mut dyn FnMut(MaterialIdx) -> MaterialIdx) {
+        self.material = f(self.material);
+    }
+
+    fn materials(&self) -> Vec<MaterialIdx> {
+        vec![self.material]
+    }
 }
 
 impl<const A1: Axis, const A2: Axis> Hitable for AARect<{ A1 }, { A2 }> {
@@ -69,6 +100,13 @@ impl<const A1: Axis, const A2: Axis> Hitable for AARect<{ A1 }, { A2 }> {
                 (point[A1 as usize] - self.min.x) / (self.max.x - self.min.x),
                 (point[A2 as usize] - self.min.y) / (self.max.y - self.min.y),
             ),
+            tangent: Some((self.max.x - self.min.x) * A1.unit_vec()),
+            footprint: 0.,
+            time: 0.,
+            priority: 0,
+            outside_ior: 1.,
+            light: None,
+            light_group: None,
         })
     }
 
@@ -83,6 +121,43 @@ impl<const A1: Axis, const A2: Axis> Hitable for AARect<{ A1 }, { A2 }> {
         max[Axis::other(A1, A2) as usize] = self.k + 0.01;
         AABB::new(min.into(), max.into())
     }
+
+    /// Samples the directions the rectangle covers as seen from `origin` uniformly, unless it
+    /// covers so little that the area is sampled almost the same way
+    fn sample_point(&self, origin: Vec3, rand: &mut LcRng) -> Option<SurfaceSample> {
+        let u = Vec2::new(rand.rand_f32(), rand.rand_f32());
+        let point = match self.spherical_rect(origin) {
+            Some(spherical) => spherical.sample(u),
+            None => {
+                let mut point = [0f32; 3];
+                point[A1 as usize] = self.min.x + u.x * (self.max.x - self.min.x);
+                point[A2 as usize] = self.min.y + u.y * (self.max.y - self.min.y);
+                point[Axis::other(A1, A2) as usize] = self.k;
+                point.into()
+            }
+        };
+        let normal = Axis::other(A1, A2).unit_vec();
+        Some(SurfaceSample {
+            point,
+            normal: if self.flip_normal { -normal } else { normal },
+            pdf: self.pdf(origin, point),
+        })
+    }
+
+    fn pdf(&self, origin: Vec3, point: Vec3) -> f32 {
+        match self.spherical_rect(origin) {
+            Some(spherical) => {
+                // convert the uniform density over the directions to be with respect to area
+                let to_point = point - origin;
+                let cos_light = to_point[Axis::other(A1, A2) as usize].abs() / to_point.mag();
+                cos_light / (spherical.solid_angle * to_point.mag_sq())
+            }
+            None => {
+                let size = self.max - self.min;
+                1. / (size.x * size.y)
+            }
+        }
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -110,6 +185,24 @@ impl From<YZRect> for Rect {
     }
 }
 
+impl crate::serde_compat::MapMaterials for Rect {
+    fn map_materials(&mut self, f: &mut dyn FnMut(MaterialIdx) -> MaterialIdx) {
+        match self {
+            Rect::XY(rect) => rect.map_materials(f),
+            Rect::XZ(rect) => rect.map_materials(f),
+            Rect::YZ(rect) => rect.map_materials(f),
+        }
+    }
+
+    fn materials(&self) -> Vec<MaterialIdx> {
+        match self {
+            Rect::XY(rect) => rect.materials(),
+            Rect::XZ(rect) => rect.materials(),
+            Rect::YZ(rect) => rect.materials(),
+        }
+    }
+}
+
 impl Hitable for Rect {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rand: &mut LcRng) -> Option<RaycastHit> {
         match self {
@@ -126,4 +219,20 @@ impl Hitable for Rect {
             Rect::YZ(rect) => rect.bounding_box(),
         }
     }
+
+    fn sample_point(&self, origin: Vec3, rand: &mut LcRng) -> Option<SurfaceSample> {
+        match self {
+            Rect::XY(rect) => rect.sample_point(origin, rand),
+            Rect::XZ(rect) => rect.sample_point(origin, rand),
+            Rect::YZ(rect) => rect.sample_point(origin, rand),
+        }
+    }
+
+    fn pdf(&self, origin: Vec3, point: Vec3) -> f32 {
+        match self {
+            Rect::XY(rect) => rect.pdf(origin, point),
+            Rect::XZ(rect) => rect.pdf(origin, point),
+            Rect::YZ(rect) => rect.pdf(origin, point),
+        }
+    }
 }
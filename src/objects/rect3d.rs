@@ -15,7 +15,7 @@ pub struct Rect3d {
 
 impl Rect3d {
     // TODO: Remove the position here, it should be handled by `RenderObject`
-    fn new(pos: Vec3, size: Vec3, material: MaterialIdx) -> Rect3d {
+    pub(crate) fn new(pos: Vec3, size: Vec3, material: MaterialIdx) -> Rect3d {
         let faces: Vec<Rect> = vec![
             XYRect::new(
                 pos.x,
@@ -85,6 +85,21 @@ impl Rect3d {
     }
 }
 
+impl crate::serde_compat::MapMaterials for Rect3d {
+    fn map_materials(&mut self, f: &mut dyn FnMut(MaterialIdx) -> MaterialIdx) {
+        for face in &mut self.faces {
+            face.map_materials(f);
+        }
+    }
+
+    fn materials(&self) -> Vec<MaterialIdx> {
+        self.faces
+            .iter()
+            .flat_map(|face| face.materials())
+            .collect()
+    }
+}
+
 impl Hitable for Rect3d {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rand: &mut LcRng) -> Option<RaycastHit> {
         let mut last_hit = None;
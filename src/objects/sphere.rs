@@ -1,9 +1,11 @@
 use crate::aabb::AABB;
 use crate::objects::solve_quadratic;
 use crate::ray::Ray;
-use crate::render::{Hitable, RaycastHit};
+use crate::render::{Hitable, RaycastHit, SurfaceSample};
 use crate::scene::MaterialIdx;
+use crate::util::{cone_solid_angle, random_unit_vector, sample_cone};
 use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
 use tiny_rng::LcRng;
 use ultraviolet::{Vec2, Vec3};
 
@@ -20,7 +22,6 @@ impl Sphere {
 }
 
 pub fn sphere_uv(point: &Vec3) -> Vec2 {
-    use std::f32::consts::PI;
     let phi = point.z.atan2(point.x);
     let theta = point.y.asin();
     let u = 1. - (phi + PI) / (2. * PI);
@@ -28,6 +29,16 @@ pub fn sphere_uv(point: &Vec3) -> Vec2 {
     Vec2::new(u, v)
 }
 
+impl crate::serde_compat::MapMaterials for Sphere {
+    fn map_materials(&mut self, f: &mut dyn FnMut(MaterialIdx) -> MaterialIdx) {
+        self.material = f(self.material);
+    }
+
+    fn materials(&self) -> Vec<MaterialIdx> {
+        vec![self.material]
+    }
+}
+
 impl Hitable for Sphere {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32, _rand: &mut LcRng) -> Option<RaycastHit> {
         let o = *r.origin();
@@ -53,6 +64,14 @@ impl Hitable for Sphere {
                 normal: point / self.radius,
                 material: self.material,
                 uv: sphere_uv(&(point / self.radius)),
+                // `u` decreases as phi increases, so this points opposite to dp/dphi
+                tangent: Some(2. * PI * Vec3::new(point.z, 0., -point.x)),
+                footprint: 0.,
+                time: 0.,
+                priority: 0,
+                outside_ior: 1.,
+                light: None,
+                light_group: None,
             })
         } else {
             None
@@ -62,4 +81,46 @@ impl Hitable for Sphere {
     fn bounding_box(&self) -> AABB {
         AABB::new(-Vec3::one() * self.radius, Vec3::one() * self.radius)
     }
+
+    /// Points outside the sphere choose a direction in the cone it covers, so only the side
+    /// facing them is sampled, and none of the samples are wasted on the far side
+    fn sample_point(&self, origin: Vec3, rand: &mut LcRng) -> Option<SurfaceSample> {
+        let distance = origin.mag();
+        if distance <= self.radius * 1.0001 {
+            let normal = random_unit_vector(rand);
+            return Some(SurfaceSample {
+                point: self.radius * normal,
+                normal,
+                pdf: 1. / (4. * PI * self.radius * self.radius),
+            });
+        }
+
+        let half_angle = (self.radius / distance).asin();
+        let dir = sample_cone(-origin / distance, half_angle, rand);
+        // the closest intersection, or where the ray grazes the sphere if precision misses it
+        let b = dir.dot(origin);
+        let disc = (b * b - distance * distance + self.radius * self.radius).max(0.);
+        let normal = (origin + (-b - disc.sqrt()) * dir).normalized();
+        let point = self.radius * normal;
+        Some(SurfaceSample {
+            point,
+            normal,
+            pdf: self.pdf(origin, point),
+        })
+    }
+
+    fn pdf(&self, origin: Vec3, point: Vec3) -> f32 {
+        let distance = origin.mag();
+        if distance <= self.radius * 1.0001 {
+            return 1. / (4. * PI * self.radius * self.radius);
+        }
+        // convert the uniform density over the cone to be with respect to area
+        let to_point = point - origin;
+        let cos_light = -to_point.dot(point) / (to_point.mag() * self.radius);
+        if cos_light <= 0. {
+            return 0.;
+        }
+        let half_angle = (self.radius / distance).asin();
+        cos_light / (cone_solid_angle(half_angle) * to_point.mag_sq())
+    }
 }
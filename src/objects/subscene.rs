@@ -0,0 +1,156 @@
+use crate::aabb::AABB;
+use crate::bvh::BVHNode;
+use crate::ray::Ray;
+use crate::render::{Hitable, RaycastHit};
+use crate::scene::{MaterialIdx, RenderObject, RenderObjectInternal};
+use crate::serde_compat::{AsHitable, InspectShape, MapMaterials};
+use serde::{Deserialize, Serialize};
+use tiny_rng::LcRng;
+use ultraviolet::Vec3;
+
+/// A collection of objects which is placed in a scene as a single object, with its own BVH, e.g.
+/// to move, rotate or scale a whole prop at once, or to keep a cluster of many small objects from
+/// spreading out the BVH of the rest of the scene. The objects use the materials of the scene the
+/// `SubScene` is added to, and are placed relative to the `SubScene`.
+///
+/// The objects in a `SubScene` can't be sampled as lights (though emissive materials still
+/// glow), and their light groups, priorities, `parent`s and names are ignored, in favour of
+/// those of the object the `SubScene` is in. Participating media should be added to the scene
+/// itself.
+/// ```
+/// use firework::material::LambertianMat;
+/// use firework::objects::{Sphere, SubScene};
+/// use firework::{RenderObject, Scene};
+/// use ultraviolet::{Rotor3, Vec3};
+///
+/// let mut scene = Scene::new();
+/// let white = scene.add_material(LambertianMat::with_color(Vec3::one()));
+///
+/// let mut cluster = SubScene::new();
+/// for i in 0..10 {
+///     cluster.add_object(RenderObject::new(Sphere::new(0.1, white)).position(i as f32, 0., 0.));
+/// }
+/// scene.add_object(
+///     RenderObject::new(cluster)
+///         .rotate(Rotor3::from_rotation_xz(0.3))
+///         .position(1., 2.7, 3.95),
+/// );
+/// ```
+#[derive(Serialize, Deserialize)]
+pub struct SubScene {
+    objects: Vec<RenderObject>,
+}
+
+impl SubScene {
+    /// Creates an empty `SubScene`
+    pub fn new() -> SubScene {
+        SubScene {
+            objects: Vec::new(),
+        }
+    }
+
+    /// Adds an object, placed relative to the `SubScene`
+    pub fn add_object(&mut self, obj: RenderObject) {
+        self.objects.push(obj);
+    }
+
+    /// The objects in the `SubScene`
+    pub fn objects(&self) -> &[RenderObject] {
+        &self.objects
+    }
+}
+
+impl Default for SubScene {
+    fn default() -> Self {
+        SubScene::new()
+    }
+}
+
+impl AsHitable for SubScene {
+    fn to_hitable(self: Box<Self>) -> Box<dyn Hitable>
+    where
+        Self: 'static,
+    {
+        let objects: Vec<RenderObjectInternal> = self.objects.into_iter().map(Into::into).collect();
+        // the BVH can't be built over nothing
+        let bvh = if objects.is_empty() {
+            None
+        } else {
+            Some(BVHNode::from_owned(objects))
+        };
+        Box::new(SubSceneAggregate { bvh })
+    }
+}
+
+/// A `SubScene` with a BVH over its objects
+struct SubSceneAggregate {
+    bvh: Option<BVHNode<RenderObjectInternal>>,
+}
+
+impl Hitable for SubSceneAggregate {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rand: &mut LcRng) -> Option<RaycastHit> {
+        self.bvh.as_ref()?.hit(r, t_min, t_max, rand)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        match &self.bvh {
+            Some(bvh) => bvh.bounding_box(),
+            None => AABB::new(Vec3::zero(), Vec3::zero()),
+        }
+    }
+}
+
+impl MapMaterials for SubScene {
+    fn map_materials(&mut self, f: &mut dyn FnMut(MaterialIdx) -> MaterialIdx) {
+        for obj in &mut self.objects {
+            obj.obj.map_materials(f);
+        }
+    }
+
+    fn materials(&self) -> Vec<MaterialIdx> {
+        self.objects
+            .iter()
+            .flat_map(|obj| obj.obj.materials())
+            .collect()
+    }
+}
+
+impl InspectShape for SubScene {
+    fn local_bounds(&self) -> AABB {
+        let mut bounds = self.objects.iter().map(RenderObject::bounds);
+        match bounds.next() {
+            Some(first) => bounds.fold(first, |aabb, bounds| aabb.expand(&bounds)),
+            None => AABB::new(Vec3::zero(), Vec3::zero()),
+        }
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (idx, obj) in self.objects.iter().enumerate() {
+            for problem in obj.obj.problems() {
+                problems.push(format!("object {} of the sub-scene: {}", idx, problem));
+            }
+        }
+        problems
+    }
+
+    fn triangle_count(&self) -> usize {
+        self.objects
+            .iter()
+            .map(|obj| obj.obj.triangle_count())
+            .sum()
+    }
+
+    fn memory_size(&self) -> usize {
+        // the objects are stored in the leaves of the BVH, two to a leaf, and there are about as
+        // many inner nodes as leaves
+        let per_object = std::mem::size_of::<BVHNode<RenderObjectInternal>>();
+        self.objects
+            .iter()
+            .map(|obj| per_object + obj.obj.memory_size())
+            .sum()
+    }
+}
+
+#[typetag::serde]
+impl crate::serde_compat::SerializableShape for SubScene {}
@@ -1,21 +1,28 @@
 use crate::aabb::AABB;
 use crate::ray::Ray;
-use crate::render::{Hitable, RaycastHit};
+use crate::render::{Hitable, Medium, RaycastHit};
 use crate::scene::MaterialIdx;
-use crate::serde_compat::{AsHitable, SerializableShape};
+use crate::serde_compat::{AsHitable, InspectShape, MapMaterials, SerializableShape};
+use crate::texture::{Texture, VolumeTexture};
 use serde::{Deserialize, Serialize};
-use tiny_rng::{LcRng, Rand};
+use std::convert::TryFrom;
+use tiny_rng::LcRng;
 use ultraviolet::{Vec2, Vec3};
 
 #[derive(Serialize, Deserialize)]
 pub struct ConstantMedium<T> {
     obj: T,
     density: f32,
+    /// Scales the density for each color channel
+    #[serde(default = "Vec3::one")]
+    extinction: Vec3,
     material: MaterialIdx,
 }
 
 impl ConstantMedium<Box<dyn SerializableShape>> {
-    pub(crate) fn _new<T: SerializableShape + 'static>(
+    /// Creates a medium filling `obj`, which scatters light using `material` (usually a phase
+    /// function, like `IsotropicMat` or `HenyeyGreensteinMat`)
+    pub fn new<T: SerializableShape + 'static>(
         obj: T,
         density: f32,
         material: crate::scene::MaterialIdx,
@@ -23,6 +30,7 @@ impl ConstantMedium<Box<dyn SerializableShape>> {
         ConstantMedium {
             obj: Box::new(obj),
             density,
+            extinction: Vec3::one(),
             material,
         }
     }
@@ -35,9 +43,31 @@ impl ConstantMedium<Box<dyn SerializableShape>> {
         ConstantMedium {
             obj,
             density,
+            extinction: Vec3::one(),
             material,
         }
     }
+
+    /// Scales the density for each color channel, so the medium absorbs some colors more than
+    /// others and tints the light passing through it. For example, an extinction of
+    /// `(1., 0.4, 0.8)` lets green light travel furthest, like murky water.
+    /// ```
+    /// use firework::material::IsotropicMat;
+    /// use firework::objects::{ConstantMedium, Sphere};
+    /// use firework::texture::ConstantTexture;
+    /// use firework::{RenderObject, Scene};
+    /// use ultraviolet::Vec3;
+    ///
+    /// let mut scene = Scene::new();
+    /// let water = scene.add_material(IsotropicMat::new(ConstantTexture::from_rgb(0.6, 0.8, 0.7)));
+    /// let medium = ConstantMedium::new(Sphere::new(2., water), 0.3, water)
+    ///     .extinction(Vec3::new(1., 0.4, 0.8));
+    /// scene.add_object(RenderObject::new(medium));
+    /// ```
+    pub fn extinction(mut self, extinction: Vec3) -> Self {
+        self.extinction = extinction;
+        self
+    }
 }
 
 impl AsHitable for ConstantMedium<Box<dyn SerializableShape>> {
@@ -48,40 +78,262 @@ impl AsHitable for ConstantMedium<Box<dyn SerializableShape>> {
         Box::new(ConstantMedium {
             obj: self.obj.to_hitable(),
             density: self.density,
+            extinction: self.extinction,
             material: self.material,
         })
     }
 }
 
+/// Returns the parameters at which `r` enters and leaves the (closed) boundary `obj`. If the ray
+/// starts inside the boundary, the entry is negative.
+fn boundary_interval<T: Hitable>(obj: &T, r: &Ray, rand: &mut LcRng) -> Option<(f32, f32)> {
+    let enter = obj.hit(r, -f32::MAX, f32::MAX, rand)?;
+    let exit = obj.hit(r, enter.t + 0.0001, f32::MAX, rand)?;
+    Some((enter.t, exit.t))
+}
+
+impl<T: MapMaterials> MapMaterials for ConstantMedium<T> {
+    fn map_materials(&mut self, f: &mut dyn FnMut(MaterialIdx) -> MaterialIdx) {
+        self.obj.map_materials(f);
+        self.material = f(self.material);
+    }
+
+    fn materials(&self) -> Vec<MaterialIdx> {
+        let mut materials = self.obj.materials();
+        materials.push(self.material);
+        materials
+    }
+}
+
+impl<T: InspectShape> InspectShape for ConstantMedium<T> {
+    fn local_bounds(&self) -> AABB {
+        self.obj.local_bounds()
+    }
+
+    fn triangle_count(&self) -> usize {
+        self.obj.triangle_count()
+    }
+
+    fn memory_size(&self) -> usize {
+        self.obj.memory_size() + std::mem::size_of::<Self>()
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = self.obj.problems();
+        if self.density.is_nan() || self.density < 0. || self.extinction.component_min() < 0. {
+            problems.push(format!(
+                "the medium has a negative density ({})",
+                self.density
+            ));
+        }
+        problems
+    }
+}
+
+impl<T: Hitable> Medium for ConstantMedium<T> {
+    fn interval(&self, r: &Ray, rand: &mut LcRng) -> Option<(f32, f32)> {
+        boundary_interval(&self.obj, r, rand)
+    }
+
+    fn density(&self, _point: Vec3) -> Vec3 {
+        self.density * self.extinction
+    }
+
+    fn max_density(&self) -> f32 {
+        let e = self.extinction;
+        self.density * e.x.max(e.y).max(e.z)
+    }
+
+    fn material(&self) -> MaterialIdx {
+        self.material
+    }
+}
+
 impl<T: Hitable> Hitable for ConstantMedium<T> {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rand: &mut LcRng) -> Option<RaycastHit> {
-        if let Some(mut rec1) = self.obj.hit(r, -std::f32::MAX, std::f32::MAX, rand) {
-            if let Some(mut rec2) = self.obj.hit(r, rec1.t + 0.0001, std::f32::MAX, rand) {
-                rec1.t = rec1.t.max(t_min);
-                rec2.t = rec2.t.min(t_max);
-                if rec1.t >= rec2.t {
-                    return None;
-                }
-                rec1.t = rec1.t.max(0.);
-                let dist_inside_boundary = (rec2.t - rec1.t) * r.direction().mag();
-                let hit_distance = -(1. / self.density) * rand.rand_f32().log10();
-
-                if hit_distance < dist_inside_boundary {
-                    let t = rec1.t + hit_distance / r.direction().mag();
-                    return Some(RaycastHit {
-                        t,
-                        point: r.point(t),
-                        normal: Vec3::unit_y(), // arbitrary
-                        material: self.material,
-                        uv: Vec2::new(0., 0.),
-                    });
-                }
-            }
+    /// Rays pass straight through media, the renderer samples collisions inside them instead
+    fn hit(&self, _r: &Ray, _t_min: f32, _t_max: f32, _rand: &mut LcRng) -> Option<RaycastHit> {
+        None
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.obj.bounding_box()
+    }
+
+    fn as_medium(&self) -> Option<&dyn Medium> {
+        Some(self)
+    }
+}
+
+/// A participating medium whose density varies through space, like a cloud or a puff of smoke.
+/// The density is the first component of a texture (e.g. a `VolumeTexture` or `FbmTexture`),
+/// sampled at points in the object's local space.
+/// Collisions are found with delta tracking, which needs an upper bound on the density inside the
+/// boundary: `max_density`. Tighter bounds are faster, but the result is biased if the
+/// density ever exceeds it. It must be positive, or delta tracking would never finish.
+#[derive(Serialize, Deserialize)]
+#[serde(try_from = "HeterogeneousMediumDef<T>")]
+pub struct HeterogeneousMedium<T> {
+    obj: T,
+    density: Box<dyn Texture>,
+    max_density: f32,
+    material: MaterialIdx,
+}
+
+#[derive(Deserialize)]
+struct HeterogeneousMediumDef<T> {
+    obj: T,
+    density: Box<dyn Texture>,
+    max_density: f32,
+    material: MaterialIdx,
+}
+
+impl<T> TryFrom<HeterogeneousMediumDef<T>> for HeterogeneousMedium<T> {
+    type Error = String;
+    fn try_from(def: HeterogeneousMediumDef<T>) -> Result<Self, String> {
+        check_max_density(def.max_density)?;
+        Ok(HeterogeneousMedium {
+            obj: def.obj,
+            density: def.density,
+            max_density: def.max_density,
+            material: def.material,
+        })
+    }
+}
+
+/// Checks the `max_density` of a `HeterogeneousMedium` is a positive number
+fn check_max_density(max_density: f32) -> Result<(), String> {
+    if max_density.is_finite() && max_density > 0. {
+        Ok(())
+    } else {
+        Err(format!(
+            "the medium's max_density must be positive, not {}",
+            max_density
+        ))
+    }
+}
+
+impl HeterogeneousMedium<Box<dyn SerializableShape>> {
+    /// Creates a medium filling `obj`, which scatters light using `material` (usually a phase
+    /// function, like `IsotropicMat` or `HenyeyGreensteinMat`). Panics if `max_density` isn't
+    /// positive.
+    pub fn new<S, D>(obj: S, density: D, max_density: f32, material: MaterialIdx) -> Self
+    where
+        S: SerializableShape + 'static,
+        D: Texture + 'static,
+    {
+        HeterogeneousMedium::from_boxed(Box::new(obj), Box::new(density), max_density, material)
+    }
+
+    /// Creates a medium filling the box covered by `volume`, using its values as the density and
+    /// its largest value as `max_density`. Useful for volumes loaded from simulations, e.g. with
+    /// `VolumeTexture::from_nanovdb`.
+    pub fn from_volume(volume: VolumeTexture, material: MaterialIdx) -> Self {
+        let (min, max) = volume.extent();
+        let max_density = volume.max_density().max(f32::EPSILON);
+        HeterogeneousMedium::new(
+            super::Rect3d::new(min, max - min, material),
+            volume,
+            max_density,
+            material,
+        )
+    }
+
+    pub(crate) fn from_boxed(
+        obj: Box<dyn SerializableShape>,
+        density: Box<dyn Texture>,
+        max_density: f32,
+        material: MaterialIdx,
+    ) -> Self {
+        if let Err(e) = check_max_density(max_density) {
+            panic!("HeterogeneousMedium::new() -- {}", e);
+        }
+        HeterogeneousMedium {
+            obj,
+            density,
+            max_density,
+            material,
+        }
+    }
+}
+
+impl AsHitable for HeterogeneousMedium<Box<dyn SerializableShape>> {
+    fn to_hitable(self: Box<Self>) -> Box<dyn Hitable>
+    where
+        Self: 'static,
+    {
+        Box::new(HeterogeneousMedium {
+            obj: self.obj.to_hitable(),
+            density: self.density,
+            max_density: self.max_density,
+            material: self.material,
+        })
+    }
+}
+
+impl<T: MapMaterials> MapMaterials for HeterogeneousMedium<T> {
+    fn map_materials(&mut self, f: &mut dyn FnMut(MaterialIdx) -> MaterialIdx) {
+        self.obj.map_materials(f);
+        self.material = f(self.material);
+    }
+
+    fn materials(&self) -> Vec<MaterialIdx> {
+        let mut materials = self.obj.materials();
+        materials.push(self.material);
+        materials
+    }
+}
+
+impl<T: InspectShape> InspectShape for HeterogeneousMedium<T> {
+    fn local_bounds(&self) -> AABB {
+        self.obj.local_bounds()
+    }
+
+    fn triangle_count(&self) -> usize {
+        self.obj.triangle_count()
+    }
+
+    fn memory_size(&self) -> usize {
+        self.obj.memory_size() + self.density.memory_size()
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = self.obj.problems();
+        if let Err(problem) = check_max_density(self.max_density) {
+            problems.push(problem);
         }
+        problems
+    }
+}
+
+impl<T: Hitable> Medium for HeterogeneousMedium<T> {
+    fn interval(&self, r: &Ray, rand: &mut LcRng) -> Option<(f32, f32)> {
+        boundary_interval(&self.obj, r, rand)
+    }
+
+    fn density(&self, point: Vec3) -> Vec3 {
+        Vec3::broadcast(self.density.sample(Vec2::zero(), &point).x)
+    }
+
+    fn max_density(&self) -> f32 {
+        self.max_density
+    }
+
+    fn material(&self) -> MaterialIdx {
+        self.material
+    }
+}
+
+impl<T: Hitable> Hitable for HeterogeneousMedium<T> {
+    /// Rays pass straight through media, the renderer samples collisions inside them instead
+    fn hit(&self, _r: &Ray, _t_min: f32, _t_max: f32, _rand: &mut LcRng) -> Option<RaycastHit> {
         None
     }
 
     fn bounding_box(&self) -> AABB {
         self.obj.bounding_box()
     }
+
+    fn as_medium(&self) -> Option<&dyn Medium> {
+        Some(self)
+    }
 }
@@ -0,0 +1,686 @@
+use crate::render::{RenderBuffer, Renderer};
+use crate::util::Color;
+use image::{save_buffer, ColorType, ImageBuffer, Rgba};
+use std::convert::AsRef;
+use std::path::Path;
+use std::time::Duration;
+use ultraviolet::Vec3;
+
+pub fn save_image<P>(render: &[Color], path: P, width: usize, height: usize)
+where
+    P: AsRef<Path>,
+{
+    let new_buf: Vec<u8> = render.iter().flat_map(|&x| [x.0, x.1, x.2]).collect();
+    save_buffer(path, &new_buf, width as u32, height as u32, ColorType::Rgb8)
+        .expect("Failed to save");
+}
+
+/// Saves a `RenderBuffer` as an 8 bit image with an alpha channel (e.g. a PNG), gamma correcting
+/// the colors with `gamma`
+pub fn save_image_rgba<P>(buffer: &RenderBuffer, path: P, gamma: f32)
+where
+    P: AsRef<Path>,
+{
+    save_buffer(
+        path,
+        &buffer.to_rgba8(gamma),
+        buffer.width as u32,
+        buffer.height as u32,
+        ColorType::Rgba8,
+    )
+    .expect("Failed to save");
+}
+
+/// Saves a `RenderBuffer` as an image with 16 bits for each of the red, green, blue and alpha
+/// values, which keeps smooth gradients (like skies) from banding, gamma correcting the colors
+/// with `gamma` before they're quantized. The format is given by the extension of `path`, and
+/// must be PNG or TIFF.
+/// ```no_run
+/// use firework::render::Renderer;
+/// use firework::output::save_image_16bit;
+/// use firework::Scene;
+///
+/// let renderer = Renderer::default();
+/// let buffer = renderer.render_buffer(Scene::new());
+/// save_image_16bit(&buffer, "render.png", renderer.gamma).unwrap();
+/// ```
+pub fn save_image_16bit<P>(buffer: &RenderBuffer, path: P, gamma: f32) -> image::ImageResult<()>
+where
+    P: AsRef<Path>,
+{
+    let (width, height) = (buffer.width as u32, buffer.height as u32);
+    let image: ImageBuffer<Rgba<u16>, _> =
+        ImageBuffer::from_raw(width, height, buffer.to_rgba16(gamma))
+            .expect("the buffer has 4 values for each pixel");
+    image.save(path)
+}
+
+/// Saves a `RenderBuffer` as a PFM file of 32 bit floats, with the full range of its colors (as
+/// they'd appear over a black background, without gamma correction). Many tools for comparing
+/// renders read PFM files, and they're simple enough to read without any library.
+/// ```
+/// use firework::render::RenderBuffer;
+/// use firework::output::save_pfm;
+///
+/// let path = std::env::temp_dir().join("firework_save_pfm.pfm");
+/// save_pfm(&RenderBuffer::new(4, 3), &path).unwrap();
+/// let file = std::fs::read(&path).unwrap();
+/// assert!(file.starts_with(b"PF\n4 3\n-1.0\n"));
+/// assert_eq!(file.len(), 12 + 4 * 3 * 3 * 4);
+/// ```
+pub fn save_pfm<P>(buffer: &RenderBuffer, path: P) -> std::io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    use std::io::Write;
+
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    // a negative scale means the values are little endian
+    write!(file, "PF\n{} {}\n-1.0\n", buffer.width, buffer.height)?;
+    // the rows go from the bottom of the image to the top
+    for row in buffer.color.chunks(buffer.width).rev() {
+        for c in row {
+            for x in [c.x, c.y, c.z] {
+                file.write_all(&x.to_le_bytes())?;
+            }
+        }
+    }
+    file.flush()
+}
+
+/// Saves a `RenderBuffer` as an 8 bit PPM file, gamma correcting the colors with `gamma`. If
+/// `ascii` is set, the values are written as text (a `P3` file, with a pixel on each line), which
+/// can be read, or diffed, as it is. Otherwise they're written as bytes (a `P6` file).
+/// ```
+/// use firework::render::RenderBuffer;
+/// use firework::output::save_ppm;
+///
+/// let path = std::env::temp_dir().join("firework_save_ppm.ppm");
+/// save_ppm(&RenderBuffer::new(2, 1), &path, 2.2, true).unwrap();
+/// let file = std::fs::read_to_string(&path).unwrap();
+/// assert_eq!(file, "P3\n2 1\n255\n0 0 0\n0 0 0\n");
+/// ```
+pub fn save_ppm<P>(buffer: &RenderBuffer, path: P, gamma: f32, ascii: bool) -> std::io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    use std::io::Write;
+
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let magic = if ascii { "P3" } else { "P6" };
+    write!(file, "{}\n{} {}\n255\n", magic, buffer.width, buffer.height)?;
+    for Color(r, g, b) in buffer.to_colors(gamma) {
+        if ascii {
+            writeln!(file, "{} {} {}", r, g, b)?;
+        } else {
+            file.write_all(&[r, g, b])?;
+        }
+    }
+    file.flush()
+}
+
+/// The type of the values in an EXR file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExrPrecision {
+    /// 16 bit floats, which are enough for nearly all images, at half the size
+    Half,
+    /// 32 bit floats
+    Float,
+}
+
+/// How `save_exr_with` writes an EXR file
+#[derive(Debug, Clone, Copy)]
+pub struct ExrOptions {
+    pub precision: ExrPrecision,
+    /// Whether to write the light from each light group as a layer (see
+    /// `RenderObject::light_group`) along with the image
+    pub light_groups: bool,
+}
+
+impl Default for ExrOptions {
+    fn default() -> Self {
+        ExrOptions {
+            precision: ExrPrecision::Half,
+            light_groups: true,
+        }
+    }
+}
+
+impl ExrOptions {
+    pub fn precision(mut self, precision: ExrPrecision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    pub fn light_groups(mut self, light_groups: bool) -> Self {
+        self.light_groups = light_groups;
+        self
+    }
+}
+
+/// Saves a `RenderBuffer` as an OpenEXR file of 16 bit floats, with the full range of its colors
+/// (which aren't gamma corrected), its alpha, and the light groups as layers named after them
+/// (e.g. `key.R`, `key.G` and `key.B` for a light group called "key"). See `save_exr_with` for
+/// the other options.
+/// ```no_run
+/// use firework::render::Renderer;
+/// use firework::output::save_exr;
+/// use firework::Scene;
+///
+/// let buffer = Renderer::default().render_buffer(Scene::new());
+/// save_exr(&buffer, "render.exr").unwrap();
+/// ```
+pub fn save_exr<P>(buffer: &RenderBuffer, path: P) -> std::io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    save_exr_with(buffer, path, ExrOptions::default())
+}
+
+/// Saves a `RenderBuffer` as an uncompressed OpenEXR file, with the given options
+/// ```
+/// use firework::render::RenderBuffer;
+/// use firework::output::{save_exr_with, ExrOptions, ExrPrecision};
+///
+/// let path = std::env::temp_dir().join("firework_save_exr.exr");
+/// let options = ExrOptions::default().precision(ExrPrecision::Float);
+/// save_exr_with(&RenderBuffer::new(4, 3), &path, options).unwrap();
+/// // the magic number of EXR files
+/// assert_eq!(std::fs::read(&path).unwrap()[..4], [0x76, 0x2f, 0x31, 0x01]);
+/// ```
+pub fn save_exr_with<P>(buffer: &RenderBuffer, path: P, options: ExrOptions) -> std::io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    write_exr(buffer, path.as_ref(), options, &[])
+}
+
+/// Writes an EXR file, with the given pairs of names and values as string attributes
+fn write_exr(
+    buffer: &RenderBuffer,
+    path: &Path,
+    options: ExrOptions,
+    metadata: &[(String, String)],
+) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut channels = vec![("A".to_owned(), buffer.alpha.clone())];
+    for (i, suffix) in ["R", "G", "B"].iter().enumerate() {
+        let component = |c: &Vec3| [c.x, c.y, c.z][i];
+        channels.push((
+            suffix.to_string(),
+            buffer.color.iter().map(component).collect(),
+        ));
+        if options.light_groups {
+            for (name, light) in &buffer.light_groups {
+                let values = light.iter().map(component).collect();
+                channels.push((format!("{}.{}", name, suffix), values));
+            }
+        }
+    }
+    // the channels are stored in alphabetical order
+    channels.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let (pixel_type, size) = match options.precision {
+        ExrPrecision::Half => (1i32, 2),
+        ExrPrecision::Float => (2, 4),
+    };
+    let (width, height) = (buffer.width as i32, buffer.height as i32);
+
+    let mut header = Vec::new();
+    let mut attribute = |name: &str, kind: &str, value: &[u8]| {
+        header.extend(name.as_bytes());
+        header.push(0);
+        header.extend(kind.as_bytes());
+        header.push(0);
+        header.extend(&(value.len() as i32).to_le_bytes());
+        header.extend(value);
+    };
+    let mut list = Vec::new();
+    for (name, _) in &channels {
+        list.extend(name.as_bytes());
+        list.push(0);
+        list.extend(&pixel_type.to_le_bytes());
+        // not perceptually linear, 3 reserved bytes, and no subsampling
+        list.extend(&[0; 4]);
+        list.extend(&1i32.to_le_bytes());
+        list.extend(&1i32.to_le_bytes());
+    }
+    list.push(0);
+    attribute("channels", "chlist", &list);
+    attribute("compression", "compression", &[0]);
+    let window: Vec<u8> = [0, 0, width - 1, height - 1]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    attribute("dataWindow", "box2i", &window);
+    attribute("displayWindow", "box2i", &window);
+    attribute("lineOrder", "lineOrder", &[0]);
+    attribute("pixelAspectRatio", "float", &1f32.to_le_bytes());
+    attribute("screenWindowCenter", "v2f", &[0; 8]);
+    attribute("screenWindowWidth", "float", &1f32.to_le_bytes());
+    for (name, value) in metadata {
+        attribute(name, "string", value.as_bytes());
+    }
+    header.push(0);
+
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    file.write_all(&20000630i32.to_le_bytes())?;
+    // version 2, with the flag for names longer than 31 bytes if there are any
+    let long_names = channels.iter().any(|(name, _)| name.len() > 31);
+    file.write_all(&(2 | if long_names { 0x400 } else { 0 } as i32).to_le_bytes())?;
+    file.write_all(&header)?;
+
+    // every block is one scanline, of a y coordinate, the size of the data, and the values of
+    // each channel in turn
+    let line_size = (channels.len() * buffer.width * size) as i32;
+    let first_line = (8 + header.len() + 8 * buffer.height) as u64;
+    for y in 0..height as u64 {
+        let offset = first_line + y * (8 + line_size as u64);
+        file.write_all(&offset.to_le_bytes())?;
+    }
+    for y in 0..buffer.height {
+        file.write_all(&(y as i32).to_le_bytes())?;
+        file.write_all(&line_size.to_le_bytes())?;
+        for (_, values) in &channels {
+            for &value in &values[y * buffer.width..(y + 1) * buffer.width] {
+                match options.precision {
+                    ExrPrecision::Half => file.write_all(&f32_to_half(value).to_le_bytes())?,
+                    ExrPrecision::Float => file.write_all(&value.to_le_bytes())?,
+                }
+            }
+        }
+    }
+    file.flush()
+}
+
+/// Converts a float to the bits of the nearest 16 bit float, rounding ties to even
+fn f32_to_half(x: f32) -> u16 {
+    let bits = x.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // infinity stays infinite, and NaN stays NaN
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // too small for a normal half, so it's subnormal or rounds to 0
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let half = mantissa >> shift;
+        let rest = mantissa & ((1 << shift) - 1);
+        let middle = 1 << (shift - 1);
+        let round_up = rest > middle || (rest == middle && half & 1 == 1);
+        return sign | (half + round_up as u32) as u16;
+    }
+    let half = (exponent as u32) << 10 | mantissa >> 13;
+    let rest = mantissa & 0x1fff;
+    let round_up = rest > 0x1000 || (rest == 0x1000 && half & 1 == 1);
+    // rounding up the largest mantissa carries into the exponent, as it should
+    sign | (half + round_up as u32) as u16
+}
+
+/// The ways `save_render` can save a render
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// An 8 bit image with an alpha channel, like `Image16`, in the format given by the extension
+    /// of the path (e.g. PNG, or JPEG, which leaves out the alpha)
+    Image,
+    /// A 16 bit PNG or TIFF image (see `save_image_16bit`)
+    Image16,
+    /// An OpenEXR file, with the light groups as layers (see `save_exr`)
+    Exr,
+    /// A PFM file of 32 bit floats (see `save_pfm`)
+    Pfm,
+    /// An 8 bit PPM file, as text if `ascii` is set, or as bytes otherwise (see `save_ppm`)
+    Ppm { ascii: bool },
+}
+
+impl OutputFormat {
+    /// The format a path's extension implies: EXR, PFM and (binary) PPM files for those
+    /// extensions, and 8 bit images for anything else
+    pub fn from_path<P: AsRef<Path>>(path: P) -> OutputFormat {
+        let extension = path.as_ref().extension().unwrap_or_default();
+        let extension = extension.to_string_lossy().to_ascii_lowercase();
+        match extension.as_str() {
+            "exr" => OutputFormat::Exr,
+            "pfm" => OutputFormat::Pfm,
+            "ppm" => OutputFormat::Ppm { ascii: false },
+            _ => OutputFormat::Image,
+        }
+    }
+
+    /// The extension of files in this format, `png` for images
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Image | OutputFormat::Image16 => "png",
+            OutputFormat::Exr => "exr",
+            OutputFormat::Pfm => "pfm",
+            OutputFormat::Ppm { .. } => "ppm",
+        }
+    }
+}
+
+/// Saves a `RenderBuffer` in the given format, gamma correcting the colors with `gamma` for the
+/// formats which need it
+/// ```no_run
+/// use firework::render::Renderer;
+/// use firework::output::{save_render, OutputFormat};
+/// use firework::Scene;
+///
+/// let renderer = Renderer::default();
+/// let buffer = renderer.render_buffer(Scene::new());
+/// save_render(&buffer, "render.pfm", renderer.gamma, OutputFormat::from_path("render.pfm"))
+///     .unwrap();
+/// save_render(&buffer, "render.ppm", renderer.gamma, OutputFormat::Ppm { ascii: true })
+///     .unwrap();
+/// ```
+pub fn save_render<P>(
+    buffer: &RenderBuffer,
+    path: P,
+    gamma: f32,
+    format: OutputFormat,
+) -> image::ImageResult<()>
+where
+    P: AsRef<Path>,
+{
+    match format {
+        OutputFormat::Image => {
+            let (width, height) = (buffer.width as u32, buffer.height as u32);
+            save_buffer(path, &buffer.to_rgba8(gamma), width, height, ColorType::Rgba8)
+        }
+        OutputFormat::Image16 => save_image_16bit(buffer, path, gamma),
+        OutputFormat::Exr => Ok(save_exr(buffer, path)?),
+        OutputFormat::Pfm => Ok(save_pfm(buffer, path)?),
+        OutputFormat::Ppm { ascii } => Ok(save_ppm(buffer, path, gamma, ascii)?),
+    }
+}
+
+/// Information about how a render was made, which `save_render_with_metadata` saves along with
+/// it, so it can be traced back to what made it, and made again. The version of firework is
+/// always included.
+/// ```
+/// use firework::output::RenderMetadata;
+/// use firework::Renderer;
+/// use std::time::Duration;
+///
+/// let metadata = RenderMetadata::new(&Renderer::default().samples(64))
+///     .render_time(Duration::from_millis(1500))
+///     .seed(7)
+///     .scene_source(b"objects: []");
+/// let entries = metadata.entries();
+/// assert!(entries.contains(&("Samples".to_owned(), "64".to_owned())));
+/// assert!(entries.contains(&("RenderTime".to_owned(), "1.500 s".to_owned())));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RenderMetadata {
+    /// The number of samples of each pixel
+    pub samples: Option<usize>,
+    /// The width and height of the render
+    pub resolution: Option<(usize, usize)>,
+    pub render_time: Option<Duration>,
+    /// The seed the scene was made with, e.g. for one from `procgen`. Renders are otherwise
+    /// the same every time.
+    pub seed: Option<u64>,
+    /// A 64 bit FNV-1a hash of the scene file (see `scene_source`)
+    pub scene_hash: Option<u64>,
+}
+
+impl RenderMetadata {
+    /// The metadata of a render with the given settings
+    pub fn new(renderer: &Renderer) -> Self {
+        RenderMetadata {
+            samples: Some(renderer.samples),
+            resolution: Some((renderer.width, renderer.height)),
+            ..RenderMetadata::default()
+        }
+    }
+
+    pub fn render_time(mut self, render_time: Duration) -> Self {
+        self.render_time = Some(render_time);
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Sets the hash of the scene from the contents of its file
+    pub fn scene_source(mut self, contents: &[u8]) -> Self {
+        let hash = contents.iter().fold(0xcbf29ce484222325u64, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        self.scene_hash = Some(hash);
+        self
+    }
+
+    /// The metadata as pairs of names and values, as they're saved
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = vec![(
+            "Software".to_owned(),
+            format!("firework {}", env!("CARGO_PKG_VERSION")),
+        )];
+        let mut entry = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                entries.push((name.to_owned(), value));
+            }
+        };
+        entry("Samples", self.samples.map(|samples| samples.to_string()));
+        entry(
+            "Resolution",
+            self.resolution.map(|(w, h)| format!("{}x{}", w, h)),
+        );
+        entry(
+            "RenderTime",
+            self.render_time
+                .map(|time| format!("{:.3} s", time.as_secs_f64())),
+        );
+        entry("Seed", self.seed.map(|seed| seed.to_string()));
+        entry(
+            "SceneHash",
+            self.scene_hash.map(|hash| format!("{:016x}", hash)),
+        );
+        entries
+    }
+}
+
+/// Saves a `RenderBuffer` like `save_render`, with metadata about how it was made. PNG files
+/// get it as text chunks, and EXR files as string attributes. It's left out of the other
+/// formats, which have nowhere to put it.
+/// ```
+/// use firework::output::{save_render_with_metadata, OutputFormat, RenderMetadata};
+/// use firework::render::RenderBuffer;
+/// use firework::Renderer;
+///
+/// let path = std::env::temp_dir().join("firework_metadata.png");
+/// let renderer = Renderer::default().width(4).height(3);
+/// let metadata = RenderMetadata::new(&renderer);
+/// let buffer = RenderBuffer::new(4, 3);
+/// save_render_with_metadata(&buffer, &path, 2.2, OutputFormat::Image, &metadata).unwrap();
+///
+/// let file = std::fs::read(&path).unwrap();
+/// assert!(file.windows(12).any(|chunk| chunk == b"tEXtSamples\0"));
+/// // the file can still be read
+/// assert_eq!(image::open(&path).unwrap().to_rgb8().dimensions(), (4, 3));
+/// ```
+pub fn save_render_with_metadata<P>(
+    buffer: &RenderBuffer,
+    path: P,
+    gamma: f32,
+    format: OutputFormat,
+    metadata: &RenderMetadata,
+) -> image::ImageResult<()>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let entries = metadata.entries();
+    match format {
+        OutputFormat::Exr => Ok(write_exr(buffer, path, ExrOptions::default(), &entries)?),
+        OutputFormat::Image | OutputFormat::Image16 => {
+            save_render(buffer, path, gamma, format)?;
+            if path
+                .extension()
+                .unwrap_or_default()
+                .eq_ignore_ascii_case("png")
+            {
+                add_png_text(path, &entries)?;
+            }
+            Ok(())
+        }
+        _ => save_render(buffer, path, gamma, format),
+    }
+}
+
+/// Adds text chunks to a PNG file, after its header
+fn add_png_text(path: &Path, entries: &[(String, String)]) -> std::io::Result<()> {
+    let png = std::fs::read(path)?;
+    // the signature, and the header chunk, which is always 13 bytes of data, along with its
+    // length, type and checksum
+    let header_end = 8 + 12 + 13;
+    let mut file = png[..header_end].to_vec();
+    for (keyword, text) in entries {
+        let mut chunk = b"tEXt".to_vec();
+        chunk.extend(keyword.as_bytes());
+        chunk.push(0);
+        chunk.extend(text.as_bytes());
+        file.extend(&(chunk.len() as u32 - 4).to_be_bytes());
+        file.extend(&chunk);
+        file.extend(&crc32(&chunk).to_be_bytes());
+    }
+    file.extend(&png[header_end..]);
+    std::fs::write(path, file)
+}
+
+/// The CRC-32 checksum used by PNG files
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// How `terminal_preview` draws an image with text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminalStyle {
+    /// Half blocks colored with 24 bit ANSI escape codes, with two pixels of the preview in each
+    /// character, one above the other
+    TrueColor,
+    /// Plain characters getting denser with the brightness of the pixels, e.g. for logs which
+    /// don't keep escape codes
+    Ascii,
+}
+
+impl TerminalStyle {
+    /// `TrueColor` if the terminal says it supports it (by setting `COLORTERM` to `truecolor` or
+    /// `24bit`, as most do), and `Ascii` otherwise
+    pub fn from_env() -> TerminalStyle {
+        match std::env::var("COLORTERM").as_deref() {
+            Ok("truecolor") | Ok("24bit") => TerminalStyle::TrueColor,
+            _ => TerminalStyle::Ascii,
+        }
+    }
+}
+
+/// Draws a `RenderBuffer` as text, `columns` characters wide (or as wide as the render, if it's
+/// narrower), to see a render where there's no window or image viewer, e.g. over SSH or in CI
+/// logs. The colors are averaged over the pixels each character covers, and gamma corrected
+/// with `gamma`. Characters are taken to be twice as tall as they're wide.
+/// ```
+/// use firework::output::{terminal_preview, TerminalStyle};
+/// use firework::render::RenderBuffer;
+/// use ultraviolet::Vec3;
+///
+/// // white on top, black below
+/// let mut buffer = RenderBuffer::new(8, 8);
+/// for color in &mut buffer.color[..32] {
+///     *color = Vec3::one();
+/// }
+/// let preview = terminal_preview(&buffer, 4, 2.2, TerminalStyle::Ascii);
+/// assert_eq!(preview, "@@@@\n    \n");
+/// ```
+pub fn terminal_preview(
+    buffer: &RenderBuffer,
+    columns: usize,
+    gamma: f32,
+    style: TerminalStyle,
+) -> String {
+    use std::fmt::Write;
+
+    let mut text = String::new();
+    if buffer.width == 0 || buffer.height == 0 {
+        return text;
+    }
+
+    let columns = columns.clamp(1, buffer.width);
+    // the width of a character, in pixels of the render
+    let scale = buffer.width as f32 / columns as f32;
+    let rows = ((buffer.height as f32 / (2. * scale)).round() as usize).max(1);
+    let (sx, sy) = (scale, buffer.height as f32 / rows as f32);
+
+    // the average color of a part of the render, in pixels of the render, gamma corrected
+    let average = |x0: f32, y0: f32, x1: f32, y1: f32| {
+        let x0 = (x0 as usize).min(buffer.width - 1);
+        let y0 = (y0 as usize).min(buffer.height - 1);
+        let x1 = (x1.round() as usize).clamp(x0 + 1, buffer.width);
+        let y1 = (y1.round() as usize).clamp(y0 + 1, buffer.height);
+        let mut sum = Vec3::zero();
+        for y in y0..y1 {
+            for x in x0..x1 {
+                sum += buffer.color[y * buffer.width + x];
+            }
+        }
+        let color = sum / ((x1 - x0) * (y1 - y0)) as f32;
+        Color::from(color.map(|x| x.max(0.).powf(1. / gamma).min(1.)))
+    };
+
+    for row in 0..rows {
+        let (y0, y1) = (row as f32 * sy, (row + 1) as f32 * sy);
+        for column in 0..columns {
+            let (x0, x1) = (column as f32 * sx, (column + 1) as f32 * sx);
+            match style {
+                TerminalStyle::TrueColor => {
+                    let middle = (y0 + y1) / 2.;
+                    let Color(r, g, b) = average(x0, y0, x1, middle);
+                    let Color(r2, g2, b2) = average(x0, middle, x1, y1);
+                    write!(
+                        text,
+                        "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+                        r, g, b, r2, g2, b2
+                    )
+                    .unwrap();
+                }
+                TerminalStyle::Ascii => {
+                    const RAMP: &[u8] = b" .:-=+*#%@";
+                    let Color(r, g, b) = average(x0, y0, x1, y1);
+                    let luminance = 0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32;
+                    let idx = (luminance / 255. * (RAMP.len() - 1) as f32).round() as usize;
+                    text.push(RAMP[idx.min(RAMP.len() - 1)] as char);
+                }
+            }
+        }
+        if style == TerminalStyle::TrueColor {
+            text.push_str("\x1b[0m");
+        }
+        text.push('\n');
+    }
+    text
+}
@@ -0,0 +1,532 @@
+use crate::camera::CameraSettings;
+use crate::environment::SkyEnv;
+use crate::material::{DielectricMat, EmissiveMat, IsotropicMat, LambertianMat, MetalMat};
+use crate::objects::{
+    Cone, ConstantMedium, Cylinder, Disk, Rect3d, Sphere, SubScene, XYRect, XZRect, YZRect,
+};
+use crate::render::Renderer;
+use crate::scene::{RenderObject, Scene};
+use crate::texture::{CheckerTexture, ConstantTexture, SharedTexture, Texture, TurbulenceTexture};
+use tiny_rng::{Rand, Rng};
+use ultraviolet::{Rotor3, Vec3};
+
+/// The scene on the cover of "Ray Tracing in One Weekend": three large spheres, surrounded by
+/// small spheres scattered over a checkered ground, with a random mix of diffuse, metal and
+/// glass materials. The scene includes render settings with the camera from the book.
+/// ```
+/// use firework::procgen::RandomSpheres;
+///
+/// let scene = RandomSpheres::new().seed(7).count(10).area(5.).build();
+/// // at most 10 * 10 small spheres, the ground and the three large spheres
+/// assert!(scene.render_objects.len() <= 104);
+/// assert!(scene.render_settings.is_some());
+/// ```
+#[derive(Debug, Clone)]
+pub struct RandomSpheres {
+    /// The seed of the random numbers, so the same settings always give the same scene
+    pub seed: u64,
+    /// The number of cells along each side of the grid the small spheres are scattered over,
+    /// with one sphere in each cell (except those too close to the large spheres)
+    pub count: usize,
+    /// Half the width of the square the small spheres are scattered over
+    pub area: f32,
+    /// The relative amounts of diffuse, metal and glass small spheres
+    pub material_mix: [f32; 3],
+}
+
+impl RandomSpheres {
+    /// The scene from the book: a 22 by 22 grid of small spheres, 80% of them diffuse, 15%
+    /// metal and 5% glass
+    pub fn new() -> Self {
+        RandomSpheres {
+            seed: 12345,
+            count: 22,
+            area: 11.,
+            material_mix: [0.8, 0.15, 0.05],
+        }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn count(mut self, count: usize) -> Self {
+        self.count = count;
+        self
+    }
+
+    pub fn area(mut self, area: f32) -> Self {
+        self.area = area;
+        self
+    }
+
+    /// Sets the relative amounts of diffuse, metal and glass small spheres, which don't need to
+    /// add up to 1
+    pub fn material_mix(mut self, diffuse: f32, metal: f32, glass: f32) -> Self {
+        self.material_mix = [diffuse, metal, glass];
+        self
+    }
+
+    /// Generates the scene
+    pub fn build(&self) -> Scene {
+        let mut rand = Rng::new(self.seed);
+        let mut scene = Scene::new();
+
+        let checker_mat = scene.add_material(LambertianMat::new(CheckerTexture::with_colors(
+            Vec3::new(0.2, 0.4, 0.1),
+            Vec3::new(0.9, 0.9, 0.9),
+            10.,
+        )));
+        scene.add_object(
+            RenderObject::new(Sphere::new(1000., checker_mat)).position(0., -1000., -1.),
+        );
+
+        let [diffuse, metal, _] = self.material_mix;
+        let total: f32 = self.material_mix.iter().sum();
+        let cell = 2. * self.area / self.count as f32;
+        for x in 0..self.count {
+            for z in 0..self.count {
+                let center = Vec3::new(
+                    -self.area + cell * (x as f32 + 0.9 * rand.rand_f32()),
+                    0.2,
+                    -self.area + cell * (z as f32 + 0.9 * rand.rand_f32()),
+                );
+                if (center - Vec3::new(4., 0.2, 0.9)).mag() <= 0.9 {
+                    continue;
+                }
+
+                let choice = total * rand.rand_f32();
+                let mat = if choice < diffuse {
+                    scene.add_material(LambertianMat::with_color(Vec3::new(
+                        rand.rand_f32() * rand.rand_f32(),
+                        rand.rand_f32() * rand.rand_f32(),
+                        rand.rand_f32() * rand.rand_f32(),
+                    )))
+                } else if choice < diffuse + metal {
+                    scene.add_material(MetalMat::new(
+                        Vec3::new(
+                            0.5 * (1. + rand.rand_f32()),
+                            0.5 * (1. + rand.rand_f32()),
+                            0.5 * (1. + rand.rand_f32()),
+                        ),
+                        0.5 * rand.rand_f32(),
+                    ))
+                } else {
+                    scene.add_material(DielectricMat::new(1.5))
+                };
+                scene.add_object(RenderObject::new(Sphere::new(0.2, mat)).position_vec(center));
+            }
+        }
+
+        let glass = scene.add_material(DielectricMat::new(1.5));
+        let diffuse = scene.add_material(LambertianMat::with_color(Vec3::new(0.4, 0.2, 0.1)));
+        let metal = scene.add_material(MetalMat::new(Vec3::new(0.7, 0.6, 0.5), 0.0));
+
+        scene.add_object(RenderObject::new(Sphere::new(1.0, glass)).position(0., 1., 0.));
+        scene.add_object(RenderObject::new(Sphere::new(1.0, diffuse)).position(-4., 1., 0.));
+        scene.add_object(RenderObject::new(Sphere::new(1.0, metal)).position(4., 1., 0.));
+
+        scene.set_environment(SkyEnv::default());
+
+        let camera = CameraSettings::default()
+            .cam_pos(Vec3::new(13., 2., 3.))
+            .look_at(Vec3::zero())
+            .aperture(0.1);
+        scene.set_render_settings(
+            Renderer::default()
+                .width(960)
+                .height(540)
+                .samples(32)
+                .use_bvh(true)
+                .camera(camera),
+        );
+
+        scene
+    }
+}
+
+impl Default for RandomSpheres {
+    fn default() -> Self {
+        RandomSpheres::new()
+    }
+}
+
+/// The final scene of "Ray Tracing: The Next Week": a ground of boxes of random heights, a
+/// cluster of small spheres in a `SubScene`, and a few spheres showing off the materials,
+/// textures and media, all lit by a single area light in a thin fog. The scene includes render
+/// settings with the camera from the book.
+/// ```
+/// use firework::procgen::FinalScene;
+///
+/// let scene = FinalScene::new().boxes(5).cluster(100).build();
+/// assert_eq!(scene.stats().objects["Rect3d"], 25);
+/// ```
+#[derive(Clone)]
+pub struct FinalScene {
+    /// The seed of the random numbers, so the same settings always give the same scene
+    pub seed: u64,
+    /// The number of boxes along each side of the ground
+    pub boxes: usize,
+    /// Half the width of the square covered by the ground
+    pub area: f32,
+    /// The number of spheres in the cluster
+    pub cluster: usize,
+    /// The texture of the sphere which is an earth map in the book, a checker pattern if unset
+    pub earth: Option<SharedTexture>,
+}
+
+impl FinalScene {
+    /// The scene from the book (except for the earth texture): a 20 by 20 grid of boxes, and
+    /// 1000 spheres in the cluster
+    pub fn new() -> Self {
+        FinalScene {
+            seed: 12345,
+            boxes: 20,
+            area: 10.,
+            cluster: 1000,
+            earth: None,
+        }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn boxes(mut self, boxes: usize) -> Self {
+        self.boxes = boxes;
+        self
+    }
+
+    pub fn area(mut self, area: f32) -> Self {
+        self.area = area;
+        self
+    }
+
+    pub fn cluster(mut self, cluster: usize) -> Self {
+        self.cluster = cluster;
+        self
+    }
+
+    /// Sets the texture of the earth sphere, e.g. an `ImageTexture` of an earth map
+    pub fn earth<T: Texture + 'static>(mut self, texture: T) -> Self {
+        self.earth = Some(SharedTexture::new(texture));
+        self
+    }
+
+    /// Generates the scene
+    pub fn build(&self) -> Scene {
+        let mut rand = Rng::new(self.seed);
+        let mut scene = Scene::new();
+
+        let ground = scene.add_material(LambertianMat::with_color(Vec3::new(0.48, 0.83, 0.53)));
+
+        let width = 2. * self.area / self.boxes as f32;
+        let origin = Vec3::new(-self.area, 0., -self.area);
+        for x in 0..self.boxes {
+            for z in 0..self.boxes {
+                let pos = origin + width * Vec3::new(x as f32, 0., z as f32);
+                let size = Vec3::new(width, rand.rand_f32() + 0.01, width);
+                scene.add_object(
+                    RenderObject::new(Rect3d::with_size(size, ground)).position_vec(pos),
+                );
+            }
+        }
+
+        let light = scene.add_material(EmissiveMat::with_color(7. * Vec3::one()));
+        scene.add_object(RenderObject::new(XZRect::new(
+            1.23, 4.23, 1.47, 4.12, 5.54, light,
+        )));
+
+        let brown = scene.add_material(LambertianMat::with_color(Vec3::new(0.7, 0.3, 0.1)));
+        scene.add_object(RenderObject::new(Sphere::new(0.5, brown)).position(4., 4., 2.));
+
+        let glass = scene.add_material(DielectricMat::new(1.5));
+        scene.add_object(RenderObject::new(Sphere::new(0.5, glass)).position(2.6, 1.5, 0.45));
+
+        let metal = scene.add_material(MetalMat::new(Vec3::new(0.8, 0.8, 0.9), 10.));
+        scene.add_object(RenderObject::new(Sphere::new(0.5, metal)).position(0., 1.5, 1.45));
+
+        scene.add_object(RenderObject::new(Sphere::new(0.7, glass)).position(3.6, 1.5, 1.45));
+        let blue = scene.add_material(IsotropicMat::new(ConstantTexture::new(Vec3::new(
+            0.2, 0.4, 0.9,
+        ))));
+        let volume = ConstantMedium::new(Sphere::new(0.7, blue), 0.2, blue);
+        scene.add_object(RenderObject::new(volume).position(3.6, 1.5, 1.45));
+
+        let earth_mat = scene.add_material(match &self.earth {
+            Some(earth) => LambertianMat::new(earth.clone()),
+            None => LambertianMat::new(CheckerTexture::with_colors(
+                Vec3::new(0.1, 0.2, 0.5),
+                Vec3::new(0.2, 0.5, 0.1),
+                10.,
+            )),
+        });
+        scene.add_object(RenderObject::new(Sphere::new(1., earth_mat)).position(4., 2., 4.));
+
+        let noise = TurbulenceTexture::new(5, 10.);
+        let noise = scene.add_material(LambertianMat::new(noise));
+        scene.add_object(RenderObject::new(Sphere::new(0.8, noise)).position(2.2, 2.8, 3.0));
+
+        let mut cluster = SubScene::new();
+        let white = scene.add_material(LambertianMat::with_color(0.73 * Vec3::one()));
+        for _ in 0..self.cluster {
+            let pos = 1.65 * Vec3::new(rand.rand_f32(), rand.rand_f32(), rand.rand_f32());
+            cluster.add_object(RenderObject::new(Sphere::new(0.1, white)).position_vec(pos));
+        }
+        scene.add_object(
+            RenderObject::new(cluster)
+                .rotate(Rotor3::from_rotation_xz(15f32.to_radians()))
+                .position(1., 2.7, 3.95),
+        );
+
+        let fog = scene.add_material(IsotropicMat::new(ConstantTexture::new(Vec3::one())));
+        let fog = ConstantMedium::new(Sphere::new(5000., fog), 0.0001, fog);
+        scene.add_object(RenderObject::new(fog));
+
+        let camera = CameraSettings::default()
+            .cam_pos(Vec3::new(-9., 3., -9.))
+            .look_at(Vec3::new(1., 3., 2.))
+            .field_of_view(25.);
+        scene.set_render_settings(
+            Renderer::default()
+                .width(600)
+                .height(800)
+                .samples(10_000)
+                .use_bvh(true)
+                .camera(camera),
+        );
+
+        scene
+    }
+}
+
+impl Default for FinalScene {
+    fn default() -> Self {
+        FinalScene::new()
+    }
+}
+
+/// The demo scenes of the crate, which are built without reading any files, e.g. to check that
+/// firework works, or to benchmark changes to the renderer. Each includes render settings.
+/// ```
+/// use firework::procgen::Preset;
+///
+/// let preset: Preset = "cornell".parse().unwrap();
+/// assert_eq!(preset, Preset::CornellBox);
+/// assert!(preset.build().render_settings.is_some());
+/// assert!("teapot".parse::<Preset>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// The Cornell box, with two boxes lit by an area light in the ceiling
+    CornellBox,
+    /// The scene on the cover of "Ray Tracing in One Weekend" (see `RandomSpheres`)
+    RandomSpheres,
+    /// Cylinders, disks and a cone, some of them partial, on a ground plane under a sky
+    Conics,
+    /// A purple volume behind a glass sphere, on a ground plane under a sky
+    Volume,
+}
+
+impl Preset {
+    /// All the presets
+    pub const ALL: [Preset; 4] = [
+        Preset::CornellBox,
+        Preset::RandomSpheres,
+        Preset::Conics,
+        Preset::Volume,
+    ];
+
+    /// The name the preset is chosen by, e.g. `random-spheres`
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::CornellBox => "cornell",
+            Preset::RandomSpheres => "random-spheres",
+            Preset::Conics => "conics",
+            Preset::Volume => "volume",
+        }
+    }
+
+    /// Generates the scene
+    pub fn build(self) -> Scene {
+        match self {
+            Preset::CornellBox => cornell_box(),
+            Preset::RandomSpheres => RandomSpheres::new().build(),
+            Preset::Conics => conics(),
+            Preset::Volume => volume(),
+        }
+    }
+
+    /// The seed of the random numbers the scene is generated with, for the presets which use them
+    /// ```
+    /// use firework::procgen::Preset;
+    ///
+    /// assert_eq!(Preset::RandomSpheres.seed(), Some(12345));
+    /// assert_eq!(Preset::CornellBox.seed(), None);
+    /// ```
+    pub fn seed(self) -> Option<u64> {
+        match self {
+            Preset::RandomSpheres => Some(RandomSpheres::new().seed),
+            Preset::CornellBox | Preset::Conics | Preset::Volume => None,
+        }
+    }
+}
+
+impl std::str::FromStr for Preset {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Preset, String> {
+        Preset::ALL
+            .iter()
+            .copied()
+            .find(|preset| preset.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<_> = Preset::ALL.iter().map(|preset| preset.name()).collect();
+                format!(
+                    "unknown preset {:?}, expected one of {}",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+fn cornell_box() -> Scene {
+    let mut scene = Scene::new();
+
+    let red = scene.add_material(LambertianMat::with_color(Vec3::new(0.65, 0.05, 0.05)));
+    let white = scene.add_material(LambertianMat::with_color(Vec3::new(0.73, 0.73, 0.73)));
+    let green = scene.add_material(LambertianMat::with_color(Vec3::new(0.12, 0.45, 0.15)));
+    let light = scene.add_material(EmissiveMat::with_color(Vec3::new(15., 15., 15.)));
+
+    scene.add_light(RenderObject::new(XZRect::new(
+        213., 343., 227., 332., 554., light,
+    )));
+    scene
+        .add_object(RenderObject::new(YZRect::new(0., 555., 0., 555., 555., green)).flip_normals());
+    scene.add_object(RenderObject::new(YZRect::new(0., 555., 0., 555., 0., red)));
+    scene.add_object(RenderObject::new(XZRect::new(
+        0., 555., 0., 555., 0., white,
+    )));
+    scene
+        .add_object(RenderObject::new(XZRect::new(0., 555., 0., 555., 555., white)).flip_normals());
+    scene
+        .add_object(RenderObject::new(XYRect::new(0., 555., 0., 555., 555., white)).flip_normals());
+    scene.add_object(
+        RenderObject::new(Rect3d::with_size(Vec3::new(165., 165., 165.), white))
+            .rotate(Rotor3::from_rotation_xz(18_f32.to_radians()))
+            .position(130., 0., 65.),
+    );
+    scene.add_object(
+        RenderObject::new(Rect3d::with_size(Vec3::new(165., 330., 165.), white))
+            .rotate(Rotor3::from_rotation_xz(-15_f32.to_radians()))
+            .position(265., 0., 295.),
+    );
+
+    let camera = CameraSettings::default()
+        .cam_pos(Vec3::new(278., 278., -800.))
+        .look_at(Vec3::new(278., 278., 0.))
+        .field_of_view(40.);
+    scene.set_render_settings(
+        Renderer::default()
+            .width(300)
+            .height(300)
+            .samples(256)
+            .use_bvh(true)
+            .camera(camera),
+    );
+
+    scene
+}
+
+/// Adds a ground plane, a light off to the side, and a sky to the scene
+fn add_ground_and_sky(scene: &mut Scene, ground: Vec3) {
+    let ground = scene.add_material(LambertianMat::with_color(ground));
+    scene.add_object(RenderObject::new(XZRect::new(
+        -100., 100., -100., 100., 0., ground,
+    )));
+
+    let light = scene.add_material(EmissiveMat::with_color(Vec3::broadcast(8.)));
+    scene.add_object(
+        RenderObject::new(YZRect::new(0., 20., 0., 10., -3., light))
+            .rotate(Rotor3::from_rotation_xz(-30.))
+            .position(0., 0., -10.),
+    );
+
+    scene.set_environment(SkyEnv::default());
+}
+
+fn conics() -> Scene {
+    let mut scene = Scene::new();
+
+    // the example this is from uses a UV test image, which can't be relied on to be there
+    let checker = scene.add_material(LambertianMat::new(CheckerTexture::with_colors(
+        Vec3::new(0.8, 0.3, 0.1),
+        Vec3::new(0.9, 0.9, 0.9),
+        10.,
+    )));
+    let blue = scene.add_material(LambertianMat::with_color(Vec3::new(0., 0.2, 0.4)));
+
+    scene.add_object(RenderObject::new(Cylinder::new(2., 3., checker)).position(-4.2, 0., 0.));
+    scene.add_object(RenderObject::new(Disk::new(2., blue)).position(-4.2, 3., 0.));
+    scene.add_object(RenderObject::new(Cone::new(2., 3., checker)).position(-1., 0., -4.));
+    let tilt =
+        Rotor3::from_euler_angles(90f32.to_radians(), 30f32.to_radians(), -35f32.to_radians());
+    scene.add_object(
+        RenderObject::new(Cylinder::partial(1.5, 3., 300., checker))
+            .position(3.0, 1.5, 1.)
+            .rotate(tilt)
+            .two_sided(),
+    );
+    scene.add_object(
+        RenderObject::new(Disk::partial(1.5, 300., 0.8, checker))
+            .position(3.0, 1.5, 1.)
+            .rotate(tilt),
+    );
+    add_ground_and_sky(&mut scene, Vec3::broadcast(0.5));
+
+    let camera = CameraSettings::default()
+        .cam_pos(Vec3::new(6., 4., -7.))
+        .look_at(Vec3::new(0., 1.5, 0.))
+        .field_of_view(60.);
+    scene.set_render_settings(
+        Renderer::default()
+            .width(960)
+            .height(540)
+            .samples(128)
+            .use_bvh(true)
+            .camera(camera),
+    );
+
+    scene
+}
+
+fn volume() -> Scene {
+    let mut scene = Scene::new();
+
+    let glass = scene.add_material(DielectricMat::new(1.5));
+    let diffuse = scene.add_material(LambertianMat::with_color(Vec3::new(0.8, 0.8, 0.8)));
+    scene.add_volume(
+        RenderObject::new(Sphere::new(1.0, diffuse)).position(0., 1., 0.),
+        0.5,
+        ConstantTexture::from_rgb(0.5, 0.0, 0.8),
+    );
+    scene.add_object(RenderObject::new(Sphere::new(1.01, glass)).position(0., 1., 1.));
+    add_ground_and_sky(&mut scene, Vec3::new(0.8, 0.8, 0.8));
+
+    let camera = CameraSettings::default()
+        .cam_pos(Vec3::new(0., 2., -10.))
+        .look_at(Vec3::zero());
+    scene.set_render_settings(
+        Renderer::default()
+            .width(960)
+            .height(540)
+            .samples(256)
+            .use_bvh(true)
+            .camera(camera),
+    );
+
+    scene
+}
@@ -1,49 +1,582 @@
 use crate::aabb::AABB;
 use crate::camera::{Camera, CameraSettings};
+use crate::checkpoint::Checkpoint;
+use crate::material::{apply_normal_map, sample_henyey_greenstein, Material};
 use crate::ray::Ray;
 use crate::scene::{MaterialIdx, Scene, SceneInternal};
 use crate::util::Color;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
 use tiny_rng::{LcRng, Rand};
 use ultraviolet::{Vec2, Vec3};
 
 /// Performs the ray tracing for a given ray in the world and returns it's color.
 /// TODO: Solve the inconsistency between `scene` and `bvh_root` arguments
+/// `spread` is the angle covered by a single pixel, and `distance` is the length of the path so
+/// far, which are used to estimate the footprint of the ray for texture filtering.
+/// `interiors` are the dielectric objects the ray starts inside of.
+/// `bsdf` is how the material at the previous bounce chose the direction of `r`, or `None` if it
+/// wasn't chosen by a material (or was a specular bounce). If the ray reaches a light or escapes,
+/// it's used to weight the light against the direct sampling in `sample_lights` and
+/// `sample_environment`.
+/// `transparency` is only given for rays seen directly by the camera (possibly through specular
+/// transmission, like glass) when rendering with a transparent background. If the ray escapes,
+/// the environment is left out, and the fraction of the background that would have been seen is
+/// written to it instead.
+/// `throughput` is the fraction of the light arriving along `r` that reaches the camera, and the
+/// light from each light group (see `RenderObject::light_group`) is added to `groups`, scaled by
+/// it.
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn color(
     r: &Ray,
     scene: &SceneInternal,
     root: &impl Hitable,
     depth: usize,
+    spread: f32,
+    distance: f32,
+    interiors: &[Interior],
+    bsdf: Option<BsdfSample>,
+    transparency: Option<&mut Vec3>,
+    throughput: Vec3,
+    groups: &mut [Vec3],
     rand: &mut LcRng,
 ) -> Vec3 {
-    if let Some(hit) = root.hit(r, 0.001, 2e9, rand) {
-        let emit = scene.get_material(hit.material).emit(hit.uv, &hit.point);
+    crate::bench::count_ray(depth);
+    let mut hit = root.hit(r, 0.001, 2e9, rand);
+    let speed = r.direction().mag();
+
+    // sample the distance to the next collision with the atmosphere, which only fills the space
+    // outside of dielectric objects, and keep it if it's closer than the surface
+    let atmosphere = scene
+        .atmosphere
+        .as_ref()
+        .filter(|_| interiors.is_empty())
+        .and_then(|atmosphere| {
+            let limit = hit
+                .as_ref()
+                .map_or(atmosphere.max_distance, |hit| hit.t * speed);
+            let dist = -(1. - rand.rand_f32()).ln() / atmosphere.density;
+            if dist < limit {
+                Some((atmosphere, dist))
+            } else {
+                None
+            }
+        });
+
+    // a collision in one of the media before the surface (or the atmosphere) replaces the hit
+    let t_max = match atmosphere {
+        Some((_, dist)) => dist / speed,
+        None => hit.as_ref().map_or(f32::INFINITY, |hit| hit.t),
+    };
+    let (collision, weight) = scene.sample_media(r, t_max, rand);
+    if collision.is_some() {
+        hit = collision;
+    } else if let Some((atmosphere, dist)) = atmosphere {
+        if depth >= 10 {
+            return Vec3::zero();
+        }
+        let point = r.point(dist / speed);
+        let attenuation = weight * atmosphere.color;
+        let throughput = throughput * attenuation;
+
+        // light the collision directly, like a surface, with the phase function as its material
+        let phase = scene
+            .atmosphere_phase
+            .as_ref()
+            .expect("the phase function is prepared with the atmosphere");
+        let collision = RaycastHit {
+            t: dist / speed,
+            point,
+            normal: Vec3::unit_y(), // arbitrary
+            material: 0,            // unused, the phase function is passed separately
+            uv: Vec2::zero(),
+            tangent: None,
+            footprint: 0.,
+            time: scene.time,
+            priority: 0,
+            outside_ior: 1.,
+            light: None,
+            light_group: None,
+        };
+        let environment = sample_environment(r, &collision, phase, scene, root, interiors, rand);
+        add_to_group(
+            groups,
+            scene.environment_light_group,
+            throughput * environment,
+        );
+        let (lights, group) = sample_lights(r, &collision, phase, scene, root, interiors, rand);
+        add_to_group(groups, group, throughput * lights);
+
+        let wo = -*r.direction() / speed;
+        let direction = sample_henyey_greenstein(atmosphere.g.clamp(-0.99, 0.99), -wo, rand);
+        let incoming = color(
+            &Ray::new(point, direction),
+            scene,
+            root,
+            depth + 1,
+            spread,
+            distance + dist,
+            interiors,
+            Some(BsdfSample {
+                origin: point,
+                pdf: phase.pdf(wo, direction, &collision),
+            }),
+            None,
+            throughput,
+            groups,
+            rand,
+        );
+        return attenuation * (environment + lights + incoming);
+    }
+
+    let mut transparency = transparency;
+    let radiance = shade(
+        r,
+        hit,
+        scene,
+        root,
+        depth,
+        spread,
+        distance,
+        interiors,
+        bsdf,
+        transparency.as_deref_mut(),
+        throughput * weight,
+        groups,
+        rand,
+    );
+    if let Some(transparency) = transparency {
+        *transparency *= weight;
+    }
+    weight * radiance
+}
+
+/// How a material chose the direction of a ray, which is used to weight the light found along it
+/// against the direct lighting done at the same point
+#[derive(Clone, Copy)]
+pub(crate) struct BsdfSample {
+    /// Where the direction was chosen
+    origin: Vec3,
+    /// The probability density of choosing the direction
+    pdf: f32,
+}
+
+/// Finds the light leaving `hit` (the first surface or collision along `r`) towards the origin of
+/// the ray, or the environment if nothing was hit
+#[allow(clippy::too_many_arguments)]
+fn shade(
+    r: &Ray,
+    hit: Option<RaycastHit>,
+    scene: &SceneInternal,
+    root: &impl Hitable,
+    depth: usize,
+    spread: f32,
+    distance: f32,
+    interiors: &[Interior],
+    bsdf: Option<BsdfSample>,
+    transparency: Option<&mut Vec3>,
+    throughput: Vec3,
+    groups: &mut [Vec3],
+    rand: &mut LcRng,
+) -> Vec3 {
+    if let Some(mut hit) = hit {
+        let distance = distance + hit.t * r.direction().mag();
+        hit.footprint = spread * distance;
+        hit.time = scene.time;
+        let material = scene.get_material(hit.material);
+        if let Some(normal_map) = material.normal_map() {
+            apply_normal_map(&mut hit, normal_map);
+        }
+
+        let exiting = r.direction().dot(hit.normal) > 0.;
+        // the interiors the path will be inside of if it passes through this surface
+        let mut crossed = None;
+        if let Some(ior) = material.ior() {
+            let this = Interior {
+                material: hit.material,
+                priority: hit.priority,
+                ior,
+            };
+            let mut next = interiors.to_vec();
+            if exiting {
+                if let Some(i) = next.iter().rposition(|i| *i == this) {
+                    next.remove(i);
+                }
+            } else {
+                next.push(this);
+            }
+
+            // Surfaces of objects inside a medium with a higher priority are ignored, which lets
+            // overlapping objects (like a liquid slightly intersecting its glass) resolve cleanly.
+            // The ray carries on from the surface, but `bsdf` still starts where it was chosen.
+            if interiors.iter().any(|i| i.priority > hit.priority) {
+                return color(
+                    &Ray::new(hit.point, *r.direction()),
+                    scene,
+                    root,
+                    depth,
+                    spread,
+                    distance,
+                    &next,
+                    bsdf,
+                    transparency,
+                    throughput,
+                    groups,
+                    rand,
+                );
+            }
+
+            let outside = if exiting { &next } else { interiors };
+            hit.outside_ior = current_interior(outside).map_or(1., |i| i.ior);
+            crossed = Some(next);
+        }
+
+        let mut emit = material.emit_directional(r, &hit);
+        if let (Some(light), Some(bsdf)) = (hit.light, bsdf) {
+            // weight against the chance of `sample_lights` having chosen this point instead
+            let to_light = hit.point - bsdf.origin;
+            let cos_light = to_light.normalized().dot(hit.normal.normalized()).abs();
+            let light_pdf =
+                scene.light_pdf(light, bsdf.origin, hit.point) * to_light.mag_sq() / cos_light;
+            emit *= power_heuristic(bsdf.pdf, light_pdf);
+        }
+        add_to_group(groups, hit.light_group, throughput * emit);
         if depth < 10 {
-            if let Some(result) = scene.get_material(hit.material).scatter(r, &hit, rand) {
-                emit + result.attenuation * color(&result.scattered, scene, root, depth + 1, rand)
+            let environment = sample_environment(r, &hit, material, scene, root, interiors, rand);
+            add_to_group(
+                groups,
+                scene.environment_light_group,
+                throughput * environment,
+            );
+            let (lights, group) = sample_lights(r, &hit, material, scene, root, interiors, rand);
+            add_to_group(groups, group, throughput * lights);
+            let direct = environment + lights;
+            if let Some(result) = material.scatter(r, &hit, rand) {
+                let transmitted = (result.scattered.direction().dot(hit.normal) > 0.) == exiting;
+                let interiors = match &crossed {
+                    Some(next) if transmitted => next,
+                    _ => interiors,
+                };
+                let bsdf = if result.specular {
+                    None
+                } else {
+                    let wo = -r.direction().normalized();
+                    let wi = result.scattered.direction().normalized();
+                    Some(BsdfSample {
+                        origin: hit.point,
+                        pdf: material.pdf(wo, wi, &hit),
+                    })
+                };
+                // the background stays visible through specular transmission
+                let mut transparency = transparency.filter(|_| result.specular && transmitted);
+                let incoming = color(
+                    &result.scattered,
+                    scene,
+                    root,
+                    depth + 1,
+                    spread,
+                    distance,
+                    interiors,
+                    bsdf,
+                    transparency.as_deref_mut(),
+                    throughput * result.attenuation,
+                    groups,
+                    rand,
+                );
+                if let Some(transparency) = transparency {
+                    *transparency *= result.attenuation;
+                }
+                emit + direct + result.attenuation * incoming
             } else {
-                emit
+                emit + direct
             }
         } else {
             emit
         }
     } else {
-        scene.environment.sample(r.direction().normalized())
+        if let Some(transparency) = transparency {
+            *transparency = Vec3::one();
+            return Vec3::zero();
+        }
+        let dir = r.direction().normalized();
+        if let (0, Some(background)) = (depth, &scene.background) {
+            return background.sample(dir);
+        }
+        let mut radiance = scene.environment.sample(dir);
+        if let Some(bsdf) = bsdf {
+            radiance *= power_heuristic(bsdf.pdf, scene.environment_pdf(bsdf.origin, dir));
+        }
+        add_to_group(groups, scene.environment_light_group, throughput * radiance);
+        radiance
+    }
+}
+
+/// Adds `light` (already scaled by the throughput of the path) to the image of `group`, if it's
+/// in one
+fn add_to_group(groups: &mut [Vec3], group: Option<usize>, light: Vec3) {
+    if let Some(group) = group {
+        groups[group] += light;
+    }
+}
+
+/// Estimates the light arriving at `hit` directly from the environment and leaving towards the
+/// origin of `r`, by choosing a direction with `Environment::sample_direction` (or through one
+/// of the scene's portals) and tracing a shadow ray. This is combined with the directions chosen
+/// by the material using multiple importance sampling, which keeps both small bright lights (like
+/// the sun) and glossy reflections from being noisy.
+fn sample_environment(
+    r: &Ray,
+    hit: &RaycastHit,
+    material: &dyn Material,
+    scene: &SceneInternal,
+    root: &impl Hitable,
+    interiors: &[Interior],
+    rand: &mut LcRng,
+) -> Vec3 {
+    let (wi, light_pdf) = match scene.sample_environment(hit.point, rand) {
+        Some((wi, pdf)) if pdf > 0. => (wi, pdf),
+        _ => return Vec3::zero(),
+    };
+    let radiance = scene.environment.sample(wi);
+    let wo = -r.direction().normalized();
+    let f = material.eval(wo, wi, hit);
+    if radiance == Vec3::zero() || f == Vec3::zero() {
+        return Vec3::zero();
+    }
+
+    let shadow = Ray::new(hit.point, wi);
+    crate::bench::count_shadow_ray();
+    if root.hit(&shadow, 0.001, 2e9, rand).is_some() {
+        return Vec3::zero();
+    }
+    let mut transmittance = scene.transmittance(&shadow, 0.001, f32::INFINITY, rand);
+    if let Some(atmosphere) = scene.atmosphere.as_ref().filter(|_| interiors.is_empty()) {
+        transmittance *= (-atmosphere.density * atmosphere.max_distance).exp();
+    }
+
+    let weight = power_heuristic(light_pdf, material.pdf(wo, wi, hit));
+    f * radiance * transmittance * weight / light_pdf
+}
+
+/// Estimates the light arriving at `hit` directly from the lights in the scene (see
+/// `Scene::add_light` and `Scene::add_analytic_light`) and leaving towards the origin of `r`, by
+/// choosing one of them and tracing a shadow ray towards it. Also returns the light group of the
+/// light chosen.
+fn sample_lights(
+    r: &Ray,
+    hit: &RaycastHit,
+    material: &dyn Material,
+    scene: &SceneInternal,
+    root: &impl Hitable,
+    interiors: &[Interior],
+    rand: &mut LcRng,
+) -> (Vec3, Option<usize>) {
+    match scene.choose_light(hit.point, rand) {
+        Some((light, probability)) => {
+            let radiance = sample_light(
+                r,
+                hit,
+                material,
+                scene,
+                root,
+                interiors,
+                light,
+                probability,
+                rand,
+            );
+            (radiance, scene.light_group(light))
+        }
+        None => (Vec3::zero(), None),
     }
 }
 
+/// Estimates the light arriving at `hit` from the light `light`, which was chosen with the given
+/// probability
+#[allow(clippy::too_many_arguments)]
+fn sample_light(
+    r: &Ray,
+    hit: &RaycastHit,
+    material: &dyn Material,
+    scene: &SceneInternal,
+    root: &impl Hitable,
+    interiors: &[Interior],
+    light: usize,
+    probability: f32,
+    rand: &mut LcRng,
+) -> Vec3 {
+    let wo = -r.direction().normalized();
+    let atmosphere = scene.atmosphere.as_ref().filter(|_| interiors.is_empty());
+
+    // area lights can also be found by the directions the material chooses, so they're combined
+    // with those using multiple importance sampling, like `sample_environment`
+    if light < scene.lights.len() {
+        let object = scene.get_object(scene.lights[light]);
+        let sample = match object.sample_point(hit.point, rand) {
+            Some(sample) if sample.pdf > 0. => sample,
+            _ => return Vec3::zero(),
+        };
+        let to_light = sample.point - hit.point;
+        let distance = to_light.mag();
+        let wi = to_light / distance;
+        let cos_light = wi.dot(sample.normal.normalized()).abs();
+        if cos_light == 0. {
+            return Vec3::zero();
+        }
+        // convert the density to be with respect to solid angle
+        let light_pdf = probability * sample.pdf * distance * distance / cos_light;
+
+        let f = material.eval(wo, wi, hit);
+        if f == Vec3::zero() {
+            return Vec3::zero();
+        }
+
+        // the shadow ray reaches the light at t = 1, and the light's own material decides how
+        // much it emits towards the surface (e.g. one sided lights only emit from the front)
+        let shadow = Ray::new(hit.point, to_light);
+        let epsilon = 0.001 / distance;
+        crate::bench::count_shadow_ray();
+        let mut light_hit = match root.hit(&shadow, epsilon, 1. + epsilon, rand) {
+            Some(light_hit) if light_hit.light == Some(light) && light_hit.t > 1. - epsilon => {
+                light_hit
+            }
+            _ => return Vec3::zero(),
+        };
+        light_hit.time = scene.time;
+        let emit = scene
+            .get_material(light_hit.material)
+            .emit_directional(&shadow, &light_hit);
+        if emit == Vec3::zero() {
+            return Vec3::zero();
+        }
+
+        let mut transmittance = scene.transmittance(&shadow, epsilon, light_hit.t, rand);
+        if let Some(atmosphere) = atmosphere {
+            transmittance *= (-atmosphere.density * distance).exp();
+        }
+
+        let weight = power_heuristic(light_pdf, material.pdf(wo, wi, hit));
+        f * emit * transmittance * weight / light_pdf
+    } else {
+        let analytic = &scene.analytic_lights[light - scene.lights.len()];
+        let sample = match analytic.sample(hit.point, rand) {
+            Some(sample) if sample.pdf > 0. => sample,
+            _ => return Vec3::zero(),
+        };
+        let f = material.eval(wo, sample.wi, hit);
+        if f == Vec3::zero() || sample.radiance == Vec3::zero() {
+            return Vec3::zero();
+        }
+
+        let shadow = Ray::new(hit.point, sample.wi);
+        let t_max = (sample.distance - 0.001).min(2e9);
+        crate::bench::count_shadow_ray();
+        if root.hit(&shadow, 0.001, t_max, rand).is_some() {
+            return Vec3::zero();
+        }
+        let mut transmittance = scene.transmittance(&shadow, 0.001, t_max, rand);
+        if let Some(atmosphere) = atmosphere {
+            let distance = sample.distance.min(atmosphere.max_distance);
+            transmittance *= (-atmosphere.density * distance).exp();
+        }
+
+        let light_pdf = sample.pdf * probability;
+        f * sample.radiance * transmittance / light_pdf
+    }
+}
+
+/// The power heuristic (with an exponent of 2) for multiple importance sampling, the weight for a
+/// sample chosen with density `pdf` when another strategy could have chosen it with `other_pdf`.
+/// See The PBR Book Section 13.10.1.
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0. {
+        a / (a + b)
+    } else {
+        0.
+    }
+}
+
+#[derive(Clone)]
 pub struct RaycastHit {
     pub t: f32,
     pub point: Vec3,
     pub normal: Vec3,
     pub material: MaterialIdx,
     pub uv: Vec2,
+    /// The direction in which `u` increases along the surface (dp/du), if the shape has one.
+    /// Used to build the tangent frame for normal mapping.
+    pub tangent: Option<Vec3>,
+    /// The approximate width of the area on the surface covered by the ray, in world units.
+    /// Filled in by the renderer (shapes should leave it as 0), and used for texture filtering.
+    pub footprint: f32,
+    /// The time (in seconds) of the frame being rendered. Filled in by the renderer (shapes
+    /// should leave it as 0), and used by animated textures.
+    pub time: f32,
+    /// The priority of the object that was hit, see `RenderObject::priority`. Filled in by the
+    /// `RenderObject` (shapes should leave it as 0).
+    pub priority: u32,
+    /// The index of refraction of the medium on the side the normal points to. Filled in by the
+    /// renderer (shapes should leave it as 1), and used by dielectrics.
+    pub outside_ior: f32,
+    /// The index of the light that was hit, if the object is one (see `Scene::add_light`).
+    /// Filled in by the `RenderObject` (shapes should leave it as `None`).
+    pub light: Option<usize>,
+    /// The light group the light emitted by the object goes to, if any (see
+    /// `RenderObject::light_group`). Filled in by the `RenderObject` (shapes should leave it as
+    /// `None`).
+    pub light_group: Option<usize>,
+}
+
+/// A point chosen on the surface of a shape by `Hitable::sample_point`
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceSample {
+    pub point: Vec3,
+    pub normal: Vec3,
+    /// The probability density of choosing the point, with respect to surface area
+    pub pdf: f32,
+}
+
+/// A dielectric object that a path is inside of. The renderer keeps track of these, so that
+/// nested dielectrics (e.g. a liquid in a glass) use the right ratio of refractive indices.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct Interior {
+    material: MaterialIdx,
+    priority: u32,
+    ior: f32,
+}
+
+/// Returns the interior that the path is travelling through: the one with the highest priority,
+/// or the most recently entered one if there's a tie.
+fn current_interior(interiors: &[Interior]) -> Option<&Interior> {
+    interiors.iter().max_by_key(|i| i.priority)
 }
 
 /// Trait that allows something to be ray-traced, i.e. something that can be hit by a ray.
 pub trait Hitable: Sync {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rand: &mut LcRng) -> Option<RaycastHit>;
     fn bounding_box(&self) -> AABB;
+
+    /// Returns the participating medium, if this is one (like `ConstantMedium`). Rays pass
+    /// straight through media, and the renderer samples collisions inside them instead.
+    fn as_medium(&self) -> Option<&dyn Medium> {
+        None
+    }
+
+    /// Chooses a point on the surface to light `origin` from, for shapes used as lights (see
+    /// `Scene::add_light`). Shapes which can't be sampled return `None`, and can't be lights.
+    fn sample_point(&self, _origin: Vec3, _rand: &mut LcRng) -> Option<SurfaceSample> {
+        None
+    }
+
+    /// The probability density (with respect to surface area) that `sample_point` chooses
+    /// `point` when lighting `origin`
+    fn pdf(&self, _origin: Vec3, _point: Vec3) -> f32 {
+        0.
+    }
 }
 
 impl Hitable for Box<dyn Hitable> {
@@ -54,8 +587,69 @@ impl Hitable for Box<dyn Hitable> {
     fn bounding_box(&self) -> AABB {
         self.as_ref().bounding_box()
     }
+
+    fn as_medium(&self) -> Option<&dyn Medium> {
+        self.as_ref().as_medium()
+    }
+
+    fn sample_point(&self, origin: Vec3, rand: &mut LcRng) -> Option<SurfaceSample> {
+        self.as_ref().sample_point(origin, rand)
+    }
+
+    fn pdf(&self, origin: Vec3, point: Vec3) -> f32 {
+        self.as_ref().pdf(origin, point)
+    }
 }
 
+/// A participating medium, like fog, smoke or a cloud, where light scatters at random points
+/// inside a volume rather than at a surface. The renderer finds these collisions with delta
+/// tracking, which handles media whose density varies, and any number of overlapping media.
+pub trait Medium: Sync {
+    /// Returns the parameters at which the ray enters and leaves the medium, if it passes through
+    /// it. If the ray starts inside the medium, the entry is negative.
+    fn interval(&self, r: &Ray, rand: &mut LcRng) -> Option<(f32, f32)>;
+
+    /// The density (the probability of a collision per unit of distance) for each color channel,
+    /// at a point inside the medium. Media with different densities per channel tint the light
+    /// passing through them.
+    fn density(&self, point: Vec3) -> Vec3;
+
+    /// An upper bound on the density of any channel anywhere inside the medium
+    fn max_density(&self) -> f32;
+
+    /// The material (usually a phase function, like `IsotropicMat`) used at collisions
+    fn material(&self) -> MaterialIdx;
+
+    /// Estimates the fraction of light in each channel that passes through the medium along `r`
+    /// between `t_min` and `t_max` without colliding, using ratio tracking
+    fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32, rand: &mut LcRng) -> Vec3 {
+        let (enter, exit) = match self.interval(r, rand) {
+            Some(interval) => interval,
+            None => return Vec3::one(),
+        };
+        let majorant = self.max_density();
+        if !majorant.is_finite() || majorant <= 0. {
+            return Vec3::one();
+        }
+        let end = exit.min(t_max);
+        let mut t = enter.max(t_min);
+        let mut transmittance = Vec3::one();
+        let step = 1. / (majorant * r.direction().mag());
+        loop {
+            t -= (1. - rand.rand_f32()).ln() * step;
+            if t >= end {
+                return transmittance;
+            }
+            transmittance *= (Vec3::one() - self.density(r.point(t)) / majorant).map(|x| x.max(0.));
+        }
+    }
+}
+
+/// The settings of a render. These can also be stored in a scene file, as the
+/// `render_settings` of the `Scene`, where anything left out is taken from
+/// `Renderer::scene_default`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default = "Renderer::scene_default")]
 pub struct Renderer {
     /// The width of the render (in pixels)
     pub width: usize,
@@ -67,6 +661,9 @@ pub struct Renderer {
     /// TODO: Make this a cargo feature or something, so we don't pull rayon in as a dependency
     /// unless we must to
     pub multithreaded: bool,
+    /// The number of threads to render with when `multithreaded` is set, e.g. to leave some of
+    /// the cores of a shared machine free. Every core is used if it's unset.
+    pub threads: Option<usize>,
     /// Whether or not to us a bounding volume hierarchy. Recommended only for scenes with a
     /// large number of objects
     pub use_bvh: bool,
@@ -74,9 +671,186 @@ pub struct Renderer {
     pub gamma: f32,
     /// The settings to create the camera
     camera: CameraSettings,
+    /// If set, outlines are drawn along depth and normal discontinuities after rendering
+    pub outline: Option<Outline>,
+    /// The time (in seconds) of the frame being rendered, used by animated textures
+    pub time: f32,
+    /// If true, the environment seen directly by the camera (or through glass) is left out, and
+    /// the alpha of those pixels is 0, so the render can be composited over other images
+    pub transparent_background: bool,
+    /// If set, only the pixels in this rectangle are rendered, given as `[x, y, width, height]`
+    /// (in pixels, from the top left), and the rest are left black and transparent, e.g. to
+    /// look closely at part of an image without rendering all of it
+    pub crop: Option<[usize; 4]>,
+}
+
+/// A block of pixels of a render in progress, with the colors (before gamma correction) of the
+/// samples taken so far, row by row
+// only read by `RenderWindow`
+#[cfg_attr(not(feature = "window"), allow(dead_code))]
+pub(crate) struct Tile {
+    /// The column of the top left pixel
+    pub x: usize,
+    /// The row of the top left pixel
+    pub y: usize,
+    pub width: usize,
+    pub color: Vec<Vec3>,
+    /// The number of samples taken of each pixel so far
+    pub samples: usize,
+}
+
+/// The pixels outlines are drawn over (see `Outline`)
+struct OutlinePixels {
+    edges: Vec<bool>,
+    /// The color of the outlines, with the gamma correction undone
+    color: Vec3,
+}
+
+/// What a progressive render sends as it goes, so the tiles being rendered can be shown
+#[cfg_attr(not(feature = "window"), allow(dead_code))]
+pub(crate) enum TileUpdate {
+    /// A pass over the tile with its top left pixel at `(x, y)`, of the given size, has started
+    Started {
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    },
+    /// A pass over a tile is done
+    Done(Tile),
+}
+
+/// The width and height of the tiles of a progressive render, in pixels (except at the right
+/// and bottom edges of the image, or the crop window, where they're cut off)
+pub(crate) const TILE_SIZE: usize = 32;
+
+/// The most samples taken of the pixels of a tile at once while checkpointing
+const MAX_CHECKPOINT_PASS: usize = 16;
+
+/// A tile of a progressive render, with the random numbers and the sums of the samples of each
+/// of its pixels
+pub(crate) struct TileState {
+    pub(crate) x: usize,
+    pub(crate) y: usize,
+    pub(crate) width: usize,
+    pub(crate) samples: usize,
+    pub(crate) rngs: Vec<LcRng>,
+    pub(crate) totals: Vec<(Vec3, f32, Vec<Vec3>)>,
+}
+
+/// The result of a render, before gamma correction. Colors are linear, and premultiplied by
+/// alpha (i.e. a pixel that is half covered by an object has half of its color, and an alpha of
+/// 0.5). Pixels are stored row by row, starting from the top left.
+#[derive(Debug, Clone)]
+pub struct RenderBuffer {
+    pub width: usize,
+    pub height: usize,
+    pub color: Vec<Vec3>,
+    /// The coverage of each pixel, 1 unless the background is transparent (see
+    /// `Renderer::transparent_background`)
+    pub alpha: Vec<f32>,
+    /// The light from each light group by itself (see `RenderObject::light_group`), by name
+    pub light_groups: BTreeMap<String, Vec<Vec3>>,
+}
+
+impl RenderBuffer {
+    /// Creates a black, fully opaque buffer
+    pub fn new(width: usize, height: usize) -> RenderBuffer {
+        RenderBuffer {
+            width,
+            height,
+            color: vec![Vec3::zero(); width * height],
+            alpha: vec![1.; width * height],
+            light_groups: BTreeMap::new(),
+        }
+    }
+
+    /// Gamma corrects and quantizes the colors, as they'd appear over a black background
+    pub fn to_colors(&self, gamma: f32) -> Vec<Color> {
+        self.color
+            .iter()
+            .map(|c| c.map(|x| x.powf(1. / gamma).clamp(0., 1.)).into())
+            .collect()
+    }
+
+    /// Gamma corrects and quantizes the colors, and returns the red, green, blue and alpha of
+    /// each pixel, with the colors divided by alpha (as expected by PNG files).
+    pub fn to_rgba8(&self, gamma: f32) -> Vec<u8> {
+        self.color
+            .iter()
+            .zip(&self.alpha)
+            .flat_map(|(c, &a)| {
+                let c = if a > 0. { *c / a } else { Vec3::zero() };
+                let Color(r, g, b) = c.map(|x| x.powf(1. / gamma).clamp(0., 1.)).into();
+                [r, g, b, (a.clamp(0., 1.) * 255.99) as u8]
+            })
+            .collect()
+    }
+
+    /// Like `to_rgba8`, but with 16 bits for each value, for smooth gradients without banding
+    pub fn to_rgba16(&self, gamma: f32) -> Vec<u16> {
+        let quantize = |x: f32| (x.clamp(0., 1.) * 65535. + 0.5) as u16;
+        self.color
+            .iter()
+            .zip(&self.alpha)
+            .flat_map(|(c, &a)| {
+                let c = if a > 0. { *c / a } else { Vec3::zero() };
+                let c = c.map(|x| x.max(0.).powf(1. / gamma));
+                [quantize(c.x), quantize(c.y), quantize(c.z), quantize(a)]
+            })
+            .collect()
+    }
+}
+
+/// Settings for the outline post-process, which detects edges from the depth and normals of the
+/// surfaces seen through the center of each pixel. Useful for non-photorealistic renders (see
+/// `ToonMat`).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Outline {
+    /// The color of the outlines, in the range 0..1
+    pub color: Vec3,
+    /// The relative difference in depth between neighbouring pixels required to draw an outline
+    pub depth_threshold: f32,
+    /// The angle (in degrees) between the normals of neighbouring pixels required to draw an
+    /// outline
+    pub normal_threshold: f32,
+}
+
+impl Default for Outline {
+    fn default() -> Self {
+        Outline {
+            color: Vec3::zero(),
+            depth_threshold: 0.1,
+            normal_threshold: 30.,
+        }
+    }
 }
 
 impl Renderer {
+    /// The settings used for anything a scene file's `render_settings` leave out, and by the
+    /// command line for scenes without them: a 960x540 render using a BVH, looking at the origin
+    /// from above, and otherwise the same as `Renderer::default`.
+    /// ```
+    /// use firework::Renderer;
+    ///
+    /// let renderer: Renderer = serde_yaml::from_str("samples: 16").unwrap();
+    /// assert_eq!((renderer.width, renderer.samples), (960, 16));
+    /// assert!(renderer.use_bvh);
+    /// ```
+    pub fn scene_default() -> Renderer {
+        let camera = CameraSettings::default()
+            .cam_pos(Vec3::new(0., 30., 50.))
+            .look_at(Vec3::new(0., 0., 0.))
+            .field_of_view(40.);
+
+        Renderer::default()
+            .width(960)
+            .height(540)
+            .use_bvh(true)
+            .camera(camera)
+    }
+
     pub fn width(mut self, width: usize) -> Renderer {
         self.width = width;
         self
@@ -93,6 +867,18 @@ impl Renderer {
         self.multithreaded = multithreaded;
         self
     }
+    /// Limits the number of threads used to render (see `Renderer::threads`)
+    /// ```
+    /// use firework::{Renderer, Scene};
+    ///
+    /// let renderer = Renderer::default().width(8).height(8).samples(1).threads(2);
+    /// let buffer = renderer.render_buffer(Scene::new());
+    /// assert_eq!(buffer.color.len(), 64);
+    /// ```
+    pub fn threads(mut self, threads: usize) -> Renderer {
+        self.threads = Some(threads);
+        self
+    }
     pub fn use_bvh(mut self, use_bvh: bool) -> Renderer {
         self.use_bvh = use_bvh;
         self
@@ -105,14 +891,183 @@ impl Renderer {
         self.camera = settings;
         self
     }
+    /// The settings the camera is created with
+    pub fn camera_settings(&self) -> CameraSettings {
+        self.camera
+    }
+    pub fn outline(mut self, outline: Outline) -> Renderer {
+        self.outline = Some(outline);
+        self
+    }
+    pub fn time(mut self, time: f32) -> Renderer {
+        self.time = time;
+        self
+    }
+    pub fn transparent_background(mut self, transparent_background: bool) -> Renderer {
+        self.transparent_background = transparent_background;
+        self
+    }
+    /// Only renders the pixels in the rectangle with its top left pixel at `(x, y)`, of the given
+    /// size
+    /// ```
+    /// use firework::{Renderer, Scene};
+    ///
+    /// let renderer = Renderer::default().width(8).height(8).samples(1);
+    /// let buffer = renderer.crop(2, 2, 4, 4).render_buffer(Scene::new());
+    /// assert_eq!(buffer.alpha[0], 0.);
+    /// assert_eq!(buffer.alpha[3 * 8 + 3], 1.);
+    /// ```
+    pub fn crop(mut self, x: usize, y: usize, width: usize, height: usize) -> Renderer {
+        self.crop = Some([x, y, width, height]);
+        self
+    }
 
+    /// The pixels to render, as the rows and columns from the top left to (but not including)
+    /// the bottom right of the crop window, or the whole image
+    fn crop_bounds(&self) -> ([usize; 2], [usize; 2]) {
+        match self.crop {
+            Some([x, y, width, height]) => {
+                let (x, y) = (x.min(self.width), y.min(self.height));
+                (
+                    [x, y],
+                    [(x + width).min(self.width), (y + height).min(self.height)],
+                )
+            }
+            None => ([0, 0], [self.width, self.height]),
+        }
+    }
+
+    /// The tiles of a progressive render, as `[x, y, width, height]`, row by row
+    pub(crate) fn tile_rects(&self) -> Vec<[usize; 4]> {
+        let ([x0, y0], [x1, y1]) = self.crop_bounds();
+        let mut rects = Vec::new();
+        for y in (y0..y1).step_by(TILE_SIZE) {
+            for x in (x0..x1).step_by(TILE_SIZE) {
+                rects.push([x, y, TILE_SIZE.min(x1 - x), TILE_SIZE.min(y1 - y)]);
+            }
+        }
+        rects
+    }
+
+    /// Whether a pixel is in the crop window, if there is one
+    fn in_crop(&self, idx: usize) -> bool {
+        let ([x0, y0], [x1, y1]) = self.crop_bounds();
+        let (x, y) = (idx % self.width, idx / self.width);
+        (x0..x1).contains(&x) && (y0..y1).contains(&y)
+    }
+
+    /// Renders the scene, and returns the gamma corrected colors of each pixel
     pub fn render(&self, scene: Scene) -> Vec<Color> {
+        self.render_buffer(scene).to_colors(self.gamma)
+    }
+
+    /// Renders the scene, and returns the linear colors and alpha of each pixel. Use this to keep
+    /// the full dynamic range of the image, or to save it with transparency.
+    /// ```no_run
+    /// use firework::render::Renderer;
+    /// use firework::output::save_image_rgba;
+    /// use firework::Scene;
+    ///
+    /// let renderer = Renderer::default().transparent_background(true);
+    /// let buffer = renderer.render_buffer(Scene::new());
+    /// save_image_rgba(&buffer, "render.png", renderer.gamma);
+    /// ```
+    pub fn render_buffer(&self, scene: Scene) -> RenderBuffer {
+        self.render_prepared(&self.prepare(scene), None, None)
+    }
+
+    /// Renders the scene like `render_buffer`, saving its progress to the file at `path` every
+    /// `interval`, so that a long render which is stopped (e.g. by a crash or a reboot) can carry
+    /// on from where it was saved, by rendering it again with the same file. The render starts
+    /// from the beginning if the file doesn't exist yet, and the file is left once it's done.
+    ///
+    /// The scene and the render settings must be the same as when the file was saved, except
+    /// for the number of samples, which can be raised to refine a finished render. Only the size
+    /// of the image, the crop window and the light groups are checked, and a file which doesn't
+    /// match them is an error. A resumed render has as many samples as one which wasn't stopped,
+    /// but isn't identical to it, since the random numbers it was using can't be saved.
+    /// ```
+    /// use firework::{Renderer, Scene};
+    /// use std::time::Duration;
+    ///
+    /// let path = std::env::temp_dir().join("firework_render_checkpointed.ckpt");
+    /// # let _ = std::fs::remove_file(&path);
+    /// let renderer = Renderer::default().width(40).height(30).samples(4);
+    /// // saved after every tile
+    /// let always = Duration::from_secs(0);
+    /// let buffer = renderer.render_checkpointed(Scene::new(), &path, always);
+    /// assert!(buffer.is_ok() && path.exists());
+    ///
+    /// // carries on from 4 samples
+    /// let buffer = renderer.samples(8).render_checkpointed(Scene::new(), &path, always);
+    /// assert!(buffer.is_ok());
+    ///
+    /// // the checkpoint doesn't match a render of a different size
+    /// let renderer = Renderer::default().width(20).height(30).samples(4);
+    /// assert!(renderer.render_checkpointed(Scene::new(), &path, always).is_err());
+    /// ```
+    pub fn render_checkpointed(
+        &self,
+        scene: Scene,
+        path: impl AsRef<Path>,
+        interval: Duration,
+    ) -> std::io::Result<RenderBuffer> {
+        let scene = self.prepare(scene);
+        let checkpoint = Checkpoint::open(path.as_ref(), interval, self, &scene)?;
+        let buffer = self.render_prepared(&scene, None, Some(&checkpoint));
+        checkpoint.save()?;
+        Ok(buffer)
+    }
+
+    /// Converts a scene into the form it's rendered in
+    pub(crate) fn prepare(&self, scene: Scene) -> SceneInternal {
+        let mut scene: SceneInternal = scene.into();
+        scene.time = self.time;
+        scene
+    }
+
+    /// Renders a prepared scene. If `progress` is given, the image is rendered in passes over
+    /// tiles, which are sent to the channel as they're started and done (see `render_passes`).
+    /// The same goes for `checkpoint`, which the progress of the tiles is saved to.
+    pub(crate) fn render_prepared(
+        &self,
+        scene: &SceneInternal,
+        progress: Option<(Sender<TileUpdate>, &AtomicBool)>,
+        checkpoint: Option<&Checkpoint>,
+    ) -> RenderBuffer {
+        let threads = if self.multithreaded {
+            self.threads
+        } else {
+            Some(1)
+        };
+        match threads {
+            // rendering in a thread pool of its own keeps it to that many threads
+            Some(threads) => rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .expect("Renderer::render() -- the threads to render with couldn't be started")
+                .install(|| self.render_in_pool(scene, progress, checkpoint)),
+            None => self.render_in_pool(scene, progress, checkpoint),
+        }
+    }
+
+    /// Renders a prepared scene (see `render_prepared`) on the current thread pool
+    fn render_in_pool(
+        &self,
+        scene: &SceneInternal,
+        progress: Option<(Sender<TileUpdate>, &AtomicBool)>,
+        checkpoint: Option<&Checkpoint>,
+    ) -> RenderBuffer {
         use crate::bvh::Aggregate;
         use rayon::prelude::*;
 
-        let scene: SceneInternal = scene.into();
-
-        let mut buffer = vec![Color(0, 0, 0); self.width * self.height];
+        // the pixels outside of the crop window are left black and transparent
+        let empty = (
+            Vec3::zero(),
+            0.,
+            vec![Vec3::zero(); scene.light_groups.len()],
+        );
+        let mut pixels = vec![empty; self.width * self.height];
 
         let bvh = if self.use_bvh {
             Some(scene.build_bvh())
@@ -122,17 +1077,39 @@ impl Renderer {
 
         let camera = self.camera.create_camera(self.width, self.height);
 
-        if self.multithreaded {
+        // the outlines are found first, so the tiles of a progressive render are shown with them
+        let outlines = self.outline.as_ref().map(|outline| {
+            if let Some(bvh) = &bvh {
+                self.outline_pixels(bvh, &camera, outline)
+            } else {
+                self.outline_pixels(scene, &camera, outline)
+            }
+        });
+
+        if progress.is_some() || checkpoint.is_some() {
+            // without a window, nothing listens for the tiles, and the render isn't cancelled
+            let never = AtomicBool::new(false);
+            let progress = progress.unwrap_or_else(|| (std::sync::mpsc::channel().0, &never));
+            let outlines = outlines.as_ref();
+            pixels = if let Some(bvh) = &bvh {
+                self.render_passes(scene, bvh, &camera, progress, checkpoint, outlines)
+            } else {
+                self.render_passes(scene, scene, &camera, progress, checkpoint, outlines)
+            };
+        } else if self.multithreaded {
             let completed = AtomicUsize::new(0);
-            buffer.par_iter_mut().enumerate().for_each(|(idx, pix)| {
+            pixels.par_iter_mut().enumerate().for_each(|(idx, pix)| {
+                if !self.in_crop(idx) {
+                    return;
+                }
                 if let Some(bvh) = &bvh {
-                    *pix = self.render_pixel(&scene, bvh, &camera, idx)
+                    *pix = self.render_pixel(scene, bvh, &camera, idx)
                 } else {
-                    *pix = self.render_pixel(&scene, &scene, &camera, idx)
+                    *pix = self.render_pixel(scene, scene, &camera, idx)
                 }
                 let count = completed.fetch_add(1, Ordering::SeqCst);
-                if count % 10000 == 0 {
-                    println!(
+                if count.is_multiple_of(10000) {
+                    eprintln!(
                         "Completed {}/{}",
                         count / 10000,
                         self.width * self.height / 10000
@@ -140,15 +1117,18 @@ impl Renderer {
                 }
             })
         } else {
-            buffer.iter_mut().enumerate().for_each(|(idx, pix)| {
+            pixels.iter_mut().enumerate().for_each(|(idx, pix)| {
+                if !self.in_crop(idx) {
+                    return;
+                }
                 if let Some(bvh) = &bvh {
-                    *pix = self.render_pixel(&scene, bvh, &camera, idx)
+                    *pix = self.render_pixel(scene, bvh, &camera, idx)
                 } else {
-                    *pix = self.render_pixel(&scene, &scene, &camera, idx)
+                    *pix = self.render_pixel(scene, scene, &camera, idx)
                 }
 
                 if idx % 10000 == 0 {
-                    println!(
+                    eprintln!(
                         "Completed {}/{}",
                         idx / 10000,
                         self.width * self.height / 10000
@@ -157,43 +1137,277 @@ impl Renderer {
             })
         }
 
+        let mut buffer = RenderBuffer::new(self.width, self.height);
+        let mut groups = vec![Vec::with_capacity(pixels.len()); scene.light_groups.len()];
+        for (i, (color, alpha, light)) in pixels.into_iter().enumerate() {
+            buffer.color[i] = color;
+            buffer.alpha[i] = alpha;
+            for (group, light) in groups.iter_mut().zip(light) {
+                group.push(light);
+            }
+        }
+        buffer.light_groups = scene.light_groups.iter().cloned().zip(groups).collect();
+
+        if let Some(outlines) = &outlines {
+            for idx in 0..buffer.color.len() {
+                if outlines.edges[idx] && self.in_crop(idx) {
+                    buffer.color[idx] = outlines.color;
+                    buffer.alpha[idx] = 1.;
+                }
+            }
+        }
+
         buffer
     }
 
+    /// Finds the distance to, and normal of, the surface seen through the center of each pixel
+    fn geometry_buffer(&self, root: &impl Hitable, camera: &Camera) -> Vec<Option<(f32, Vec3)>> {
+        use crate::util::Coord;
+        use rayon::prelude::*;
+
+        (0..self.width * self.height)
+            .into_par_iter()
+            .map(|idx| {
+                let mut rng = LcRng::new(idx as u64);
+                let pos = Coord::from_index(idx, self.width, self.height);
+                let u = (pos.0 as f32 + 0.5) / self.width as f32;
+                let v = (pos.1 as f32 + 0.5) / self.height as f32;
+                let ray = camera.ray(u, v, &mut rng);
+                root.hit(&ray, 0.001, 2e9, &mut rng)
+                    .map(|hit| (hit.t * ray.direction().mag(), hit.normal.normalized()))
+            })
+            .collect()
+    }
+
+    /// Finds the pixels outlines are drawn over, from the surfaces seen through them
+    fn outline_pixels(
+        &self,
+        root: &impl Hitable,
+        camera: &Camera,
+        outline: &Outline,
+    ) -> OutlinePixels {
+        let geometry = self.geometry_buffer(root, camera);
+        let cos_threshold = outline.normal_threshold.to_radians().cos();
+        let is_edge = |a: &Option<(f32, Vec3)>, b: &Option<(f32, Vec3)>| match (a, b) {
+            (None, None) => false,
+            (Some(_), None) | (None, Some(_)) => true,
+            (Some((d1, n1)), Some((d2, n2))) => {
+                (d1 - d2).abs() / d1.min(*d2) > outline.depth_threshold
+                    || n1.dot(*n2) < cos_threshold
+            }
+        };
+
+        let mut edges = vec![false; self.width * self.height];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let idx = y * self.width + x;
+                let right = x + 1 < self.width && is_edge(&geometry[idx], &geometry[idx + 1]);
+                let below =
+                    y + 1 < self.height && is_edge(&geometry[idx], &geometry[idx + self.width]);
+                edges[idx] = right || below;
+            }
+        }
+        OutlinePixels {
+            edges,
+            // undo the gamma correction, so the outlines end up as `outline.color`
+            color: outline.color.map(|x| x.clamp(0., 1.).powf(self.gamma)),
+        }
+    }
+
+    /// Renders the pixels in passes over tiles of the image, each taking twice as many samples of
+    /// every pixel as the one before. Each tile is sent to `tiles` when a pass over it starts,
+    /// and once it's done (with the `outlines` drawn over it), so the image can be shown while it
+    /// forms. The pixels end up the same as with `render_pixel`, unless `cancel` is set, which
+    /// stops the render after the tiles being rendered, or the render is resumed from a
+    /// checkpoint.
+    fn render_passes(
+        &self,
+        scene: &SceneInternal,
+        root: &impl Hitable,
+        camera: &Camera,
+        (tiles, cancel): (Sender<TileUpdate>, &AtomicBool),
+        checkpoint: Option<&Checkpoint>,
+        outlines: Option<&OutlinePixels>,
+    ) -> Vec<(Vec3, f32, Vec<Vec3>)> {
+        use rayon::prelude::*;
+
+        let mut states: Vec<TileState> = self
+            .tile_rects()
+            .into_iter()
+            .map(|[x, y, width, height]| {
+                let pixels =
+                    (0..width * height).map(|i| (y + i / width) * self.width + x + i % width);
+                TileState {
+                    x,
+                    y,
+                    width,
+                    samples: 0,
+                    rngs: pixels.map(|idx| LcRng::new(idx as u64)).collect(),
+                    totals: vec![
+                        (
+                            Vec3::zero(),
+                            0.,
+                            vec![Vec3::zero(); scene.light_groups.len()]
+                        );
+                        width * height
+                    ],
+                }
+            })
+            .collect();
+        if let Some(checkpoint) = checkpoint {
+            checkpoint.resume(&mut states);
+        }
+
+        let mut pass = 1;
+        while states.iter().any(|tile| tile.samples < self.samples)
+            && !cancel.load(Ordering::Relaxed)
+        {
+            states.par_iter_mut().enumerate().for_each_with(
+                tiles.clone(),
+                |tiles, (tile_idx, tile)| {
+                    let samples = pass.min(self.samples.saturating_sub(tile.samples));
+                    if samples == 0 || cancel.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    // nothing may be listening any more, which doesn't matter
+                    let (x, y, width) = (tile.x, tile.y, tile.width);
+                    let height = tile.rngs.len() / width;
+                    let _ = tiles.send(TileUpdate::Started {
+                        x,
+                        y,
+                        width,
+                        height,
+                    });
+                    for (i, (rng, total)) in tile.rngs.iter_mut().zip(&mut tile.totals).enumerate()
+                    {
+                        let idx = (tile.y + i / tile.width) * self.width + tile.x + i % tile.width;
+                        self.sample_pixel(scene, root, camera, idx, samples, rng, total);
+                    }
+                    tile.samples += samples;
+                    let color = tile
+                        .totals
+                        .iter()
+                        .enumerate()
+                        .map(|(i, (color, _, _))| {
+                            let idx = (y + i / width) * self.width + x + i % width;
+                            match outlines {
+                                Some(outlines) if outlines.edges[idx] => outlines.color,
+                                _ => *color / tile.samples as f32,
+                            }
+                        })
+                        .collect();
+                    let _ = tiles.send(TileUpdate::Done(Tile {
+                        x,
+                        y,
+                        width,
+                        color,
+                        samples: tile.samples,
+                    }));
+                    if let Some(checkpoint) = checkpoint {
+                        checkpoint.tile_done(tile_idx, tile, self.samples);
+                    }
+                },
+            );
+            // passes are kept short while checkpointing, so the tiles are saved often
+            pass = match checkpoint {
+                Some(_) => (pass * 2).min(MAX_CHECKPOINT_PASS),
+                None => pass * 2,
+            };
+        }
+
+        let empty = (
+            Vec3::zero(),
+            0.,
+            vec![Vec3::zero(); scene.light_groups.len()],
+        );
+        let mut pixels = vec![empty; self.width * self.height];
+        for tile in states {
+            let samples = tile.samples.max(1) as f32;
+            for (i, (color, alpha, groups)) in tile.totals.into_iter().enumerate() {
+                let idx = (tile.y + i / tile.width) * self.width + tile.x + i % tile.width;
+                let groups = groups.into_iter().map(|light| light / samples).collect();
+                pixels[idx] = (color / samples, alpha / samples, groups);
+            }
+        }
+        pixels
+    }
+
     fn render_pixel(
         &self,
         scene: &SceneInternal,
         root: &impl Hitable,
         camera: &Camera,
         idx: usize,
-    ) -> Color {
-        use crate::util::Coord;
+    ) -> (Vec3, f32, Vec<Vec3>) {
         // NOTE: I have no idea if seeding the Rng with the idx is valid.
         let mut rng = LcRng::new(idx as u64);
-        let pos = Coord::from_index(idx, self.width, self.height);
-
-        let mut total_color = Vec3::zero();
-
-        for _ in 0..self.samples {
-            let u = (pos.0 as f32 + rng.rand_f32()) / self.width as f32;
-            let v = (pos.1 as f32 + rng.rand_f32()) / self.height as f32;
-            let ray = camera.ray(u, v, &mut rng);
-            total_color += color(&ray, &scene, root, 0, &mut rng);
-        }
+        let mut total = (
+            Vec3::zero(),
+            0.,
+            vec![Vec3::zero(); scene.light_groups.len()],
+        );
+        self.sample_pixel(scene, root, camera, idx, self.samples, &mut rng, &mut total);
+        let (mut total_color, mut total_alpha, mut total_groups) = total;
 
         total_color /= self.samples as f32;
-        total_color = total_color
-            .map(|x| x.powf(1. / self.gamma))
-            .map(|x| x.clamp(0., 1.));
-
-        let colori: Color = total_color.into();
-        colori
+        total_alpha /= self.samples as f32;
+        for light in &mut total_groups {
+            *light /= self.samples as f32;
+        }
+        (total_color, total_alpha, total_groups)
 
         //let count = completed.fetch_add(1, Ordering::SeqCst);
         //if idx % 10000 == 0 {
         //println!("Completed {}/{}", count / 10000, WIDTH * HEIGHT / 10000)
         //}
     }
+
+    /// Takes `samples` more samples of the pixel at `idx`, adding their color, alpha and the
+    /// light in each light group to `total`
+    #[allow(clippy::too_many_arguments)]
+    fn sample_pixel(
+        &self,
+        scene: &SceneInternal,
+        root: &impl Hitable,
+        camera: &Camera,
+        idx: usize,
+        samples: usize,
+        rng: &mut LcRng,
+        total: &mut (Vec3, f32, Vec<Vec3>),
+    ) {
+        use crate::util::Coord;
+        let pos = Coord::from_index(idx, self.width, self.height);
+        let (total_color, total_alpha, total_groups) = total;
+
+        for _ in 0..samples {
+            let u = (pos.0 as f32 + rng.rand_f32()) / self.width as f32;
+            let v = (pos.1 as f32 + rng.rand_f32()) / self.height as f32;
+            let ray = camera.ray(u, v, rng);
+            let mut transparency = if self.transparent_background {
+                Some(Vec3::zero())
+            } else {
+                None
+            };
+            *total_color += color(
+                &ray,
+                scene,
+                root,
+                0,
+                camera.pixel_spread(),
+                0.,
+                &[],
+                None,
+                transparency.as_mut(),
+                Vec3::one(),
+                total_groups,
+                rng,
+            );
+            // the fraction of the (transparent) background seen through this sample
+            let background = transparency.map_or(0., |t| (t.x + t.y + t.z) / 3.);
+            *total_alpha += 1. - background.clamp(0., 1.);
+        }
+        crate::bench::flush_rays();
+    }
 }
 
 impl Default for Renderer {
@@ -202,17 +1416,27 @@ impl Default for Renderer {
     /// height: 1080
     /// samples: 128
     /// multithreaded: true
+    /// threads: None
     /// use_bvh: false
     /// gamma: 2.2
+    /// outline: None
+    /// time: 0
+    /// transparent_background: false
+    /// crop: None
     fn default() -> Self {
         Renderer {
             width: 1920,
             height: 1080,
             samples: 128,
             multithreaded: true,
+            threads: None,
             use_bvh: false,
             gamma: 2.2,
             camera: Default::default(),
+            outline: None,
+            time: 0.,
+            transparent_background: false,
+            crop: None,
         }
     }
 }
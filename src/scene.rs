@@ -1,26 +1,611 @@
 use crate::aabb::AABB;
-use crate::environment::{ColorEnv, Environment};
-use crate::material::Material;
+use crate::animation::Animation;
+use crate::assets::AssetPaths;
+use crate::environment::{ColorEnv, Environment, Portal};
+use crate::light::Light;
+use crate::light_bvh::{area_light_bounds, LightBVH};
+use crate::material::{HenyeyGreensteinMat, Material};
 use crate::ray::Ray;
-use crate::render::{Hitable, RaycastHit};
+use crate::render::{Hitable, RaycastHit, Renderer, SurfaceSample};
 use crate::serde_compat::SerializableShape;
+use crate::texture::{ConstantTexture, ImageTexture, SharedTexture, Texture};
 use itertools::iproduct;
 use serde::{Deserialize, Serialize};
-use tiny_rng::LcRng;
-use ultraviolet::{Mat3, Rotor3, Vec3};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use tiny_rng::{LcRng, Rand};
+use ultraviolet::{Mat3, Rotor3, Vec2, Vec3};
 
 /// Used to index `Material`s in a `Scene`
 pub type MaterialIdx = usize;
 
-/// Used to index `Material`s in a `Scene`
-pub type RenderObjectIdx = usize;
+/// A handle to a `RenderObject` in a `Scene`, returned when it's added. A handle keeps referring
+/// to the same object until it's removed, and is never reused for another object after that. In
+/// scene files, it's written as the object's position in `render_objects`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RenderObjectIdx(pub(crate) usize);
+
+impl RenderObjectIdx {
+    /// The handle of the same object once the objects of its scene are appended to another, after
+    /// `offset` objects
+    pub(crate) fn offset(self, offset: usize) -> RenderObjectIdx {
+        RenderObjectIdx(self.0 + offset)
+    }
+}
+
+impl std::fmt::Display for RenderObjectIdx {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Used to index `Group`s in a `Scene`
+pub type GroupIdx = usize;
 
 /// Represents a Scene
 #[derive(Serialize, Deserialize)]
 pub struct Scene {
-    pub render_objects: Vec<RenderObject>,
+    /// The objects in the scene, by `RenderObjectIdx`. Removed objects leave an empty slot
+    /// behind, so the indices of the others stay the same.
+    #[serde(default)]
+    pub render_objects: Vec<Option<RenderObject>>,
+    #[serde(default)]
     pub materials: Vec<Box<dyn Material + 'static>>, // TODO: Remove the layer of indirection here
+    #[serde(default = "default_environment")]
     pub environment: Box<dyn Environment + 'static>,
+    /// Other scene files whose contents are added to this one when it's read from a file (see
+    /// `Scene::from_path`). Empty once the scene has been read.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<PathBuf>,
+    /// Maps names to materials, so they can be referred to without remembering indices
+    #[serde(default)]
+    material_names: BTreeMap<String, MaterialIdx>,
+    /// Maps names to objects, so they can be referred to without remembering indices
+    #[serde(default)]
+    object_names: BTreeMap<String, RenderObjectIdx>,
+    /// Incremented every time the scene is modified through its methods
+    #[serde(skip)]
+    revision: u64,
+    /// Textures loaded with `load_texture`, by path
+    #[serde(skip)]
+    textures: BTreeMap<PathBuf, SharedTexture>,
+    /// A medium filling the whole scene, if any
+    #[serde(default)]
+    pub atmosphere: Option<Atmosphere>,
+    /// If set, this is seen directly by the camera instead of `environment`, which then only
+    /// lights the scene
+    #[serde(default)]
+    pub background: Option<Box<dyn Environment + 'static>>,
+    /// The objects which are sampled directly as lights (see `add_light`)
+    #[serde(default)]
+    pub lights: Vec<RenderObjectIdx>,
+    /// Lights which aren't part of the geometry, like point lights
+    #[serde(default)]
+    pub analytic_lights: Vec<Box<dyn Light + 'static>>,
+    /// The openings the environment is sampled through (see `Portal`)
+    #[serde(default)]
+    pub portals: Vec<Portal>,
+    /// The light group of each of the `analytic_lights`, if any (see `RenderObject::light_group`)
+    #[serde(default)]
+    pub analytic_light_groups: Vec<Option<String>>,
+    /// The light group the light from the environment goes to, if any
+    #[serde(default)]
+    pub environment_light_group: Option<String>,
+    /// The nodes of the scene graph, which objects can be attached to (see `Group`)
+    #[serde(default)]
+    pub groups: Vec<Group>,
+    /// How the scene should be rendered (resolution, samples, camera, etc.), so a scene file can
+    /// describe the final image by itself
+    #[serde(default)]
+    pub render_settings: Option<Renderer>,
+    /// How the objects and the camera move, if they do (see `Scene::set_time`)
+    #[serde(default)]
+    pub animation: Option<Animation>,
+}
+
+fn default_environment() -> Box<dyn Environment + 'static> {
+    Box::new(ColorEnv::default())
+}
+
+/// How serious a problem found by `Scene::validate` is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The scene will render, but probably not as intended
+    Warning,
+    /// The scene would fail to render, or render garbage
+    Error,
+}
+
+/// A problem with a `Scene`, found by `Scene::validate`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn error(message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message,
+        }
+    }
+
+    fn warning(message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning: {}", self.message),
+            Severity::Error => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+/// The file formats a `Scene` can be read from and written to. Every format holds the same
+/// data, so a scene can be converted between them by reading it in one and writing it in another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SceneFormat {
+    Yaml,
+    /// JSON has no infinite numbers, so e.g. an `Atmosphere` with an infinite `max_distance`
+    /// can't be read back from it.
+    Json,
+    Ron,
+    /// TOML can't represent missing values in lists, so scenes with removed objects, or analytic
+    /// lights without a light group among ones with one, can't be written as TOML. `Rect3d`s
+    /// can't be read from it either.
+    Toml,
+}
+
+impl SceneFormat {
+    /// Guesses the format of a scene file from its extension
+    /// ```
+    /// use firework::scene::SceneFormat;
+    /// assert_eq!(SceneFormat::from_path("scenes/teapot.yml"), Some(SceneFormat::Yaml));
+    /// assert_eq!(SceneFormat::from_path("props.ron"), Some(SceneFormat::Ron));
+    /// assert_eq!(SceneFormat::from_path("notes.txt"), None);
+    /// ```
+    pub fn from_path(path: impl AsRef<Path>) -> Option<SceneFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "yml" | "yaml" => Some(SceneFormat::Yaml),
+            "json" => Some(SceneFormat::Json),
+            "ron" => Some(SceneFormat::Ron),
+            "toml" => Some(SceneFormat::Toml),
+            _ => None,
+        }
+    }
+}
+
+/// The error returned when a scene file can't be read or written
+pub type SceneFileError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The error returned (as a `SceneFileError`) when a scene file doesn't describe a valid scene,
+/// e.g. because of a typo in the name of a material, or a missing field.
+/// ```
+/// use firework::scene::{SceneFormat, SceneParseError};
+/// use firework::Scene;
+///
+/// let yaml = "
+/// render_objects:
+///   - obj:
+///       object_type: Spher
+///       radius: 1.0
+///       material: 0
+///     position: {x: 0, y: 0, z: 0}
+///     rotation: {s: 1, bv: {xy: 0, xz: 0, yz: 0}}
+///     flip_normals: false
+///     name: ball
+/// ";
+/// let error = Scene::from_reader(yaml.as_bytes(), SceneFormat::Yaml).err().unwrap();
+/// let error = error.downcast::<SceneParseError>().unwrap();
+/// assert_eq!(error.part.as_deref(), Some("object 0 (ball)"));
+/// assert!(error.message.contains("unknown variant `Spher`"));
+/// assert!(error.message.contains("line 4"));
+/// ```
+#[derive(Debug)]
+pub struct SceneParseError {
+    /// The file the error is in, if the scene was read from one
+    pub file: Option<PathBuf>,
+    /// The part of the scene the error is in, e.g. `object 12 (teapot)` or `material 3`, if it
+    /// could be found
+    pub part: Option<String>,
+    /// The error from the parser of the format, which usually says where in the file it is
+    pub message: String,
+}
+
+impl std::fmt::Display for SceneParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}: ", file.display())?;
+        }
+        if let Some(part) = &self.part {
+            write!(f, "in {}: ", part)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for SceneParseError {}
+
+/// Finds which part of a scene file fails to deserialize, by reading the file into a `Value`
+/// (which only fails if its syntax is wrong) and deserializing each part of it by itself.
+fn find_failing_part(text: &str, format: SceneFormat) -> Option<String> {
+    use serde::de::DeserializeOwned;
+    use serde_json::Value;
+
+    let value: Value = match format {
+        SceneFormat::Yaml => serde_yaml::from_str(text).ok()?,
+        SceneFormat::Json => serde_json::from_str(text).ok()?,
+        // RON can't read the names of fields into a JSON `Value` directly
+        SceneFormat::Ron => {
+            serde_json::to_value(ron::de::from_str::<ron::Value>(text).ok()?).ok()?
+        }
+        SceneFormat::Toml => toml::from_str(text).ok()?,
+    };
+    let scene = value.as_object()?;
+
+    fn fails<T: DeserializeOwned>(value: &Value) -> bool {
+        serde_json::from_value::<T>(value.clone()).is_err()
+    }
+    // the names in `material_names` or `object_names`, by index
+    let names = |key: &str| -> BTreeMap<usize, String> {
+        let names = scene.get(key).and_then(Value::as_object);
+        (names.into_iter().flatten())
+            .filter_map(|(name, idx)| Some((idx.as_u64()? as usize, name.clone())))
+            .collect()
+    };
+    let describe = |what: &str, idx: usize, name: Option<String>| match name {
+        Some(name) => format!("{} {} ({})", what, idx, name),
+        None => format!("{} {}", what, idx),
+    };
+    let list = |key: &str| {
+        scene
+            .get(key)
+            .and_then(Value::as_array)
+            .map(|list| list.iter())
+    };
+
+    let object_names = names("object_names");
+    for (idx, obj) in list("render_objects").into_iter().flatten().enumerate() {
+        if !obj.is_null() && fails::<RenderObject>(obj) {
+            let name = (obj.get("name").and_then(Value::as_str).map(str::to_owned))
+                .or_else(|| object_names.get(&idx).cloned());
+            return Some(describe("object", idx, name));
+        }
+    }
+    let material_names = names("material_names");
+    for (idx, material) in list("materials").into_iter().flatten().enumerate() {
+        if fails::<Box<dyn Material>>(material) {
+            return Some(describe("material", idx, material_names.get(&idx).cloned()));
+        }
+    }
+    for (idx, light) in list("analytic_lights").into_iter().flatten().enumerate() {
+        if fails::<Box<dyn Light>>(light) {
+            return Some(describe("analytic light", idx, None));
+        }
+    }
+    for (idx, portal) in list("portals").into_iter().flatten().enumerate() {
+        if fails::<Portal>(portal) {
+            return Some(describe("portal", idx, None));
+        }
+    }
+    for (idx, group) in list("groups").into_iter().flatten().enumerate() {
+        if fails::<Group>(group) {
+            return Some(describe("group", idx, None));
+        }
+    }
+
+    let part = |key: &str| scene.get(key).filter(|value| !value.is_null());
+    let failing = [
+        (
+            "environment",
+            part("environment").map(fails::<Box<dyn Environment>>),
+        ),
+        (
+            "background",
+            part("background").map(fails::<Box<dyn Environment>>),
+        ),
+        ("atmosphere", part("atmosphere").map(fails::<Atmosphere>)),
+        (
+            "render settings",
+            part("render_settings").map(fails::<Renderer>),
+        ),
+        ("animation", part("animation").map(fails::<Animation>)),
+    ];
+    failing
+        .iter()
+        .find(|(_, fails)| *fails == Some(true))
+        .map(|(what, _)| format!("the {}", what))
+}
+
+/// Writes a scene (or a value describing one) as TOML
+fn write_toml(
+    mut writer: impl std::io::Write,
+    scene: &impl Serialize,
+) -> Result<(), SceneFileError> {
+    // TOML has no null, and typetag writes `None` fields as entries in a map rather than leaving
+    // them out, so they're taken out of a JSON value first. Going through a `Value` also puts the
+    // tables after the plain values, as TOML needs.
+    let mut value = serde_json::to_value(scene)?;
+    remove_nulls(&mut value)?;
+    let value = toml::Value::try_from(value)?;
+    writer.write_all(toml::to_string(&value)?.as_bytes())?;
+    Ok(())
+}
+
+/// Sorts the entries of the maps in a value by key, and writes numbers in their shortest form
+fn canonicalize(value: serde_yaml::Value) -> serde_yaml::Value {
+    use serde_yaml::Value;
+    match value {
+        Value::Mapping(map) => {
+            let mut entries: Vec<_> = map
+                .into_iter()
+                .map(|(key, value)| (key, canonicalize(value)))
+                .collect();
+            // every key in a scene is a string, other keys are put first
+            entries.sort_by(|(a, _), (b, _)| a.as_str().cmp(&b.as_str()));
+            Value::Mapping(entries.into_iter().collect())
+        }
+        Value::Sequence(values) => Value::Sequence(values.into_iter().map(canonicalize).collect()),
+        Value::Number(number) if number.is_f64() => {
+            // every number in a scene is an `f32`, which `to_value` widens to an `f64` (so 0.1
+            // would be written as 0.10000000149011612). The shortest decimal which reads back as
+            // the same `f32` is used instead.
+            let x = number.as_f64().unwrap_or_default() as f32;
+            let x = if x == 0. { 0. } else { x };
+            match x.to_string().parse::<f64>() {
+                Ok(x) if x.is_finite() => Value::Number(x.into()),
+                _ => Value::Number(number),
+            }
+        }
+        value => value,
+    }
+}
+
+/// Leaves out the entries of maps which are null, which deserialize the same as `None` fields
+fn remove_nulls(value: &mut serde_json::Value) -> Result<(), SceneFileError> {
+    use serde_json::Value;
+    match value {
+        Value::Object(map) => {
+            map.retain(|_, value| !value.is_null());
+            map.values_mut().try_for_each(remove_nulls)
+        }
+        Value::Array(values) if values.iter().any(Value::is_null) => {
+            Err("TOML can't represent missing values in lists".into())
+        }
+        Value::Array(values) => values.iter_mut().try_for_each(remove_nulls),
+        _ => Ok(()),
+    }
+}
+
+/// A summary of what's in a `Scene`, from `Scene::stats`
+#[derive(Debug, Clone)]
+pub struct SceneStats {
+    /// The number of objects of each type, by the names used in scene files
+    pub objects: BTreeMap<&'static str, usize>,
+    /// The number of lights, counting both objects and analytic lights
+    pub lights: usize,
+    pub triangles: usize,
+    pub materials: usize,
+    /// The number of textures loaded with `Scene::load_texture`
+    pub textures: usize,
+    /// A rough estimate of the memory taken up by the objects (including any volumes in them)
+    /// and the textures loaded with `Scene::load_texture`, in bytes
+    pub memory: usize,
+    /// The corners of the box around every object, if there are any
+    pub bounds: Option<(Vec3, Vec3)>,
+}
+
+impl std::fmt::Display for SceneStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let count: usize = self.objects.values().sum();
+        writeln!(f, "objects: {}", count)?;
+        for (name, count) in &self.objects {
+            writeln!(f, "    {}: {}", name, count)?;
+        }
+        writeln!(f, "lights: {}", self.lights)?;
+        writeln!(f, "triangles: {}", self.triangles)?;
+        writeln!(f, "materials: {}", self.materials)?;
+        writeln!(f, "textures: {}", self.textures)?;
+        writeln!(f, "memory: {:.1} MiB", self.memory as f64 / (1024. * 1024.))?;
+        match self.bounds {
+            Some((min, max)) => write!(
+                f,
+                "bounds: ({}, {}, {}) to ({}, {}, {})",
+                min.x, min.y, min.z, max.x, max.y, max.z
+            ),
+            None => write!(f, "bounds: empty"),
+        }
+    }
+}
+
+/// A homogeneous medium filling the whole scene, like haze or fog. Light is scattered along every
+/// ray, including rays that escape to the environment, which fades distant objects and makes
+/// shafts of light visible.
+/// ```
+/// use firework::scene::Atmosphere;
+/// use firework::Scene;
+/// use ultraviolet::Vec3;
+///
+/// let mut scene = Scene::new();
+/// scene.set_atmosphere(Atmosphere::new(0.05, Vec3::new(0.9, 0.9, 0.95)).g(0.6));
+/// ```
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Atmosphere {
+    /// The average number of collisions per unit distance
+    pub density: f32,
+    /// The fraction of light scattered (rather than absorbed) at each collision
+    pub color: Vec3,
+    /// The anisotropy of the Henyey-Greenstein phase function, from -1 (back scattering) through
+    /// 0 (isotropic) to 1 (forward scattering)
+    #[serde(default)]
+    pub g: f32,
+    /// How far rays that don't hit anything travel through the atmosphere before reaching the
+    /// environment. If this is infinite, the environment is never seen directly.
+    #[serde(default = "default_max_distance")]
+    pub max_distance: f32,
+}
+
+fn default_max_distance() -> f32 {
+    1000.
+}
+
+impl Atmosphere {
+    /// Creates an isotropic atmosphere
+    pub fn new(density: f32, color: Vec3) -> Self {
+        Atmosphere {
+            density,
+            color,
+            g: 0.,
+            max_distance: default_max_distance(),
+        }
+    }
+
+    pub fn g(mut self, g: f32) -> Self {
+        // the phase function is singular at |g| = 1
+        self.g = g.clamp(-0.99, 0.99);
+        self
+    }
+
+    pub fn max_distance(mut self, max_distance: f32) -> Self {
+        self.max_distance = max_distance;
+        self
+    }
+}
+
+/// A node in the scene graph. Objects (and other groups) attached to a group are placed relative
+/// to it, so moving, rotating or scaling the group moves everything attached to it together.
+/// ```
+/// use firework::material::{EmissiveMat, LambertianMat};
+/// use firework::objects::{Cylinder, Sphere};
+/// use firework::scene::Group;
+/// use firework::{RenderObject, Scene};
+/// use ultraviolet::{Rotor3, Vec3};
+///
+/// let mut scene = Scene::new();
+/// let metal = scene.add_material(LambertianMat::with_color(Vec3::broadcast(0.5)));
+/// let bulb = scene.add_material(EmissiveMat::with_color(Vec3::broadcast(10.)));
+///
+/// let lamp = scene.add_group(Group::new().position(2., 0., 0.));
+/// scene.add_object(RenderObject::new(Cylinder::new(0.05, 1., metal)).parent(lamp));
+/// scene.add_light(RenderObject::new(Sphere::new(0.1, bulb)).position(0., 1.1, 0.).parent(lamp));
+///
+/// // knock the whole lamp over
+/// scene.group_mut(lamp).rotation = Rotor3::from_rotation_xy(std::f32::consts::FRAC_PI_2);
+/// ```
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Group {
+    /// The position of the group, relative to its parent
+    pub position: Vec3,
+    /// The rotation of the group, relative to its parent
+    #[serde(with = "crate::serde_compat::Rotor3Def")]
+    pub rotation: Rotor3,
+    /// The scale of the group, the same along every axis
+    #[serde(default = "default_group_scale")]
+    pub scale: f32,
+    /// The group this one is attached to, if any
+    #[serde(default)]
+    pub parent: Option<GroupIdx>,
+}
+
+fn default_group_scale() -> f32 {
+    1.
+}
+
+impl Default for Group {
+    fn default() -> Self {
+        Group::new()
+    }
+}
+
+impl Group {
+    /// Creates a group at the origin, with no rotation or scale
+    pub fn new() -> Self {
+        Group {
+            position: Vec3::zero(),
+            rotation: Rotor3::identity(),
+            scale: 1.,
+            parent: None,
+        }
+    }
+
+    pub fn position(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.position = Vec3::new(x, y, z);
+        self
+    }
+
+    pub fn position_vec(mut self, pos: Vec3) -> Self {
+        self.position = pos;
+        self
+    }
+
+    pub fn rotate(mut self, rotor: Rotor3) -> Self {
+        self.rotation = rotor;
+        self
+    }
+
+    pub fn scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Attaches the group to another group, which must already be in the `Scene`
+    pub fn parent(mut self, parent: GroupIdx) -> Self {
+        self.parent = Some(parent);
+        self
+    }
+}
+
+/// The transform of a `Group` relative to the world, found by composing the transforms of all its
+/// ancestors
+struct GroupTransform {
+    position: Vec3,
+    rotation_mat: Mat3,
+    inv_rotation_mat: Mat3,
+    scale: f32,
+}
+
+impl GroupTransform {
+    fn identity() -> Self {
+        GroupTransform {
+            position: Vec3::zero(),
+            rotation_mat: Mat3::identity(),
+            inv_rotation_mat: Mat3::identity(),
+            scale: 1.,
+        }
+    }
+
+    fn of(groups: &[Group], idx: GroupIdx) -> Self {
+        // walk up to the root, stopping at a missing parent or a cycle
+        let mut chain = Vec::new();
+        let mut current = Some(idx).filter(|&idx| idx < groups.len());
+        while let Some(idx) = current {
+            if chain.len() >= groups.len() {
+                break;
+            }
+            chain.push(idx);
+            current = groups[idx].parent.filter(|&parent| parent < groups.len());
+        }
+
+        // and then compose the transforms back down
+        chain.iter().rev().fold(Self::identity(), |parent, &idx| {
+            let group = &groups[idx];
+            GroupTransform {
+                position: parent.position + parent.rotation_mat * (parent.scale * group.position),
+                rotation_mat: parent.rotation_mat * group.rotation.into_matrix(),
+                inv_rotation_mat: group.rotation.reversed().into_matrix() * parent.inv_rotation_mat,
+                scale: parent.scale * group.scale,
+            }
+        })
+    }
 }
 
 impl Scene {
@@ -33,14 +618,456 @@ impl Scene {
         Scene {
             render_objects: Vec::new(),
             materials: Vec::new(),
-            environment: Box::new(ColorEnv::default()),
+            environment: default_environment(),
+            include: Vec::new(),
+            material_names: BTreeMap::new(),
+            object_names: BTreeMap::new(),
+            revision: 0,
+            textures: BTreeMap::new(),
+            atmosphere: None,
+            background: None,
+            lights: Vec::new(),
+            analytic_lights: Vec::new(),
+            portals: Vec::new(),
+            analytic_light_groups: Vec::new(),
+            environment_light_group: None,
+            groups: Vec::new(),
+            render_settings: None,
+            animation: None,
         }
     }
 
-    /// Adds a material to the `Scene` and returns it's `MaterialIdx`
+    /// Reads a scene in the given format. Any files it includes, and the assets it refers to, are
+    /// looked for relative to the working directory (see `from_path`).
+    /// ```
+    /// use firework::scene::SceneFormat;
+    /// use firework::Scene;
+    ///
+    /// let json = r#"{
+    ///     "render_objects": [],
+    ///     "materials": [],
+    ///     "environment": {"environment": "ColorEnv", "color": [0.5, 0.7, 1.0]}
+    /// }"#;
+    /// let scene = Scene::from_reader(json.as_bytes(), SceneFormat::Json).unwrap();
+    /// ```
+    pub fn from_reader(
+        reader: impl std::io::Read,
+        format: SceneFormat,
+    ) -> Result<Scene, SceneFileError> {
+        Scene::read(reader, format, None)?.resolve_includes(Path::new(""), &mut Vec::new())
+    }
+
+    /// Reads a scene without looking at what it includes. `file` is the file it's read from, if
+    /// any, for errors.
+    fn read(
+        mut reader: impl std::io::Read,
+        format: SceneFormat,
+        file: Option<&Path>,
+    ) -> Result<Scene, SceneFileError> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        // assets are found relative to the file they're referred to in
+        let base = file.map(|file| file.parent().unwrap_or_else(|| Path::new("")));
+        AssetPaths::current().scope(base, || {
+            let scene = match format {
+                SceneFormat::Yaml => serde_yaml::from_str(&text).map_err(|e| e.to_string()),
+                SceneFormat::Json => serde_json::from_str(&text).map_err(|e| e.to_string()),
+                SceneFormat::Ron => ron::de::from_str(&text).map_err(|e| e.to_string()),
+                SceneFormat::Toml => toml::from_str(&text).map_err(|e| e.to_string()),
+            };
+            // the parts are read again to find the broken one, so their assets must still be
+            // found relative to this file
+            scene.map_err(|message| {
+                SceneParseError {
+                    file: file.map(Path::to_owned),
+                    part: find_failing_part(&text, format),
+                    message,
+                }
+                .into()
+            })
+        })
+    }
+
+    /// Reads a scene file, in the format given by its extension (see `SceneFormat::from_path`).
+    ///
+    /// A scene file can `include` other files (e.g. a library of materials, or a prop used in
+    /// several scenes), given relative to the file including them, which may be in any of the
+    /// formats and include files themselves. Their contents come first, in the order they're
+    /// listed, so in the including file material 0 is the first material of the first included
+    /// file, and its own materials are numbered after those of the included files (the same
+    /// goes for objects and groups). The environment of the including file is used, and the
+    /// background, atmosphere and render settings are taken from the included files if it
+    /// doesn't have them.
+    ///
+    /// Paths of textures, environment maps and other assets are relative to the file they're
+    /// in, falling back to the working directory (see `AssetPaths`).
+    /// ```yaml
+    /// include:
+    ///   - materials.yml # material 0 in this file is the first one in here
+    ///   - props/table.yml
+    /// ```
+    /// ```
+    /// use firework::Scene;
+    /// let teapot = Scene::from_path("scenes/teapot.yml").unwrap();
+    /// ```
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Scene, SceneFileError> {
+        let path = path.as_ref();
+        let canonical = path.canonicalize();
+        let mut including = vec![canonical.map_err(|e| format!("{}: {}", path.display(), e))?];
+        Scene::read_path(path)?.resolve_includes(
+            path.parent().unwrap_or_else(|| Path::new("")),
+            &mut including,
+        )
+    }
+
+    /// Reads a scene file like `from_path`, also looking for the assets it refers to in the
+    /// search paths of `assets`
+    pub fn from_path_with_assets(
+        path: impl AsRef<Path>,
+        assets: &AssetPaths,
+    ) -> Result<Scene, SceneFileError> {
+        assets.scope(None, || Scene::from_path(path))
+    }
+
+    fn read_path(path: &Path) -> Result<Scene, SceneFileError> {
+        let format = SceneFormat::from_path(path)
+            .ok_or_else(|| format!("{} isn't a .yml, .json, .ron or .toml file", path.display()))?;
+        let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Scene::read(std::io::BufReader::new(file), format, Some(path))
+    }
+
+    /// Adds the contents of the files in `include`, relative to `dir`, before the contents of
+    /// this scene. `including` are the files currently being read, to catch files including
+    /// themselves.
+    fn resolve_includes(
+        mut self,
+        dir: &Path,
+        including: &mut Vec<PathBuf>,
+    ) -> Result<Scene, SceneFileError> {
+        if self.include.is_empty() {
+            return Ok(self);
+        }
+
+        let mut scene = Scene::new();
+        for path in std::mem::take(&mut self.include) {
+            let path = dir.join(path);
+            let read = |including: &mut Vec<PathBuf>| -> Result<Scene, SceneFileError> {
+                let canonical = path.canonicalize()?;
+                if including.contains(&canonical) {
+                    return Err("the file includes itself".into());
+                }
+                including.push(canonical);
+                let included = Scene::read_path(&path)?
+                    .resolve_includes(path.parent().unwrap_or_else(|| Path::new("")), including);
+                including.pop();
+                included
+            };
+            // errors in the contents of a file already say which file they're in
+            let included = read(including).map_err(|e| -> SceneFileError {
+                match e.downcast::<SceneParseError>() {
+                    Ok(e) => e,
+                    Err(e) => format!("in {}: {}", path.display(), e).into(),
+                }
+            })?;
+            scene.merge(included);
+        }
+
+        // the indices in this scene already count the contents of the included files
+        scene.environment = std::mem::replace(&mut self.environment, default_environment());
+        let background = self.background.take();
+        let atmosphere = self.atmosphere.take();
+        let environment_light_group = self.environment_light_group.take();
+        let render_settings = self.render_settings.take();
+        let animation = self.animation.take();
+        scene.append(self, false);
+        scene.background = background.or_else(|| scene.background.take());
+        scene.atmosphere = atmosphere.or(scene.atmosphere);
+        scene.environment_light_group =
+            environment_light_group.or_else(|| scene.environment_light_group.take());
+        scene.render_settings = render_settings.or_else(|| scene.render_settings.take());
+        scene.animation = Animation::combine(animation, scene.animation.take(), 0);
+        Ok(scene)
+    }
+
+    /// Writes the scene in the given format
+    /// ```
+    /// use firework::scene::SceneFormat;
+    /// use firework::Scene;
+    ///
+    /// let teapot = Scene::from_path("scenes/teapot.yml").unwrap();
+    /// let mut ron = Vec::new();
+    /// teapot.to_writer(&mut ron, SceneFormat::Ron).unwrap();
+    /// let teapot = Scene::from_reader(&ron[..], SceneFormat::Ron).unwrap();
+    /// ```
+    pub fn to_writer(
+        &self,
+        mut writer: impl std::io::Write,
+        format: SceneFormat,
+    ) -> Result<(), SceneFileError> {
+        match format {
+            SceneFormat::Yaml => serde_yaml::to_writer(writer, self)?,
+            SceneFormat::Json => serde_json::to_writer_pretty(writer, self)?,
+            SceneFormat::Ron => {
+                let config = ron::ser::PrettyConfig::default();
+                writer.write_all(ron::ser::to_string_pretty(self, config)?.as_bytes())?
+            }
+            SceneFormat::Toml => write_toml(writer, self)?,
+        }
+        Ok(())
+    }
+
+    /// Writes the scene in a canonical form, for tools which diff or merge scene files: the
+    /// entries of every map are sorted by key, every field is written even if it has its default
+    /// value, and numbers are written in their shortest form (and `-0` as `0`). The same scene
+    /// is always written the same way, however it was built.
+    ///
+    /// RON isn't supported, since its structs are always written in the order of their fields.
+    /// ```
+    /// use firework::scene::SceneFormat;
+    /// use firework::Scene;
+    ///
+    /// let teapot = Scene::from_path("scenes/teapot.yml").unwrap();
+    /// let mut yaml = Vec::new();
+    /// teapot.to_writer_canonical(&mut yaml, SceneFormat::Yaml).unwrap();
+    /// ```
+    pub fn to_writer_canonical(
+        &self,
+        writer: impl std::io::Write,
+        format: SceneFormat,
+    ) -> Result<(), SceneFileError> {
+        let mut value = canonicalize(serde_yaml::to_value(self)?);
+        if let serde_yaml::Value::Mapping(map) = &mut value {
+            map.insert("include".into(), serde_yaml::Value::Sequence(Vec::new()));
+        }
+        match format {
+            SceneFormat::Yaml => serde_yaml::to_writer(writer, &value)?,
+            SceneFormat::Json => serde_json::to_writer_pretty(writer, &value)?,
+            SceneFormat::Ron => return Err("RON can't be written in canonical form".into()),
+            SceneFormat::Toml => write_toml(writer, &value)?,
+        }
+        Ok(())
+    }
+
+    /// Checks that writing the scene in `format` and reading it back gives the same scene, by
+    /// comparing their canonical forms (see `to_writer_canonical`). Fails with the first line
+    /// of the canonical YAML which changed if it doesn't, or if the scene can't be written or
+    /// read back.
+    /// ```
+    /// use firework::scene::SceneFormat;
+    /// use firework::Scene;
+    ///
+    /// let teapot = Scene::from_path("scenes/teapot.yml").unwrap();
+    /// teapot.check_round_trip(SceneFormat::Ron).unwrap();
+    /// ```
+    pub fn check_round_trip(&self, format: SceneFormat) -> Result<(), SceneFileError> {
+        let mut written = Vec::new();
+        self.to_writer(&mut written, format)?;
+        let read = Scene::from_reader(&written[..], format)?;
+
+        let (mut before, mut after) = (Vec::new(), Vec::new());
+        self.to_writer_canonical(&mut before, SceneFormat::Yaml)?;
+        read.to_writer_canonical(&mut after, SceneFormat::Yaml)?;
+        let (before, after) = (String::from_utf8(before)?, String::from_utf8(after)?);
+
+        let mut after_lines = after.lines();
+        for (number, line) in before.lines().enumerate() {
+            match after_lines.next() {
+                Some(after_line) if after_line == line => {}
+                after_line => {
+                    return Err(format!(
+                        "line {} of the scene changed when it was read back, from `{}` to `{}`",
+                        number + 1,
+                        line.trim(),
+                        after_line.unwrap_or_default().trim()
+                    )
+                    .into())
+                }
+            }
+        }
+        match after_lines.next() {
+            Some(line) => Err(format!("`{}` was added when the scene was read back", line).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the revision of the scene, which changes whenever the scene is modified through
+    /// its methods (adding objects, replacing materials, etc.).
+    /// Anything derived from the scene, like samples accumulated for a progressive preview,
+    /// should be discarded when the revision changes.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Adds an object to the `Scene` and returns its `RenderObjectIdx`
     pub fn add_object(&mut self, obj: RenderObject) -> RenderObjectIdx {
-        self.render_objects.push(obj);
-        self.render_objects.len() - 1
+        self.revision += 1;
+        self.render_objects.push(Some(obj));
+        RenderObjectIdx(self.render_objects.len() - 1)
+    }
+
+    /// Removes the object at the given `RenderObjectIdx` from the `Scene` and returns it, or
+    /// `None` if it was already removed. The indices of the other objects don't change.
+    /// ```
+    /// use firework::objects::Sphere;
+    /// use firework::{RenderObject, Scene};
+    ///
+    /// let mut scene = Scene::new();
+    /// let a = scene.add_object_named("a", RenderObject::new(Sphere::new(1., 0)));
+    /// let b = scene.add_object(RenderObject::new(Sphere::new(2., 0)));
+    /// assert!(scene.remove_object(a).is_some());
+    /// assert!(scene.try_get_object(a).is_none());
+    /// assert!(scene.try_get_object(b).is_some());
+    /// assert_eq!(scene.object_by_name("a"), None);
+    /// ```
+    pub fn remove_object(&mut self, idx: RenderObjectIdx) -> Option<RenderObject> {
+        let obj = self.render_objects.get_mut(idx.0)?.take()?;
+        self.revision += 1;
+        self.lights.retain(|&light| light != idx);
+        self.object_names.retain(|_, &mut i| i != idx);
+        Some(obj)
+    }
+
+    /// Replaces the object at the given `RenderObjectIdx`, returning the old one. The new object
+    /// keeps the handle, so if the old one was a light, so is the new one. If the object was
+    /// removed, nothing changes, and `None` is returned.
+    /// ```
+    /// use firework::objects::Sphere;
+    /// use firework::{RenderObject, Scene};
+    ///
+    /// let mut scene = Scene::new();
+    /// let ball = scene.add_object(RenderObject::new(Sphere::new(1., 0)));
+    /// assert!(scene.replace_object(ball, RenderObject::new(Sphere::new(2., 0))).is_some());
+    ///
+    /// scene.remove_object(ball);
+    /// let revision = scene.revision();
+    /// assert!(scene.replace_object(ball, RenderObject::new(Sphere::new(3., 0))).is_none());
+    /// assert_eq!(scene.revision(), revision);
+    /// ```
+    pub fn replace_object(
+        &mut self,
+        idx: RenderObjectIdx,
+        obj: RenderObject,
+    ) -> Option<RenderObject> {
+        let slot = self.render_objects.get_mut(idx.0)?.as_mut()?;
+        self.revision += 1;
+        Some(std::mem::replace(slot, obj))
+    }
+
+    /// Changes the material of the object at the given `RenderObjectIdx`. Every part of the
+    /// object (e.g. each face of a `Rect3d`) is given the same material.
+    /// ```
+    /// use firework::material::{LambertianMat, MetalMat};
+    /// use firework::objects::Sphere;
+    /// use firework::{RenderObject, Scene};
+    /// use ultraviolet::Vec3;
+    ///
+    /// let mut scene = Scene::new();
+    /// let matte = scene.add_material(LambertianMat::with_color(Vec3::one()));
+    /// let shiny = scene.add_material(MetalMat::new(Vec3::one(), 0.1));
+    /// let ball = scene.add_object(RenderObject::new(Sphere::new(1., matte)));
+    /// scene.set_material_of(ball, shiny);
+    /// ```
+    pub fn set_material_of(&mut self, idx: RenderObjectIdx, mat: MaterialIdx) {
+        if let Some(Some(obj)) = self.render_objects.get_mut(idx.0) {
+            self.revision += 1;
+            obj.obj.map_materials(&mut |_| mat);
+        }
+    }
+
+    /// Adds an object with an emissive material to the `Scene` as a light, and returns its
+    /// `RenderObjectIdx`. Every surface is lit by choosing points on the lights and checking if
+    /// they're visible, rather than waiting for paths to hit them by chance, which removes most of
+    /// the noise from small lights. Only spheres, rects, disks and triangle meshes can be sampled,
+    /// other shapes are added as ordinary objects.
+    /// ```
+    /// use firework::material::EmissiveMat;
+    /// use firework::objects::XZRect;
+    /// use firework::{RenderObject, Scene};
+    /// use ultraviolet::Vec3;
+    ///
+    /// let mut scene = Scene::new();
+    /// let light = scene.add_material(EmissiveMat::with_color(Vec3::broadcast(15.)));
+    /// scene.add_light(RenderObject::new(XZRect::new(-1., 1., -1., 1., 5., light)).flip_normals());
+    /// ```
+    pub fn add_light(&mut self, obj: RenderObject) -> RenderObjectIdx {
+        let idx = self.add_object(obj);
+        self.lights.push(idx);
+        idx
+    }
+
+    /// Adds a light which isn't part of the geometry, like a `PointLight`, to the `Scene`
+    pub fn add_analytic_light(&mut self, light: impl Light + 'static) {
+        self.revision += 1;
+        self.analytic_lights.push(Box::new(light));
+        self.analytic_light_groups.push(None);
+    }
+
+    /// Adds a light which isn't part of the geometry to the `Scene`, with its light in the given
+    /// light group (see `RenderObject::light_group`)
+    pub fn add_analytic_light_in_group(&mut self, light: impl Light + 'static, group: &str) {
+        self.add_analytic_light(light);
+        if let Some(last) = self.analytic_light_groups.last_mut() {
+            *last = Some(group.to_string());
+        }
+    }
+
+    /// Adds an object to the `Scene` under the given name and returns its `RenderObjectIdx`. If
+    /// another object already has that name, the name will refer to the new object.
+    pub fn add_object_named(&mut self, name: &str, obj: RenderObject) -> RenderObjectIdx {
+        let idx = self.add_object(obj);
+        self.object_names.insert(name.to_owned(), idx);
+        idx
+    }
+
+    /// Adds a `Group` to the `Scene` and returns its `GroupIdx`, so objects can be attached to it
+    /// with `RenderObject::parent`
+    pub fn add_group(&mut self, group: Group) -> GroupIdx {
+        self.revision += 1;
+        self.groups.push(group);
+        self.groups.len() - 1
+    }
+
+    /// Returns a mutable reference to the `Group` at the given `GroupIdx`, e.g. to move it
+    /// along with everything attached to it
+    pub fn group_mut(&mut self, idx: GroupIdx) -> &mut Group {
+        self.revision += 1;
+        &mut self.groups[idx]
+    }
+
+    /// Returns the `RenderObjectIdx` of the object with the given name, if there is one. Objects
+    /// can be named either when they're added (`add_object_named`) or with `RenderObject::name`,
+    /// which is kept in the object itself when the scene is saved. If several objects share a
+    /// name, the last one added is returned.
+    /// ```
+    /// use firework::objects::Sphere;
+    /// use firework::{RenderObject, Scene};
+    ///
+    /// let mut scene = Scene::new();
+    /// let ball = scene.add_object(RenderObject::new(Sphere::new(1., 0)).name("ball"));
+    /// assert_eq!(scene.object_by_name("ball"), Some(ball));
+    /// assert_eq!(scene.object_name(ball), Some("ball"));
+    /// ```
+    pub fn object_by_name(&self, name: &str) -> Option<RenderObjectIdx> {
+        let inline = self
+            .render_objects
+            .iter()
+            .rposition(|obj| obj.as_ref().and_then(|obj| obj.name.as_deref()) == Some(name))
+            .map(RenderObjectIdx);
+        match (self.object_names.get(name).copied(), inline) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Returns the name of the object at the given `RenderObjectIdx`, if it has one
+    pub fn object_name(&self, idx: RenderObjectIdx) -> Option<&str> {
+        let obj = self.render_objects.get(idx.0)?.as_ref()?;
+        obj.name.as_deref().or_else(|| {
+            self.object_names
+                .iter()
+                .find(|&(_, &i)| i == idx)
+                .map(|(name, _)| name.as_str())
+        })
     }
 
     /// Adds a volume to the `Scene` and returns its `RenderObjectIdx`.
@@ -55,15 +1082,63 @@ impl Scene {
 
         let mat = self.add_material(IsotropicMat::new(texture));
         let ro = RenderObject {
-            obj: Box::new(ConstantMedium::from_boxed(obj.obj, density, mat)),
+            obj: Box::new(ConstantMedium::from_boxed(obj.obj, density, mat)),
+            ..obj
+        };
+        self.add_object(ro)
+    }
+
+    /// Adds a volume whose density varies through space to the `Scene`, and returns its
+    /// `RenderObjectIdx`. The density is read from the first component of `density`, and must
+    /// never exceed `max_density` inside the object (see `HeterogeneousMedium`). Panics if
+    /// `max_density` isn't positive.
+    /// ```
+    /// use firework::texture::{ConstantTexture, FbmTexture};
+    /// use firework::objects::Sphere;
+    /// use firework::{RenderObject, Scene};
+    ///
+    /// let mut scene = Scene::new();
+    /// let cloud = scene.add_heterogeneous_volume(
+    ///     RenderObject::new(Sphere::new(1., 0)),
+    ///     FbmTexture::new(5, 2.).remap(0., 4.),
+    ///     4.,
+    ///     ConstantTexture::from_rgb(0.9, 0.9, 0.9),
+    /// );
+    /// ```
+    pub fn add_heterogeneous_volume<D, T>(
+        &mut self,
+        obj: RenderObject,
+        density: D,
+        max_density: f32,
+        texture: T,
+    ) -> RenderObjectIdx
+    where
+        D: crate::texture::Texture + 'static,
+        T: crate::texture::Texture + 'static,
+    {
+        use crate::material::IsotropicMat;
+        use crate::objects::HeterogeneousMedium;
+
+        let mat = self.add_material(IsotropicMat::new(texture));
+        let medium = HeterogeneousMedium::from_boxed(obj.obj, Box::new(density), max_density, mat);
+        let ro = RenderObject {
+            obj: Box::new(medium),
             ..obj
         };
         self.add_object(ro)
     }
 
-    /// Returns a reference to the `RenderObject` stored at the given `RenderObjectIdx`
+    /// Returns a reference to the `RenderObject` stored at the given `RenderObjectIdx`. Panics if
+    /// the object has been removed, see `try_get_object`.
     pub fn get_object(&self, idx: RenderObjectIdx) -> &RenderObject {
-        &self.render_objects[idx]
+        self.try_get_object(idx)
+            .expect("Scene::get_object() -- the object has been removed")
+    }
+
+    /// Returns a reference to the `RenderObject` stored at the given `RenderObjectIdx`, or `None`
+    /// if it has been removed
+    pub fn try_get_object(&self, idx: RenderObjectIdx) -> Option<&RenderObject> {
+        self.render_objects.get(idx.0)?.as_ref()
     }
 
     /// Adds a material to the `Scene` and returns it's `MaterialIdx`
@@ -75,30 +1150,583 @@ impl Scene {
     /// let red = scene.add_material(LambertianMat::with_color(Vec3::new(1., 0., 0.)));
     /// ```
     pub fn add_material<T: Material + Sync + 'static>(&mut self, mat: T) -> MaterialIdx {
+        self.revision += 1;
         self.materials.push(Box::new(mat));
         self.materials.len() - 1
     }
 
+    /// Adds a material to the `Scene` under the given name and returns it's `MaterialIdx`. If
+    /// another material already has that name, the name will refer to the new material.
+    /// ```
+    /// use firework::Scene;
+    /// use firework::material::DielectricMat;
+    /// let mut scene = Scene::new();
+    /// let glass = scene.add_material_named("glass", DielectricMat::new(1.5));
+    /// assert_eq!(scene.material_by_name("glass"), Some(glass));
+    /// ```
+    pub fn add_material_named<T: Material + Sync + 'static>(
+        &mut self,
+        name: &str,
+        mat: T,
+    ) -> MaterialIdx {
+        let idx = self.add_material(mat);
+        self.material_names.insert(name.to_owned(), idx);
+        idx
+    }
+
+    /// Returns the `MaterialIdx` of the material with the given name, if there is one
+    pub fn material_by_name(&self, name: &str) -> Option<MaterialIdx> {
+        self.material_names.get(name).copied()
+    }
+
+    /// Gives a name to a material which is already in the `Scene`, e.g. one loaded from a file
+    pub fn set_material_name(&mut self, idx: MaterialIdx, name: &str) {
+        self.material_names.insert(name.to_owned(), idx);
+    }
+
+    /// Returns the name of the material at the given `MaterialIdx`, if it has one
+    /// ```
+    /// use firework::Scene;
+    /// use firework::material::DielectricMat;
+    /// let mut scene = Scene::new();
+    /// let glass = scene.add_material(DielectricMat::new(1.5));
+    /// scene.set_material_name(glass, "glass");
+    /// assert_eq!(scene.material_name(glass), Some("glass"));
+    /// ```
+    pub fn material_name(&self, idx: MaterialIdx) -> Option<&str> {
+        self.material_names
+            .iter()
+            .find(|&(_, &i)| i == idx)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Replaces the material stored at the given `MaterialIdx`, returning the old one. All
+    /// objects using the index will use the new material.
+    /// This is intended for tuning materials while previewing, without rebuilding the scene.
+    /// ```
+    /// use firework::Scene;
+    /// use firework::material::{LambertianMat, MetalMat};
+    /// use ultraviolet::Vec3;
+    /// let mut scene = Scene::new();
+    /// let mat = scene.add_material(LambertianMat::with_color(Vec3::new(1., 0., 0.)));
+    /// let revision = scene.revision();
+    /// scene.replace_material(mat, MetalMat::new(Vec3::new(0.8, 0.8, 0.8), 0.2));
+    /// assert_ne!(scene.revision(), revision);
+    /// ```
+    pub fn replace_material<T: Material + Sync + 'static>(
+        &mut self,
+        idx: MaterialIdx,
+        mat: T,
+    ) -> Box<dyn Material> {
+        self.revision += 1;
+        std::mem::replace(&mut self.materials[idx], Box::new(mat))
+    }
+
     /// Returns a reference to `Material` stored at the given `MaterialIdx`
     pub fn get_material(&self, idx: MaterialIdx) -> &dyn Material {
         self.materials[idx].as_ref()
     }
 
+    /// Loads an image texture, or returns the already loaded texture if the same path has been
+    /// loaded before, so an image used by several materials is only decoded and stored once.
+    /// ```
+    /// use firework::Scene;
+    /// use firework::material::LambertianMat;
+    ///
+    /// # fn main() -> Result<(), image::ImageError> {
+    /// let mut scene = Scene::new();
+    /// let uvmap = scene.load_texture("uvmap.png")?;
+    /// assert!(uvmap.ptr_eq(&scene.load_texture("uvmap.png")?));
+    /// let walls = scene.add_material(LambertianMat::new(uvmap.clone()));
+    /// let floor = scene.add_material(LambertianMat::new(uvmap));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn load_texture(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<SharedTexture, image::ImageError> {
+        let path = path.as_ref();
+        // different spellings of the same path should share a texture too
+        let key = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        if let Some(texture) = self.textures.get(&key) {
+            return Ok(texture.clone());
+        }
+        let texture = SharedTexture::new(ImageTexture::from_path(path)?);
+        self.textures.insert(key, texture.clone());
+        Ok(texture)
+    }
+
+    /// Sets how the scene should be rendered, which is saved with it
+    /// ```
+    /// use firework::camera::CameraSettings;
+    /// use firework::{Renderer, Scene};
+    /// use ultraviolet::Vec3;
+    ///
+    /// let mut scene = Scene::new();
+    /// let camera = CameraSettings::default().cam_pos(Vec3::new(0., 2., 10.));
+    /// scene.set_render_settings(Renderer::default().width(640).height(480).camera(camera));
+    /// let renderer = scene.render_settings.clone().unwrap_or_else(Renderer::scene_default);
+    /// let image = renderer.samples(1).render(scene);
+    /// ```
+    pub fn set_render_settings(&mut self, renderer: Renderer) {
+        self.revision += 1;
+        self.render_settings = Some(renderer);
+    }
+
+    /// Sets how the objects and the camera move over time
+    pub fn set_animation(&mut self, animation: Animation) {
+        self.revision += 1;
+        self.animation = Some(animation);
+    }
+
+    /// Moves the animated objects and the camera to where they are at `time` (in seconds), and
+    /// sets the time in the render settings, for animated textures (starting from
+    /// `Renderer::scene_default` if the scene has none). Since rendering consumes the scene, each
+    /// frame of an animation is usually rendered from a freshly loaded scene.
+    /// ```no_run
+    /// use firework::output::save_image;
+    /// use firework::{Renderer, Scene};
+    ///
+    /// let frames = Scene::from_path("shot.yml").unwrap().animation.unwrap().frames();
+    /// for frame in frames {
+    ///     let mut scene = Scene::from_path("shot.yml").unwrap();
+    ///     scene.set_frame(frame);
+    ///     let renderer = scene.render_settings.take().unwrap_or_else(Renderer::scene_default);
+    ///     let image = renderer.render(scene);
+    ///     save_image(&image, format!("shot_{:04}.png", frame), renderer.width, renderer.height);
+    /// }
+    /// ```
+    pub fn set_time(&mut self, time: f32) {
+        let animation = match &self.animation {
+            Some(animation) => animation,
+            None => return,
+        };
+        self.revision += 1;
+        for track in &animation.objects {
+            let obj = self.render_objects.get_mut(track.object.0);
+            if let (Some(Some(obj)), Some(key)) = (obj, Animation::transform_at(&track.keys, time))
+            {
+                obj.position = key.position;
+                obj.rotation = key.rotation;
+                obj.scale = key.scale;
+            }
+        }
+        let mut settings = self
+            .render_settings
+            .take()
+            .unwrap_or_else(Renderer::scene_default);
+        if let Some(camera) = animation.camera_at(time) {
+            settings = settings.camera(camera);
+        }
+        settings.time = time;
+        self.render_settings = Some(settings);
+    }
+
+    /// Moves everything to where it is in a frame of the animation (see `set_time`)
+    pub fn set_frame(&mut self, frame: u32) {
+        if let Some(time) = self.animation.as_ref().map(|a| a.frame_time(frame)) {
+            self.set_time(time);
+        }
+    }
+
     /// Sets the closure for the "environment"
     pub fn set_environment(&mut self, env: impl Environment + Sync + 'static) {
+        self.revision += 1;
         self.environment = Box::new(env);
     }
+
+    /// Sets an environment that is only seen by rays from the camera, e.g. a plain backdrop for a
+    /// product shot that is still lit by an HDRI. Reflections, refractions and lighting still use
+    /// the environment set with `set_environment`.
+    /// ```
+    /// use firework::environment::{ColorEnv, SkyEnv};
+    /// use firework::Scene;
+    /// use ultraviolet::Vec3;
+    ///
+    /// let mut scene = Scene::new();
+    /// scene.set_environment(SkyEnv::default());
+    /// scene.set_background(ColorEnv::new(Vec3::broadcast(0.5)));
+    /// ```
+    pub fn set_background(&mut self, env: impl Environment + 'static) {
+        self.revision += 1;
+        self.background = Some(Box::new(env));
+    }
+
+    /// Puts the light from the environment in a light group (see `RenderObject::light_group`).
+    /// The background seen directly by the camera (see `set_background`) isn't part of it.
+    pub fn set_environment_light_group(&mut self, group: &str) {
+        self.revision += 1;
+        self.environment_light_group = Some(group.to_string());
+    }
+
+    /// Adds an opening that the environment lights the scene through (see `Portal`)
+    pub fn add_portal(&mut self, portal: Portal) {
+        self.revision += 1;
+        self.portals.push(portal);
+    }
+
+    /// Fills the scene with a homogeneous medium (see `Atmosphere`)
+    pub fn set_atmosphere(&mut self, atmosphere: Atmosphere) {
+        self.revision += 1;
+        self.atmosphere = Some(atmosphere);
+    }
+
+    /// Adds everything in `other` to this `Scene`, so a scene can be put together from separate
+    /// files (e.g. the props in one and the lighting in another). The materials of the objects
+    /// from `other` are remapped to where its materials end up, and its names, groups and
+    /// lights are carried over. If both scenes name something the same way, the name refers
+    /// to the one from `other`. This scene's environment is kept, and its background,
+    /// atmosphere, environment light group and render settings are only taken from `other` if
+    /// it doesn't have them, so the scene with the lighting should usually be the one merged
+    /// into.
+    /// ```
+    /// use firework::material::LambertianMat;
+    /// use firework::objects::Sphere;
+    /// use firework::{RenderObject, Scene};
+    /// use ultraviolet::Vec3;
+    ///
+    /// let mut lighting = Scene::new();
+    /// lighting.add_material(LambertianMat::with_color(Vec3::one()));
+    ///
+    /// let mut props = Scene::new();
+    /// let red = props.add_material_named("red", LambertianMat::with_color(Vec3::unit_x()));
+    /// props.add_object_named("ball", RenderObject::new(Sphere::new(1., red)));
+    ///
+    /// lighting.merge(props);
+    /// assert_eq!(lighting.material_by_name("red"), Some(1));
+    /// assert!(lighting.object_by_name("ball").is_some());
+    /// ```
+    pub fn merge(&mut self, mut other: Scene) {
+        let background = other.background.take();
+        let atmosphere = other.atmosphere.take();
+        let environment_light_group = other.environment_light_group.take();
+        let render_settings = other.render_settings.take();
+        let animation = other.animation.take();
+        let object_offset = self.render_objects.len();
+        self.append(other, true);
+
+        self.background = self.background.take().or(background);
+        self.atmosphere = self.atmosphere.or(atmosphere);
+        self.environment_light_group = self
+            .environment_light_group
+            .take()
+            .or(environment_light_group);
+        self.render_settings = self.render_settings.take().or(render_settings);
+        self.animation = Animation::combine(self.animation.take(), animation, object_offset);
+    }
+
+    /// Adds the materials, objects, groups, lights and names of `other` after those of this
+    /// scene. If `remap` is false, the indices in `other` are kept as they are, since they
+    /// already count the contents of this scene.
+    fn append(&mut self, other: Scene, remap: bool) {
+        self.revision += 1;
+        let (material_offset, object_offset, group_offset) = if remap {
+            (
+                self.materials.len(),
+                self.render_objects.len(),
+                self.groups.len(),
+            )
+        } else {
+            (0, 0, 0)
+        };
+
+        self.materials.extend(other.materials);
+        self.render_objects
+            .extend(other.render_objects.into_iter().map(|obj| {
+                obj.map(|mut obj| {
+                    obj.obj.map_materials(&mut |idx| idx + material_offset);
+                    obj.parent = obj.parent.map(|idx| idx + group_offset);
+                    obj
+                })
+            }));
+        self.groups
+            .extend(other.groups.into_iter().map(|mut group| {
+                group.parent = group.parent.map(|idx| idx + group_offset);
+                group
+            }));
+        self.lights.extend(
+            other
+                .lights
+                .into_iter()
+                .map(|idx| idx.offset(object_offset)),
+        );
+
+        self.material_names.extend(
+            (other.material_names.into_iter()).map(|(name, idx)| (name, idx + material_offset)),
+        );
+        self.object_names.extend(
+            (other.object_names.into_iter()).map(|(name, idx)| (name, idx.offset(object_offset))),
+        );
+        self.textures.extend(other.textures);
+
+        // the groups of the analytic lights may be missing in scenes from files
+        self.analytic_light_groups
+            .resize(self.analytic_lights.len(), None);
+        let mut groups = other.analytic_light_groups;
+        groups.resize(other.analytic_lights.len(), None);
+        self.analytic_lights.extend(other.analytic_lights);
+        self.analytic_light_groups.extend(groups);
+        self.portals.extend(other.portals);
+    }
+
+    /// Summarizes what's in the scene, e.g. to see why it's slow to render. The memory use is
+    /// only an estimate, and doesn't include textures which weren't loaded with `load_texture`.
+    /// ```
+    /// use firework::objects::{Sphere, XZRect};
+    /// use firework::{RenderObject, Scene};
+    ///
+    /// let mut scene = Scene::new();
+    /// scene.add_object(RenderObject::new(Sphere::new(1., 0)).position(0., 1., 0.));
+    /// scene.add_object(RenderObject::new(XZRect::new(-5., 5., -5., 5., 0., 0)));
+    /// let stats = scene.stats();
+    /// assert_eq!(stats.objects["Sphere"], 1);
+    /// assert_eq!(stats.bounds.unwrap().1.y, 2.);
+    /// println!("{}", stats);
+    /// ```
+    pub fn stats(&self) -> SceneStats {
+        let mut objects = BTreeMap::new();
+        let mut triangles = 0;
+        let mut memory = 0;
+        let mut bounds: Option<AABB> = None;
+        for obj in self.render_objects.iter().flatten() {
+            *objects.entry(obj.obj.typetag_name()).or_insert(0) += 1;
+            triangles += obj.obj.triangle_count();
+            memory += std::mem::size_of::<RenderObjectInternal>() + obj.obj.memory_size();
+
+            let group = match obj.parent {
+                Some(parent) => GroupTransform::of(&self.groups, parent),
+                None => GroupTransform::identity(),
+            };
+            let aabb = obj.bounds_in(group);
+            bounds = Some(match bounds {
+                Some(bounds) => bounds.expand(&aabb),
+                None => aabb,
+            });
+        }
+        memory += self
+            .textures
+            .values()
+            .map(|texture| texture.memory_size())
+            .sum::<usize>();
+
+        SceneStats {
+            objects,
+            lights: self.lights.len() + self.analytic_lights.len(),
+            triangles,
+            materials: self.materials.len(),
+            textures: self.textures.len(),
+            memory,
+            bounds: bounds.map(|aabb| (aabb.min, aabb.max)),
+        }
+    }
+
+    /// Checks the scene for problems which would otherwise only show up while rendering (as a
+    /// panic, or NaNs in the image), like objects using materials which don't exist, meshes with
+    /// out of range indices, infinite bounding boxes, or rotations which aren't normalized.
+    /// This is mostly useful for scenes loaded from files (images and volumes in a file are
+    /// loaded as it's read, so a missing one is already an error then).
+    /// ```
+    /// use firework::objects::Sphere;
+    /// use firework::scene::Severity;
+    /// use firework::{RenderObject, Scene};
+    ///
+    /// let mut scene = Scene::new();
+    /// scene.add_object(RenderObject::new(Sphere::new(1., 3)));
+    /// let problems = scene.validate();
+    /// assert_eq!(problems[0].severity, Severity::Error);
+    /// assert_eq!(
+    ///     problems[0].to_string(),
+    ///     "error: object 0 uses material 3, but the scene only has 0 materials"
+    /// );
+    /// ```
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let finite = |v: Vec3| v.x.is_finite() && v.y.is_finite() && v.z.is_finite();
+        let normalized = |rotor: Rotor3| (rotor.mag() - 1.).abs() < 1e-3;
+
+        for (idx, obj) in self.render_objects.iter().enumerate() {
+            let (idx, obj) = match obj {
+                Some(obj) => (RenderObjectIdx(idx), obj),
+                None => continue,
+            };
+            let what = match self.object_name(idx) {
+                Some(name) => format!("object {} ({})", idx, name),
+                None => format!("object {}", idx),
+            };
+            for mat in obj.obj.materials() {
+                if mat >= self.materials.len() {
+                    diagnostics.push(Diagnostic::error(format!(
+                        "{} uses material {}, but the scene only has {} materials",
+                        what,
+                        mat,
+                        self.materials.len()
+                    )));
+                }
+            }
+            for problem in obj.obj.problems() {
+                diagnostics.push(Diagnostic::error(format!("{}: {}", what, problem)));
+            }
+            let bounds = obj.obj.local_bounds();
+            if !(finite(bounds.min) && finite(bounds.max)) {
+                diagnostics.push(Diagnostic::error(format!(
+                    "{} has a bounding box which isn't finite",
+                    what
+                )));
+            }
+            if !finite(obj.position) || !finite(obj.scale) || obj.scale.component_min() == 0. {
+                diagnostics.push(Diagnostic::error(format!(
+                    "{} has a position or scale which isn't finite (or a scale of 0)",
+                    what
+                )));
+            }
+            if !normalized(obj.rotation) {
+                diagnostics.push(Diagnostic::warning(format!(
+                    "{}'s rotation isn't normalized (its magnitude is {})",
+                    what,
+                    obj.rotation.mag()
+                )));
+            }
+            if let Some(parent) = obj.parent.filter(|&parent| parent >= self.groups.len()) {
+                diagnostics.push(Diagnostic::error(format!(
+                    "{} is attached to group {}, which doesn't exist",
+                    what, parent
+                )));
+            }
+        }
+
+        for (idx, material) in self.materials.iter().enumerate() {
+            let what = match self.material_name(idx) {
+                Some(name) => format!("material {} ({})", idx, name),
+                None => format!("material {}", idx),
+            };
+            for problem in material.problems() {
+                diagnostics.push(Diagnostic::error(format!("{}: {}", what, problem)));
+            }
+        }
+
+        for (idx, group) in self.groups.iter().enumerate() {
+            if !normalized(group.rotation) {
+                diagnostics.push(Diagnostic::warning(format!(
+                    "group {}'s rotation isn't normalized (its magnitude is {})",
+                    idx,
+                    group.rotation.mag()
+                )));
+            }
+            // walk up the parents, and see if we end up back here
+            let mut current = group.parent;
+            for _ in 0..self.groups.len() {
+                match current {
+                    Some(parent) if parent == idx => {
+                        diagnostics.push(Diagnostic::error(format!(
+                            "group {} is its own ancestor",
+                            idx
+                        )));
+                        break;
+                    }
+                    Some(parent) if parent < self.groups.len() => {
+                        current = self.groups[parent].parent;
+                    }
+                    Some(parent) => {
+                        diagnostics.push(Diagnostic::error(format!(
+                            "group {} is attached to group {}, which doesn't exist",
+                            idx, parent
+                        )));
+                        break;
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        for &idx in &self.lights {
+            if self.try_get_object(idx).is_none() {
+                diagnostics.push(Diagnostic::warning(format!(
+                    "object {} is listed as a light, but doesn't exist",
+                    idx
+                )));
+            }
+        }
+
+        if let Some(animation) = &self.animation {
+            if animation.frame_rate.is_nan() || animation.frame_rate <= 0. {
+                diagnostics.push(Diagnostic::error(format!(
+                    "the animation's frame rate is {}, rather than a positive number",
+                    animation.frame_rate
+                )));
+            }
+            let in_order = |times: &[f32]| times.windows(2).all(|pair| pair[0] <= pair[1]);
+            for track in &animation.objects {
+                if self.try_get_object(track.object).is_none() {
+                    diagnostics.push(Diagnostic::warning(format!(
+                        "object {} is animated, but doesn't exist",
+                        track.object
+                    )));
+                }
+                let times: Vec<f32> = track.keys.iter().map(|key| key.time).collect();
+                if !in_order(&times) {
+                    diagnostics.push(Diagnostic::error(format!(
+                        "the keyframes of object {} aren't in order of time",
+                        track.object
+                    )));
+                }
+            }
+            let times: Vec<f32> = animation.camera.iter().map(|key| key.time).collect();
+            if !in_order(&times) {
+                diagnostics.push(Diagnostic::error(
+                    "the keyframes of the camera aren't in order of time".to_string(),
+                ));
+            }
+        }
+
+        for path in self.textures.keys() {
+            if !path.exists() {
+                diagnostics.push(Diagnostic::warning(format!(
+                    "the texture {} no longer exists, so the scene can't be loaded again once \
+                     it's saved",
+                    path.display()
+                )));
+            }
+        }
+
+        diagnostics
+    }
 }
 
 pub(crate) struct SceneInternal {
     pub render_objects: Vec<RenderObjectInternal>,
+    /// Objects which are participating media, which rays pass through instead of hitting
+    pub media: Vec<RenderObjectInternal>,
     pub materials: Vec<Box<dyn Material + 'static>>, // TODO: Remove the layer of indirection here
     pub environment: Box<dyn Environment + 'static>,
+    /// Seen by camera rays instead of `environment`, if set
+    pub background: Option<Box<dyn Environment + 'static>>,
+    /// The time of the frame being rendered
+    pub time: f32,
+    pub atmosphere: Option<Atmosphere>,
+    /// The phase function of `atmosphere` (with an albedo of 1, since its color is applied
+    /// separately), used to light the points where rays scatter in it
+    pub atmosphere_phase: Option<HenyeyGreensteinMat>,
+    /// The indices (in `render_objects`) of the objects which are lights
+    pub lights: Vec<usize>,
+    pub analytic_lights: Vec<Box<dyn Light + 'static>>,
+    pub portals: Vec<Portal>,
+    /// Chooses between `lights` and `analytic_lights` for direct lighting
+    pub light_bvh: LightBVH,
+    /// The names of the light groups, in the order of their indices
+    pub light_groups: Vec<String>,
+    /// The light group of each of the `analytic_lights`
+    pub analytic_light_groups: Vec<Option<usize>>,
+    /// The light group of the light from the environment
+    pub environment_light_group: Option<usize>,
+    /// How often `sample_environment` goes through the portals, rather than using the
+    /// environment's own sampling
+    pub portal_probability: f32,
 }
 
 impl SceneInternal {
-    /// Returns a reference to the `RenderObject` stored at the given `RenderObjectIdx`
-    pub fn get_object(&self, idx: RenderObjectIdx) -> &RenderObjectInternal {
+    /// Returns a reference to the object stored at the given index in `render_objects`
+    pub fn get_object(&self, idx: usize) -> &RenderObjectInternal {
         &self.render_objects[idx]
     }
 
@@ -106,11 +1734,229 @@ impl SceneInternal {
     pub fn get_material(&self, idx: MaterialIdx) -> &dyn Material {
         self.materials[idx].as_ref()
     }
+
+    /// Samples the first collision along `r` before `t_max` with any of the media in the scene,
+    /// using delta tracking. Overlapping media are tracked together, so their densities add up.
+    /// Also returns the weight for each color channel, which is not 1 when media have different
+    /// densities per channel (see Kutz et al., "Spectral and Decomposition Tracking for Rendering
+    /// Heterogeneous Volumes"), and should multiply the light arriving along the ray.
+    pub fn sample_media(
+        &self,
+        r: &Ray,
+        t_max: f32,
+        rand: &mut LcRng,
+    ) -> (Option<RaycastHit>, Vec3) {
+        // the media the ray passes through, each with the ray in its local space
+        let segments: Vec<_> = self
+            .media
+            .iter()
+            .filter_map(|obj| {
+                let medium = obj.obj.as_medium()?;
+                let local = obj.local_ray(r);
+                let (enter, exit) = medium.interval(&local, rand)?;
+                let (enter, exit) = (enter.max(0.001), exit.min(t_max));
+                if enter < exit {
+                    Some((medium, local, enter, exit))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        // densities are per unit of distance in each medium's own space, so they're converted to
+        // be per unit of the ray's parameter, which the media share
+        let majorant: f32 = segments
+            .iter()
+            .map(|s| s.0.max_density() * s.1.direction().mag())
+            .sum();
+        let mut weight = Vec3::one();
+        // a majorant which isn't positive would never step out of the media
+        if segments.is_empty() || !majorant.is_finite() || majorant <= 0. {
+            return (None, weight);
+        }
+
+        // Take exponentially distributed steps as if the media had a combined density of
+        // `majorant`, and at each point, choose a medium in proportion to its average density
+        // over the channels (or a null collision, with the remaining probability).
+        let mean = |v: Vec3| (v.x + v.y + v.z) / 3.;
+        let step = 1. / majorant;
+        let mut t = segments.iter().map(|s| s.2).fold(f32::MAX, f32::min);
+        let end = segments.iter().map(|s| s.3).fold(f32::MIN, f32::max);
+        loop {
+            t -= (1. - rand.rand_f32()).ln() * step;
+            if t >= end {
+                return (None, weight);
+            }
+            let mut xi = rand.rand_f32() * majorant;
+            let mut total = Vec3::zero();
+            for (medium, local, enter, exit) in &segments {
+                if t < *enter || t > *exit {
+                    continue;
+                }
+                let density = medium.density(local.point(t)) * local.direction().mag();
+                total += density;
+                xi -= mean(density);
+                if xi < 0. {
+                    weight *= density / mean(density);
+                    let hit = RaycastHit {
+                        t,
+                        point: r.point(t),
+                        normal: Vec3::unit_y(), // arbitrary
+                        material: medium.material(),
+                        uv: Vec2::zero(),
+                        tangent: None,
+                        footprint: 0.,
+                        time: 0.,
+                        priority: 0,
+                        outside_ior: 1.,
+                        light: None,
+                        light_group: None,
+                    };
+                    return (Some(hit), weight);
+                }
+            }
+            // correct for the channels which are more or less likely to pass through than average
+            let null = majorant - mean(total);
+            if null > 0. {
+                weight *= (Vec3::broadcast(majorant) - total) / null;
+            }
+        }
+    }
+
+    /// Chooses a direction towards the environment to light `origin` from, and returns it with
+    /// its probability density (with respect to solid angle). If there are portals, directions
+    /// through them are mixed with the environment's own sampling, which still finds bright
+    /// spots in the environment (like the sun) best.
+    pub fn sample_environment(&self, origin: Vec3, rand: &mut LcRng) -> Option<(Vec3, f32)> {
+        let dir = if rand.rand_f32() < self.portal_probability {
+            let count = self.portals.len();
+            let idx = ((rand.rand_f32() * count as f32) as usize).min(count - 1);
+            self.portals[idx].sample_direction(origin, rand)?.0
+        } else {
+            self.environment.sample_direction(rand)?.0
+        };
+        // the portals may overlap as seen from `origin`, and the environment could have chosen
+        // the same direction
+        Some((dir, self.environment_pdf(origin, dir)))
+    }
+
+    /// The probability density that `sample_environment` chooses `dir` from `origin`
+    pub fn environment_pdf(&self, origin: Vec3, dir: Vec3) -> f32 {
+        let mut pdf = (1. - self.portal_probability) * self.environment.pdf(dir);
+        if self.portal_probability > 0. {
+            let total: f32 = self.portals.iter().map(|p| p.pdf(origin, dir)).sum();
+            pdf += self.portal_probability * total / self.portals.len() as f32;
+        }
+        pdf
+    }
+
+    /// Chooses a light to light `point` with (see `LightBVH`), and returns its index along with
+    /// the probability of choosing it. The area lights (in `lights`) come first, followed by the
+    /// analytic lights.
+    pub fn choose_light(&self, point: Vec3, rand: &mut LcRng) -> Option<(usize, f32)> {
+        self.light_bvh.choose(point, rand)
+    }
+
+    /// The light group of the light `idx`, of either kind (see `choose_light`)
+    pub fn light_group(&self, idx: usize) -> Option<usize> {
+        match idx.checked_sub(self.lights.len()) {
+            Some(analytic) => self.analytic_light_groups[analytic],
+            None => self.get_object(self.lights[idx]).light_group,
+        }
+    }
+
+    /// The probability that `choose_light` chooses the light `idx` for `point`
+    pub fn light_probability(&self, idx: usize, point: Vec3) -> f32 {
+        self.light_bvh.probability(idx, point)
+    }
+
+    /// The probability density (with respect to surface area) that direct lighting chooses
+    /// `point` on the area light `idx` when lighting `origin`, including the choice of light
+    pub fn light_pdf(&self, idx: usize, origin: Vec3, point: Vec3) -> f32 {
+        let probability = self.light_probability(idx, origin);
+        if probability == 0. {
+            return 0.;
+        }
+        self.get_object(self.lights[idx]).pdf(origin, point) * probability
+    }
+
+    /// Estimates the fraction of light in each channel that travels along `r` between `t_min` and
+    /// `t_max` without colliding with any of the media in the scene, e.g. for shadow rays.
+    pub fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32, rand: &mut LcRng) -> Vec3 {
+        self.media
+            .iter()
+            .filter_map(|obj| Some((obj.obj.as_medium()?, obj.local_ray(r))))
+            .fold(Vec3::one(), |transmittance, (medium, local)| {
+                transmittance * medium.transmittance(&local, t_min, t_max, rand)
+            })
+    }
 }
 
 impl From<Scene> for SceneInternal {
     fn from(scene: Scene) -> Self {
-        let render_objects: Vec<_> = scene.render_objects.into_iter().map(|x| x.into()).collect();
+        // the light groups are numbered in the order of their names
+        let light_groups: Vec<String> = scene
+            .render_objects
+            .iter()
+            .flatten()
+            .filter_map(|obj| obj.light_group.clone())
+            .chain(scene.analytic_light_groups.iter().flatten().cloned())
+            .chain(scene.environment_light_group.clone())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let group_index = |group: &Option<String>| {
+            let group = group.as_ref()?;
+            light_groups.binary_search(group).ok()
+        };
+        let analytic_light_groups = (0..scene.analytic_lights.len())
+            .map(|idx| scene.analytic_light_groups.get(idx).and_then(group_index))
+            .collect();
+        let environment_light_group = group_index(&scene.environment_light_group);
+
+        let mut media = Vec::new();
+        let mut render_objects = Vec::new();
+        // the materials of each of `render_objects`, for bounding the light they emit
+        let mut object_materials = Vec::new();
+        // where each object ends up in `render_objects`, since the media are split off
+        let mut indices = Vec::with_capacity(scene.render_objects.len());
+        for obj in scene.render_objects {
+            let obj = match obj {
+                Some(obj) => obj,
+                None => {
+                    indices.push(None);
+                    continue;
+                }
+            };
+            let light_group = group_index(&obj.light_group);
+            let parent = obj.parent;
+            let used = obj.obj.materials();
+            let mut obj: RenderObjectInternal = obj.into();
+            obj.light_group = light_group;
+            if let Some(parent) = parent {
+                obj.apply_group(GroupTransform::of(&scene.groups, parent));
+            }
+            if obj.obj.as_medium().is_some() {
+                indices.push(None);
+                media.push(obj);
+            } else {
+                indices.push(Some(render_objects.len()));
+                render_objects.push(obj);
+                object_materials.push(used);
+            }
+        }
+
+        // shapes which can't be sampled stay ordinary objects
+        let mut lights = Vec::new();
+        for idx in scene.lights {
+            if let Some(Some(idx)) = indices.get(idx.0).copied() {
+                let obj = &mut render_objects[idx];
+                let samplable = obj.sample_point(Vec3::zero(), &mut LcRng::new(0)).is_some();
+                if obj.light.is_none() && samplable {
+                    obj.light = Some(lights.len());
+                    lights.push(idx);
+                }
+            }
+        }
 
         //render_objects.extend(scene.meshes.into_iter().map(|m| {
         //use crate::serde_compat::AsHitable;
@@ -126,10 +1972,47 @@ impl From<Scene> for SceneInternal {
         //}
         //}));
 
+        let mut environment = scene.environment;
+        environment.prepare();
+        let portal_probability = if scene.portals.is_empty() {
+            0.
+        } else if environment.can_sample() {
+            0.5
+        } else {
+            1.
+        };
+        let mut background = scene.background;
+        if let Some(background) = &mut background {
+            background.prepare();
+        }
+
+        let materials = scene.materials;
+        let light_bounds = lights
+            .iter()
+            .map(|&idx| area_light_bounds(&render_objects[idx], &object_materials[idx], &materials))
+            .chain(scene.analytic_lights.iter().map(|light| light.bounds()))
+            .collect();
+        let light_bvh = LightBVH::new(light_bounds);
+
         SceneInternal {
             render_objects,
-            materials: scene.materials,
-            environment: scene.environment,
+            media,
+            materials,
+            environment,
+            background,
+            time: 0.,
+            atmosphere: scene.atmosphere,
+            atmosphere_phase: scene.atmosphere.map(|atmosphere| {
+                HenyeyGreensteinMat::new(ConstantTexture::new(Vec3::one()), atmosphere.g)
+            }),
+            lights,
+            analytic_lights: scene.analytic_lights,
+            portals: scene.portals,
+            light_bvh,
+            light_groups,
+            analytic_light_groups,
+            environment_light_group,
+            portal_probability,
         }
     }
 }
@@ -169,14 +2052,76 @@ pub(crate) struct RenderObjectInternal {
     pub(crate) position: Vec3,
     pub(crate) rotation_mat: Mat3,
     pub(crate) inv_rotation_mat: Mat3,
+    /// The scale along each of the object's own axes, applied before rotating it
+    pub(crate) scale: Vec3,
     pub(crate) flip_normals: bool,
+    pub(crate) two_sided: bool,
+    pub(crate) priority: u32,
     pub(crate) aabb: AABB,
+    /// The index of the light this object is, if it's one
+    pub(crate) light: Option<usize>,
+    /// The light group its emitted light goes to, if any
+    pub(crate) light_group: Option<usize>,
 }
 
 impl RenderObjectInternal {
+    /// Transforms a ray from world space into the object's local space. Since the transformation
+    /// is affine, points along both rays have the same parameters (though with a scale, the
+    /// direction of the local ray has a different length).
+    pub(crate) fn local_ray(&self, r: &Ray) -> Ray {
+        let cos_trace = {
+            let trace = self.rotation_mat[0][0] + self.rotation_mat[1][1] + self.rotation_mat[2][2];
+            0.5 * (trace - 1.) // .acos()
+        };
+        let (origin, direction) = if cos_trace < 0.999 {
+            (
+                self.inv_rotation_mat * (*r.origin() - self.position),
+                self.inv_rotation_mat * *r.direction(),
+            )
+        } else {
+            (*r.origin() - self.position, *r.direction())
+        };
+        if self.scale == Vec3::one() {
+            Ray::new(origin, direction)
+        } else {
+            Ray::new(origin / self.scale, direction / self.scale)
+        }
+    }
+
+    /// Transforms a point from the object's local space into world space
+    pub(crate) fn world_point(&self, point: Vec3) -> Vec3 {
+        self.rotation_mat * (self.scale * point) + self.position
+    }
+
+    /// Transforms a point from world space into the object's local space
+    pub(crate) fn local_point(&self, point: Vec3) -> Vec3 {
+        (self.inv_rotation_mat * (point - self.position)) / self.scale
+    }
+
+    /// The scale, if it's the same along every axis. Only objects scaled uniformly can be sampled
+    /// as lights, since the density of points on other objects would depend on their normals.
+    fn uniform_scale(&self) -> Option<f32> {
+        let scale = self.scale.x.abs();
+        if self.scale.y.abs() == scale && self.scale.z.abs() == scale {
+            Some(scale)
+        } else {
+            None
+        }
+    }
+
+    /// Places the object relative to a `Group`, rather than the world
+    fn apply_group(&mut self, group: GroupTransform) {
+        self.position = group.position + group.rotation_mat * (group.scale * self.position);
+        self.rotation_mat = group.rotation_mat * self.rotation_mat;
+        self.inv_rotation_mat = self.inv_rotation_mat * group.inv_rotation_mat;
+        self.scale *= group.scale;
+        self.update_bounding_box();
+    }
+
     pub(crate) fn update_bounding_box(&mut self) {
         self.aabb = {
             let bbox = self.obj.bounding_box();
+            let bbox = AABB::from_two_points(self.scale * bbox.min, self.scale * bbox.max);
             // First, rotate the bounding box
             // If there is a signficant rotation
             let cos_trace = {
@@ -220,6 +2165,30 @@ impl Hitable for RenderObjectInternal {
     fn bounding_box(&self) -> AABB {
         self.aabb.clone()
     }
+
+    fn sample_point(&self, origin: Vec3, rand: &mut LcRng) -> Option<SurfaceSample> {
+        let scale = self.uniform_scale()?;
+        let mut sample = self.obj.sample_point(self.local_point(origin), rand)?;
+        sample.point = self.world_point(sample.point);
+        sample.normal = self.rotation_mat * (self.scale.map(f32::signum) * sample.normal);
+        if self.flip_normals {
+            sample.normal = -sample.normal;
+        }
+        // areas grow with the square of the scale
+        sample.pdf /= scale * scale;
+        Some(sample)
+    }
+
+    fn pdf(&self, origin: Vec3, point: Vec3) -> f32 {
+        match self.uniform_scale() {
+            Some(scale) => {
+                self.obj
+                    .pdf(self.local_point(origin), self.local_point(point))
+                    / (scale * scale)
+            }
+            None => 0.,
+        }
+    }
 }
 
 impl Hitable for &RenderObjectInternal {
@@ -239,26 +2208,29 @@ fn render_object_internet_hit(
     t_max: f32,
     rand: &mut LcRng,
 ) -> Option<RaycastHit> {
-    let cos_trace = {
-        let trace = obj.rotation_mat[0][0] + obj.rotation_mat[1][1] + obj.rotation_mat[2][2];
-        0.5 * (trace - 1.) // .acos()
-    };
-    let new_ray = if cos_trace < 0.999 {
-        Ray::new(
-            obj.inv_rotation_mat * (*r.origin() - obj.position),
-            obj.inv_rotation_mat * *r.direction(),
-        )
-    } else {
-        Ray::new(*r.origin() - obj.position, *r.direction())
-    };
+    let new_ray = obj.local_ray(r);
     if let Some(mut hit) = obj.obj.hit(&new_ray, t_min, t_max, rand) {
-        hit.point = obj.rotation_mat * hit.point;
-        hit.point += obj.position;
+        hit.point = obj.world_point(hit.point);
 
-        hit.normal = obj.rotation_mat * hit.normal;
+        if obj.scale == Vec3::one() {
+            hit.normal = obj.rotation_mat * hit.normal;
+            hit.tangent = hit.tangent.map(|t| obj.rotation_mat * t);
+        } else {
+            // normals are transformed by the inverse transpose, which keeps them perpendicular
+            // to the stretched surface
+            hit.normal = (obj.rotation_mat * (hit.normal / obj.scale)).normalized();
+            hit.tangent = hit.tangent.map(|t| obj.rotation_mat * (obj.scale * t));
+        }
         if obj.flip_normals {
             hit.normal = -hit.normal;
         }
+        // Two sided objects always face the incoming ray
+        if obj.two_sided && hit.normal.dot(*r.direction()) > 0. {
+            hit.normal = -hit.normal;
+        }
+        hit.priority = obj.priority;
+        hit.light = obj.light;
+        hit.light_group = obj.light_group;
         Some(hit)
     } else {
         None
@@ -269,11 +2241,27 @@ fn render_object_internet_hit(
 /// any transformations on it.
 #[derive(Serialize, Deserialize)]
 pub struct RenderObject {
-    obj: Box<dyn SerializableShape>,
+    pub(crate) obj: Box<dyn SerializableShape>,
     position: Vec3,
     #[serde(with = "crate::serde_compat::Rotor3Def")]
     rotation: Rotor3,
     flip_normals: bool,
+    #[serde(default)]
+    two_sided: bool,
+    #[serde(default)]
+    priority: u32,
+    #[serde(default)]
+    light_group: Option<String>,
+    #[serde(default = "default_scale")]
+    scale: Vec3,
+    #[serde(default)]
+    parent: Option<GroupIdx>,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+fn default_scale() -> Vec3 {
+    Vec3::one()
 }
 
 impl From<RenderObject> for RenderObjectInternal {
@@ -283,8 +2271,13 @@ impl From<RenderObject> for RenderObjectInternal {
             position: s.position,
             rotation_mat: s.rotation.into_matrix(),
             inv_rotation_mat: s.rotation.reversed().into_matrix(),
+            scale: s.scale,
             flip_normals: s.flip_normals,
+            two_sided: s.two_sided,
+            priority: s.priority,
             aabb: AABB::new(Vec3::zero(), Vec3::zero()), // This will be overwritten in `update_bounding_box`
+            light: None,
+            light_group: None,
         };
         obj.update_bounding_box();
         obj
@@ -292,6 +2285,34 @@ impl From<RenderObject> for RenderObjectInternal {
 }
 
 impl RenderObject {
+    /// The bounding box of the object in the space it's placed in, ignoring its `parent`
+    pub(crate) fn bounds(&self) -> AABB {
+        self.bounds_in(GroupTransform::identity())
+    }
+
+    /// The bounding box of the object once it's placed relative to `group`
+    fn bounds_in(&self, group: GroupTransform) -> AABB {
+        // place the corners of the object's box the same way `RenderObjectInternal` does
+        let position = group.position + group.rotation_mat * (group.scale * self.position);
+        let rotation_mat = group.rotation_mat * self.rotation.into_matrix();
+        let scale = group.scale * self.scale;
+        let local = self.obj.local_bounds();
+        let corner = |(i, j, k)| {
+            let corner = Vec3::new(
+                if i == 0 { local.min.x } else { local.max.x },
+                if j == 0 { local.min.y } else { local.max.y },
+                if k == 0 { local.min.z } else { local.max.z },
+            );
+            position + rotation_mat * (scale * corner)
+        };
+        let first = corner((0, 0, 0));
+        iproduct!(0..2, 0..2, 0..2)
+            .map(corner)
+            .fold(AABB::from_two_points(first, first), |aabb, point| {
+                aabb.expand_to_point(point)
+            })
+    }
+
     /// Creates a new RenderObject
     pub fn new<T: SerializableShape + 'static>(obj: T) -> Self {
         RenderObject {
@@ -299,6 +2320,12 @@ impl RenderObject {
             position: Vec3::zero(),
             rotation: Rotor3::identity(),
             flip_normals: false,
+            two_sided: false,
+            priority: 0,
+            light_group: None,
+            scale: Vec3::one(),
+            parent: None,
+            name: None,
         }
     }
 
@@ -316,6 +2343,35 @@ impl RenderObject {
         self
     }
 
+    /// Scales the `RenderObject` by the same amount along every axis
+    #[inline(always)]
+    pub fn scale(mut self, scale: f32) -> Self {
+        self.scale = Vec3::broadcast(scale);
+        self
+    }
+
+    /// Scales the `RenderObject` along each of its own axes (before it's rotated), e.g. to
+    /// squash a sphere into an ellipsoid. None of the components should be 0. Participating media
+    /// keep their density in their own space, so they're as opaque as before from end to end.
+    /// Objects with a different scale along each axis can't be sampled as lights (see
+    /// `Scene::add_light`).
+    /// ```
+    /// use firework::material::LambertianMat;
+    /// use firework::objects::Sphere;
+    /// use firework::{RenderObject, Scene};
+    /// use ultraviolet::Vec3;
+    ///
+    /// let mut scene = Scene::new();
+    /// let white = scene.add_material(LambertianMat::with_color(Vec3::one()));
+    /// let ellipsoid = RenderObject::new(Sphere::new(1., white)).scale_vec(Vec3::new(2., 1., 1.));
+    /// scene.add_object(ellipsoid);
+    /// ```
+    #[inline(always)]
+    pub fn scale_vec(mut self, scale: Vec3) -> Self {
+        self.scale = scale;
+        self
+    }
+
     /// Sets the rotation of the `RenderObject`
     #[inline(always)]
     pub fn rotate(mut self, rotor: Rotor3) -> Self {
@@ -331,4 +2387,77 @@ impl RenderObject {
         self.flip_normals = !self.flip_normals;
         self
     }
+
+    /// Makes the `RenderObject` two sided, so the normal is flipped whenever a ray hits the back
+    /// face. This lets open surfaces (rects, partial cylinders, open meshes) shade correctly from
+    /// both sides. Note that this should not be used with dielectrics, which rely on the normal to
+    /// know whether the ray is entering or exiting the object.
+    #[inline(always)]
+    pub fn two_sided(mut self) -> Self {
+        self.two_sided = true;
+        self
+    }
+
+    /// Sets the priority of the `RenderObject`, for resolving nested and overlapping dielectrics.
+    /// While a path is inside an object, the surfaces of objects with a lower priority are
+    /// ignored, so e.g. a liquid can be modelled slightly larger than the inside of its glass (with
+    /// a higher priority than the glass) to avoid a gap of air between them. Objects with the
+    /// same priority (by default, 0) are simply nested.
+    /// ```
+    /// use firework::material::DielectricMat;
+    /// use firework::objects::Sphere;
+    /// use firework::{RenderObject, Scene};
+    ///
+    /// let mut scene = Scene::new();
+    /// let glass = scene.add_material(DielectricMat::new(1.5));
+    /// let water = scene.add_material(DielectricMat::new(1.33));
+    /// scene.add_object(RenderObject::new(Sphere::new(1., glass)));
+    /// scene.add_object(RenderObject::new(Sphere::new(0.95, water)).priority(1));
+    /// ```
+    #[inline(always)]
+    pub fn priority(mut self, priority: u32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Puts the light emitted by the object in a light group. The light from each group (after
+    /// bouncing around the scene) is also written to its own image in the `RenderBuffer`, so
+    /// lights can be rebalanced in compositing without rendering again. The light from every
+    /// object, in a group or not, is still part of the full image.
+    /// ```
+    /// use firework::material::EmissiveMat;
+    /// use firework::objects::XZRect;
+    /// use firework::render::Renderer;
+    /// use firework::{RenderObject, Scene};
+    /// use ultraviolet::Vec3;
+    ///
+    /// let mut scene = Scene::new();
+    /// let light = scene.add_material(EmissiveMat::with_color(Vec3::broadcast(15.)));
+    /// let key = XZRect::new(-1., 1., -1., 1., 5., light);
+    /// scene.add_light(RenderObject::new(key).flip_normals().light_group("key"));
+    ///
+    /// let buffer = Renderer::default().width(8).height(8).samples(1).render_buffer(scene);
+    /// assert_eq!(buffer.light_groups["key"].len(), 64);
+    /// ```
+    #[inline(always)]
+    pub fn light_group(mut self, group: &str) -> Self {
+        self.light_group = Some(group.to_string());
+        self
+    }
+
+    /// Names the `RenderObject`, so it can be found with `Scene::object_by_name`. The name is
+    /// saved along with the object, so it can be written directly in a scene file.
+    #[inline(always)]
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Attaches the `RenderObject` to a `Group`, so its position, rotation and scale are relative
+    /// to the group's (see `Scene::add_group`)
+    #[inline(always)]
+    pub fn parent(mut self, group: GroupIdx) -> Self {
+        self.parent = Some(group);
+        self
+    }
 }
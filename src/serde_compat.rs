@@ -1,4 +1,6 @@
+use crate::aabb::AABB;
 use crate::render::Hitable;
+use crate::scene::MaterialIdx;
 use serde::{Deserialize, Serialize};
 use ultraviolet::{Bivec3, Rotor3};
 
@@ -20,7 +22,7 @@ struct Bivec3Def {
 }
 
 #[typetag::serde(tag = "object_type")]
-pub trait SerializableShape: AsHitable + Sync {}
+pub trait SerializableShape: AsHitable + MapMaterials + InspectShape + Sync {}
 
 pub trait AsHitable {
     fn to_hitable(self: Box<Self>) -> Box<dyn Hitable>
@@ -28,6 +30,63 @@ pub trait AsHitable {
         Self: 'static;
 }
 
+/// Lets the materials of a shape be changed after it's created, e.g. when scenes are merged
+pub trait MapMaterials {
+    /// Replaces every `MaterialIdx` the shape refers to with `f` of it
+    fn map_materials(&mut self, f: &mut dyn FnMut(MaterialIdx) -> MaterialIdx);
+
+    /// Returns every `MaterialIdx` the shape refers to
+    fn materials(&self) -> Vec<MaterialIdx>;
+}
+
+impl MapMaterials for Box<dyn SerializableShape> {
+    fn map_materials(&mut self, f: &mut dyn FnMut(MaterialIdx) -> MaterialIdx) {
+        self.as_mut().map_materials(f)
+    }
+
+    fn materials(&self) -> Vec<MaterialIdx> {
+        self.as_ref().materials()
+    }
+}
+
+/// Lets `Scene::validate` and `Scene::stats` look at a shape without building it
+pub trait InspectShape {
+    /// The bounding box of the shape in its own space
+    fn local_bounds(&self) -> AABB;
+
+    /// Describes anything wrong with the shape's data which would break rendering
+    fn problems(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn triangle_count(&self) -> usize {
+        0
+    }
+
+    /// A rough estimate of the memory the shape takes up once it's built, in bytes
+    fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
+}
+
+impl InspectShape for Box<dyn SerializableShape> {
+    fn local_bounds(&self) -> AABB {
+        self.as_ref().local_bounds()
+    }
+
+    fn problems(&self) -> Vec<String> {
+        self.as_ref().problems()
+    }
+
+    fn triangle_count(&self) -> usize {
+        self.as_ref().triangle_count()
+    }
+
+    fn memory_size(&self) -> usize {
+        self.as_ref().memory_size()
+    }
+}
+
 macro_rules! impl_shape_traits {
     ($($y:path),+) => {
         $(
@@ -37,6 +96,11 @@ macro_rules! impl_shape_traits {
                 }
             }
 
+            impl InspectShape for $y {
+                fn local_bounds(&self) -> AABB {
+                    Hitable::bounding_box(self)
+                }
+            }
 
             #[typetag::serde]
             impl SerializableShape for $y {}
@@ -49,3 +113,6 @@ impl_shape_traits!(Cone, Sphere, Disk, Cylinder, Rect3d, XYRect, YZRect, XZRect)
 
 #[typetag::serde]
 impl SerializableShape for crate::objects::ConstantMedium<Box<dyn SerializableShape>> {}
+
+#[typetag::serde]
+impl SerializableShape for crate::objects::HeterogeneousMedium<Box<dyn SerializableShape>> {}
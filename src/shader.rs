@@ -0,0 +1,389 @@
+use crate::texture::Texture;
+use crate::util::schlick;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use ultraviolet::{Vec2, Vec3};
+
+/// Used to index `Node`s in a `ShaderGraph`
+pub type NodeIdx = usize;
+
+/// Everything a `ShaderGraph` can know about the point being shaded
+#[derive(Debug, Clone, Copy)]
+pub struct ShadeContext {
+    pub uv: Vec2,
+    pub point: Vec3,
+    /// The (normalized) surface normal. Zero when the graph is sampled as a plain `Texture`.
+    pub normal: Vec3,
+    /// The (normalized) direction towards the viewer. Zero when the graph is sampled as a plain
+    /// `Texture`.
+    pub view: Vec3,
+}
+
+/// A single operation in a `ShaderGraph`. Every node produces a `Vec3`; scalar inputs (like the
+/// factor of a `Mix`) use the first component.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "node", try_from = "NodeFields")]
+pub enum Node {
+    Constant {
+        value: Vec3,
+    },
+    Texture {
+        texture: Box<dyn Texture>,
+    },
+    /// The uv coordinates of the hit, as (u, v, 0)
+    Uv,
+    Position,
+    Normal,
+    Add {
+        a: NodeIdx,
+        b: NodeIdx,
+    },
+    Subtract {
+        a: NodeIdx,
+        b: NodeIdx,
+    },
+    Multiply {
+        a: NodeIdx,
+        b: NodeIdx,
+    },
+    /// Linearly interpolates between `a` (factor = 0) and `b` (factor = 1)
+    Mix {
+        a: NodeIdx,
+        b: NodeIdx,
+        factor: NodeIdx,
+    },
+    /// Computes `1 - input`
+    Invert {
+        input: NodeIdx,
+    },
+    Power {
+        input: NodeIdx,
+        exponent: f32,
+    },
+    Clamp {
+        input: NodeIdx,
+        min: f32,
+        max: f32,
+    },
+    /// Converts a color to a grayscale value
+    Luminance {
+        input: NodeIdx,
+    },
+    /// The Fresnel reflectance of a dielectric with the given index of refraction
+    Fresnel {
+        ior: f32,
+    },
+    /// The cosine of the angle between the normal and the view direction
+    Facing,
+}
+
+/// Every field a `Node` can have, which a `Node` is deserialized through. Deriving `Deserialize`
+/// for an internally tagged enum buffers the whole node first, which formats like RON can't do
+/// for structs, so the tag is matched by hand instead.
+#[derive(Deserialize)]
+struct NodeFields {
+    node: String,
+    #[serde(default, deserialize_with = "present")]
+    value: Option<Vec3>,
+    #[serde(default, deserialize_with = "present")]
+    texture: Option<Box<dyn Texture>>,
+    #[serde(default, deserialize_with = "present")]
+    a: Option<NodeIdx>,
+    #[serde(default, deserialize_with = "present")]
+    b: Option<NodeIdx>,
+    #[serde(default, deserialize_with = "present")]
+    factor: Option<NodeIdx>,
+    #[serde(default, deserialize_with = "present")]
+    input: Option<NodeIdx>,
+    #[serde(default, deserialize_with = "present")]
+    exponent: Option<f32>,
+    #[serde(default, deserialize_with = "present")]
+    min: Option<f32>,
+    #[serde(default, deserialize_with = "present")]
+    max: Option<f32>,
+    #[serde(default, deserialize_with = "present")]
+    ior: Option<f32>,
+}
+
+/// Deserializes a field that's there, so the fields of `NodeFields` don't have to be written as
+/// options (e.g. with `Some(..)` in RON)
+fn present<'de, D: serde::Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
+}
+
+impl TryFrom<NodeFields> for Node {
+    type Error = String;
+
+    fn try_from(fields: NodeFields) -> Result<Node, String> {
+        let NodeFields {
+            node,
+            value,
+            texture,
+            a,
+            b,
+            factor,
+            input,
+            exponent,
+            min,
+            max,
+            ior,
+        } = fields;
+        fn required<T>(field: Option<T>, name: &str) -> Result<T, String> {
+            field.ok_or_else(|| format!("missing field `{}`", name))
+        }
+        Ok(match node.as_str() {
+            "Constant" => Node::Constant {
+                value: required(value, "value")?,
+            },
+            "Texture" => Node::Texture {
+                texture: required(texture, "texture")?,
+            },
+            "Uv" => Node::Uv,
+            "Position" => Node::Position,
+            "Normal" => Node::Normal,
+            "Add" => Node::Add {
+                a: required(a, "a")?,
+                b: required(b, "b")?,
+            },
+            "Subtract" => Node::Subtract {
+                a: required(a, "a")?,
+                b: required(b, "b")?,
+            },
+            "Multiply" => Node::Multiply {
+                a: required(a, "a")?,
+                b: required(b, "b")?,
+            },
+            "Mix" => Node::Mix {
+                a: required(a, "a")?,
+                b: required(b, "b")?,
+                factor: required(factor, "factor")?,
+            },
+            "Invert" => Node::Invert {
+                input: required(input, "input")?,
+            },
+            "Power" => Node::Power {
+                input: required(input, "input")?,
+                exponent: required(exponent, "exponent")?,
+            },
+            "Clamp" => Node::Clamp {
+                input: required(input, "input")?,
+                min: required(min, "min")?,
+                max: required(max, "max")?,
+            },
+            "Luminance" => Node::Luminance {
+                input: required(input, "input")?,
+            },
+            "Fresnel" => Node::Fresnel {
+                ior: required(ior, "ior")?,
+            },
+            "Facing" => Node::Facing,
+            _ => return Err(format!("unknown node `{}`", node)),
+        })
+    }
+}
+
+impl Node {
+    /// Returns the indices of the nodes this node takes as inputs
+    fn inputs(&self) -> Vec<NodeIdx> {
+        match *self {
+            Node::Add { a, b } | Node::Subtract { a, b } | Node::Multiply { a, b } => vec![a, b],
+            Node::Mix { a, b, factor } => vec![a, b, factor],
+            Node::Invert { input }
+            | Node::Power { input, .. }
+            | Node::Clamp { input, .. }
+            | Node::Luminance { input } => vec![input],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// A small graph of nodes, evaluated at shade time, which can be used to build up complex looks
+/// from textures and math operations in data (e.g. a scene file), rather than writing new
+/// `Texture` or `Material` types.
+/// Nodes can only use the outputs of nodes that were added before them, so the graph can never
+/// contain cycles. A graph read from a scene file is checked for this, and that its output is one
+/// of its nodes, as it's read.
+/// ```
+/// use firework::shader::{Node, ShaderGraph};
+/// use firework::texture::PerlinNoiseTexture;
+/// use ultraviolet::Vec3;
+///
+/// let mut graph = ShaderGraph::new();
+/// let red = graph.add(Node::Constant { value: Vec3::new(0.8, 0.1, 0.1) });
+/// let blue = graph.add(Node::Constant { value: Vec3::new(0.1, 0.1, 0.8) });
+/// let noise = graph.add(Node::Texture { texture: Box::new(PerlinNoiseTexture::new(4.)) });
+/// let mix = graph.add(Node::Mix { a: red, b: blue, factor: noise });
+/// graph.set_output(mix);
+///
+/// // in a scene file, a node can't use its own output either
+/// let cycle = "{nodes: [{node: Invert, input: 0}], output: 0}";
+/// assert!(serde_yaml::from_str::<ShaderGraph>(cycle).is_err());
+/// // and an empty graph has nothing to output
+/// assert_eq!(ShaderGraph::new().problems().len(), 1);
+/// ```
+#[derive(Serialize, Deserialize)]
+#[serde(try_from = "ShaderGraphDef")]
+pub struct ShaderGraph {
+    nodes: Vec<Node>,
+    output: NodeIdx,
+}
+
+/// A `ShaderGraph` as it's written in a scene file, before it's checked
+#[derive(Deserialize)]
+struct ShaderGraphDef {
+    nodes: Vec<Node>,
+    output: NodeIdx,
+}
+
+impl TryFrom<ShaderGraphDef> for ShaderGraph {
+    type Error = String;
+
+    fn try_from(def: ShaderGraphDef) -> Result<ShaderGraph, String> {
+        let graph = ShaderGraph {
+            nodes: def.nodes,
+            output: def.output,
+        };
+        match graph.problems().into_iter().next() {
+            Some(problem) => Err(problem),
+            None => Ok(graph),
+        }
+    }
+}
+
+impl ShaderGraph {
+    /// Creates an empty graph, which outputs black until nodes are added to it.
+    /// ```
+    /// use firework::shader::ShaderGraph;
+    /// use firework::texture::Texture;
+    /// use ultraviolet::{Vec2, Vec3};
+    ///
+    /// assert_eq!(ShaderGraph::new().sample(Vec2::zero(), &Vec3::zero()), Vec3::zero());
+    /// ```
+    pub fn new() -> Self {
+        ShaderGraph {
+            nodes: Vec::new(),
+            output: 0,
+        }
+    }
+
+    /// Creates a graph that always outputs the given value
+    pub fn constant(value: Vec3) -> Self {
+        let mut graph = ShaderGraph::new();
+        graph.add(Node::Constant { value });
+        graph
+    }
+
+    /// Adds a node to the graph, and returns its `NodeIdx`. The most recently added node is the
+    /// output of the graph, unless `set_output` is called.
+    /// Panics if the node refers to a node that hasn't been added yet.
+    pub fn add(&mut self, node: Node) -> NodeIdx {
+        let idx = self.nodes.len();
+        assert!(
+            Self::inputs_before(&node, idx),
+            "ShaderGraph::add() -- nodes may only use the outputs of earlier nodes"
+        );
+        self.nodes.push(node);
+        self.output = idx;
+        idx
+    }
+
+    /// Sets which node's value is returned by the graph.
+    /// Panics if the node hasn't been added.
+    pub fn set_output(&mut self, output: NodeIdx) {
+        assert!(
+            output < self.nodes.len(),
+            "ShaderGraph::set_output() -- the output must be a node of the graph"
+        );
+        self.output = output;
+    }
+
+    /// Whether a node at `idx` only uses the outputs of the nodes before it
+    fn inputs_before(node: &Node, idx: NodeIdx) -> bool {
+        node.inputs().iter().all(|&input| input < idx)
+    }
+
+    /// Describes anything wrong with the graph which would stop it from being evaluated, i.e. it
+    /// has no nodes, or its nodes use the outputs of nodes which aren't before them
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.output >= self.nodes.len() {
+            problems.push(format!(
+                "the output of the shader graph is node {}, but it only has {} nodes",
+                self.output,
+                self.nodes.len()
+            ));
+        }
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if !Self::inputs_before(node, idx) {
+                problems.push(format!(
+                    "node {} of the shader graph uses the output of a node which isn't before it",
+                    idx
+                ));
+            }
+        }
+        problems
+    }
+
+    /// Evaluates the graph at the given point. The nodes are evaluated in order, up to the
+    /// output, so each one is evaluated once, however many nodes use it. An empty graph is black.
+    pub fn eval(&self, ctx: &ShadeContext) -> Vec3 {
+        let nodes = match self.nodes.get(..=self.output) {
+            Some(nodes) => nodes,
+            None => return Vec3::zero(),
+        };
+        let mut values: Vec<Vec3> = Vec::with_capacity(nodes.len());
+        for node in nodes {
+            let value = Self::eval_node(node, &values, ctx);
+            values.push(value);
+        }
+        values[self.output]
+    }
+
+    /// Evaluates a node, given the `values` of the nodes before it
+    fn eval_node(node: &Node, values: &[Vec3], ctx: &ShadeContext) -> Vec3 {
+        match node {
+            Node::Constant { value } => *value,
+            Node::Texture { texture } => texture.sample(ctx.uv, &ctx.point),
+            Node::Uv => Vec3::new(ctx.uv.x, ctx.uv.y, 0.),
+            Node::Position => ctx.point,
+            Node::Normal => ctx.normal,
+            Node::Add { a, b } => values[*a] + values[*b],
+            Node::Subtract { a, b } => values[*a] - values[*b],
+            Node::Multiply { a, b } => values[*a] * values[*b],
+            Node::Mix { a, b, factor } => {
+                let t = values[*factor].x;
+                (1. - t) * values[*a] + t * values[*b]
+            }
+            Node::Invert { input } => Vec3::one() - values[*input],
+            Node::Power { input, exponent } => values[*input].map(|x| x.max(0.).powf(*exponent)),
+            Node::Clamp { input, min, max } => values[*input].map(|x| x.clamp(*min, *max)),
+            Node::Luminance { input } => {
+                Vec3::broadcast(values[*input].dot(Vec3::new(0.2126, 0.7152, 0.0722)))
+            }
+            Node::Fresnel { ior } => {
+                Vec3::broadcast(schlick(ctx.normal.dot(ctx.view).max(0.), *ior))
+            }
+            Node::Facing => Vec3::broadcast(ctx.normal.dot(ctx.view).max(0.)),
+        }
+    }
+}
+
+impl Default for ShaderGraph {
+    fn default() -> Self {
+        ShaderGraph::new()
+    }
+}
+
+#[typetag::serde]
+impl Texture for ShaderGraph {
+    fn sample(&self, uv: Vec2, point: &Vec3) -> Vec3 {
+        self.eval(&ShadeContext {
+            uv,
+            point: *point,
+            normal: Vec3::zero(),
+            view: Vec3::zero(),
+        })
+    }
+}
@@ -1,12 +1,35 @@
-use image::{GenericImageView, Pixel, Rgba};
-use serde::{Deserialize, Serialize};
+use crate::assets::resolve_asset;
+use crate::objects::TriangleMesh;
+use crate::render::RaycastHit;
+use image::GenericImageView;
+use serde::de::{IgnoredAny, MapAccess, Visitor};
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::convert::TryFrom;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use ultraviolet::{Vec2, Vec3};
 
 #[typetag::serde(tag = "texture")]
-pub trait Texture: Sync {
+pub trait Texture: Send + Sync {
     fn sample(&self, uv: Vec2, point: &Vec3) -> Vec3;
+
+    /// Samples the texture for a `RaycastHit`. Textures that can make use of more information
+    /// about the hit (like the ray footprint, for filtering) should override this.
+    fn sample_hit(&self, hit: &RaycastHit) -> Vec3 {
+        self.sample(hit.uv, &hit.point)
+    }
+
+    /// A rough estimate of the memory the texture takes up, in bytes
+    fn memory_size(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
+
+    /// The largest value of each channel anywhere in the texture, or at least an upper bound on
+    /// it, if one is known. Used to bound the light emitted by lights with textured materials.
+    fn max(&self) -> Option<Vec3> {
+        None
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -31,6 +54,10 @@ impl Texture for ConstantTexture {
     fn sample(&self, _uv: Vec2, _point: &Vec3) -> Vec3 {
         self.color
     }
+
+    fn max(&self) -> Option<Vec3> {
+        Some(self.color)
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -70,11 +97,17 @@ impl Texture for CheckerTexture {
             self.odd.sample(uv, point)
         }
     }
+
+    fn max(&self) -> Option<Vec3> {
+        Some(self.odd.max()?.max_by_component(self.even.max()?))
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct PerlinNoiseTexture {
     scale: f32,
+    #[serde(default, rename = "seed")]
+    perm: Permutation,
 }
 
 static P: [usize; 512] = [
@@ -105,12 +138,64 @@ static P: [usize; 512] = [
     222, 114, 67, 29, 24, 72, 243, 141, 128, 195, 78, 66, 215, 61, 156, 180,
 ];
 
+/// The permutation table used to hash lattice points in Perlin noise, serialized as the seed it
+/// was generated from. Seed 0 gives Ken Perlin's original table (`P`).
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "u32", into = "u32")]
+struct Permutation {
+    seed: u32,
+    table: Vec<usize>,
+}
+
+impl From<u32> for Permutation {
+    fn from(seed: u32) -> Self {
+        if seed == 0 {
+            return Permutation {
+                seed,
+                table: P.to_vec(),
+            };
+        }
+
+        // Fisher-Yates shuffle, with the randomness coming from hashing the seed
+        let mut table: Vec<usize> = (0..256).collect();
+        for i in (1..256).rev() {
+            let j = hash_u32(i as i32, 0, 0, seed) as usize % (i + 1);
+            table.swap(i, j);
+        }
+        // repeated, so lookups of `table[x] + y` don't need to wrap
+        table.extend_from_within(..);
+        Permutation { seed, table }
+    }
+}
+
+impl From<Permutation> for u32 {
+    fn from(perm: Permutation) -> u32 {
+        perm.seed
+    }
+}
+
+impl Default for Permutation {
+    fn default() -> Self {
+        Permutation::from(0)
+    }
+}
+
 impl PerlinNoiseTexture {
     pub fn new(scale: f32) -> PerlinNoiseTexture {
-        PerlinNoiseTexture { scale }
+        PerlinNoiseTexture {
+            scale,
+            perm: Permutation::default(),
+        }
+    }
+
+    /// Sets the seed used to generate the noise, so different textures can have different
+    /// patterns. Seed 0 (the default) gives the classic Perlin noise.
+    pub fn seed(mut self, seed: u32) -> Self {
+        self.perm = Permutation::from(seed);
+        self
     }
 
-    fn noise(p: &Vec3) -> f32 {
+    fn noise(perm: &Permutation, p: &Vec3) -> f32 {
         let x0 = p.x.floor() as usize & 255;
         let y0 = p.y.floor() as usize & 255;
         let z0 = p.z.floor() as usize & 255;
@@ -123,35 +208,39 @@ impl PerlinNoiseTexture {
         let v = fade(y);
         let w = fade(z);
 
-        let a = P[x0] + y0;
-        let aa = P[a] + z0;
-        let ab = P[a + 1] + z0;
-        let b = P[x0 + 1] + y0;
-        let ba = P[b] + z0;
-        let bb = P[b + 1] + z0;
+        let a = perm.table[x0] + y0;
+        let aa = perm.table[a] + z0;
+        let ab = perm.table[a + 1] + z0;
+        let b = perm.table[x0 + 1] + y0;
+        let ba = perm.table[b] + z0;
+        let bb = perm.table[b + 1] + z0;
 
         lerp(
             w,
             lerp(
                 v,
-                lerp(u, grad(P[aa], x, y, z), grad(P[ba], x - 1.0, y, z)),
                 lerp(
                     u,
-                    grad(P[ab], x, y - 1.0, z),
-                    grad(P[bb], x - 1.0, y - 1.0, z),
+                    grad(perm.table[aa], x, y, z),
+                    grad(perm.table[ba], x - 1.0, y, z),
+                ),
+                lerp(
+                    u,
+                    grad(perm.table[ab], x, y - 1.0, z),
+                    grad(perm.table[bb], x - 1.0, y - 1.0, z),
                 ),
             ),
             lerp(
                 v,
                 lerp(
                     u,
-                    grad(P[aa + 1], x, y, z - 1.0),
-                    grad(P[ba + 1], x - 1.0, y, z - 1.0),
+                    grad(perm.table[aa + 1], x, y, z - 1.0),
+                    grad(perm.table[ba + 1], x - 1.0, y, z - 1.0),
                 ),
                 lerp(
                     u,
-                    grad(P[ab + 1], x, y - 1.0, z - 1.0),
-                    grad(P[bb + 1], x - 1.0, y - 1.0, z - 1.0),
+                    grad(perm.table[ab + 1], x, y - 1.0, z - 1.0),
+                    grad(perm.table[bb + 1], x - 1.0, y - 1.0, z - 1.0),
                 ),
             ),
         )
@@ -161,12 +250,18 @@ impl PerlinNoiseTexture {
 #[typetag::serde]
 impl Texture for PerlinNoiseTexture {
     fn sample(&self, _uv: Vec2, point: &Vec3) -> Vec3 {
-        let a = PerlinNoiseTexture::noise(&(*point * self.scale));
+        let a = PerlinNoiseTexture::noise(&self.perm, &(*point * self.scale));
         Vec3::one() * (a + 0.5).min(1.)
         //Vec3::new(-0.5, 0., 0.)
     }
 }
 
+/// Hashes an integer lattice point to a pseudorandom value in 0..256, using the Perlin
+/// permutation table.
+pub(crate) fn lattice_hash(x: i32, y: i32, z: i32) -> usize {
+    P[P[P[(x & 255) as usize] + (y & 255) as usize] + (z & 255) as usize]
+}
+
 fn fade(t: f32) -> f32 {
     //t * t * t * (t * (t * 6. - 15.) + 10.)
     t * t * (3. - 2. * t)
@@ -196,19 +291,31 @@ fn lerp(t: f32, a: f32, b: f32) -> f32 {
 pub struct TurbulenceTexture {
     depth: usize,
     scale: f32,
+    #[serde(default, rename = "seed")]
+    perm: Permutation,
 }
 
 impl TurbulenceTexture {
     pub fn new(depth: usize, scale: f32) -> Self {
-        TurbulenceTexture { depth, scale }
+        TurbulenceTexture {
+            depth,
+            scale,
+            perm: Permutation::default(),
+        }
+    }
+
+    /// Sets the seed used to generate the noise (see `PerlinNoiseTexture::seed`)
+    pub fn seed(mut self, seed: u32) -> Self {
+        self.perm = Permutation::from(seed);
+        self
     }
 
-    fn turb(depth: usize, point: Vec3) -> f32 {
+    fn turb(perm: &Permutation, depth: usize, point: Vec3) -> f32 {
         let mut accum = 0.;
         let mut p = point;
         let mut weight = 1.;
         for _ in 0..depth {
-            let a = PerlinNoiseTexture::noise(&p);
+            let a = PerlinNoiseTexture::noise(perm, &p);
             accum += weight * a;
             weight *= 0.5;
             p *= 2.;
@@ -220,7 +327,515 @@ impl TurbulenceTexture {
 #[typetag::serde]
 impl Texture for TurbulenceTexture {
     fn sample(&self, _uv: Vec2, point: &Vec3) -> Vec3 {
-        Vec3::one() * TurbulenceTexture::turb(self.depth, *point * self.scale)
+        Vec3::one() * TurbulenceTexture::turb(&self.perm, self.depth, *point * self.scale)
+    }
+}
+
+/// How the octaves of an `FbmTexture` are shaped before being summed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FbmKind {
+    /// Plain Perlin noise in each octave
+    #[default]
+    Standard,
+    /// `(offset - |noise|)^2`, which gives sharp ridges, like mountain ranges or veins
+    Ridged,
+    /// `|noise|`, which gives soft, billowing shapes, like clouds
+    Billow,
+}
+
+/// Fractal Brownian motion: several octaves of Perlin noise, each at a higher frequency
+/// (`lacunarity`) and lower amplitude (`gain`) than the last. The result is normalized to
+/// roughly 0..1, and then remapped to `min..max`.
+/// ```
+/// use firework::texture::{FbmKind, FbmTexture};
+///
+/// let mountains = FbmTexture::new(6, 0.5)
+///     .kind(FbmKind::Ridged)
+///     .lacunarity(2.2)
+///     .gain(0.45);
+/// ```
+#[derive(Serialize, Deserialize)]
+pub struct FbmTexture {
+    octaves: usize,
+    scale: f32,
+    #[serde(default = "default_lacunarity")]
+    lacunarity: f32,
+    #[serde(default = "default_gain")]
+    gain: f32,
+    /// Added to each octave of `Standard` and `Billow` noise, and subtracted from by `Ridged`
+    /// noise
+    #[serde(default)]
+    offset: f32,
+    #[serde(default)]
+    kind: FbmKind,
+    #[serde(default)]
+    min: f32,
+    #[serde(default = "default_max")]
+    max: f32,
+    #[serde(default, rename = "seed")]
+    perm: Permutation,
+}
+
+fn default_lacunarity() -> f32 {
+    2.
+}
+
+fn default_gain() -> f32 {
+    0.5
+}
+
+fn default_max() -> f32 {
+    1.
+}
+
+impl FbmTexture {
+    pub fn new(octaves: usize, scale: f32) -> Self {
+        FbmTexture {
+            octaves,
+            scale,
+            lacunarity: default_lacunarity(),
+            gain: default_gain(),
+            offset: 0.,
+            kind: FbmKind::Standard,
+            min: 0.,
+            max: 1.,
+            perm: Permutation::default(),
+        }
+    }
+
+    /// Sets the seed used to generate the noise (see `PerlinNoiseTexture::seed`)
+    pub fn seed(mut self, seed: u32) -> Self {
+        self.perm = Permutation::from(seed);
+        self
+    }
+
+    /// Sets the frequency multiplier between octaves
+    pub fn lacunarity(mut self, lacunarity: f32) -> Self {
+        self.lacunarity = lacunarity;
+        self
+    }
+
+    /// Sets the amplitude multiplier between octaves
+    pub fn gain(mut self, gain: f32) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    pub fn offset(mut self, offset: f32) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn kind(mut self, kind: FbmKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Sets the range the output is remapped to
+    pub fn remap(mut self, min: f32, max: f32) -> Self {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    /// Evaluates the fBm at `point`, normalized to roughly 0..1
+    fn fbm(&self, point: Vec3) -> f32 {
+        let mut accum = 0.;
+        let mut total_weight = 0.;
+        let mut weight = 1.;
+        let mut p = point;
+        for _ in 0..self.octaves {
+            let n = PerlinNoiseTexture::noise(&self.perm, &p);
+            let signal = match self.kind {
+                FbmKind::Standard => 0.5 * (n + 1.) + self.offset,
+                FbmKind::Ridged => (1. + self.offset - n.abs()).powi(2),
+                FbmKind::Billow => n.abs() + self.offset,
+            };
+            accum += weight * signal;
+            total_weight += weight;
+            weight *= self.gain;
+            p *= self.lacunarity;
+        }
+        if total_weight > 0. {
+            accum / total_weight
+        } else {
+            0.
+        }
+    }
+}
+
+#[typetag::serde]
+impl Texture for FbmTexture {
+    fn sample(&self, _uv: Vec2, point: &Vec3) -> Vec3 {
+        let v = self.fbm(*point * self.scale).clamp(0., 1.);
+        Vec3::broadcast(self.min + v * (self.max - self.min))
+    }
+}
+
+/// Procedural wood grain. Growth rings are concentric cylinders around `axis` (a line through the
+/// origin), perturbed by turbulence so they wobble like real grain, and colored by blending
+/// between `light` and `dark`.
+/// ```
+/// use firework::texture::WoodTexture;
+/// use ultraviolet::Vec3;
+///
+/// let oak = WoodTexture::new(Vec3::new(0.75, 0.55, 0.33), Vec3::new(0.45, 0.28, 0.14))
+///     .rings(8.)
+///     .grain(0.3, 4.)
+///     .axis(Vec3::unit_x());
+/// ```
+#[derive(Serialize, Deserialize)]
+pub struct WoodTexture {
+    light: Vec3,
+    dark: Vec3,
+    /// The number of rings per unit distance from the axis
+    ring_frequency: f32,
+    /// How far the rings are pushed around by the turbulence, in rings
+    grain_strength: f32,
+    grain_scale: f32,
+    axis: Vec3,
+    #[serde(default, rename = "seed")]
+    perm: Permutation,
+}
+
+impl WoodTexture {
+    pub fn new(light: Vec3, dark: Vec3) -> Self {
+        WoodTexture {
+            light,
+            dark,
+            ring_frequency: 6.,
+            grain_strength: 0.2,
+            grain_scale: 3.,
+            axis: Vec3::unit_y(),
+            perm: Permutation::default(),
+        }
+    }
+
+    /// Sets the seed used to generate the noise (see `PerlinNoiseTexture::seed`)
+    pub fn seed(mut self, seed: u32) -> Self {
+        self.perm = Permutation::from(seed);
+        self
+    }
+
+    /// Sets the number of rings per unit distance from the axis
+    pub fn rings(mut self, ring_frequency: f32) -> Self {
+        self.ring_frequency = ring_frequency;
+        self
+    }
+
+    /// Sets how strongly, and at what scale, the rings are distorted by noise
+    pub fn grain(mut self, strength: f32, scale: f32) -> Self {
+        self.grain_strength = strength;
+        self.grain_scale = scale;
+        self
+    }
+
+    /// Sets the direction the trunk of the tree runs along
+    pub fn axis(mut self, axis: Vec3) -> Self {
+        self.axis = axis.normalized();
+        self
+    }
+}
+
+#[typetag::serde]
+impl Texture for WoodTexture {
+    fn sample(&self, _uv: Vec2, point: &Vec3) -> Vec3 {
+        let radial = *point - self.axis * point.dot(self.axis);
+        let grain =
+            self.grain_strength * TurbulenceTexture::turb(&self.perm, 4, *point * self.grain_scale);
+        let rings = radial.mag() * self.ring_frequency + grain;
+        // sharpen the rings, so most of the wood is light with thin, dark late-wood bands
+        let t = (0.5 + 0.5 * f32::cos(2. * std::f32::consts::PI * rings)).powi(4);
+        self.light + t * (self.dark - self.light)
+    }
+}
+
+/// The scalar value a `RampTexture` looks up its color with
+#[derive(Serialize, Deserialize)]
+pub enum RampInput {
+    U,
+    V,
+    /// The world-space height (y coordinate) of the point, remapped so `min` is 0 and `max` is 1
+    Height {
+        min: f32,
+        max: f32,
+    },
+    /// The luminance of another texture, e.g. a noise texture
+    Texture(Box<dyn Texture>),
+}
+
+/// How a `RampTexture` blends between neighbouring stops
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RampInterpolation {
+    /// Uses the color of the stop below the value, giving hard bands
+    Constant,
+    Linear,
+    /// Smoothstep between stops, which hides the kinks at each stop
+    Smooth,
+}
+
+/// A color at a given position along a `RampTexture`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ColorStop {
+    pub position: f32,
+    pub color: Vec3,
+}
+
+/// Maps a scalar (see `RampInput`) through a list of color stops. Values outside the first and
+/// last stop take the color of the nearest stop.
+/// ```
+/// use firework::texture::{ColorStop, RampInput, RampInterpolation, RampTexture};
+/// use ultraviolet::Vec3;
+///
+/// let terrain = RampTexture::new(
+///     RampInput::Height { min: 0., max: 10. },
+///     vec![
+///         ColorStop { position: 0.0, color: Vec3::new(0.1, 0.3, 0.1) },
+///         ColorStop { position: 0.7, color: Vec3::new(0.4, 0.35, 0.3) },
+///         ColorStop { position: 0.9, color: Vec3::one() },
+///     ],
+/// )
+/// .interpolation(RampInterpolation::Smooth);
+///
+/// // the stops in a scene file are sorted too, and there must be at least one
+/// let empty = "{input: U, stops: [], interpolation: Linear}";
+/// assert!(serde_yaml::from_str::<RampTexture>(empty).is_err());
+/// ```
+#[derive(Serialize, Deserialize)]
+#[serde(try_from = "RampTextureDef")]
+pub struct RampTexture {
+    input: RampInput,
+    /// Sorted by position
+    stops: Vec<ColorStop>,
+    interpolation: RampInterpolation,
+}
+
+#[derive(Deserialize)]
+struct RampTextureDef {
+    input: RampInput,
+    stops: Vec<ColorStop>,
+    interpolation: RampInterpolation,
+}
+
+impl TryFrom<RampTextureDef> for RampTexture {
+    type Error = String;
+    fn try_from(def: RampTextureDef) -> Result<RampTexture, String> {
+        Ok(RampTexture {
+            input: def.input,
+            stops: sorted_stops(def.stops)?,
+            interpolation: def.interpolation,
+        })
+    }
+}
+
+/// Sorts color stops by position, checking there's at least one, and that their positions are
+/// numbers
+pub(crate) fn sorted_stops(mut stops: Vec<ColorStop>) -> Result<Vec<ColorStop>, String> {
+    if stops.is_empty() {
+        return Err("there must be at least one color stop".to_string());
+    }
+    if let Some(stop) = stops.iter().find(|stop| stop.position.is_nan()) {
+        return Err(format!("the color stop {:?} has no position", stop));
+    }
+    stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+    Ok(stops)
+}
+
+impl RampTexture {
+    /// Creates a linearly interpolated ramp. Panics if `stops` is empty, or a position is NaN.
+    pub fn new(input: RampInput, stops: Vec<ColorStop>) -> Self {
+        let stops = sorted_stops(stops).unwrap_or_else(|e| panic!("RampTexture::new() -- {}", e));
+        RampTexture {
+            input,
+            stops,
+            interpolation: RampInterpolation::Linear,
+        }
+    }
+
+    pub fn interpolation(mut self, interpolation: RampInterpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
+    /// Looks up the color of the ramp at `t`
+    pub fn eval(&self, t: f32) -> Vec3 {
+        eval_stops(&self.stops, self.interpolation, t)
+    }
+
+    fn input_value(&self, uv: Vec2, point: &Vec3, hit: Option<&RaycastHit>) -> f32 {
+        match &self.input {
+            RampInput::U => uv.x,
+            RampInput::V => uv.y,
+            RampInput::Height { min, max } => (point.y - min) / (max - min),
+            RampInput::Texture(texture) => {
+                let c = match hit {
+                    Some(hit) => texture.sample_hit(hit),
+                    None => texture.sample(uv, point),
+                };
+                c.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+            }
+        }
+    }
+}
+
+/// Looks up the color at `t` in a list of stops sorted by position (which must not be empty)
+pub(crate) fn eval_stops(stops: &[ColorStop], interpolation: RampInterpolation, t: f32) -> Vec3 {
+    let first = stops[0];
+    if t <= first.position {
+        return first.color;
+    }
+    for pair in stops.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        if t < b.position {
+            let s = (t - a.position) / (b.position - a.position);
+            let s = match interpolation {
+                RampInterpolation::Constant => 0.,
+                RampInterpolation::Linear => s,
+                RampInterpolation::Smooth => s * s * (3. - 2. * s),
+            };
+            return a.color + s * (b.color - a.color);
+        }
+    }
+    stops[stops.len() - 1].color
+}
+
+#[typetag::serde]
+impl Texture for RampTexture {
+    fn sample(&self, uv: Vec2, point: &Vec3) -> Vec3 {
+        self.eval(self.input_value(uv, point, None))
+    }
+
+    fn sample_hit(&self, hit: &RaycastHit) -> Vec3 {
+        self.eval(self.input_value(hit.uv, &hit.point, Some(hit)))
+    }
+}
+
+/// A procedural texture for checking the uv parameterization of a shape. The uv square is
+/// divided into a checkered grid of `cells` x `cells` cells, with grid lines along the cell edges.
+/// Each quadrant of the uv square has its own color (red at u, v < 0.5, green at u > 0.5, blue at
+/// v > 0.5 and yellow at u, v > 0.5), and each cell can be labelled with its column and row.
+/// ```
+/// use firework::texture::UvGridTexture;
+///
+/// let grid = UvGridTexture::new(8).labels(false);
+/// ```
+#[derive(Serialize, Deserialize)]
+pub struct UvGridTexture {
+    cells: u32,
+    #[serde(default = "default_labels")]
+    labels: bool,
+}
+
+fn default_labels() -> bool {
+    true
+}
+
+/// A 3x5 pixel font for the digits 0-9 and '-'. Each glyph is stored a row at a time, from the
+/// top, with the leftmost pixel of each row in the highest bit.
+const GLYPHS: [u16; 11] = [
+    0b111_101_101_101_111,
+    0b010_110_010_010_111,
+    0b111_001_111_100_111,
+    0b111_001_111_001_111,
+    0b101_101_111_001_001,
+    0b111_100_111_001_111,
+    0b111_100_111_101_111,
+    0b111_001_001_001_001,
+    0b111_101_111_101_111,
+    0b111_101_111_001_111,
+    0b000_000_111_000_000,
+];
+const DASH: usize = 10;
+
+impl UvGridTexture {
+    pub fn new(cells: u32) -> Self {
+        UvGridTexture {
+            cells: cells.max(1),
+            labels: true,
+        }
+    }
+
+    /// Sets whether each cell is labelled with its column and row (as "column-row")
+    pub fn labels(mut self, labels: bool) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// Returns true if the point at `local` (in 0..1 across the cell) is part of the label for
+    /// the given cell
+    fn in_label(column: u32, row: u32, local: Vec2) -> bool {
+        // the label is 5 font pixels tall, and starts near the top left corner of the cell
+        const PIXEL: f32 = 0.04;
+        let px = ((local.x - 0.1) / PIXEL).floor();
+        let py = ((0.85 - local.y) / PIXEL).floor();
+        if px < 0. || !(0. ..5.).contains(&py) {
+            return false;
+        }
+        let (px, py) = (px as usize, py as usize);
+
+        // each character is 3 pixels wide, with one pixel of space after it
+        let (char_idx, char_x) = (px / 4, px % 4);
+        if char_x == 3 {
+            return false;
+        }
+
+        let mut text = [0; 21];
+        let mut len = 0;
+        for (i, &n) in [column, row].iter().enumerate() {
+            if i == 1 {
+                text[len] = DASH;
+                len += 1;
+            }
+            let mut digits = 1;
+            while 10u32.pow(digits) <= n && digits < 10 {
+                digits += 1;
+            }
+            for d in (0..digits).rev() {
+                text[len] = (n / 10u32.pow(d) % 10) as usize;
+                len += 1;
+            }
+        }
+        if char_idx >= len {
+            return false;
+        }
+
+        let bit = 14 - (py * 3 + char_x);
+        GLYPHS[text[char_idx]] & (1 << bit) != 0
+    }
+}
+
+#[typetag::serde]
+impl Texture for UvGridTexture {
+    fn sample(&self, uv: Vec2, _point: &Vec3) -> Vec3 {
+        let uv = Vec2::new(uv.x.rem_euclid(1.), uv.y.rem_euclid(1.));
+        let quadrant = match (uv.x < 0.5, uv.y < 0.5) {
+            (true, true) => Vec3::new(0.9, 0.2, 0.2),
+            (false, true) => Vec3::new(0.2, 0.8, 0.2),
+            (true, false) => Vec3::new(0.2, 0.3, 0.9),
+            (false, false) => Vec3::new(0.9, 0.8, 0.1),
+        };
+
+        let cells = self.cells as f32;
+        let scaled = uv * cells;
+        let (column, row) = (scaled.x.floor() as u32, scaled.y.floor() as u32);
+        let local = Vec2::new(scaled.x.fract(), scaled.y.fract());
+
+        let line = 0.02;
+        let on_line =
+            local.x < line || local.x > 1. - line || local.y < line || local.y > 1. - line;
+        if on_line {
+            return Vec3::broadcast(0.05);
+        }
+
+        let light = (column + row) % 2 == 0;
+        if self.labels && UvGridTexture::in_label(column, row, local) {
+            // labels contrast with the cell they're in
+            Vec3::broadcast(if light { 0.05 } else { 0.95 })
+        } else if light {
+            quadrant + 0.6 * (Vec3::one() - quadrant)
+        } else {
+            0.6 * quadrant
+        }
     }
 }
 
@@ -228,11 +843,23 @@ impl Texture for TurbulenceTexture {
 pub struct MarbleTexture {
     depth: usize,
     scale: f32,
+    #[serde(default, rename = "seed")]
+    perm: Permutation,
 }
 
 impl MarbleTexture {
     pub fn new(depth: usize, scale: f32) -> Self {
-        MarbleTexture { depth, scale }
+        MarbleTexture {
+            depth,
+            scale,
+            perm: Permutation::default(),
+        }
+    }
+
+    /// Sets the seed used to generate the noise (see `PerlinNoiseTexture::seed`)
+    pub fn seed(mut self, seed: u32) -> Self {
+        self.perm = Permutation::from(seed);
+        self
     }
 }
 
@@ -243,68 +870,1783 @@ impl Texture for MarbleTexture {
             * 0.5
             * (1.
                 + f32::sin(
-                    self.scale * point.z + 10. * TurbulenceTexture::turb(self.depth, *point),
+                    self.scale * point.z
+                        + 10. * TurbulenceTexture::turb(&self.perm, self.depth, *point),
                 ))
     }
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(transparent)]
-#[repr(transparent)]
-struct ImagePath(PathBuf);
+/// The color space the values in an image are stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ColorSpace {
+    /// The standard color space for 8 bit color images (albedo maps, photos, etc.)
+    #[default]
+    Srgb,
+    /// Used for images storing data, rather than colors (normal maps, roughness maps, etc.)
+    Linear,
+}
+
+impl ColorSpace {
+    /// Converts a value in the range 0..1 from this color space to linear
+    pub fn to_linear(self, c: f32) -> f32 {
+        match self {
+            ColorSpace::Linear => c,
+            ColorSpace::Srgb if c <= 0.04045 => c / 12.92,
+            ColorSpace::Srgb => ((c + 0.055) / 1.055).powf(2.4),
+        }
+    }
+
+    /// Converts a linear value in the range 0..1 to this color space
+    pub fn from_linear(self, c: f32) -> f32 {
+        match self {
+            ColorSpace::Linear => c,
+            ColorSpace::Srgb if c <= 0.0031308 => c * 12.92,
+            ColorSpace::Srgb => 1.055 * c.powf(1. / 2.4) - 0.055,
+        }
+    }
+}
+
+/// How an `ImageTexture` handles uv coordinates outside of 0..1
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum WrapMode {
+    /// Uses the closest texel on the edge of the image
+    #[default]
+    Clamp,
+    /// Tiles the image
+    Repeat,
+    /// Tiles the image, flipping every other tile so there are no seams
+    Mirror,
+    /// Uses the given (linear) color outside of the image
+    Border(Vec3),
+}
+
+impl WrapMode {
+    /// Maps a texel index to an index inside an image `n` texels wide, or returns the border
+    /// color
+    fn apply(self, i: isize, n: usize) -> Result<usize, Vec3> {
+        let n = n as isize;
+        match self {
+            WrapMode::Clamp => Ok(i.clamp(0, n - 1) as usize),
+            WrapMode::Repeat => Ok(i.rem_euclid(n) as usize),
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * n);
+                Ok(if i < n { i } else { 2 * n - 1 - i } as usize)
+            }
+            WrapMode::Border(color) if i < 0 || i >= n => Err(color),
+            WrapMode::Border(_) => Ok(i as usize),
+        }
+    }
+}
+
+/// An `ImageTexture` is serialized as just its path, unless it has a non-default color space or
+/// wrap modes.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ImagePath {
+    Path(PathBuf),
+    WithOptions {
+        path: PathBuf,
+        color_space: ColorSpace,
+        wrap_u: WrapMode,
+        wrap_v: WrapMode,
+    },
+}
+
+/// This is written by hand rather than derived for the untagged enum, since an untagged enum
+/// buffers the options first, which loses the names of their variants in formats like RON.
+impl<'de> Deserialize<'de> for ImagePath {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ImagePathVisitor;
+
+        impl<'de> Visitor<'de> for ImagePathVisitor {
+            type Value = ImagePath;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("the path of an image, or a map with its path and options")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, path: &str) -> Result<ImagePath, E> {
+                Ok(ImagePath::Path(path.into()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<ImagePath, A::Error> {
+                let mut path = None;
+                let mut color_space = ColorSpace::default();
+                let mut wrap_u = WrapMode::default();
+                let mut wrap_v = WrapMode::default();
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        // typetag stores a bare path under a `value` key (e.g. `{texture:
+                        // ImageTexture, value: uvmap.png}`)
+                        "path" | "value" => path = Some(map.next_value::<PathBuf>()?),
+                        "color_space" => color_space = map.next_value()?,
+                        "wrap_u" => wrap_u = map.next_value()?,
+                        "wrap_v" => wrap_v = map.next_value()?,
+                        _ => {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                    }
+                }
+                let path = path.ok_or_else(|| serde::de::Error::missing_field("path"))?;
+                Ok(ImagePath::WithOptions {
+                    path,
+                    color_space,
+                    wrap_u,
+                    wrap_v,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(ImagePathVisitor)
+    }
+}
 
 impl TryFrom<ImagePath> for ImageTexture {
     type Error = image::ImageError;
     fn try_from(path: ImagePath) -> Result<ImageTexture, Self::Error> {
-        ImageTexture::from_path(&path.0)
+        let (path, color_space, wrap_u, wrap_v) = match path {
+            ImagePath::Path(path) => (
+                path,
+                ColorSpace::default(),
+                WrapMode::default(),
+                WrapMode::default(),
+            ),
+            ImagePath::WithOptions {
+                path,
+                color_space,
+                wrap_u,
+                wrap_v,
+            } => (path, color_space, wrap_u, wrap_v),
+        };
+        let mut texture = ImageTexture::from_path(resolve_asset(&path))?
+            .with_color_space(color_space)
+            .wrap(wrap_u, wrap_v);
+        // keep the path as it was written, so the scene is written back the same way
+        texture.path = Some(path);
+        Ok(texture)
     }
 }
 
-impl Into<ImagePath> for ImageTexture {
-    #[inline(always)]
-    fn into(self) -> ImagePath {
-        ImagePath(self.path.expect("ImageTexture.path not specified"))
+/// Serializes the path the image was loaded from (so the pixels aren't copied into the scene
+/// file), or fails if there isn't one.
+impl Serialize for ImageTexture {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let path = self.path.clone().ok_or_else(|| {
+            S::Error::custom("an ImageTexture can only be serialized if its `path` is set")
+        })?;
+        let defaults = self.color_space == ColorSpace::default()
+            && self.wrap_u == WrapMode::default()
+            && self.wrap_v == WrapMode::default();
+        if defaults {
+            ImagePath::Path(path)
+        } else {
+            ImagePath::WithOptions {
+                path,
+                color_space: self.color_space,
+                wrap_u: self.wrap_u,
+                wrap_v: self.wrap_v,
+            }
+        }
+        .serialize(serializer)
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Deserialize, Clone)]
 #[serde(try_from = "ImagePath")]
-#[serde(into = "ImagePath")]
 /// An Image texture.
-/// Note: Serialization will panic if `path` is not specified
+/// A mipmap pyramid is built when the texture is created, and is used to filter the texture when
+/// it is far away (see `Texture::sample_hit`). Note that the mipmaps are not updated if `image` is
+/// modified afterwards.
+/// By default, the image is assumed to be in the sRGB color space, and is converted to linear
+/// colors when the texture is created. Use `with_color_space(ColorSpace::Linear)` for images
+/// that store data (normal maps, roughness maps, etc.).
+/// An `ImageTexture` is serialized as the path of the image, and the image is loaded again when
+/// it is deserialized, so `path` must be set (e.g. by using `from_path`) to serialize it.
+/// ```
+/// use firework::texture::{ImageTexture, Texture};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let texture: Box<dyn Texture> = Box::new(ImageTexture::from_path("uvmap.png")?);
+/// let yaml = serde_yaml::to_string(&texture)?;
+/// let loaded: Box<dyn Texture> = serde_yaml::from_str(&yaml)?;
+/// # Ok(())
+/// # }
+/// ```
 pub struct ImageTexture {
     pub image: image::DynamicImage,
     pub path: Option<PathBuf>,
+    color_space: ColorSpace,
+    wrap_u: WrapMode,
+    wrap_v: WrapMode,
+    mipmaps: Vec<MipLevel>,
+}
+
+/// A single level in a mipmap pyramid, stored as linear floating point colors
+#[derive(Clone)]
+struct MipLevel {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+}
+
+impl MipLevel {
+    fn from_image(image: &image::DynamicImage, color_space: ColorSpace) -> MipLevel {
+        let (width, height) = image.dimensions();
+        let decode = |c: u8| color_space.to_linear(c as f32 / 255.);
+        let pixels = image
+            .pixels()
+            .map(|(_, _, p)| Vec3::new(decode(p[0]), decode(p[1]), decode(p[2])))
+            .collect();
+        MipLevel {
+            width: width as usize,
+            height: height as usize,
+            pixels,
+        }
+    }
+
+    /// Creates the next level of the pyramid by averaging 2x2 blocks of pixels
+    fn downsample(&self) -> MipLevel {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let x0 = (2 * x).min(self.width - 1);
+                let x1 = (2 * x + 1).min(self.width - 1);
+                let y0 = (2 * y).min(self.height - 1);
+                let y1 = (2 * y + 1).min(self.height - 1);
+                pixels.push(
+                    0.25 * (self.texel(x0, y0)
+                        + self.texel(x1, y0)
+                        + self.texel(x0, y1)
+                        + self.texel(x1, y1)),
+                );
+            }
+        }
+        MipLevel {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    fn texel(&self, x: usize, y: usize) -> Vec3 {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Looks up a texel, using the wrap modes for coordinates outside of the image
+    fn fetch(&self, x: isize, y: isize, wrap_u: WrapMode, wrap_v: WrapMode) -> Vec3 {
+        match (wrap_u.apply(x, self.width), wrap_v.apply(y, self.height)) {
+            (Ok(x), Ok(y)) => self.texel(x, y),
+            (Err(border), _) | (_, Err(border)) => border,
+        }
+    }
+
+    /// Bilinearly interpolates the level at the given uv coordinates
+    fn bilinear(&self, uv: Vec2, wrap_u: WrapMode, wrap_v: WrapMode) -> Vec3 {
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = (1. - uv.y) * self.height as f32 - 0.5;
+        let x0 = x.floor() as isize;
+        let y0 = y.floor() as isize;
+        let tx = x - x.floor();
+        let ty = y - y.floor();
+        let texel = |x, y| self.fetch(x, y, wrap_u, wrap_v);
+        let top = (1. - tx) * texel(x0, y0) + tx * texel(x0 + 1, y0);
+        let bottom = (1. - tx) * texel(x0, y0 + 1) + tx * texel(x0 + 1, y0 + 1);
+        (1. - ty) * top + ty * bottom
+    }
+}
+
+fn build_mipmaps(image: &image::DynamicImage, color_space: ColorSpace) -> Vec<MipLevel> {
+    let mut mipmaps = vec![MipLevel::from_image(image, color_space)];
+    loop {
+        let last = mipmaps.last().unwrap();
+        if last.width == 1 && last.height == 1 {
+            break;
+        }
+        let next = last.downsample();
+        mipmaps.push(next);
+    }
+    mipmaps
 }
 
 impl ImageTexture {
     pub fn new(image: image::DynamicImage) -> ImageTexture {
-        ImageTexture { image, path: None }
+        let color_space = ColorSpace::default();
+        let mipmaps = build_mipmaps(&image, color_space);
+        ImageTexture {
+            image,
+            path: None,
+            color_space,
+            wrap_u: WrapMode::default(),
+            wrap_v: WrapMode::default(),
+            mipmaps,
+        }
     }
 
     pub fn from_path(path: impl AsRef<Path>) -> Result<ImageTexture, image::ImageError> {
         let path_buf = Some(path.as_ref().to_owned());
-        Ok(ImageTexture {
-            image: image::open(path)?,
-            path: path_buf,
-        })
+        let mut texture = ImageTexture::new(image::open(path)?);
+        texture.path = path_buf;
+        Ok(texture)
+    }
+
+    /// Sets the color space the image is stored in, and decodes it again.
+    /// ```
+    /// use firework::texture::{ColorSpace, ImageTexture};
+    ///
+    /// # fn main() -> Result<(), image::ImageError> {
+    /// let height_map =
+    ///     ImageTexture::from_path("heightmap.png")?.with_color_space(ColorSpace::Linear);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> ImageTexture {
+        if color_space != self.color_space {
+            self.color_space = color_space;
+            self.mipmaps = build_mipmaps(&self.image, color_space);
+        }
+        self
+    }
+
+    /// Sets how uv coordinates outside of 0..1 are handled, in the u and v directions.
+    /// ```
+    /// use firework::texture::{ImageTexture, WrapMode};
+    ///
+    /// # fn main() -> Result<(), image::ImageError> {
+    /// let tiles = ImageTexture::from_path("uvmap.png")?.wrap(WrapMode::Repeat, WrapMode::Mirror);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn wrap(mut self, wrap_u: WrapMode, wrap_v: WrapMode) -> ImageTexture {
+        self.wrap_u = wrap_u;
+        self.wrap_v = wrap_v;
+        self
+    }
+
+    /// Samples the mipmap pyramid with trilinear filtering, where `lod` is the (fractional) level
+    /// to sample
+    fn trilinear(&self, uv: Vec2, lod: f32) -> Vec3 {
+        let max_level = (self.mipmaps.len() - 1) as f32;
+        let lod = lod.clamp(0., max_level);
+        let lower = lod.floor() as usize;
+        let upper = lod.ceil() as usize;
+        let t = lod - lod.floor();
+        let (wrap_u, wrap_v) = (self.wrap_u, self.wrap_v);
+        (1. - t) * self.mipmaps[lower].bilinear(uv, wrap_u, wrap_v)
+            + t * self.mipmaps[upper].bilinear(uv, wrap_u, wrap_v)
     }
 }
 
 #[typetag::serde]
 impl Texture for ImageTexture {
-    fn sample(&self, uv: Vec2, _point: &Vec3) -> Vec3 {
+    /// Selects a mipmap level so that the footprint of the ray covers roughly one texel, and
+    /// samples it with trilinear filtering. The footprint is converted to uv space using the
+    /// length of the tangent (dp/du), if the shape provides one.
+    fn sample_hit(&self, hit: &RaycastHit) -> Vec3 {
+        if hit.footprint <= 0. {
+            return self.sample(hit.uv, &hit.point);
+        }
+        let uv_scale = hit.tangent.map_or(1., |t| t.mag()).max(1e-6);
         let (w, h) = self.image.dimensions();
-        let i = uv.x * self.image.dimensions().0 as f32;
-        let j = (1. - uv.y) * self.image.dimensions().1 as f32;
+        let texels = hit.footprint / uv_scale * w.max(h) as f32;
+        if texels <= 1. {
+            self.sample(hit.uv, &hit.point)
+        } else {
+            self.trilinear(hit.uv, texels.log2())
+        }
+    }
+
+    fn sample(&self, uv: Vec2, _point: &Vec3) -> Vec3 {
+        let level = &self.mipmaps[0];
+        let i = (uv.x * level.width as f32).floor() as isize;
+        let j = ((1. - uv.y) * level.height as f32).floor() as isize;
+
+        level.fetch(i, j, self.wrap_u, self.wrap_v)
+    }
 
-        let i = (i as u32).clamp(0, w - 1);
-        let j = (j as u32).clamp(0, h - 1);
+    fn memory_size(&self) -> usize {
+        let mipmaps: usize = self.mipmaps.iter().map(|level| level.pixels.len()).sum();
+        std::mem::size_of::<Self>()
+            + self.image.as_bytes().len()
+            + mipmaps * std::mem::size_of::<Vec3>()
+    }
 
-        let c: Rgba<u8> = self.image.get_pixel(i as u32, j as u32).to_rgba();
+    fn max(&self) -> Option<Vec3> {
+        // the smaller mipmaps only average the full resolution image, so they're no brighter
+        let pixels = &self.mipmaps[0].pixels;
+        Some(
+            pixels
+                .iter()
+                .fold(Vec3::zero(), |max, p| max.max_by_component(*p)),
+        )
+    }
+}
 
-        //println!("{:?} ({}, {}) : {:?}", uv, i, j, c);
+/// Wraps another texture, transforming the uv coordinates before they are passed to it. The uvs
+/// are first scaled (so a scale of 4 tiles the texture 4 times across the surface), then rotated
+/// around the origin, and finally offset. The result is wrapped back into 0..1, so the texture
+/// repeats.
+/// ```
+/// use firework::texture::{ImageTexture, UvTransformTexture};
+///
+/// # fn main() -> Result<(), image::ImageError> {
+/// let tiles = UvTransformTexture::new(ImageTexture::from_path("uvmap.png")?)
+///     .scale(4., 2.)
+///     .rotation(45.)
+///     .offset(0.5, 0.);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Serialize, Deserialize)]
+pub struct UvTransformTexture {
+    /// The texture being transformed. This can't be called `texture`, since that's the key for
+    /// the type of texture in scene files.
+    base: Box<dyn Texture>,
+    scale: Vec2,
+    offset: Vec2,
+    /// The rotation, in radians
+    rotation: f32,
+}
 
-        Vec3::new(c[0].into(), c[1].into(), c[2].into()) / 255.
+impl UvTransformTexture {
+    /// Wraps a texture, with an identity transform
+    pub fn new<T: Texture + 'static>(texture: T) -> Self {
+        UvTransformTexture {
+            base: Box::new(texture),
+            scale: Vec2::one(),
+            offset: Vec2::zero(),
+            rotation: 0.,
+        }
+    }
+
+    /// Sets the number of times the texture is repeated along u and v
+    pub fn scale(mut self, u: f32, v: f32) -> Self {
+        self.scale = Vec2::new(u, v);
+        self
+    }
+
+    /// Sets the offset added to the uvs
+    pub fn offset(mut self, u: f32, v: f32) -> Self {
+        self.offset = Vec2::new(u, v);
+        self
+    }
+
+    /// Sets the rotation of the uvs, in degrees
+    pub fn rotation(mut self, degrees: f32) -> Self {
+        self.rotation = degrees.to_radians();
+        self
+    }
+
+    fn transform(&self, uv: Vec2) -> Vec2 {
+        let scaled = uv * self.scale;
+        let (sin, cos) = self.rotation.sin_cos();
+        let rotated = Vec2::new(
+            cos * scaled.x - sin * scaled.y,
+            sin * scaled.x + cos * scaled.y,
+        );
+        let uv = rotated + self.offset;
+        Vec2::new(uv.x - uv.x.floor(), uv.y - uv.y.floor())
+    }
+}
+
+#[typetag::serde]
+impl Texture for UvTransformTexture {
+    fn sample(&self, uv: Vec2, point: &Vec3) -> Vec3 {
+        self.base.sample(self.transform(uv), point)
+    }
+
+    fn sample_hit(&self, hit: &RaycastHit) -> Vec3 {
+        let mut hit = hit.clone();
+        hit.uv = self.transform(hit.uv);
+        // Tiling the texture makes each texel smaller on the surface
+        hit.tangent = hit.tangent.map(|t| t / self.scale.x.abs().max(1e-6));
+        self.base.sample_hit(&hit)
+    }
+
+    fn max(&self) -> Option<Vec3> {
+        self.base.max()
+    }
+}
+
+/// Which feature of the Worley noise is output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WorleyFeature {
+    /// The distance to the closest feature point
+    F1,
+    /// The distance to the second closest feature point
+    F2,
+    /// The difference between the two, which gives cell borders
+    F2MinusF1,
+}
+
+/// Worley (cellular) noise. Space is divided into cubic cells, each containing a randomly placed
+/// feature point, and the texture value depends on the distance from the sample point to the
+/// nearest feature points. Useful for stone, scales, cells, and caustic-like patterns, or as a
+/// mask for other textures.
+/// ```
+/// use firework::texture::{WorleyFeature, WorleyTexture};
+///
+/// let cells = WorleyTexture::new(4., WorleyFeature::F2MinusF1).seed(42);
+/// ```
+#[derive(Serialize, Deserialize)]
+pub struct WorleyTexture {
+    /// The number of cells per unit distance
+    density: f32,
+    feature: WorleyFeature,
+    #[serde(default)]
+    seed: u32,
+}
+
+impl WorleyTexture {
+    pub fn new(density: f32, feature: WorleyFeature) -> Self {
+        WorleyTexture {
+            density,
+            feature,
+            seed: 0,
+        }
+    }
+
+    /// Sets the seed used to place the feature points
+    pub fn seed(mut self, seed: u32) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Returns the position of the feature point in the given cell, relative to the cell's
+    /// corner
+    fn feature_point(&self, x: i32, y: i32, z: i32) -> Vec3 {
+        let h = |k: u32| hash_u32(x, y, z, self.seed.wrapping_add(k)) as f32 / u32::MAX as f32;
+        Vec3::new(h(0), h(1), h(2))
+    }
+
+    /// Returns the distances to the closest and second closest feature points
+    fn distances(&self, point: Vec3) -> (f32, f32) {
+        let p = point * self.density;
+        let (cx, cy, cz) = (p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32);
+        let mut f1 = f32::MAX;
+        let mut f2 = f32::MAX;
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let (x, y, z) = (cx + dx, cy + dy, cz + dz);
+                    let feature =
+                        Vec3::new(x as f32, y as f32, z as f32) + self.feature_point(x, y, z);
+                    let dist = (feature - p).mag();
+                    if dist < f1 {
+                        f2 = f1;
+                        f1 = dist;
+                    } else if dist < f2 {
+                        f2 = dist;
+                    }
+                }
+            }
+        }
+        (f1, f2)
+    }
+}
+
+/// A simple integer hash of a lattice point and a seed
+pub(crate) fn hash_u32(x: i32, y: i32, z: i32, seed: u32) -> u32 {
+    let mut h = seed.wrapping_mul(0x9E37_79B9);
+    for v in [x as u32, y as u32, z as u32].iter() {
+        h ^= v.wrapping_mul(0x85EB_CA6B);
+        h = h.rotate_left(13).wrapping_mul(0xC2B2_AE35);
+    }
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7FEB_352D);
+    h ^= h >> 15;
+    h
+}
+
+#[typetag::serde]
+impl Texture for WorleyTexture {
+    fn sample(&self, _uv: Vec2, point: &Vec3) -> Vec3 {
+        let (f1, f2) = self.distances(*point);
+        let value = match self.feature {
+            WorleyFeature::F1 => f1,
+            WorleyFeature::F2 => f2,
+            WorleyFeature::F2MinusF1 => f2 - f1,
+        };
+        Vec3::broadcast(value.min(1.))
+    }
+}
+
+/// The format of each value in a raw volume file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RawFormat {
+    /// Unsigned bytes, mapped to 0..1
+    U8,
+    /// Little endian unsigned 16 bit integers, mapped to 0..1
+    U16,
+    /// Little endian 32 bit floats
+    F32,
+}
+
+impl RawFormat {
+    fn size(self) -> usize {
+        match self {
+            RawFormat::U8 => 1,
+            RawFormat::U16 => 2,
+            RawFormat::F32 => 4,
+        }
+    }
+
+    fn decode(self, bytes: &[u8], big_endian: bool) -> Vec<f32> {
+        bytes
+            .chunks_exact(self.size())
+            .map(|b| match (self, big_endian) {
+                (RawFormat::U8, _) => b[0] as f32 / 255.,
+                (RawFormat::U16, false) => u16::from_le_bytes([b[0], b[1]]) as f32 / 65535.,
+                (RawFormat::U16, true) => u16::from_be_bytes([b[0], b[1]]) as f32 / 65535.,
+                (RawFormat::F32, false) => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+                (RawFormat::F32, true) => f32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+            })
+            .collect()
+    }
+}
+
+/// Where the data in a `VolumeTexture` was loaded from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum VolumeSource {
+    /// A headerless file of `dims[0] * dims[1] * dims[2]` values, with x varying fastest
+    Raw {
+        path: PathBuf,
+        dims: [usize; 3],
+        format: RawFormat,
+    },
+    /// An NRRD file, with raw encoding
+    Nrrd { path: PathBuf },
+    /// A binary grid volume file, as used by Mitsuba
+    Vol { path: PathBuf },
+    /// A float grid in an uncompressed NanoVDB file
+    #[cfg(feature = "vdb")]
+    NanoVdb { path: PathBuf, grid: String },
+}
+
+#[derive(Serialize, Deserialize)]
+struct VolumeTextureDef {
+    source: VolumeSource,
+    min: Vec3,
+    max: Vec3,
+}
+
+impl TryFrom<VolumeTextureDef> for VolumeTexture {
+    type Error = std::io::Error;
+    fn try_from(def: VolumeTextureDef) -> Result<VolumeTexture, Self::Error> {
+        let mut texture = match &def.source {
+            VolumeSource::Raw { path, dims, format } => {
+                VolumeTexture::from_raw(resolve_asset(path), *dims, *format)?
+            }
+            VolumeSource::Nrrd { path } => VolumeTexture::from_nrrd(resolve_asset(path))?,
+            VolumeSource::Vol { path } => VolumeTexture::from_vol(resolve_asset(path))?,
+            #[cfg(feature = "vdb")]
+            VolumeSource::NanoVdb { path, grid } => {
+                VolumeTexture::from_nanovdb(resolve_asset(path), grid)?
+            }
+        };
+        // keep the path as it was written, so the scene is written back the same way
+        texture.source = Some(def.source);
+        Ok(texture.bounds(def.min, def.max))
+    }
+}
+
+impl Serialize for VolumeTexture {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let source = self.source.clone().ok_or_else(|| {
+            S::Error::custom("a VolumeTexture can only be serialized if it was loaded from a file")
+        })?;
+        VolumeTextureDef {
+            source,
+            min: self.min,
+            max: self.max,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// A 3D grid of scalar values, stretched over the box from `min` to `max` in world space, and
+/// sampled at the hit point with trilinear interpolation. Points outside the box sample as 0.
+/// Can be used as a regular texture, or as the density of a heterogeneous medium.
+/// Like `ImageTexture`, a `VolumeTexture` is serialized as the file it was loaded from, so
+/// volumes created with `new` can't be serialized.
+/// ```no_run
+/// use firework::texture::{RawFormat, VolumeTexture};
+/// use ultraviolet::Vec3;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let smoke = VolumeTexture::from_raw("smoke.raw", [128, 128, 128], RawFormat::F32)?
+///     .bounds(Vec3::new(-1., 0., -1.), Vec3::new(1., 2., 1.));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Deserialize, Clone)]
+#[serde(try_from = "VolumeTextureDef")]
+pub struct VolumeTexture {
+    dims: [usize; 3],
+    data: Vec<f32>,
+    min: Vec3,
+    max: Vec3,
+    source: Option<VolumeSource>,
+}
+
+fn invalid_data(msg: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.into())
+}
+
+/// The number of bytes taken by a volume of `dims` values of `value_size` bytes each, from the
+/// header of a file, which is an error if the volume is empty, or too large to count
+fn volume_len(dims: [usize; 3], value_size: usize) -> std::io::Result<usize> {
+    if dims.contains(&0) {
+        return Err(invalid_data(format!(
+            "a volume can't have {:?} voxels",
+            dims
+        )));
+    }
+    dims.iter()
+        .try_fold(value_size, |len, &dim| len.checked_mul(dim))
+        .ok_or_else(|| invalid_data(format!("a volume of {:?} voxels is too large", dims)))
+}
+
+impl VolumeTexture {
+    /// Creates a volume from values in x-fastest order, covering the unit cube. Panics if `data`
+    /// doesn't have `dims[0] * dims[1] * dims[2]` values, or the volume is empty.
+    pub fn new(dims: [usize; 3], data: Vec<f32>) -> Self {
+        assert!(
+            !dims.contains(&0),
+            "VolumeTexture::new() -- the volume is empty"
+        );
+        assert_eq!(
+            data.len(),
+            dims[0] * dims[1] * dims[2],
+            "VolumeTexture::new() -- data doesn't match dims"
+        );
+        VolumeTexture {
+            dims,
+            data,
+            min: Vec3::zero(),
+            max: Vec3::one(),
+            source: None,
+        }
+    }
+
+    /// Loads a headerless volume file
+    pub fn from_raw(
+        path: impl AsRef<Path>,
+        dims: [usize; 3],
+        format: RawFormat,
+    ) -> std::io::Result<Self> {
+        let bytes = std::fs::read(path.as_ref())?;
+        let len = volume_len(dims, format.size())?;
+        if bytes.len() < len {
+            return Err(invalid_data(format!(
+                "{} is too small for a {:?} volume of {:?} values",
+                path.as_ref().display(),
+                dims,
+                format
+            )));
+        }
+        let mut texture = VolumeTexture::new(dims, format.decode(&bytes[..len], false));
+        texture.source = Some(VolumeSource::Raw {
+            path: path.as_ref().to_owned(),
+            dims,
+            format,
+        });
+        Ok(texture)
+    }
+
+    /// Loads a 3D NRRD file. Only the `raw` encoding, and `uchar`, `ushort` and `float` types, are
+    /// supported. Detached data files are resolved relative to the header.
+    /// ```
+    /// use firework::texture::VolumeTexture;
+    ///
+    /// let path = std::env::temp_dir().join("firework_from_nrrd.nrrd");
+    /// let nrrd = |sizes: &str| {
+    ///     let header = format!(
+    ///         "NRRD0004\r\ntype: uchar\r\ndimension: 3\r\nsizes: {}\r\nencoding: raw\r\n\r\n",
+    ///         sizes
+    ///     );
+    ///     std::fs::write(&path, [header.as_bytes(), &[0, 255]].concat()).unwrap();
+    ///     VolumeTexture::from_nrrd(&path)
+    /// };
+    /// assert_eq!(nrrd("2 1 1").unwrap().max_density(), 1.);
+    /// assert!(nrrd("2 0 1").is_err());
+    /// ```
+    pub fn from_nrrd(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let bytes = std::fs::read(path.as_ref())?;
+        if !bytes.starts_with(b"NRRD") {
+            return Err(invalid_data("not an NRRD file"));
+        }
+
+        // the header is terminated by a blank line (with Unix or Windows line endings), and is
+        // followed by the data
+        let (header_end, data_start) = (0..bytes.len())
+            .find_map(|i| match &bytes[i..] {
+                [b'\n', b'\n', ..] => Some((i, i + 2)),
+                [b'\n', b'\r', b'\n', ..] => Some((i, i + 3)),
+                _ => None,
+            })
+            .ok_or_else(|| invalid_data("NRRD header is not terminated"))?;
+        let header = String::from_utf8_lossy(&bytes[..header_end]);
+
+        let mut format = None;
+        let mut dims = None;
+        let mut big_endian = false;
+        let mut data_file = None;
+        for line in header.lines().skip(1).filter(|l| !l.starts_with('#')) {
+            let (key, value) = match line.find(':') {
+                Some(i) => (&line[..i], line[i + 1..].trim_start_matches('=').trim()),
+                None => continue,
+            };
+            match key {
+                "type" => {
+                    format = Some(match value {
+                        "uchar" | "unsigned char" | "uint8" | "uint8_t" => RawFormat::U8,
+                        "ushort" | "unsigned short" | "uint16" | "uint16_t" => RawFormat::U16,
+                        "float" => RawFormat::F32,
+                        _ => return Err(invalid_data(format!("unsupported NRRD type {}", value))),
+                    })
+                }
+                "dimension" if value != "3" => {
+                    return Err(invalid_data("only 3 dimensional NRRD files are supported"))
+                }
+                "sizes" => {
+                    let sizes = value
+                        .split_whitespace()
+                        .map(|s| s.parse())
+                        .collect::<Result<Vec<usize>, _>>()
+                        .map_err(|_| invalid_data("invalid NRRD sizes"))?;
+                    if sizes.len() != 3 {
+                        return Err(invalid_data("invalid NRRD sizes"));
+                    }
+                    dims = Some([sizes[0], sizes[1], sizes[2]]);
+                }
+                "encoding" if value != "raw" => {
+                    return Err(invalid_data(format!("unsupported NRRD encoding {}", value)))
+                }
+                "endian" => big_endian = value == "big",
+                "data file" | "datafile" => data_file = Some(value.to_owned()),
+                _ => {}
+            }
+        }
+
+        let format = format.ok_or_else(|| invalid_data("NRRD file has no type"))?;
+        let dims = dims.ok_or_else(|| invalid_data("NRRD file has no sizes"))?;
+        let data = match data_file {
+            Some(file) => {
+                let dir = path.as_ref().parent().unwrap_or_else(|| Path::new(""));
+                std::fs::read(dir.join(file))?
+            }
+            None => bytes[data_start..].to_vec(),
+        };
+        let len = volume_len(dims, format.size())?;
+        if data.len() < len {
+            return Err(invalid_data("NRRD data is smaller than its sizes"));
+        }
+
+        let mut texture = VolumeTexture::new(dims, format.decode(&data[..len], big_endian));
+        texture.source = Some(VolumeSource::Nrrd {
+            path: path.as_ref().to_owned(),
+        });
+        Ok(texture)
+    }
+
+    /// Loads a binary `.vol` grid, the format used by Mitsuba (and for many published smoke and
+    /// cloud datasets). The volume covers the bounding box stored in the file. Only the float32
+    /// and uint8 encodings are supported, and for grids with several channels (e.g. an albedo),
+    /// only the first channel is used.
+    pub fn from_vol(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let bytes = std::fs::read(path.as_ref())?;
+        if bytes.len() < 48 || !bytes.starts_with(b"VOL") || bytes[3] != 3 {
+            return Err(invalid_data("not a version 3 .vol file"));
+        }
+        let int = |at: usize| {
+            i32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+        };
+        let float = |at: usize| {
+            f32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+        };
+
+        let format = match int(4) {
+            1 => RawFormat::F32,
+            3 => RawFormat::U8,
+            encoding => {
+                return Err(invalid_data(format!(
+                    "unsupported .vol encoding {}",
+                    encoding
+                )))
+            }
+        };
+        let dims = [int(8), int(12), int(16)];
+        let channels = int(20);
+        if dims.iter().any(|&d| d <= 0) || channels <= 0 {
+            return Err(invalid_data("invalid .vol dimensions"));
+        }
+        let dims = dims.map(|d| d as usize);
+        let channels = channels as usize;
+
+        // the channels fit in an i32, so this can't overflow
+        let len = volume_len(dims, channels * format.size())?;
+        if bytes.len() - 48 < len {
+            return Err(invalid_data(".vol data is smaller than its dimensions"));
+        }
+        let data = format
+            .decode(&bytes[48..48 + len], false)
+            .into_iter()
+            .step_by(channels)
+            .collect();
+
+        let mut texture = VolumeTexture::new(dims, data).bounds(
+            Vec3::new(float(24), float(28), float(32)),
+            Vec3::new(float(36), float(40), float(44)),
+        );
+        texture.source = Some(VolumeSource::Vol {
+            path: path.as_ref().to_owned(),
+        });
+        Ok(texture)
+    }
+
+    /// Loads the float grid called `grid` (usually `"density"`) from an uncompressed NanoVDB
+    /// file, e.g. one converted from an OpenVDB file with `nanovdb_convert`. The voxels in the
+    /// grid's leaf nodes are copied into a dense volume covering their bounding box, which is
+    /// placed in world space with the grid's voxel size and translation (rotations are ignored).
+    /// Constant regions stored as active tiles in the internal nodes are filled in with their
+    /// values, but tiles in the root node (each 4096 voxels across) aren't supported. Requires
+    /// the `vdb` feature.
+    /// ```no_run
+    /// use firework::objects::HeterogeneousMedium;
+    /// use firework::texture::{ConstantTexture, VolumeTexture};
+    /// use firework::{material::IsotropicMat, RenderObject, Scene};
+    ///
+    /// # fn main() -> std::io::Result<()> {
+    /// let mut scene = Scene::new();
+    /// let smoke = scene.add_material(IsotropicMat::new(ConstantTexture::from_rgb(0.8, 0.8, 0.8)));
+    /// let density = VolumeTexture::from_nanovdb("smoke.nvdb", "density")?;
+    /// scene.add_object(RenderObject::new(HeterogeneousMedium::from_volume(
+    ///     density, smoke,
+    /// )));
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "vdb")]
+    pub fn from_nanovdb(path: impl AsRef<Path>, grid: &str) -> std::io::Result<Self> {
+        let bytes = std::fs::read(path.as_ref())?;
+        let mut texture = nanovdb::read_float_grid(nanovdb::find_grid(&bytes, grid)?)?;
+        texture.source = Some(VolumeSource::NanoVdb {
+            path: path.as_ref().to_owned(),
+            grid: grid.to_owned(),
+        });
+        Ok(texture)
+    }
+
+    /// Sets the box in world space that the volume covers
+    pub fn bounds(mut self, min: Vec3, max: Vec3) -> Self {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    /// Returns the corners of the box in world space that the volume covers
+    pub fn extent(&self) -> (Vec3, Vec3) {
+        (self.min, self.max)
+    }
+
+    /// Returns the largest value in the volume
+    pub fn max_density(&self) -> f32 {
+        self.data.iter().copied().fold(0., f32::max)
+    }
+
+    /// Samples the volume at a point in world space
+    pub fn density(&self, point: Vec3) -> f32 {
+        let local = (point - self.min) / (self.max - self.min);
+        if local.x < 0.
+            || local.y < 0.
+            || local.z < 0.
+            || local.x > 1.
+            || local.y > 1.
+            || local.z > 1.
+        {
+            return 0.;
+        }
+
+        // voxel centers are at half-integer grid coordinates
+        let coord = |local: f32, dim: usize| {
+            let x = (local * dim as f32 - 0.5).clamp(0., (dim - 1) as f32);
+            let i = (x.floor() as usize).min(dim.saturating_sub(2));
+            (i, (i + 1).min(dim - 1), x - i as f32)
+        };
+        let (x0, x1, tx) = coord(local.x, self.dims[0]);
+        let (y0, y1, ty) = coord(local.y, self.dims[1]);
+        let (z0, z1, tz) = coord(local.z, self.dims[2]);
+
+        let at =
+            |x: usize, y: usize, z: usize| self.data[x + self.dims[0] * (y + self.dims[1] * z)];
+        let c00 = lerp(tx, at(x0, y0, z0), at(x1, y0, z0));
+        let c10 = lerp(tx, at(x0, y1, z0), at(x1, y1, z0));
+        let c01 = lerp(tx, at(x0, y0, z1), at(x1, y0, z1));
+        let c11 = lerp(tx, at(x0, y1, z1), at(x1, y1, z1));
+        lerp(tz, lerp(ty, c00, c10), lerp(ty, c01, c11))
+    }
+}
+
+#[typetag::serde]
+impl Texture for VolumeTexture {
+    fn sample(&self, _uv: Vec2, point: &Vec3) -> Vec3 {
+        Vec3::broadcast(self.density(*point))
+    }
+
+    fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.data.len() * std::mem::size_of::<f32>()
+    }
+}
+
+/// A minimal reader for the float grids in uncompressed NanoVDB files. Offsets are from the
+/// structs in `nanovdb/NanoVDB.h` and `nanovdb/util/IO.h`, and everything is little endian.
+#[cfg(feature = "vdb")]
+mod nanovdb {
+    use super::{invalid_data, volume_len, VolumeTexture};
+    use std::convert::TryInto;
+    use std::io::Result;
+    use ultraviolet::Vec3;
+
+    /// "NanoVDB0" and "NanoVDB2"
+    const MAGIC: [u64; 2] = [0x304244566f6e614e, 0x324244566f6e614e];
+    const FILE_HEADER_SIZE: usize = 16;
+    const FILE_META_DATA_SIZE: usize = 176;
+    const GRID_DATA_SIZE: usize = 672;
+    const LEAF_SIZE: usize = 2144;
+    /// The size of the lower and upper internal nodes of a float grid, and where their tables
+    /// of children and tiles start
+    const LOWER_SIZE: usize = 33856;
+    const LOWER_TABLE: usize = 1088;
+    const UPPER_SIZE: usize = 270400;
+    const UPPER_TABLE: usize = 8256;
+    const GRID_TYPE_FLOAT: u32 = 1;
+
+    fn truncated() -> std::io::Error {
+        invalid_data("NanoVDB file is truncated")
+    }
+
+    /// Adds offsets read from a file, which is an error if they overflow
+    fn add_offset(a: usize, b: usize) -> Result<usize> {
+        a.checked_add(b).ok_or_else(truncated)
+    }
+
+    /// Finds the `index`th of an array of nodes of `size` bytes, checking it's in the file, so
+    /// the fields of the node can be read without overflowing
+    fn node_at(data: &[u8], start: usize, index: usize, size: usize) -> Result<usize> {
+        let at = add_offset(start, index.checked_mul(size).ok_or_else(truncated)?)?;
+        if add_offset(at, size)? > data.len() {
+            return Err(truncated());
+        }
+        Ok(at)
+    }
+
+    fn read<const N: usize>(data: &[u8], at: usize) -> Result<[u8; N]> {
+        data.get(at..add_offset(at, N)?)
+            .and_then(|b| b.try_into().ok())
+            .ok_or_else(truncated)
+    }
+
+    fn u16_at(data: &[u8], at: usize) -> Result<u16> {
+        Ok(u16::from_le_bytes(read(data, at)?))
+    }
+
+    fn u32_at(data: &[u8], at: usize) -> Result<u32> {
+        Ok(u32::from_le_bytes(read(data, at)?))
+    }
+
+    fn i32_at(data: &[u8], at: usize) -> Result<i32> {
+        Ok(i32::from_le_bytes(read(data, at)?))
+    }
+
+    fn u64_at(data: &[u8], at: usize) -> Result<u64> {
+        Ok(u64::from_le_bytes(read(data, at)?))
+    }
+
+    fn f32_at(data: &[u8], at: usize) -> Result<f32> {
+        Ok(f32::from_le_bytes(read(data, at)?))
+    }
+
+    fn vec3_at(data: &[u8], at: usize) -> Result<Vec3> {
+        let f64_at = |at| -> Result<f32> { Ok(f64::from_le_bytes(read(data, at)?) as f32) };
+        Ok(Vec3::new(f64_at(at)?, f64_at(at + 8)?, f64_at(at + 16)?))
+    }
+
+    /// Finds the grid called `name` and returns its data. A file is a sequence of segments, each
+    /// of which has a header, then the metadata and name of each grid, then the grids themselves.
+    pub(super) fn find_grid<'a>(data: &'a [u8], name: &str) -> Result<&'a [u8]> {
+        let mut offset = 0;
+        while offset < data.len() {
+            if !MAGIC.contains(&u64_at(data, offset)?) {
+                return Err(invalid_data("not a NanoVDB file"));
+            }
+            let grid_count = u16_at(data, offset + 12)?;
+            if u16_at(data, offset + 14)? != 0 {
+                return Err(invalid_data("compressed NanoVDB files are not supported"));
+            }
+            offset += FILE_HEADER_SIZE;
+
+            let mut grids = Vec::with_capacity(grid_count as usize);
+            for _ in 0..grid_count {
+                let grid_size = u64_at(data, offset)? as usize;
+                let grid_type = u32_at(data, offset + 32)?;
+                let name_size = u32_at(data, offset + 136)? as usize;
+                let start = offset + FILE_META_DATA_SIZE;
+                let grid_name = data
+                    .get(start..add_offset(start, name_size)?)
+                    .ok_or_else(truncated)?;
+                let is_match = grid_name.split(|&b| b == 0).next() == Some(name.as_bytes());
+                grids.push((is_match, grid_size, grid_type));
+                offset = start + name_size;
+            }
+
+            for (is_match, grid_size, grid_type) in grids {
+                if is_match {
+                    if grid_type != GRID_TYPE_FLOAT {
+                        return Err(invalid_data(format!("{} is not a float grid", name)));
+                    }
+                    return data
+                        .get(offset..add_offset(offset, grid_size)?)
+                        .ok_or_else(truncated);
+                }
+                offset = add_offset(offset, grid_size)?;
+            }
+        }
+        Err(invalid_data(format!(
+            "NanoVDB file has no grid called {}",
+            name
+        )))
+    }
+
+    /// A cube of voxels with the same value, stored as an active tile in an internal node
+    struct Tile {
+        origin: [i32; 3],
+        size: i32,
+        value: f32,
+    }
+
+    /// Finds the active tiles in `count` internal nodes starting at `nodes`, which each have
+    /// `2^log2_dim` children (or tiles) along each axis, of `2^log2_child` voxels each
+    fn internal_tiles(
+        grid: &[u8],
+        (nodes, count): (usize, usize),
+        (node_size, table): (usize, usize),
+        (log2_dim, log2_child): (u32, u32),
+    ) -> Result<Vec<Tile>> {
+        let mask_size = 1 << (3 * log2_dim - 3);
+        let mut tiles = Vec::new();
+        for node in 0..count {
+            let at = node_at(grid, nodes, node, node_size)?;
+            // the node starts at a multiple of its size, and its bounding box is inside it
+            let node_mask = !((1 << (log2_dim + log2_child)) - 1);
+            let origin = [
+                i32_at(grid, at)? & node_mask,
+                i32_at(grid, at + 4)? & node_mask,
+                i32_at(grid, at + 8)? & node_mask,
+            ];
+            let (values, children) = (at + 32, at + 32 + mask_size);
+            for word in 0..mask_size / 8 {
+                // tiles are the active values which aren't children
+                let active = u64_at(grid, values + 8 * word)? & !u64_at(grid, children + 8 * word)?;
+                for bit in (0..64).filter(|bit| active & (1 << bit) != 0) {
+                    let n = 64 * word + bit;
+                    let dim_mask = (1 << log2_dim) - 1;
+                    let offset = |shift: u32| (((n >> shift) & dim_mask) << log2_child) as i32;
+                    tiles.push(Tile {
+                        origin: [
+                            origin[0] + offset(2 * log2_dim),
+                            origin[1] + offset(log2_dim),
+                            origin[2] + offset(0),
+                        ],
+                        size: 1 << log2_child,
+                        value: f32_at(grid, at + table + 8 * n)?,
+                    });
+                }
+            }
+        }
+        Ok(tiles)
+    }
+
+    /// Copies the values in the leaf nodes and tiles of a float grid into a dense `VolumeTexture`
+    pub(super) fn read_float_grid(grid: &[u8]) -> Result<VolumeTexture> {
+        // the translation of the index to world map, and the voxel size
+        let translation = vec3_at(grid, 528)?;
+        let voxel_size = vec3_at(grid, 608)?;
+
+        // the tree follows the grid, and the offsets to the nodes are relative to it
+        let tree = GRID_DATA_SIZE;
+        let nodes = |level: usize| -> Result<(usize, usize)> {
+            let offset = add_offset(tree, u64_at(grid, tree + 8 * level)? as usize)?;
+            Ok((offset, u32_at(grid, tree + 32 + 4 * level)? as usize))
+        };
+        let (leaves, leaf_count) = nodes(0)?;
+        let mut tiles = internal_tiles(grid, nodes(1)?, (LOWER_SIZE, LOWER_TABLE), (4, 3))?;
+        tiles.extend(internal_tiles(
+            grid,
+            nodes(2)?,
+            (UPPER_SIZE, UPPER_TABLE),
+            (5, 7),
+        )?);
+
+        // each leaf is an 8x8x8 block of voxels, starting at a multiple of 8
+        let origin = |leaf: usize| -> Result<[i32; 3]> {
+            let at = node_at(grid, leaves, leaf, LEAF_SIZE)?;
+            Ok([
+                i32_at(grid, at)? & !7,
+                i32_at(grid, at + 4)? & !7,
+                i32_at(grid, at + 8)? & !7,
+            ])
+        };
+        let mut min = [i32::MAX; 3];
+        let mut max = [i32::MIN; 3];
+        let mut include = |origin: [i32; 3], size: i32| {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(origin[axis]);
+                max[axis] = max[axis].max(origin[axis].saturating_add(size));
+            }
+        };
+        for leaf in 0..leaf_count {
+            include(origin(leaf)?, 8);
+        }
+        for tile in &tiles {
+            include(tile.origin, tile.size);
+        }
+        if leaf_count == 0 && tiles.is_empty() {
+            return Err(invalid_data("NanoVDB grid has no leaf nodes or tiles"));
+        }
+
+        let dims = [0, 1, 2].map(|axis| (max[axis] as i64 - min[axis] as i64) as usize);
+        let mut data = vec![0.; volume_len(dims, 1)?];
+        let index = |x: i32, y: i32, z: i32| {
+            let [x, y, z] = [x - min[0], y - min[1], z - min[2]].map(|c| c as usize);
+            x + dims[0] * (y + dims[1] * z)
+        };
+        for tile in &tiles {
+            let [x0, y0, z0] = tile.origin;
+            for z in z0..z0 + tile.size {
+                for y in y0..y0 + tile.size {
+                    for x in x0..x0 + tile.size {
+                        data[index(x, y, z)] = tile.value;
+                    }
+                }
+            }
+        }
+        for leaf in 0..leaf_count {
+            let [x, y, z] = origin(leaf)?;
+            let values = node_at(grid, leaves, leaf, LEAF_SIZE)? + 96;
+            for n in 0..512 {
+                let (dx, dy, dz) = ((n >> 6) as i32, ((n >> 3) & 7) as i32, (n & 7) as i32);
+                data[index(x + dx, y + dy, z + dz)] = f32_at(grid, values + 4 * n)?;
+            }
+        }
+
+        // voxel centers are at integer index coordinates
+        let index = |c: [i32; 3]| Vec3::new(c[0] as f32, c[1] as f32, c[2] as f32);
+        let lo = (index(min) - Vec3::broadcast(0.5)) * voxel_size + translation;
+        let hi = (index(max) - Vec3::broadcast(0.5)) * voxel_size + translation;
+        Ok(VolumeTexture::new(dims, data).bounds(lo, hi))
+    }
+}
+
+/// Integrates Planck's law against (an analytic fit of) the CIE 1931 color matching functions,
+/// giving the color of a black body at a temperature in Kelvin as XYZ, in arbitrary (but
+/// consistent) units
+fn blackbody_xyz(kelvin: f32) -> Vec3 {
+    // the multi-lobe gaussian fit from Wyman, Sloan and Shirley, "Simple Analytic Approximations
+    // to the CIE XYZ Color Matching Functions"
+    let g = |l: f32, mu: f32, s1: f32, s2: f32| {
+        let s = if l < mu { s1 } else { s2 };
+        (-0.5 * (l - mu) * (l - mu) / (s * s)).exp()
+    };
+    let mut xyz = Vec3::zero();
+    for i in 0..=40 {
+        let nm = 380. + 10. * i as f32;
+        let um = nm / 1000.;
+        // the second radiation constant is 14388 um K
+        let radiance = 1. / (um.powi(5) * ((14388. / (um * kelvin)).exp() - 1.));
+        let cmf = Vec3::new(
+            1.056 * g(nm, 599.8, 37.9, 31.0) + 0.362 * g(nm, 442.0, 16.0, 26.7)
+                - 0.065 * g(nm, 501.1, 20.4, 26.2),
+            0.821 * g(nm, 568.8, 46.9, 40.5) + 0.286 * g(nm, 530.9, 16.3, 31.1),
+            1.217 * g(nm, 437.0, 11.8, 36.0) + 0.681 * g(nm, 459.0, 26.0, 13.8),
+        );
+        xyz += radiance * cmf;
+    }
+    xyz
+}
+
+pub(crate) fn xyz_to_linear_srgb(xyz: Vec3) -> Vec3 {
+    Vec3::new(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    )
+    .map(|c| c.max(0.))
+}
+
+/// Returns the color of a black body at a temperature in Kelvin (in linear sRGB), scaled to a
+/// luminance of 1. Roughly 1800 K is candle light, 3000 K a warm light bulb, and 6500 K daylight.
+/// ```
+/// use firework::texture::blackbody;
+///
+/// let warm = blackbody(3000.);
+/// assert!(warm.x > warm.z);
+/// ```
+pub fn blackbody(kelvin: f32) -> Vec3 {
+    let xyz = blackbody_xyz(kelvin);
+    if xyz.y > 0. {
+        xyz_to_linear_srgb(xyz / xyz.y)
+    } else {
+        Vec3::zero()
+    }
+}
+
+/// Maps a temperature to the color of a black body at that temperature (see `blackbody`), e.g. to
+/// use the temperature channel of a fire simulation as the emission of an `EmissiveMediumMat`.
+/// The temperature is read from the first component of `temperature`, and remapped from 0..1 to
+/// `min..max` Kelvin (by default, the input is already in Kelvin).
+/// By default, the brightness follows Planck's law, so hotter regions are much brighter, relative
+/// to a black body at `reference` Kelvin (which has a luminance of 1). If `normalized`, every
+/// temperature has a luminance of 1, and only the hue changes.
+/// ```
+/// use firework::material::EmissiveMediumMat;
+/// use firework::texture::{BlackbodyTexture, ConstantTexture, FbmTexture};
+///
+/// let fire = EmissiveMediumMat::new(
+///     ConstantTexture::from_rgb(0., 0., 0.),
+///     BlackbodyTexture::new(FbmTexture::new(4, 2.)).remap(800., 2000.),
+/// );
+/// ```
+#[derive(Serialize, Deserialize)]
+#[serde(from = "BlackbodyTextureDef")]
+pub struct BlackbodyTexture {
+    temperature: Box<dyn Texture>,
+    min: f32,
+    max: f32,
+    reference: f32,
+    normalized: bool,
+    /// The luminance (Y) of a black body at `reference` Kelvin, which the colors are divided by
+    #[serde(skip)]
+    reference_luminance: f32,
+}
+
+#[derive(Deserialize)]
+struct BlackbodyTextureDef {
+    temperature: Box<dyn Texture>,
+    #[serde(default)]
+    min: f32,
+    #[serde(default = "default_max")]
+    max: f32,
+    #[serde(default = "default_reference")]
+    reference: f32,
+    #[serde(default)]
+    normalized: bool,
+}
+
+impl From<BlackbodyTextureDef> for BlackbodyTexture {
+    fn from(def: BlackbodyTextureDef) -> BlackbodyTexture {
+        BlackbodyTexture {
+            temperature: def.temperature,
+            min: def.min,
+            max: def.max,
+            reference: def.reference,
+            normalized: def.normalized,
+            reference_luminance: blackbody_xyz(def.reference).y,
+        }
+    }
+}
+
+fn default_reference() -> f32 {
+    1500.
+}
+
+impl BlackbodyTexture {
+    pub fn new<T: Texture + 'static>(temperature: T) -> Self {
+        BlackbodyTexture {
+            temperature: Box::new(temperature),
+            min: 0.,
+            max: 1.,
+            reference: default_reference(),
+            normalized: false,
+            reference_luminance: blackbody_xyz(default_reference()).y,
+        }
+    }
+
+    /// Sets the range of temperatures (in Kelvin) that the input 0..1 is remapped to
+    pub fn remap(mut self, min: f32, max: f32) -> Self {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    /// Sets the temperature (in Kelvin) which has a luminance of 1
+    pub fn reference(mut self, reference: f32) -> Self {
+        self.reference = reference;
+        self.reference_luminance = blackbody_xyz(reference).y;
+        self
+    }
+
+    /// If true, every temperature has a luminance of 1
+    pub fn normalized(mut self, normalized: bool) -> Self {
+        self.normalized = normalized;
+        self
+    }
+
+    fn color(&self, input: Vec3) -> Vec3 {
+        let kelvin = lerp(input.x, self.min, self.max);
+        if kelvin <= 0. {
+            return Vec3::zero();
+        }
+        if self.normalized {
+            blackbody(kelvin)
+        } else {
+            xyz_to_linear_srgb(blackbody_xyz(kelvin) / self.reference_luminance)
+        }
+    }
+}
+
+#[typetag::serde]
+impl Texture for BlackbodyTexture {
+    fn sample(&self, uv: Vec2, point: &Vec3) -> Vec3 {
+        self.color(self.temperature.sample(uv, point))
+    }
+
+    fn sample_hit(&self, hit: &RaycastHit) -> Vec3 {
+        self.color(self.temperature.sample_hit(hit))
+    }
+}
+
+/// A texture which plays through a sequence of frames (e.g. an image sequence), using the time of
+/// the frame being rendered (see `Renderer::time`). When sampled without a hit (through
+/// `Texture::sample`), the first frame is used.
+/// ```no_run
+/// use firework::texture::AnimatedTexture;
+///
+/// # fn main() -> Result<(), image::ImageError> {
+/// let paths = (1..=24).map(|i| format!("fire/fire_{:04}.png", i));
+/// let fire = AnimatedTexture::from_paths(paths, 24.)?.looping(true);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Serialize, Deserialize)]
+#[serde(try_from = "AnimatedTextureDef")]
+pub struct AnimatedTexture {
+    frames: Vec<Box<dyn Texture>>,
+    /// Frames per second
+    fps: f32,
+    /// If true, the animation starts again after the last frame. Otherwise, the last frame is
+    /// held.
+    #[serde(default)]
+    looping: bool,
+}
+
+#[derive(Deserialize)]
+struct AnimatedTextureDef {
+    frames: Vec<Box<dyn Texture>>,
+    fps: f32,
+    #[serde(default)]
+    looping: bool,
+}
+
+impl TryFrom<AnimatedTextureDef> for AnimatedTexture {
+    type Error = &'static str;
+    fn try_from(def: AnimatedTextureDef) -> Result<AnimatedTexture, Self::Error> {
+        if def.frames.is_empty() {
+            return Err("an AnimatedTexture must have at least one frame");
+        }
+        Ok(AnimatedTexture {
+            frames: def.frames,
+            fps: def.fps,
+            looping: def.looping,
+        })
+    }
+}
+
+impl AnimatedTexture {
+    /// Creates an animation from the given frames. Panics if `frames` is empty.
+    pub fn new(frames: Vec<Box<dyn Texture>>, fps: f32) -> Self {
+        assert!(!frames.is_empty(), "AnimatedTexture::new() -- no frames");
+        AnimatedTexture {
+            frames,
+            fps,
+            looping: false,
+        }
+    }
+
+    /// Loads each frame of an image sequence as an `ImageTexture`. An empty sequence is an
+    /// error.
+    /// ```
+    /// use firework::texture::AnimatedTexture;
+    ///
+    /// assert!(AnimatedTexture::from_paths(Vec::<String>::new(), 24.).is_err());
+    /// ```
+    pub fn from_paths<P: AsRef<Path>>(
+        paths: impl IntoIterator<Item = P>,
+        fps: f32,
+    ) -> Result<Self, image::ImageError> {
+        let frames = paths
+            .into_iter()
+            .map(|path| ImageTexture::from_path(path).map(|t| Box::new(t) as Box<dyn Texture>))
+            .collect::<Result<Vec<_>, _>>()?;
+        if frames.is_empty() {
+            return Err(image::ImageError::Parameter(
+                image::error::ParameterError::from_kind(image::error::ParameterErrorKind::Generic(
+                    "no frames were given".to_string(),
+                )),
+            ));
+        }
+        Ok(AnimatedTexture::new(frames, fps))
+    }
+
+    pub fn looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    /// Returns the frame shown at the given time
+    fn frame(&self, time: f32) -> &dyn Texture {
+        let idx = (time * self.fps).floor().max(0.) as usize;
+        let idx = if self.looping {
+            idx % self.frames.len()
+        } else {
+            idx.min(self.frames.len() - 1)
+        };
+        self.frames[idx].as_ref()
+    }
+}
+
+#[typetag::serde]
+impl Texture for AnimatedTexture {
+    fn sample(&self, uv: Vec2, point: &Vec3) -> Vec3 {
+        self.frames[0].sample(uv, point)
+    }
+
+    fn sample_hit(&self, hit: &RaycastHit) -> Vec3 {
+        self.frame(hit.time).sample_hit(hit)
+    }
+
+    fn max(&self) -> Option<Vec3> {
+        self.frames.iter().try_fold(Vec3::zero(), |max, frame| {
+            Some(max.max_by_component(frame.max()?))
+        })
+    }
+}
+
+/// A cheaply cloneable handle to a texture, so a single texture (like a large image) can be used
+/// by several materials without being loaded or stored more than once. See
+/// `Scene::load_texture`.
+/// Note that sharing is not preserved through serialization: each handle is serialized as a copy
+/// of the texture it refers to.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SharedTexture {
+    #[serde(with = "arc_texture")]
+    shared: Arc<dyn Texture>,
+}
+
+impl SharedTexture {
+    pub fn new<T: Texture + 'static>(texture: T) -> Self {
+        SharedTexture {
+            shared: Arc::new(texture),
+        }
+    }
+
+    /// Returns true if both handles refer to the same texture
+    pub fn ptr_eq(&self, other: &SharedTexture) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared)
+    }
+}
+
+mod arc_texture {
+    use super::Texture;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::sync::Arc;
+
+    pub fn serialize<S: Serializer>(
+        texture: &Arc<dyn Texture>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        texture.as_ref().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Arc<dyn Texture>, D::Error> {
+        Box::<dyn Texture>::deserialize(deserializer).map(Arc::from)
+    }
+}
+
+#[typetag::serde]
+impl Texture for SharedTexture {
+    fn sample(&self, uv: Vec2, point: &Vec3) -> Vec3 {
+        self.shared.sample(uv, point)
+    }
+
+    fn sample_hit(&self, hit: &RaycastHit) -> Vec3 {
+        self.shared.sample_hit(hit)
+    }
+
+    fn memory_size(&self) -> usize {
+        self.shared.memory_size()
+    }
+
+    fn max(&self) -> Option<Vec3> {
+        self.shared.max()
+    }
+}
+
+/// Converts a linear color to a pixel in the given color space
+fn encode_pixel(c: Vec3, color_space: ColorSpace) -> image::Rgb<u8> {
+    let encode = |c: f32| (color_space.from_linear(c.clamp(0., 1.)) * 255.99) as u8;
+    image::Rgb([encode(c.x), encode(c.y), encode(c.z)])
+}
+
+/// Evaluates a texture over the uv square and stores it in an image, e.g. to turn an expensive
+/// procedural texture into an `ImageTexture`, or to look at a texture without rendering a scene.
+/// The texture is sampled at the center of each pixel, with the point (u, v, 0), and the image is
+/// encoded in `color_space` (so `ColorSpace::Srgb` for colors, and `ColorSpace::Linear` for
+/// data).
+/// ```no_run
+/// use firework::texture::{bake, ColorSpace, UvGridTexture};
+///
+/// bake(&UvGridTexture::new(8), 1024, 1024, ColorSpace::Srgb)
+///     .save("uvgrid.png")
+///     .unwrap();
+/// ```
+pub fn bake(
+    texture: &dyn Texture,
+    width: u32,
+    height: u32,
+    color_space: ColorSpace,
+) -> image::RgbImage {
+    image::ImageBuffer::from_fn(width, height, |x, y| {
+        let uv = Vec2::new(
+            (x as f32 + 0.5) / width as f32,
+            1. - (y as f32 + 0.5) / height as f32,
+        );
+        encode_pixel(texture.sample(uv, &Vec3::new(uv.x, uv.y, 0.)), color_space)
+    })
+}
+
+/// Evaluates a texture over the surface of a mesh and stores it in the mesh's uv layout, so 3D
+/// textures (like `WoodTexture` or `PerlinNoiseTexture`) can be baked to an image. Pixels outside
+/// of every triangle are filled in from their neighbours, to avoid dark seams when the image is
+/// filtered.
+pub fn bake_mesh(
+    texture: &dyn Texture,
+    mesh: &TriangleMesh,
+    width: u32,
+    height: u32,
+    color_space: ColorSpace,
+) -> image::RgbImage {
+    let (w, h) = (width as usize, height as usize);
+    let mut pixels: Vec<Option<Vec3>> = vec![None; w * h];
+
+    for tri in 0..mesh.num_tris() {
+        let verts = mesh.get_triangle_verts(tri);
+        let normals = mesh.get_triangle_normals(tri);
+        // the triangle in pixel space
+        let uvs = mesh
+            .get_triangle_uvs(tri)
+            .map(|uv| Vec2::new(uv.x * w as f32, (1. - uv.y) * h as f32));
+
+        let area = edge(uvs[0], uvs[1], uvs[2]);
+        if area.abs() < 1e-12 {
+            continue;
+        }
+
+        // the bounding box of the triangle, in pixels
+        let (xs, ys) = (uvs.map(|uv| uv.x), uvs.map(|uv| uv.y));
+        let x0 = xs.iter().copied().fold(f32::MAX, f32::min).floor().max(0.) as usize;
+        let y0 = ys.iter().copied().fold(f32::MAX, f32::min).floor().max(0.) as usize;
+        let x1 = xs
+            .iter()
+            .copied()
+            .fold(f32::MIN, f32::max)
+            .ceil()
+            .min(w as f32) as usize;
+        let y1 = ys
+            .iter()
+            .copied()
+            .fold(f32::MIN, f32::max)
+            .ceil()
+            .min(h as f32) as usize;
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let p = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let b0 = edge(uvs[1], uvs[2], p) / area;
+                let b1 = edge(uvs[2], uvs[0], p) / area;
+                let b2 = 1. - b0 - b1;
+                if b0 < 0. || b1 < 0. || b2 < 0. {
+                    continue;
+                }
+
+                let point = b0 * verts[0] + b1 * verts[1] + b2 * verts[2];
+                let normal = match normals {
+                    Some(n) => (b0 * n[0] + b1 * n[1] + b2 * n[2]).normalized(),
+                    None => (verts[1] - verts[0])
+                        .cross(verts[2] - verts[0])
+                        .normalized(),
+                };
+                let hit = RaycastHit {
+                    t: 0.,
+                    point,
+                    normal,
+                    material: 0,
+                    uv: Vec2::new(p.x / w as f32, 1. - p.y / h as f32),
+                    tangent: None,
+                    footprint: 0.,
+                    time: 0.,
+                    priority: 0,
+                    outside_ior: 1.,
+                    light: None,
+                    light_group: None,
+                };
+                pixels[y * w + x] = Some(texture.sample_hit(&hit));
+            }
+        }
+    }
+
+    dilate(&mut pixels, w, h, 4);
+    image::ImageBuffer::from_fn(width, height, |x, y| {
+        let c = pixels[y as usize * w + x as usize].unwrap_or_else(Vec3::zero);
+        encode_pixel(c, color_space)
+    })
+}
+
+/// Twice the signed area of the triangle `a`, `b`, `c`
+fn edge(a: Vec2, b: Vec2, c: Vec2) -> f32 {
+    (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)
+}
+
+/// Fills empty pixels next to filled ones with the average of their filled neighbours, `passes`
+/// times
+fn dilate(pixels: &mut Vec<Option<Vec3>>, w: usize, h: usize, passes: usize) {
+    for _ in 0..passes {
+        let mut next = pixels.clone();
+        for y in 0..h {
+            for x in 0..w {
+                if pixels[y * w + x].is_some() {
+                    continue;
+                }
+                let mut sum = Vec3::zero();
+                let mut count = 0;
+                for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)].iter() {
+                    let (nx, ny) = (x as isize + dx, y as isize + dy);
+                    if nx < 0 || ny < 0 || nx >= w as isize || ny >= h as isize {
+                        continue;
+                    }
+                    if let Some(c) = pixels[ny as usize * w + nx as usize] {
+                        sum += c;
+                        count += 1;
+                    }
+                }
+                if count > 0 {
+                    next[y * w + x] = Some(sum / count as f32);
+                }
+            }
+        }
+        *pixels = next;
     }
 }
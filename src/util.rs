@@ -1,6 +1,7 @@
+use std::f32::consts::PI;
 use std::ops::{Add, Mul};
 use tiny_rng::Rand;
-use ultraviolet::Vec3;
+use ultraviolet::{Vec2, Vec3};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Color(pub u8, pub u8, pub u8);
@@ -42,6 +43,12 @@ pub(crate) fn random_in_unit_sphere(rng: &mut impl Rand) -> Vec3 {
     }
 }
 
+/// Returns a random point on the surface of the unit sphere. Adding this to a normal gives a
+/// cosine weighted direction on the hemisphere.
+pub(crate) fn random_unit_vector(rng: &mut impl Rand) -> Vec3 {
+    random_in_unit_sphere(rng).normalized()
+}
+
 pub(crate) fn random_in_unit_disk(rng: &mut impl Rand) -> Vec3 {
     loop {
         let p = 2.0 * Vec3::new(rng.rand_f32(), rng.rand_f32(), 0.) - Vec3::new(1., 1., 0.);
@@ -51,6 +58,113 @@ pub(crate) fn random_in_unit_disk(rng: &mut impl Rand) -> Vec3 {
     }
 }
 
+/// The solid angle of a cone with the given half-angle (in radians). This avoids computing
+/// 1 - cos(angle), which loses most of its precision for cones as small as the sun.
+pub(crate) fn cone_solid_angle(half_angle: f32) -> f32 {
+    let s = (0.5 * half_angle).sin();
+    4. * PI * s * s
+}
+
+/// Uniformly samples a direction in the cone around `axis` (which must be normalized) with the
+/// given half-angle (in radians)
+pub(crate) fn sample_cone(axis: Vec3, half_angle: f32, rand: &mut impl Rand) -> Vec3 {
+    let one_minus_cos = rand.rand_f32() * cone_solid_angle(half_angle) / (2. * PI);
+    let cos_theta = 1. - one_minus_cos;
+    let sin_theta = (one_minus_cos * (2. - one_minus_cos)).max(0.).sqrt();
+    let phi = 2. * PI * rand.rand_f32();
+    let frame = CoordinateSystem::from_one_vec(&axis);
+    cos_theta * frame.v1 + sin_theta * phi.cos() * frame.v2 + sin_theta * phi.sin() * frame.v3
+}
+
+/// The part of the sphere of directions around a point covered by a rectangle, which can be
+/// sampled uniformly. See Ureña et al., "An Area-Preserving Parametrization for Spherical
+/// Rectangles".
+pub(crate) struct SphericalRect {
+    origin: Vec3,
+    x: Vec3,
+    y: Vec3,
+    z: Vec3,
+    x0: f32,
+    x1: f32,
+    y0: f32,
+    y1: f32,
+    z0: f32,
+    b0: f32,
+    b1: f32,
+    k: f32,
+    /// The solid angle covered by the rectangle
+    pub solid_angle: f32,
+}
+
+impl SphericalRect {
+    /// The rectangle with a corner at `corner` and (perpendicular) edges `edge1` and `edge2`, as
+    /// seen from `origin`
+    pub fn new(origin: Vec3, corner: Vec3, edge1: Vec3, edge2: Vec3) -> SphericalRect {
+        let (x, y) = (edge1.normalized(), edge2.normalized());
+        let mut z = x.cross(y);
+        let d = corner - origin;
+        let (x0, y0) = (d.dot(x), d.dot(y));
+        let mut z0 = d.dot(z);
+        // the rectangle should face away from `origin` in the local frame
+        if z0 > 0. {
+            z0 = -z0;
+            z = -z;
+        }
+        let (x1, y1) = (x0 + edge1.mag(), y0 + edge2.mag());
+
+        let v00 = Vec3::new(x0, y0, z0);
+        let v01 = Vec3::new(x0, y1, z0);
+        let v10 = Vec3::new(x1, y0, z0);
+        let v11 = Vec3::new(x1, y1, z0);
+        // the normals of the planes through `origin` and each edge, and the angles between them
+        let n0 = v00.cross(v10).normalized();
+        let n1 = v10.cross(v11).normalized();
+        let n2 = v11.cross(v01).normalized();
+        let n3 = v01.cross(v00).normalized();
+        let angle = |a: Vec3, b: Vec3| (-a.dot(b)).clamp(-1., 1.).acos();
+        let (g0, g1, g2, g3) = (angle(n0, n1), angle(n1, n2), angle(n2, n3), angle(n3, n0));
+        let k = 2. * PI - g2 - g3;
+
+        SphericalRect {
+            origin,
+            x,
+            y,
+            z,
+            x0,
+            x1,
+            y0,
+            y1,
+            z0,
+            b0: n0.z,
+            b1: n2.z,
+            k,
+            solid_angle: g0 + g1 - k,
+        }
+    }
+
+    /// The point on the rectangle in the direction chosen by `u`, each of whose components is in
+    /// `0..1`
+    pub fn sample(&self, u: Vec2) -> Vec3 {
+        // choose the x coordinate, from the area of the part of the rectangle before it
+        let au = u.x * self.solid_angle + self.k;
+        let fu = (au.cos() * self.b0 - self.b1) / au.sin();
+        let cu = (fu.signum() / (fu * fu + self.b0 * self.b0).sqrt()).clamp(-1., 1.);
+        let xu = (-(cu * self.z0) / (1. - cu * cu).max(1e-12).sqrt()).clamp(self.x0, self.x1);
+
+        // then the y coordinate, uniformly in the projection of that line onto the sphere
+        let d = (xu * xu + self.z0 * self.z0).sqrt();
+        let h0 = self.y0 / (d * d + self.y0 * self.y0).sqrt();
+        let h1 = self.y1 / (d * d + self.y1 * self.y1).sqrt();
+        let hv = h0 + u.y * (h1 - h0);
+        let yv = if hv * hv < 1. - 1e-6 {
+            (hv * d / (1. - hv * hv).sqrt()).clamp(self.y0, self.y1)
+        } else {
+            self.y1
+        };
+        self.origin + xu * self.x + yv * self.y + self.z0 * self.z
+    }
+}
+
 pub(crate) fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
     *v - 2. * v.dot(*n) * *n
 }
@@ -146,7 +260,7 @@ impl CoordinateSystem {
     /// `v1` should be normalized before calling this function.
     /// Note that these values are unique only up to rotation around the vector `v1`.
     /// See The PBR Book Section 2.2.4 for more details.
-    pub fn _from_one_vec(v1: &Vec3) -> CoordinateSystem {
+    pub fn from_one_vec(v1: &Vec3) -> CoordinateSystem {
         let v2 = if v1.x.abs() > v1.y.abs() {
             Vec3::new(-v1.z, 0., v1.x).normalized()
         } else {
@@ -158,3 +272,122 @@ impl CoordinateSystem {
         CoordinateSystem { v1: *v1, v2, v3 }
     }
 }
+
+/// A piecewise-constant 1D distribution over 0..1, used to draw samples in proportion to a
+/// function. See The PBR Book Section 13.3.1.
+#[derive(Debug, Clone)]
+pub(crate) struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    /// Creates a distribution from non-negative function values over equally sized intervals. If
+    /// the function is zero everywhere, samples are distributed uniformly.
+    pub fn new(func: Vec<f32>) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.);
+        for i in 0..n {
+            cdf.push(cdf[i] + func[i] / n as f32);
+        }
+
+        let integral = cdf[n];
+        if integral == 0. {
+            for (i, c) in cdf.iter_mut().enumerate() {
+                *c = i as f32 / n as f32;
+            }
+        } else {
+            for c in &mut cdf {
+                *c /= integral;
+            }
+        }
+
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    /// The average value of the function
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// Maps `u` (uniform in 0..1) to a sample in 0..1, and returns it along with its density
+    /// and the index of the interval it fell in
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        // the last entry of the cdf that is <= u
+        let offset = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .saturating_sub(1)
+            .min(self.func.len() - 1);
+
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let du = if width > 0. {
+            (u - self.cdf[offset]) / width
+        } else {
+            0.
+        };
+
+        let x = (offset as f32 + du) / self.func.len() as f32;
+        (x, self.pdf(offset), offset)
+    }
+
+    /// The density of samples in the interval `offset`
+    pub fn pdf(&self, offset: usize) -> f32 {
+        if self.integral == 0. {
+            1.
+        } else {
+            self.func[offset] / self.integral
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.func.len()
+    }
+}
+
+/// A piecewise-constant 2D distribution over the unit square, built from a grid of function
+/// values stored row by row. Rows are chosen from the marginal distribution, then a column from
+/// that row's conditional distribution.
+#[derive(Debug, Clone)]
+pub(crate) struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f32], width: usize, height: usize) -> Distribution2D {
+        let conditional: Vec<_> = func
+            .chunks_exact(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.integral()).collect());
+
+        Distribution2D {
+            conditional,
+            marginal,
+        }
+    }
+
+    /// Maps `u` (uniform over the unit square) to a sample (x along the rows, y across them), and
+    /// returns it along with its density
+    pub fn sample(&self, u: Vec2) -> (Vec2, f32) {
+        let (y, pdf_y, row) = self.marginal.sample(u.y);
+        let (x, pdf_x, _) = self.conditional[row].sample(u.x);
+        (Vec2::new(x, y), pdf_x * pdf_y)
+    }
+
+    /// The density of sampling `p`
+    pub fn pdf(&self, p: Vec2) -> f32 {
+        let row = ((p.y * self.marginal.len() as f32) as usize).min(self.marginal.len() - 1);
+        let conditional = &self.conditional[row];
+        let col = ((p.x * conditional.len() as f32) as usize).min(conditional.len() - 1);
+        conditional.pdf(col) * self.marginal.pdf(row)
+    }
+}
@@ -1,17 +1,145 @@
+use crate::camera::CameraSettings;
+use crate::output::{save_render, OutputFormat};
+use crate::render::{RenderBuffer, Renderer, Tile, TileUpdate};
+use crate::scene::Scene;
+use crate::scene::SceneInternal;
 use crate::util::Color;
-use image::{save_buffer, ColorType};
-use minifb::{Key, Window, WindowOptions};
-use std::convert::AsRef;
-use std::path::Path;
+use crossbeam_utils::thread::Scope;
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
+use std::cell::RefCell;
+use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{SystemTime, UNIX_EPOCH};
+use ultraviolet::Vec3;
 
+/// A window to show renders in. While a render is shown from its linear colors (by
+/// `display_buffer`, `display_progressive` or `explore`), how they're mapped to the screen can be
+/// changed with the keyboard, to look into the shadows or highlights without rendering again:
+///
+/// - Up and Down raise and lower the exposure by half a stop
+/// - Right and Left raise and lower the gamma by 0.1
+/// - T switches between the tonemappers: none (the colors are clipped), Reinhard and ACES
+/// - = and - zoom in and out of the image around the cursor, by powers of two up to 64x, to see
+///   single pixels
+/// - Dragging with the left mouse button moves the zoomed image around (while holding Shift in
+///   `explore`, where dragging moves the camera)
+/// - Dragging with Ctrl and the left mouse button selects a region, which is rendered again with
+///   4 times as many samples once the button is let go (in `display_progressive` and `explore`)
+/// - H shows a histogram of the brightness of the pixels as they're shown, in the bottom left
+///   corner, with the pixels which are clipped in red
+/// - Z shows the clipped pixels (those with a color at its brightest) with zebra stripes
+/// - Backspace goes back to the exposure, gamma, tonemapper and zoom the window started with
+///
+/// The title of the window shows the position of the pixel under the cursor, its linear color,
+/// and the number of samples it has so far (for renders in progress), e.g. to look into
+/// fireflies. In any window, F3 saves the image as it's shown (without zooming) to a PNG named
+/// after the window, which can be changed with `set_save_options`. Other keys can be handled by
+/// the application with `set_key_handler`.
+///
+/// The window can be made larger or smaller than the render with `set_scale`, e.g. so a small
+/// test render fills more of the screen.
 pub struct RenderWindow<'a> {
     title: &'a str,
     options: WindowOptions,
     width: usize,
     height: usize,
     fps: u64,
+    scale: f32,
+    filter: ScaleFilter,
+    save_options: SaveOptions,
+    key_handler: Option<RefCell<KeyHandler<'a>>>,
 }
 
+/// How the image is scaled when a `RenderWindow` isn't the size of the render
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScaleFilter {
+    /// Each pixel of the render is shown as a block of the same color, to see single pixels
+    #[default]
+    Nearest,
+    /// The colors are interpolated between the pixels of the render, which looks smoother
+    Linear,
+}
+
+/// Scales an image of `0xRRGGBB` pixels of size `from` to size `to`, into `scaled`
+fn scale_image(
+    image: &[u32],
+    from: (usize, usize),
+    to: (usize, usize),
+    filter: ScaleFilter,
+    scaled: &mut Vec<u32>,
+) {
+    let (width, height) = from;
+    let (sx, sy) = (width as f32 / to.0 as f32, height as f32 / to.1 as f32);
+    scaled.resize(to.0 * to.1, 0);
+    for (idx, pixel) in scaled.iter_mut().enumerate() {
+        // the center of the pixel, in pixels of the image
+        let x = ((idx % to.0) as f32 + 0.5) * sx;
+        let y = ((idx / to.0) as f32 + 0.5) * sy;
+        *pixel = match filter {
+            ScaleFilter::Nearest => {
+                image[(y as usize).min(height - 1) * width + (x as usize).min(width - 1)]
+            }
+            ScaleFilter::Linear => {
+                // relative to the centers of the pixels around it
+                let (x, y) = ((x - 0.5).max(0.), (y - 0.5).max(0.));
+                let (x0, y0) = ((x as usize).min(width - 1), (y as usize).min(height - 1));
+                let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+                let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+                let corners = [
+                    (image[y0 * width + x0], (1. - tx) * (1. - ty)),
+                    (image[y0 * width + x1], tx * (1. - ty)),
+                    (image[y1 * width + x0], (1. - tx) * ty),
+                    (image[y1 * width + x1], tx * ty),
+                ];
+                [16, 8, 0].iter().fold(0, |pixel, &shift| {
+                    let channel: f32 = corners
+                        .iter()
+                        .map(|&(color, weight)| ((color >> shift) & 0xff) as f32 * weight)
+                        .sum();
+                    pixel | ((channel.round() as u32).min(0xff) << shift)
+                })
+            }
+        };
+    }
+}
+
+/// How many times more samples a region selected in a `RenderWindow` is rendered with
+const REGION_SAMPLES: usize = 4;
+
+/// Whether either Ctrl key is held
+fn ctrl_down(window: &Window) -> bool {
+    window.is_key_down(Key::LeftCtrl) || window.is_key_down(Key::RightCtrl)
+}
+
+/// A render running on another thread, which sends its tiles back as they're started and done.
+/// The render is stopped when this is dropped.
+struct RenderThread {
+    tiles: mpsc::Receiver<TileUpdate>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl RenderThread {
+    /// Starts rendering a prepared scene on a thread of `scope`
+    fn spawn<'s>(scope: &Scope<'s>, renderer: Renderer, scene: &'s SceneInternal) -> RenderThread {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let stop = cancel.clone();
+        let (sender, tiles) = mpsc::channel();
+        scope.spawn(move |_| renderer.render_prepared(scene, Some((sender, &stop)), None));
+        RenderThread { tiles, cancel }
+    }
+}
+
+impl Drop for RenderThread {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A function called with each key pressed in a `RenderWindow`, and the image as it's shown
+type KeyHandler<'a> = Box<dyn FnMut(Key, &[Color]) + 'a>;
+
 impl<'a> RenderWindow<'a> {
     pub fn new(
         title: &'a str,
@@ -25,6 +153,10 @@ impl<'a> RenderWindow<'a> {
             width,
             height,
             fps: 12,
+            scale: 1.,
+            filter: ScaleFilter::Nearest,
+            save_options: SaveOptions::default(),
+            key_handler: None,
         }
     }
 
@@ -32,35 +164,872 @@ impl<'a> RenderWindow<'a> {
         self.fps = fps;
     }
 
+    /// Shows the image `scale` times as large as the render, with the window sized to match, e.g.
+    /// 4 to fill more of a high resolution screen with a 200 by 100 test render
+    /// ```no_run
+    /// use firework::window::{RenderWindow, ScaleFilter};
+    ///
+    /// let mut window = RenderWindow::new("Preview", Default::default(), 200, 100);
+    /// window.set_scale(4.);
+    /// window.set_filter(ScaleFilter::Linear);
+    /// ```
+    pub fn set_scale(&mut self, scale: f32) {
+        assert!(
+            scale > 0.,
+            "RenderWindow::set_scale() -- the scale must be positive"
+        );
+        self.scale = scale;
+    }
+
+    /// Sets how the image is scaled to the size of the window (see `set_scale`)
+    pub fn set_filter(&mut self, filter: ScaleFilter) {
+        self.filter = filter;
+    }
+
+    pub fn set_save_options(&mut self, save_options: SaveOptions) {
+        self.save_options = save_options;
+    }
+
+    /// Sets a function to call with each key pressed while the window is open, and the image as
+    /// it's shown (gamma corrected, with the exposure and tonemapper of the window), so
+    /// applications can add their own controls
+    /// ```no_run
+    /// use firework::window::RenderWindow;
+    /// use minifb::Key;
+    ///
+    /// let mut window = RenderWindow::new("Preview", Default::default(), 960, 540);
+    /// window.set_key_handler(|key, image| {
+    ///     if key == Key::P {
+    ///         let brightest = image.iter().map(|c| c.0 as u32 + c.1 as u32 + c.2 as u32).max();
+    ///         println!("Brightest pixel: {:?}", brightest);
+    ///     }
+    /// });
+    /// ```
+    pub fn set_key_handler(&mut self, handler: impl FnMut(Key, &[Color]) + 'a) {
+        self.key_handler = Some(RefCell::new(Box::new(handler)));
+    }
+
     pub fn display(&self, render: &[Color]) {
         let buffer: Vec<u32> = render.iter().map(|c| u32::from(*c)).collect();
-        let mut window = Window::new(self.title, self.width, self.height, self.options)
-            .unwrap_or_else(|e| {
-                panic!("Window creation failed -- {}", e);
-            });
+        let mut window = self.open();
+        let mut scaled = Vec::new();
 
-        window.limit_update_rate(Some(std::time::Duration::from_millis(1000 / self.fps)));
+        while window.is_open() && !window.is_key_down(Key::Escape) {
+            self.handle_keys(&window, render, None);
+            self.show(&mut window, &buffer, &mut scaled);
+        }
+    }
+
+    /// Shows a render from its linear colors, starting with them gamma corrected with `gamma`,
+    /// with the exposure, gamma and tonemapper controlled by the keyboard (see `RenderWindow`).
+    ///
+    /// The window must be the size of the render.
+    /// ```no_run
+    /// use firework::procgen::RandomSpheres;
+    /// use firework::window::RenderWindow;
+    ///
+    /// let mut scene = RandomSpheres::new().build();
+    /// let renderer = scene.render_settings.take().unwrap();
+    /// let buffer = renderer.render_buffer(scene);
+    /// let window = RenderWindow::new("Spheres", Default::default(), 960, 540);
+    /// window.display_buffer(&buffer, renderer.gamma);
+    /// ```
+    pub fn display_buffer(&self, buffer: &RenderBuffer, gamma: f32) {
+        assert_eq!(
+            (self.width, self.height),
+            (buffer.width, buffer.height),
+            "RenderWindow::display_buffer() -- the window isn't the size of the render"
+        );
+        let mut view = View::new(self.width, self.height, gamma);
+        for (idx, &color) in buffer.color.iter().enumerate() {
+            view.set_pixel(idx, color);
+        }
+        let mut window = self.open();
+        let mut scaled = Vec::new();
 
         while window.is_open() && !window.is_key_down(Key::Escape) {
-            // TODO: Allow the user to do whatever they want here
-            // TODO: Use proper `Path`s or whatever instead of string concatenation
-            if window.is_key_released(Key::F3) {
-                let filename = format!("./{}.png", self.title);
-                println!("Saving image to {}", filename);
-                save_image(render, filename, self.width, self.height)
+            self.update_view(&mut window, &mut view, true);
+            self.handle_keys(&window, &view.render, Some(&view));
+            self.show(&mut window, view.screen(), &mut scaled);
+        }
+    }
+
+    /// Renders the scene while showing it in the window, so the image can be seen forming: a
+    /// noisy version appears after the first sample of each pixel, which is refined as more
+    /// samples are taken. The window stays open once the render is done, like with `display`,
+    /// and the render is returned when it's closed. Closing the window early stops the render,
+    /// and returns it as it was then.
+    ///
+    /// The image is rendered in tiles of 32 by 32 pixels. Until a tile's first pass is done, it
+    /// shows a checker pattern, and the tiles being rendered are outlined in orange, so a tile
+    /// which takes much longer than the rest stands out.
+    ///
+    /// The window must be the size of the render.
+    /// ```no_run
+    /// use firework::procgen::RandomSpheres;
+    /// use firework::output::save_image_rgba;
+    /// use firework::window::RenderWindow;
+    ///
+    /// let mut scene = RandomSpheres::new().build();
+    /// let renderer = scene.render_settings.take().unwrap();
+    /// let window = RenderWindow::new("Preview", Default::default(), 960, 540);
+    /// let buffer = window.display_progressive(&renderer, scene);
+    /// save_image_rgba(&buffer, "spheres.png", renderer.gamma);
+    /// ```
+    pub fn display_progressive(&self, renderer: &Renderer, scene: Scene) -> RenderBuffer {
+        assert_eq!(
+            (self.width, self.height),
+            (renderer.width, renderer.height),
+            "RenderWindow::display_progressive() -- the window isn't the size of the render"
+        );
+        let mut view = View::new(self.width, self.height, renderer.gamma);
+        let mut window = self.open();
+        let mut scaled = Vec::new();
+
+        // the scene can't be sent to another thread, so it's prepared here and borrowed by the
+        // thread rendering it
+        let scene = renderer.prepare(scene);
+        let cancel = AtomicBool::new(false);
+        let (sender, tiles) = mpsc::channel();
+        crossbeam_utils::thread::scope(|s| {
+            let (scene, cancel) = (&scene, &cancel);
+            let rendering =
+                s.spawn(move |_| renderer.render_prepared(scene, Some((sender, cancel)), None));
+
+            let mut region = None;
+            while window.is_open() && !window.is_key_down(Key::Escape) {
+                if let Some([x, y, width, height]) = self.select_region(&window, &mut view) {
+                    let samples = REGION_SAMPLES * renderer.samples;
+                    let renderer = renderer.clone().samples(samples).crop(x, y, width, height);
+                    region = Some(RenderThread::spawn(s, renderer, scene));
+                }
+                for update in tiles.try_iter() {
+                    view.update_tile(update);
+                }
+                for update in region.iter().flat_map(|region| region.tiles.try_iter()) {
+                    view.update_tile(update);
+                }
+                // a region is selected instead of panning while Ctrl is held
+                let pan = !ctrl_down(&window);
+                self.update_view(&mut window, &mut view, pan);
+                self.handle_keys(&window, &view.render, Some(&view));
+                self.show(&mut window, view.screen(), &mut scaled);
+            }
+
+            cancel.store(true, Ordering::Relaxed);
+            rendering.join().unwrap()
+        })
+        .unwrap()
+    }
+
+    /// Renders the scene like `display_progressive`, with the camera controlled by the mouse:
+    /// dragging with the left button (without Shift, which pans the zoomed image, see
+    /// `RenderWindow`) orbits around the point the camera looks at, dragging with the right or
+    /// middle button pans, and scrolling zooms in and out. The render starts over whenever the
+    /// camera moves, with a single sample for each pixel while a button is held, so it keeps up
+    /// with the mouse.
+    ///
+    /// Returns the camera the window was closed with, e.g. to save in the scene's render settings.
+    /// The window must be the size of the render.
+    /// ```no_run
+    /// use firework::procgen::RandomSpheres;
+    /// use firework::window::RenderWindow;
+    ///
+    /// let mut scene = RandomSpheres::new().build();
+    /// let renderer = scene.render_settings.take().unwrap();
+    /// let window = RenderWindow::new("Explore", Default::default(), 960, 540);
+    /// let camera = window.explore(&renderer, scene);
+    /// ```
+    pub fn explore(&self, renderer: &Renderer, scene: Scene) -> CameraSettings {
+        assert_eq!(
+            (self.width, self.height),
+            (renderer.width, renderer.height),
+            "RenderWindow::explore() -- the window isn't the size of the render"
+        );
+        let mut view = View::new(self.width, self.height, renderer.gamma);
+        let mut window = self.open();
+        let mut scaled = Vec::new();
+
+        let scene = renderer.prepare(scene);
+        let mut camera = renderer.camera_settings();
+        crossbeam_utils::thread::scope(|s| {
+            let scene = &scene;
+            let start = |camera: CameraSettings, samples: usize| {
+                RenderThread::spawn(s, renderer.clone().camera(camera).samples(samples), scene)
+            };
+
+            let mut render = start(camera, renderer.samples);
+            let mut region = None;
+            let mut mouse = self.mouse_pos(&window, MouseMode::Pass);
+            let mut dragging = false;
+            while window.is_open() && !window.is_key_down(Key::Escape) {
+                // the image is panned instead while Shift is held, and a region is selected
+                // while Ctrl is
+                let shift =
+                    window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+                let ctrl = ctrl_down(&window);
+                let orbiting = !shift && !ctrl && window.get_mouse_down(MouseButton::Left);
+                let panning = window.get_mouse_down(MouseButton::Right)
+                    || window.get_mouse_down(MouseButton::Middle);
+
+                let mut moved = false;
+                let new_mouse = self.mouse_pos(&window, MouseMode::Pass);
+                if let (Some((x0, y0)), Some((x, y))) = (mouse, new_mouse) {
+                    // in units of the height of the window, with y up
+                    let (dx, dy) = ((x - x0) / self.height as f32, (y0 - y) / self.height as f32);
+                    if (dx, dy) != (0., 0.) && orbiting {
+                        camera = camera.orbit(-PI * dx, -PI * dy);
+                        moved = true;
+                    } else if (dx, dy) != (0., 0.) && panning {
+                        camera = camera.pan(-dx, -dy);
+                        moved = true;
+                    }
+                }
+                mouse = new_mouse;
+                if let Some((_, scroll)) = window.get_scroll_wheel() {
+                    camera = camera.zoom(0.9f32.powf(scroll));
+                    moved = true;
+                }
+
+                // the full render starts again once the mouse is let go
+                let held = orbiting || panning;
+                if moved || (dragging && !held) {
+                    // the old render is stopped when it's dropped
+                    region = None;
+                    view.restart();
+                    let samples = if held { 1 } else { renderer.samples };
+                    render = start(camera, samples);
+                }
+                dragging = held;
+
+                if let Some([x, y, width, height]) = self.select_region(&window, &mut view) {
+                    let samples = REGION_SAMPLES * renderer.samples;
+                    let renderer = renderer.clone().camera(camera).samples(samples);
+                    let renderer = renderer.crop(x, y, width, height);
+                    region = Some(RenderThread::spawn(s, renderer, scene));
+                }
+                for update in render.tiles.try_iter() {
+                    view.update_tile(update);
+                }
+                for update in region.iter().flat_map(|region| region.tiles.try_iter()) {
+                    view.update_tile(update);
+                }
+                self.update_view(&mut window, &mut view, shift && !ctrl);
+                self.handle_keys(&window, &view.render, Some(&view));
+                self.show(&mut window, view.screen(), &mut scaled);
             }
-            window
-                .update_with_buffer(&buffer, self.width, self.height)
-                .unwrap();
+
+            camera
+        })
+        .unwrap()
+    }
+
+    /// Lets a region of the image be selected by dragging with Ctrl and the left mouse button,
+    /// and returns it as `[x, y, width, height]` once the button is let go
+    fn select_region(&self, window: &Window, view: &mut View) -> Option<[usize; 4]> {
+        let selecting = ctrl_down(window) && window.get_mouse_down(MouseButton::Left);
+        let point = self
+            .mouse_pos(window, MouseMode::Clamp)
+            .and_then(|cursor| view.pixel_at(cursor))
+            .map(|idx| (idx % self.width, idx / self.width));
+        match (view.selection, point) {
+            (None, Some(point)) if selecting => view.selection = Some([point, point]),
+            (Some([start, _]), Some(point)) if selecting => view.selection = Some([start, point]),
+            (Some([(x0, y0), (x1, y1)]), _) if !selecting => {
+                view.selection = None;
+                let (x, y) = (x0.min(x1), y0.min(y1));
+                return Some([x, y, x0.max(x1) - x + 1, y0.max(y1) - y + 1]);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Handles the keys and mouse controls of the view (see `RenderWindow`), with the image
+    /// dragged by the left mouse button if `pan` is set, and shows the settings and the pixel
+    /// under the cursor in the title of the window
+    fn update_view(&self, window: &mut Window, view: &mut View, pan: bool) {
+        let pressed = |key| window.is_key_pressed(key, KeyRepeat::Yes);
+        let (exposure, gamma, tonemapper) = (view.exposure, view.gamma, view.tonemapper);
+        if pressed(Key::Up) {
+            view.exposure += 0.5;
+        }
+        if pressed(Key::Down) {
+            view.exposure -= 0.5;
+        }
+        if pressed(Key::Right) {
+            view.gamma += 0.1;
+        }
+        if pressed(Key::Left) {
+            view.gamma = (view.gamma - 0.1).max(0.1);
+        }
+        if window.is_key_pressed(Key::T, KeyRepeat::No) {
+            view.tonemapper = view.tonemapper.next();
+        }
+        if window.is_key_pressed(Key::H, KeyRepeat::No) {
+            view.histogram = !view.histogram;
+        }
+        if window.is_key_pressed(Key::Z, KeyRepeat::No) {
+            view.zebra = !view.zebra;
+        }
+        let mouse = self.mouse_pos(window, MouseMode::Pass);
+        if pressed(Key::Equal) || pressed(Key::NumPadPlus) {
+            view.set_zoom(view.zoom * 2, mouse);
+        }
+        if pressed(Key::Minus) || pressed(Key::NumPadMinus) {
+            view.set_zoom(view.zoom / 2, mouse);
+        }
+        if window.is_key_pressed(Key::Backspace, KeyRepeat::No) {
+            view.reset();
+        }
+        if (exposure, gamma, tonemapper) != (view.exposure, view.gamma, view.tonemapper) {
+            view.redraw();
+        }
+
+        if pan && window.get_mouse_down(MouseButton::Left) {
+            if let (Some((x0, y0)), Some((x, y))) = (view.mouse, mouse) {
+                view.pan(x0 - x, y0 - y);
+            }
+        }
+        view.mouse = mouse;
+
+        let title = view.title(self.title, self.mouse_pos(window, MouseMode::Discard));
+        if title != view.title {
+            window.set_title(&title);
+            view.title = title;
+        }
+    }
+
+    /// The size of the window, which is the size of the render times the scale
+    fn window_size(&self) -> (usize, usize) {
+        let scale = |size: usize| ((size as f32 * self.scale).round() as usize).max(1);
+        (scale(self.width), scale(self.height))
+    }
+
+    /// The position of the mouse in pixels of the render rather than of the window
+    fn mouse_pos(&self, window: &Window, mode: MouseMode) -> Option<(f32, f32)> {
+        let (width, height) = self.window_size();
+        let (x, y) = window.get_mouse_pos(mode)?;
+        Some((
+            x * self.width as f32 / width as f32,
+            y * self.height as f32 / height as f32,
+        ))
+    }
+
+    /// Shows an image the size of the render, scaled to the size of the window if it isn't the
+    /// same. `scaled` holds the scaled image, so it isn't allocated again each frame.
+    fn show(&self, window: &mut Window, image: &[u32], scaled: &mut Vec<u32>) {
+        let (width, height) = self.window_size();
+        let buffer = if (width, height) == (self.width, self.height) {
+            image
+        } else {
+            let from = (self.width, self.height);
+            scale_image(image, from, (width, height), self.filter, scaled);
+            scaled
+        };
+        window.update_with_buffer(buffer, width, height).unwrap();
+    }
+
+    fn open(&self) -> Window {
+        let (width, height) = self.window_size();
+        let mut window = Window::new(self.title, width, height, self.options).unwrap_or_else(|e| {
+            panic!("Window creation failed -- {}", e);
+        });
+
+        window.limit_update_rate(Some(std::time::Duration::from_millis(1000 / self.fps)));
+        window
+    }
+
+    /// Saves the image shown when the save key is released, and passes the keys pressed to the
+    /// key handler. `view` is the view the image is shown from, if it has the linear colors.
+    fn handle_keys(&self, window: &Window, render: &[Color], view: Option<&View>) {
+        if window.is_key_released(self.save_options.key) {
+            self.save(render, view);
+        }
+        if let Some(handler) = &self.key_handler {
+            for key in window.get_keys_pressed(KeyRepeat::No).unwrap_or_default() {
+                (handler.borrow_mut())(key, render);
+            }
+        }
+    }
+
+    /// Saves the image shown as the save options describe. Without the linear colors, it can
+    /// only be saved as an 8 bit image.
+    fn save(&self, render: &[Color], view: Option<&View>) {
+        let (format, samples) = match view {
+            Some(view) => (
+                self.save_options.format,
+                view.samples.iter().copied().max().unwrap_or(0),
+            ),
+            None => (OutputFormat::Image, 0),
+        };
+        let path = self.save_options.path(self.title, samples, format);
+
+        let result = match view {
+            Some(view) if format != OutputFormat::Image => {
+                let mut buffer = RenderBuffer::new(self.width, self.height);
+                buffer.color = view.linear.clone();
+                save_render(&buffer, &path, view.gamma, format)
+            }
+            _ => {
+                let bytes: Vec<u8> = render.iter().flat_map(|&x| [x.0, x.1, x.2]).collect();
+                let (width, height) = (self.width as u32, self.height as u32);
+                image::save_buffer(&path, &bytes, width, height, image::ColorType::Rgb8)
+            }
+        };
+        match result {
+            Ok(()) => println!("Saved image to {:?}", path),
+            Err(e) => eprintln!("Failed to save image to {:?} -- {}", path, e),
         }
     }
 }
 
-pub fn save_image<P>(render: &[Color], path: P, width: usize, height: usize)
-where
-    P: AsRef<Path>,
-{
-    let new_buf: Vec<u8> = render.iter().flat_map(|&x| [x.0, x.1, x.2]).collect();
-    save_buffer(path, &new_buf, width as u32, height as u32, ColorType::Rgb8)
-        .expect("Failed to save");
+/// Where and how a `RenderWindow` saves the image it shows when a key is released. 8 bit images
+/// are saved as they're shown, with the exposure and tonemapper of the window. The other formats
+/// are saved from the linear colors of the render, gamma corrected with the gamma of the window
+/// where needed.
+/// ```no_run
+/// use firework::output::OutputFormat;
+/// use firework::window::{RenderWindow, SaveOptions};
+/// use minifb::Key;
+///
+/// let mut window = RenderWindow::new("Preview", Default::default(), 960, 540);
+/// window.set_save_options(
+///     SaveOptions::default()
+///         .key(Key::S)
+///         .directory("renders")
+///         .pattern("{title}_{samples}spp_{time}")
+///         .format(OutputFormat::Exr),
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct SaveOptions {
+    pub key: Key,
+    /// The directory the images are saved in, which must exist
+    pub directory: PathBuf,
+    /// The name of the files, without the extension. `{title}` is replaced by the title of the
+    /// window, `{time}` by the number of seconds since the Unix epoch, and `{samples}` by the
+    /// number of samples of the pixels (0 if it isn't known).
+    pub pattern: String,
+    /// The format of the files, which gives their extension. `display` only has the 8 bit
+    /// colors, so it always saves 8 bit PNGs.
+    pub format: OutputFormat,
+}
+
+impl Default for SaveOptions {
+    /// F3 saves a PNG named after the window, in the current directory
+    fn default() -> Self {
+        SaveOptions {
+            key: Key::F3,
+            directory: PathBuf::from("."),
+            pattern: "{title}".to_owned(),
+            format: OutputFormat::Image,
+        }
+    }
+}
+
+impl SaveOptions {
+    pub fn key(mut self, key: Key) -> Self {
+        self.key = key;
+        self
+    }
+
+    pub fn directory<P: AsRef<Path>>(mut self, directory: P) -> Self {
+        self.directory = directory.as_ref().to_owned();
+        self
+    }
+
+    pub fn pattern<S: Into<String>>(mut self, pattern: S) -> Self {
+        self.pattern = pattern.into();
+        self
+    }
+
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// The path to save an image in `format` to, from a window with the given title, with
+    /// `samples` samples of each pixel
+    fn path(&self, title: &str, samples: usize, format: OutputFormat) -> PathBuf {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let name = self
+            .pattern
+            .replace("{title}", title)
+            .replace("{time}", &time.to_string())
+            .replace("{samples}", &samples.to_string());
+        self.directory
+            .join(format!("{}.{}", name, format.extension()))
+    }
+}
+
+/// The ways the colors of a render can be brought into the range of the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tonemapper {
+    /// The colors are clipped
+    None,
+    /// Each color `c` becomes `c / (1 + c)`, which keeps some detail in the highlights
+    Reinhard,
+    /// A fit of the ACES filmic curve (by Krzysztof Narkowicz), with more contrast than Reinhard
+    Aces,
+}
+
+impl Tonemapper {
+    fn next(self) -> Tonemapper {
+        match self {
+            Tonemapper::None => Tonemapper::Reinhard,
+            Tonemapper::Reinhard => Tonemapper::Aces,
+            Tonemapper::Aces => Tonemapper::None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Tonemapper::None => "no tonemapping",
+            Tonemapper::Reinhard => "Reinhard",
+            Tonemapper::Aces => "ACES",
+        }
+    }
+
+    fn apply(self, color: Vec3) -> Vec3 {
+        match self {
+            Tonemapper::None => color,
+            Tonemapper::Reinhard => color.map(|x| x / (1. + x)),
+            Tonemapper::Aces => {
+                color.map(|x| (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14))
+            }
+        }
+    }
+}
+
+/// The image shown in a window, kept as the linear colors of the render along with how they're
+/// mapped to the screen, so that can be changed without rendering again
+struct View {
+    exposure: f32,
+    gamma: f32,
+    /// The gamma the view started with, to go back to
+    initial_gamma: f32,
+    tonemapper: Tonemapper,
+    /// How many pixels of the window each pixel of the image takes up in each direction
+    zoom: usize,
+    /// The point of the image at the top left of the window, in pixels
+    offset: (f32, f32),
+    /// Where the mouse was the last time the view was updated, to drag the image by
+    mouse: Option<(f32, f32)>,
+    title: String,
+    width: usize,
+    height: usize,
+    linear: Vec<Vec3>,
+    /// The number of samples of each pixel so far, or 0 if it isn't known
+    samples: Vec<usize>,
+    /// Whether each pixel has been rendered. The ones which haven't show a checker pattern.
+    filled: Vec<bool>,
+    /// The tiles being rendered, as `[x, y, width, height]`, which are outlined
+    active: Vec<[usize; 4]>,
+    /// The corners of the region being selected, in pixels of the image
+    selection: Option<[(usize, usize); 2]>,
+    /// The colors as they're shown, which are saved as 8 bit images
+    render: Vec<Color>,
+    buffer: Vec<u32>,
+    /// Whether to show a histogram of the brightness of the image
+    histogram: bool,
+    /// Whether to show the clipped pixels with zebra stripes
+    zebra: bool,
+    /// The buffer shown in the window while zoomed in, or with the histogram or zebra stripes
+    screen: Vec<u32>,
+}
+
+impl View {
+    /// A view of nothing rendered yet, which gamma corrects with `gamma`, with no exposure,
+    /// tonemapping or zoom
+    fn new(width: usize, height: usize, gamma: f32) -> View {
+        let mut view = View {
+            exposure: 0.,
+            gamma,
+            initial_gamma: gamma,
+            tonemapper: Tonemapper::None,
+            zoom: 1,
+            offset: (0., 0.),
+            mouse: None,
+            title: String::new(),
+            width,
+            height,
+            linear: vec![Vec3::zero(); width * height],
+            samples: vec![0; width * height],
+            filled: vec![false; width * height],
+            active: Vec::new(),
+            selection: None,
+            render: vec![Color(0, 0, 0); width * height],
+            buffer: vec![0; width * height],
+            histogram: false,
+            zebra: false,
+            screen: vec![0; width * height],
+        };
+        view.redraw();
+        view
+    }
+
+    fn set_pixel(&mut self, idx: usize, color: Vec3) {
+        self.linear[idx] = color;
+        self.filled[idx] = true;
+        self.render[idx] = self.map(color);
+        self.buffer[idx] = self.render[idx].into();
+    }
+
+    /// Shows a pixel as it is, without the outline of its tile
+    fn restore_pixel(&mut self, idx: usize) {
+        if self.filled[idx] {
+            self.set_pixel(idx, self.linear[idx]);
+        } else {
+            // a checker pattern of 8 pixel squares
+            let (x, y) = (idx % self.width, idx / self.width);
+            self.buffer[idx] = if (x / 8 + y / 8) % 2 == 0 {
+                0x303030
+            } else {
+                0x505050
+            };
+        }
+    }
+
+    /// Shows a tile of a render in progress being started or done
+    fn update_tile(&mut self, update: TileUpdate) {
+        match update {
+            TileUpdate::Started {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                self.active.push([x, y, width, height]);
+                self.outline_tile([x, y, width, height]);
+            }
+            TileUpdate::Done(tile) => {
+                self.active.retain(|&[x, y, ..]| (x, y) != (tile.x, tile.y));
+                self.draw_tile(&tile);
+            }
+        }
+    }
+
+    /// Puts the pixels of a tile of a render in progress in the view, unless they already have
+    /// more samples (from a render of a region, see `RenderWindow`)
+    fn draw_tile(&mut self, tile: &Tile) {
+        for (i, &color) in tile.color.iter().enumerate() {
+            let idx = (tile.y + i / tile.width) * self.width + tile.x + i % tile.width;
+            if tile.samples >= self.samples[idx] {
+                self.set_pixel(idx, color);
+                self.samples[idx] = tile.samples;
+            }
+        }
+    }
+
+    /// The pixels of a tile, given as `[x, y, width, height]`
+    fn tile_pixels(
+        &self,
+        [x, y, width, height]: [usize; 4],
+    ) -> impl Iterator<Item = (usize, usize)> {
+        (0..width * height).map(move |i| (x + i % width, y + i / width))
+    }
+
+    /// Draws a border around the edge of a tile being rendered
+    fn outline_tile(&mut self, tile: [usize; 4]) {
+        let [x, y, width, height] = tile;
+        for (px, py) in self.tile_pixels(tile).collect::<Vec<_>>() {
+            if px == x || py == y || px == x + width - 1 || py == y + height - 1 {
+                self.buffer[py * self.width + px] = 0xffa000;
+            }
+        }
+    }
+
+    /// Takes away the outlines of the tiles being rendered, and lets a new render replace all of
+    /// the pixels, when the render is started again
+    fn restart(&mut self) {
+        for tile in std::mem::take(&mut self.active) {
+            for (px, py) in self.tile_pixels(tile).collect::<Vec<_>>() {
+                self.restore_pixel(py * self.width + px);
+            }
+        }
+        self.samples.iter_mut().for_each(|samples| *samples = 0);
+    }
+
+    /// Maps all the pixels to the screen again, after the settings have changed
+    fn redraw(&mut self) {
+        for idx in 0..self.linear.len() {
+            self.restore_pixel(idx);
+        }
+        for tile in self.active.clone() {
+            self.outline_tile(tile);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.exposure = 0.;
+        self.gamma = self.initial_gamma;
+        self.tonemapper = Tonemapper::None;
+        self.set_zoom(1, None);
+    }
+
+    /// Maps a linear color to the one shown on screen
+    fn map(&self, color: Vec3) -> Color {
+        let color = self.tonemapper.apply(color * 2f32.powf(self.exposure));
+        color
+            .map(|x| x.max(0.).powf(1. / self.gamma).clamp(0., 1.))
+            .into()
+    }
+
+    /// Zooms in or out (to between 1x and 64x), keeping the point of the image under the cursor
+    /// (or the middle of the window) where it is
+    fn set_zoom(&mut self, zoom: usize, cursor: Option<(f32, f32)>) {
+        let (x, y) = cursor.unwrap_or((self.width as f32 / 2., self.height as f32 / 2.));
+        let (old, zoom) = (self.zoom as f32, zoom.clamp(1, 64));
+        let point = (self.offset.0 + x / old, self.offset.1 + y / old);
+        self.zoom = zoom;
+        self.offset = (point.0 - x / zoom as f32, point.1 - y / zoom as f32);
+        self.pan(0., 0.);
+    }
+
+    /// Moves the image by a distance in pixels of the window, keeping it covering the window
+    fn pan(&mut self, dx: f32, dy: f32) {
+        let zoom = self.zoom as f32;
+        let (max_x, max_y) = (
+            self.width as f32 * (1. - 1. / zoom),
+            self.height as f32 * (1. - 1. / zoom),
+        );
+        self.offset = (
+            (self.offset.0 + dx / zoom).clamp(0., max_x),
+            (self.offset.1 + dy / zoom).clamp(0., max_y),
+        );
+    }
+
+    /// The index of the pixel of the image at a point in the window
+    fn pixel_at(&self, (x, y): (f32, f32)) -> Option<usize> {
+        let zoom = self.zoom as f32;
+        let (x, y) = (self.offset.0 + x / zoom, self.offset.1 + y / zoom);
+        if x < 0. || y < 0. || x >= self.width as f32 || y >= self.height as f32 {
+            return None;
+        }
+        Some(y as usize * self.width + x as usize)
+    }
+
+    /// The pixels to show in the window
+    fn screen(&mut self) -> &[u32] {
+        if self.zoom == 1 && !self.histogram && !self.zebra && self.selection.is_none() {
+            return &self.buffer;
+        }
+        let mut screen = std::mem::take(&mut self.screen);
+        for (i, pixel) in screen.iter_mut().enumerate() {
+            let (x, y) = (i % self.width, i / self.width);
+            // diagonal stripes, 4 pixels wide
+            let stripe = (x + y) / 4 % 2 == 0;
+            *pixel = match self.pixel_at((x as f32 + 0.5, y as f32 + 0.5)) {
+                Some(idx) if self.on_selection(idx) => [0, 0xffffff][stripe as usize],
+                Some(idx) if self.zebra && self.clipped(idx) && stripe => 0,
+                Some(idx) => self.buffer[idx],
+                None => 0,
+            };
+        }
+        if self.histogram {
+            self.draw_histogram(&mut screen);
+        }
+        self.screen = screen;
+        &self.screen
+    }
+
+    /// Whether a pixel is on the edge of the region being selected
+    fn on_selection(&self, idx: usize) -> bool {
+        let [(x0, y0), (x1, y1)] = match self.selection {
+            Some(selection) => selection,
+            None => return false,
+        };
+        let (x, y) = (idx % self.width, idx / self.width);
+        let (left, right, top, bottom) = (x0.min(x1), x0.max(x1), y0.min(y1), y0.max(y1));
+        (left..=right).contains(&x)
+            && (top..=bottom).contains(&y)
+            && (x == left || x == right || y == top || y == bottom)
+    }
+
+    /// Whether a pixel which has been rendered is shown at its brightest in any of its colors
+    fn clipped(&self, idx: usize) -> bool {
+        let Color(r, g, b) = self.render[idx];
+        self.filled[idx] && (r == 255 || g == 255 || b == 255)
+    }
+
+    /// Draws a histogram of the brightness of the pixels as they're shown in the bottom left
+    /// corner of the window, with the last bar in red if any pixels are clipped
+    fn draw_histogram(&self, screen: &mut [u32]) {
+        let mut counts = [0usize; 256];
+        let mut clipped = 0;
+        for idx in (0..self.render.len()).filter(|&idx| self.filled[idx]) {
+            let Color(r, g, b) = self.render[idx];
+            let luminance = 0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32;
+            counts[luminance.round() as usize] += 1;
+            if self.clipped(idx) {
+                clipped += 1;
+            }
+        }
+
+        let margin = 8;
+        let width = 256.min(self.width.saturating_sub(2 * margin));
+        let height = 96.min(self.height / 3);
+        if width == 0 || height == 0 {
+            return;
+        }
+        // the darkest and brightest values often have far more pixels than the rest, so they
+        // don't set the scale
+        let max = counts[1..255].iter().copied().max().unwrap_or(0).max(1);
+        let top = self.height - margin - height;
+        for column in 0..width {
+            let bins = &counts[column * 256 / width..(column + 1) * 256 / width];
+            let count: usize = bins.iter().sum();
+            let bar = (count * height / max).min(height);
+            let color = if column == width - 1 && clipped > 0 {
+                0xff4040
+            } else {
+                0xe0e0e0
+            };
+            for row in 0..height {
+                let pixel = &mut screen[(top + row) * self.width + margin + column];
+                *pixel = if height - row <= bar {
+                    color
+                } else {
+                    // a darkened background, so the histogram can be seen over any image
+                    (*pixel >> 2) & 0x3f3f3f
+                };
+            }
+        }
+    }
+
+    /// The title of the window, with the settings if they've been changed, and the pixel under
+    /// the cursor
+    fn title(&self, name: &str, cursor: Option<(f32, f32)>) -> String {
+        let mut title = name.to_owned();
+        if self.zoom > 1 {
+            title += &format!(" ({}x)", self.zoom);
+        }
+        let settings = (self.exposure, self.gamma, self.tonemapper);
+        if settings != (0., self.initial_gamma, Tonemapper::None) {
+            title += &format!(
+                " (exposure {:+.1}, gamma {:.1}, {})",
+                self.exposure,
+                self.gamma,
+                self.tonemapper.name()
+            );
+        }
+        if let Some(idx) = cursor.and_then(|cursor| self.pixel_at(cursor)) {
+            let color = self.linear[idx];
+            title += &format!(
+                " - pixel ({}, {}): {:.4} {:.4} {:.4}",
+                idx % self.width,
+                idx / self.width,
+                color.x,
+                color.y,
+                color.z
+            );
+            if self.samples[idx] > 0 {
+                title += &format!(", {} samples", self.samples[idx]);
+            }
+        }
+        title
+    }
 }